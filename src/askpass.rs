@@ -0,0 +1,169 @@
+use anyhow::Result;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+/// How long the password file may stay on disk when ssh never reads it, e.g. because the
+/// host key prompt was left unanswered. The GUI holds each feed until then, or until it
+/// closes.
+const FEED_LIFETIME: Duration = Duration::from_secs(60);
+
+/// Hands a stored password to ssh running in a terminal the app doesn't control, through a
+/// file in a directory only the user can read: `sshpass -f` when sshpass is installed,
+/// otherwise an `SSH_ASKPASS` script that deletes itself once ssh has run it. The directory
+/// is removed on drop.
+pub struct PasswordFeed {
+    /// Replaces `ssh` on the command line, e.g. `["sshpass", "-f", FILE, "ssh"]`
+    pub command: Vec<String>,
+    dir: PathBuf,
+    created: Instant,
+}
+
+impl PasswordFeed {
+    /// `None` where neither mechanism is available (Windows, which can't run the script).
+    pub fn new(password: &str) -> Result<Option<Self>> {
+        #[cfg(unix)]
+        {
+            Self::new_unix(password, find_in_path("sshpass").is_some()).map(Some)
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = password;
+            Ok(None)
+        }
+    }
+
+    #[cfg(unix)]
+    fn new_unix(password: &str, sshpass: bool) -> Result<Self> {
+        use std::os::unix::fs::DirBuilderExt;
+        let dir = std::env::temp_dir().join(format!(
+            "mcp-ssh-manager-askpass-{}-{:016x}",
+            std::process::id(),
+            rand::random::<u64>()
+        ));
+        std::fs::DirBuilder::new().mode(0o700).create(&dir)?;
+        // From here on, drop removes the directory if writing fails
+        let mut feed = PasswordFeed {
+            command: Vec::new(),
+            dir,
+            created: Instant::now(),
+        };
+        if sshpass {
+            let file = feed.dir.join("password");
+            write_private(&file, password, 0o600)?;
+            feed.command = vec![
+                "sshpass".to_string(),
+                "-f".to_string(),
+                file.display().to_string(),
+                "ssh".to_string(),
+            ];
+        } else {
+            let script = feed.dir.join("askpass.sh");
+            write_private(&script, &askpass_script(password), 0o700)?;
+            // Through env(1), since a terminal server may not pass our environment on.
+            // SSH_ASKPASS_REQUIRE=force (OpenSSH 8.4+) uses the helper despite the terminal.
+            feed.command = vec![
+                "env".to_string(),
+                format!("SSH_ASKPASS={}", script.display()),
+                "SSH_ASKPASS_REQUIRE=force".to_string(),
+                "ssh".to_string(),
+            ];
+        }
+        Ok(feed)
+    }
+
+    /// Whether [`FEED_LIFETIME`] has passed, so the feed can be dropped.
+    pub fn expired(&self) -> bool {
+        self.created.elapsed() >= FEED_LIFETIME
+    }
+}
+
+impl Drop for PasswordFeed {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// Shell script printing `password` for ssh, deleting itself first so the password is on
+/// disk only until the first password prompt. Any other prompt, such as a host key
+/// confirmation, gets a non-zero exit and leaves the script in place.
+#[cfg(unix)]
+fn askpass_script(password: &str) -> String {
+    format!(
+        "#!/bin/sh\ncase \"$1\" in\n*[Pp]assword*) ;;\n*) exit 1 ;;\nesac\nrm -f \"$0\"\nprintf '%s\\n' '{}'\n",
+        password.replace('\'', "'\\''")
+    )
+}
+
+#[cfg(unix)]
+fn write_private(path: &std::path::Path, contents: &str, mode: u32) -> Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(mode)
+        .open(path)?;
+    file.write_all(contents.as_bytes())?;
+    Ok(())
+}
+
+/// First `program` found in a `PATH` directory.
+#[cfg(unix)]
+fn find_in_path(program: &str) -> Option<PathBuf> {
+    let paths = std::env::var_os("PATH")?;
+    std::env::split_paths(&paths)
+        .map(|dir| dir.join(program))
+        .find(|candidate| candidate.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_askpass_script_quotes_password() {
+        assert_eq!(
+            askpass_script("it's $HOME"),
+            "#!/bin/sh\ncase \"$1\" in\n*[Pp]assword*) ;;\n*) exit 1 ;;\nesac\nrm -f \"$0\"\nprintf '%s\\n' 'it'\\''s $HOME'\n"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_feed_files_are_private_and_removed_on_drop() {
+        use std::os::unix::fs::PermissionsExt;
+        for sshpass in [true, false] {
+            let feed = PasswordFeed::new_unix("hunter2", sshpass).unwrap();
+            let file = feed
+                .dir
+                .join(if sshpass { "password" } else { "askpass.sh" });
+            let mode = std::fs::metadata(&file).unwrap().permissions().mode() & 0o777;
+            assert_eq!(mode, if sshpass { 0o600 } else { 0o700 });
+            assert_eq!(feed.command.last().map(String::as_str), Some("ssh"));
+            assert!(!feed.expired());
+            if sshpass {
+                assert_eq!(std::fs::read_to_string(&file).unwrap(), "hunter2");
+            } else {
+                // Other prompts are refused and leave the script for the password prompt
+                let output = std::process::Command::new(&file)
+                    .arg("Are you sure you want to continue connecting (yes/no)? ")
+                    .output()
+                    .unwrap();
+                assert!(!output.status.success());
+                assert!(output.stdout.is_empty());
+                assert!(file.exists());
+                // The script prints the password once, then is gone
+                let output = std::process::Command::new(&file)
+                    .arg("alice@host's password: ")
+                    .output()
+                    .unwrap();
+                assert_eq!(output.stdout, b"hunter2\n");
+                assert!(!file.exists());
+            }
+            let dir = feed.dir.clone();
+            drop(feed);
+            assert!(!dir.exists());
+        }
+    }
+}
@@ -0,0 +1,155 @@
+use crate::db;
+use crate::gui;
+use crate::known_hosts;
+use anyhow::{Result, anyhow};
+use directories::ProjectDirs;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// File name looked up in the platform config directory when `--config` isn't given.
+const CONFIG_FILE: &str = "config.toml";
+
+/// Settings a config file may give defaults for. A value saved in the database (e.g.
+/// from the GUI's settings tab) still wins.
+const CONFIGURABLE_SETTINGS: [&str; 10] = [
+    gui::SETTING_RETENTION_COUNT,
+    gui::SETTING_RETENTION_DAYS,
+    gui::SETTING_AUTO_BACKUP,
+    gui::SETTING_TERMINAL_COMMAND,
+    gui::SETTING_MACOS_TERMINAL,
+    db::SETTING_SSH_RATE_LIMIT_SECS,
+    db::SETTING_TRANSFER_DIR,
+    gui::SETTING_IDLE_LOCK_MINUTES,
+    db::SETTING_ROTATION_REMINDER_DAYS,
+    known_hosts::SETTING_MANAGED_FILE,
+];
+
+/// Defaults read from `config.toml`. Command-line flags override them; anything left
+/// unset falls back to the built-in default.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub profile: Option<String>,
+    pub log_level: Option<String>,
+    pub db_path: Option<PathBuf>,
+    pub key_file: Option<PathBuf>,
+    pub strict_knowledge: Option<bool>,
+    pub no_auto_backup: Option<bool>,
+    pub add: AddDefaults,
+    /// Defaults for settings-table keys, e.g. `"backup.retention_count" = 10`
+    pub settings: HashMap<String, toml::Value>,
+}
+
+/// Defaults for `add`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AddDefaults {
+    pub owner: Option<String>,
+    pub os: Option<String>,
+}
+
+/// Default location: `config.toml` in the platform config directory. Shared by all
+/// profiles, since the file may be what selects the profile.
+pub fn default_path() -> Option<PathBuf> {
+    ProjectDirs::from("com", "veltrea", "mcp-ssh-manager")
+        .map(|dirs| dirs.config_dir().join(CONFIG_FILE))
+}
+
+/// Reads `path`, or the [`default_path`] when none is given. Only an explicitly given
+/// file has to exist.
+pub fn load(path: Option<&Path>) -> Result<Config> {
+    let (path, required) = match path {
+        Some(path) => (path.to_path_buf(), true),
+        None => match default_path() {
+            Some(path) => (path, false),
+            None => return Ok(Config::default()),
+        },
+    };
+    let content = match std::fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(e) if !required && e.kind() == std::io::ErrorKind::NotFound => {
+            return Ok(Config::default());
+        }
+        Err(e) => return Err(anyhow!("Failed to read {}: {}", path.display(), e)),
+    };
+    parse(&content).map_err(|e| anyhow!("Invalid config file {}: {}", path.display(), e))
+}
+
+fn parse(content: &str) -> Result<Config> {
+    let config: Config = toml::from_str(content)?;
+    for key in config.settings.keys() {
+        if !CONFIGURABLE_SETTINGS.contains(&key.as_str()) {
+            return Err(anyhow!(
+                "unknown setting \"{}\" (expected one of: {})",
+                key,
+                CONFIGURABLE_SETTINGS.join(", ")
+            ));
+        }
+    }
+    Ok(config)
+}
+
+impl Config {
+    /// `[settings]` as the strings the settings table stores.
+    pub fn setting_defaults(&self) -> Result<HashMap<String, String>> {
+        self.settings
+            .iter()
+            .map(|(key, value)| {
+                let value = match value {
+                    toml::Value::String(s) => s.clone(),
+                    toml::Value::Integer(n) => n.to_string(),
+                    toml::Value::Boolean(b) => b.to_string(),
+                    other => {
+                        return Err(anyhow!(
+                            "setting \"{}\" must be a string, integer, or boolean, not {}",
+                            key,
+                            other.type_str()
+                        ));
+                    }
+                };
+                Ok((key.clone(), value))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_config() {
+        let config = parse(
+            r#"
+            profile = "work"
+            log_level = "debug"
+
+            [add]
+            owner = "company"
+            os = "linux"
+
+            [settings]
+            "backup.retention_count" = 10
+            "terminal.command" = "alacritty -e ssh {user}@{host}"
+            "backup.auto" = false
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.profile.as_deref(), Some("work"));
+        assert_eq!(config.add.os.as_deref(), Some("linux"));
+        let settings = config.setting_defaults().unwrap();
+        assert_eq!(settings["backup.retention_count"], "10");
+        assert_eq!(settings["backup.auto"], "false");
+
+        assert!(parse("unknown = 1").is_err());
+        assert!(parse("[settings]\n\"schema_version\" = 3").is_err());
+        assert!(
+            parse("[settings]\n\"backup.retention_days\" = [1]")
+                .unwrap()
+                .setting_defaults()
+                .is_err()
+        );
+        assert!(parse("").unwrap().profile.is_none());
+    }
+}
@@ -1,20 +1,141 @@
+use crate::known_hosts;
+use crate::security;
+use crate::terminal_text;
 use anyhow::{Context, Result, anyhow};
 use directories::ProjectDirs;
-use rusqlite::{Connection, params};
+use rusqlite::{Connection, OptionalExtension, params};
 use rust_ssh::SecurityManager;
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
-use std::sync::RwLock;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, OnceLock, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::Duration;
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Machine {
     pub id: Option<i64>,
+    /// Stable identity, generated on insert and never changed; unlike `name`, survives renames
+    #[serde(default)]
+    pub uuid: Option<String>,
     pub name: String,
     pub ip_address: String,
     pub purpose: String,
     pub ownership: String, // "company", "personal"
-    pub os_type: String,
-    pub status: String, // "active", "broken", "maintenance"
+    pub os_type: String,   // an [`OsType`] in its canonical form
+    pub status: String,    // "active", "broken", "maintenance"
+    /// Folder the machine is listed under; ungrouped when `None`
+    #[serde(default)]
+    pub group: Option<String>,
+    /// Extra ssh arguments such as `-J admin@bastion`, checked by
+    /// [`crate::health::parse_ssh_options`]
+    #[serde(default)]
+    pub ssh_options: Option<String>,
+    /// Machine to connect through (`ssh -J`); see [`crate::health::jump_chain`]
+    #[serde(default)]
+    pub jump_host_id: Option<i64>,
+    /// Only reachable over a VPN; see [`crate::health::check_vpn`]
+    #[serde(default)]
+    pub requires_vpn: bool,
+    /// Environment variables exported before `run_command`, as a JSON object of strings
+    #[serde(default)]
+    pub env: Option<String>,
+}
+
+/// Operating system of a machine. Parsing is case-insensitive and accepts common
+/// variants ("Ubuntu", "win", "darwin", ...); `Display` gives the canonical stored form.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OsType {
+    Linux,
+    Windows,
+    MacOS,
+    Other,
+}
+
+impl OsType {
+    pub const ALL: [OsType; 4] = [OsType::Linux, OsType::Windows, OsType::MacOS, OsType::Other];
+
+    pub fn icon(self) -> &'static str {
+        match self {
+            OsType::Linux => "🐧",
+            OsType::Windows => "🖥",
+            OsType::MacOS => "🍎",
+            OsType::Other => "💻",
+        }
+    }
+}
+
+impl std::str::FromStr for OsType {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "linux" | "ubuntu" | "debian" | "centos" | "rhel" | "fedora" | "arch" | "alpine" => {
+                Ok(OsType::Linux)
+            }
+            "windows" | "win" | "win10" | "win11" | "windows server" => Ok(OsType::Windows),
+            "macos" | "mac" | "osx" | "darwin" => Ok(OsType::MacOS),
+            "other" => Ok(OsType::Other),
+            _ => Err(anyhow!(
+                "unsupported os_type \"{}\" (expected \"linux\", \"windows\", \"macos\", or \"other\")",
+                s
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for OsType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            OsType::Linux => "linux",
+            OsType::Windows => "windows",
+            OsType::MacOS => "macos",
+            OsType::Other => "other",
+        })
+    }
+}
+
+/// How an account authenticates. Parsing is case-insensitive and accepts common variants
+/// ("Password", "ssh-key", "ssh-agent", ...); `Display` gives the canonical stored form.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuthType {
+    Password,
+    Key,
+    /// ssh-agent holds the keys; nothing is stored
+    Agent,
+    /// Hardware-bound key, also reached through the agent
+    Tpm,
+}
+
+impl std::str::FromStr for AuthType {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "password" | "passwd" | "pass" => Ok(AuthType::Password),
+            "key" | "ssh-key" | "sshkey" | "publickey" | "private-key" | "identity" => {
+                Ok(AuthType::Key)
+            }
+            "agent" | "ssh-agent" => Ok(AuthType::Agent),
+            "tpm" | "tpm2" => Ok(AuthType::Tpm),
+            _ => Err(anyhow!(
+                "unsupported auth_type \"{}\" (expected \"password\", \"key\", \"agent\", or \"tpm\")",
+                s
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for AuthType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            AuthType::Password => "password",
+            AuthType::Key => "key",
+            AuthType::Agent => "agent",
+            AuthType::Tpm => "tpm",
+        })
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -22,8 +143,22 @@ pub struct Account {
     pub id: Option<i64>,
     pub machine_id: i64,
     pub username: String,
-    pub auth_type: String,  // "password", "key"
-    pub credential: String, // password or key path
+    pub auth_type: String,  // an [`AuthType`] in its canonical form
+    pub credential: String, // password or key path (empty for "agent")
+    /// SHA256 fingerprint of the key file for "key" accounts, recorded when it is stored
+    #[serde(default)]
+    pub key_fingerprint: Option<String>,
+    /// Free-form note, e.g. "deploy user, don't rotate its key"; not a secret
+    #[serde(default)]
+    pub note: Option<String>,
+}
+
+/// How often a machine has been connected to, for spotting unused entries.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ConnectionStats {
+    pub machine_id: i64,
+    pub connections: i64,
+    pub last_connected_at: String, // UTC, "YYYY-MM-DD HH:MM:SS"
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -35,6 +170,9 @@ pub struct CommandLog {
     pub command: String,
     pub stdout: Option<String>,
     pub stderr: Option<String>,
+    /// Original stderr bytes, kept only when cleaning changed them.
+    #[serde(skip)]
+    pub stderr_raw: Option<Vec<u8>>,
     pub exit_code: Option<i32>,
     pub timestamp: String,
 }
@@ -46,339 +184,4585 @@ pub struct Constraint {
     pub rule_text: String,
 }
 
-pub struct DbHandler {
-    path: PathBuf,
-    security: SecurityManager,
-    master_key: RwLock<[u8; 32]>,
+/// One recorded diagnose probe, for spotting when a machine started failing.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Diagnostic {
+    pub id: i64,
+    pub machine_id: i64,
+    pub timestamp: String, // UTC, "YYYY-MM-DD HH:MM:SS"
+    pub success: bool,
+    /// Knowledge-base pattern that matched the failure, if any
+    pub matched_pattern_id: Option<String>,
+    /// Last [`DIAGNOSTIC_EXCERPT_CHARS`] characters of the ssh output
+    pub stderr_excerpt: Option<String>,
 }
 
-impl DbHandler {
-    pub fn new() -> Result<Self> {
-        let path = Self::get_db_path()?;
-        let conn = Connection::open(&path)?;
-        let security = SecurityManager::new("mcp-ssh-manager");
-        let master_key = security
-            .get_or_create_master_key()
-            .context("Failed to initialize master key from keyring")?;
+/// Diagnostics kept per machine; older ones are dropped as new ones are recorded.
+pub const MAX_DIAGNOSTICS_PER_MACHINE: i64 = 50;
+/// Characters of ssh output kept with each diagnostic; the end is where the error is.
+pub const DIAGNOSTIC_EXCERPT_CHARS: usize = 500;
 
-        let handler = DbHandler {
-            path,
-            security,
-            master_key: RwLock::new(master_key),
-        };
-        handler.init_schema(&conn)?;
-        handler.migrate_credentials()?; // Phase 11 Task 5
-        Ok(handler)
-    }
+/// Matches per category returned by [`DbHandler::global_search`].
+pub const SEARCH_RESULTS_PER_CATEGORY: usize = 20;
 
-    fn migrate_credentials(&self) -> Result<()> {
-        let mut conn = self.get_conn()?;
-        let tx = conn.transaction()?;
+/// Matches of [`DbHandler::global_search`], grouped by table. Accounts come redacted and
+/// logs newest first.
+#[derive(Debug, Default, Clone)]
+pub struct SearchResults {
+    pub machines: Vec<Machine>,
+    pub accounts: Vec<Account>,
+    pub logs: Vec<CommandLog>,
+    pub constraints: Vec<Constraint>,
+}
 
-        let items: Vec<(i64, String)> = {
-            let mut stmt = tx.prepare("SELECT id, credential FROM accounts")?;
-            stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
-                .collect::<Result<Vec<_>, rusqlite::Error>>()?
-        };
+impl SearchResults {
+    pub fn is_empty(&self) -> bool {
+        self.machines.is_empty()
+            && self.accounts.is_empty()
+            && self.logs.is_empty()
+            && self.constraints.is_empty()
+    }
+}
 
-        for (id, cred) in items {
-            // If it can't be decrypted, it's likely plain text (or encrypted with another key - unlikely for now)
-            let master_key = *self.master_key.read().unwrap();
-            if self.security.decrypt(&master_key, &cred).is_err() {
-                let encrypted = self.security.encrypt(&master_key, &cred)?;
-                tx.execute(
-                    "UPDATE accounts SET credential = ?1 WHERE id = ?2",
-                    params![encrypted, id],
-                )?;
-            }
+/// `LIKE` pattern matching `query` anywhere, with `%`, `_` and `\` taken literally
+/// (use with `ESCAPE '\'`).
+fn like_pattern(query: &str) -> String {
+    let mut pattern = String::from("%");
+    for c in query.chars() {
+        if matches!(c, '%' | '_' | '\\') {
+            pattern.push('\\');
         }
-
-        tx.commit()?;
-        Ok(())
+        pattern.push(c);
     }
+    pattern.push('%');
+    pattern
+}
 
-    fn get_conn(&self) -> Result<Connection> {
-        Ok(Connection::open(&self.path)?)
-    }
+/// Everything [`DbHandler::delete_machine`] removed, so the deletion can be undone.
+/// Account credentials are kept encrypted as stored, so a snapshot only restores
+/// under the master key it was taken with.
+#[derive(Debug, Clone)]
+pub struct DeletedSnapshot {
+    pub machine: Machine,
+    pub accounts: Vec<Account>,
+    pub constraints: Vec<Constraint>,
+    pub stats: Option<ConnectionStats>,
+    /// Machines that used it as their jump host; the deletion cleared their `jump_host_id`
+    pub jump_dependents: Vec<i64>,
+}
 
-    fn get_db_path() -> Result<PathBuf> {
-        let proj_dirs = ProjectDirs::from("com", "veltrea", "mcp-ssh-manager")
-            .ok_or_else(|| anyhow!("Could not determine project directories"))?;
-        let db_dir = proj_dirs.data_dir();
-        std::fs::create_dir_all(db_dir)?;
-        Ok(db_dir.join("manager.db"))
-    }
+/// Version written to [`Vault::format_version`]; bump it when the layout changes.
+pub const VAULT_FORMAT_VERSION: u32 = 1;
 
-    fn init_schema(&self, conn: &Connection) -> Result<()> {
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS machines (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                name TEXT NOT NULL UNIQUE,
-                ip_address TEXT NOT NULL,
-                purpose TEXT NOT NULL,
-                ownership TEXT NOT NULL,
-                os_type TEXT NOT NULL,
-                status TEXT NOT NULL DEFAULT 'active'
-            )",
-            [],
-        )?;
+/// A portable copy of the inventory for moving to another machine. Credentials are in
+/// plaintext, so it is only ever written sealed with [`security::seal_with_passphrase`].
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Vault {
+    pub format_version: u32,
+    pub exported_at: String, // RFC 3339, UTC
+    pub machines: Vec<VaultMachine>,
+}
 
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS settings (
-                key TEXT PRIMARY KEY,
-                value TEXT NOT NULL
-            )",
-            [],
-        )?;
+/// One machine of a [`Vault`]; ids are those of the exporting database.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct VaultMachine {
+    pub machine: Machine,
+    pub accounts: Vec<Account>,
+    pub constraints: Vec<Constraint>,
+}
 
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS accounts (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                machine_id INTEGER NOT NULL,
-                username TEXT NOT NULL,
-                auth_type TEXT NOT NULL,
-                credential TEXT NOT NULL,
-                FOREIGN KEY(machine_id) REFERENCES machines(id)
-            )",
-            [],
-        )?;
+/// Application name used for the data directory and the keyring service.
+const APP_NAME: &str = "mcp-ssh-manager";
 
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS command_logs (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                machine_id INTEGER NOT NULL,
-                username TEXT NOT NULL,
-                command TEXT NOT NULL,
-                stdout TEXT,
-                stderr TEXT,
-                exit_code INTEGER,
-                timestamp DATETIME DEFAULT CURRENT_TIMESTAMP,
-                FOREIGN KEY(machine_id) REFERENCES machines(id)
-            )",
-            [],
-        )?;
+static PROFILE: OnceLock<String> = OnceLock::new();
 
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS constraints (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                machine_id INTEGER NOT NULL,
-                rule_text TEXT NOT NULL,
-                FOREIGN KEY(machine_id) REFERENCES machines(id)
-            )",
-            [],
-        )?;
-        Ok(())
+/// Switches to a named profile with its own data directory and keyring service
+/// (`mcp-ssh-manager-NAME`), so profiles share neither inventory nor master key.
+/// Must be called before anything touches the data directory.
+pub fn set_profile(name: &str) -> Result<()> {
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(anyhow!(
+            "Invalid profile name \"{}\" (use letters, digits, '-' and '_')",
+            name
+        ));
     }
+    PROFILE
+        .set(name.to_string())
+        .map_err(|_| anyhow!("Profile is already set"))
+}
 
-    pub fn add_machine(&self, machine: Machine) -> Result<i64> {
-        let conn = self.get_conn()?;
-        conn.execute(
-            "INSERT INTO machines (name, ip_address, purpose, ownership, os_type, status)
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
-            params![
-                machine.name,
-                machine.ip_address,
-                machine.purpose,
-                machine.ownership,
-                machine.os_type,
-                machine.status,
-            ],
-        )?;
-        Ok(conn.last_insert_rowid())
+/// [`APP_NAME`], suffixed with the profile name when one is selected.
+pub fn app_name() -> String {
+    match PROFILE.get() {
+        Some(profile) => format!("{}-{}", APP_NAME, profile),
+        None => APP_NAME.to_string(),
     }
+}
 
-    pub fn delete_machine(&self, id: i64) -> Result<()> {
-        let conn = self.get_conn()?;
+static DB_PATH: OnceLock<PathBuf> = OnceLock::new();
 
-        // Delete associated accounts and constraints first
-        conn.execute("DELETE FROM accounts WHERE machine_id = ?1", params![id])?;
-        conn.execute("DELETE FROM constraints WHERE machine_id = ?1", params![id])?;
-        conn.execute("DELETE FROM machines WHERE id = ?1", params![id])?;
-        Ok(())
-    }
+/// Uses `path` instead of `manager.db` in the data directory, e.g. a throwaway database or
+/// one on an external drive. Automatic backups then go to `backups/` next to it; logs and
+/// the keyring master key still follow the profile. Must be called before the database
+/// is opened.
+pub fn set_db_path(path: &Path) -> Result<()> {
+    let path = std::path::absolute(path)
+        .with_context(|| format!("Invalid database path {}", path.display()))?;
+    DB_PATH
+        .set(path)
+        .map_err(|_| anyhow!("Database path is already set"))
+}
 
-    pub fn add_account(&self, mut account: Account) -> Result<i64> {
-        // Encrypt the credential before saving
-        let encrypted = {
-            let key = self.master_key.read().unwrap();
-            self.security.encrypt(&key, &account.credential)?
-        };
-        account.credential = encrypted;
+static PROVIDED_MASTER_KEY: OnceLock<[u8; 32]> = OnceLock::new();
 
-        let conn = self.get_conn()?;
-        conn.execute(
-            "INSERT INTO accounts (machine_id, username, auth_type, credential)
-             VALUES (?1, ?2, ?3, ?4)",
-            params![
-                account.machine_id,
-                account.username,
-                account.auth_type,
-                account.credential,
-            ],
-        )?;
-        Ok(conn.last_insert_rowid())
-    }
+/// Uses `key` as the master key instead of the keyring's, e.g. from `--key-file` on a
+/// headless machine without one. Must be called before the database is opened.
+pub fn set_master_key(key: [u8; 32]) -> Result<()> {
+    PROVIDED_MASTER_KEY
+        .set(key)
+        .map_err(|_| anyhow!("Master key is already set"))
+}
 
-    pub fn list_machines(&self) -> Result<Vec<Machine>> {
-        let conn = self.get_conn()?;
-        let mut stmt = conn.prepare(
-            "SELECT id, name, ip_address, purpose, ownership, os_type, status FROM machines",
-        )?;
-        let machines = stmt
-            .query_map([], |row| {
-                Ok(Machine {
-                    id: Some(row.get(0)?),
-                    name: row.get(1)?,
-                    ip_address: row.get(2)?,
-                    purpose: row.get(3)?,
-                    ownership: row.get(4)?,
-                    os_type: row.get(5)?,
-                    status: row.get(6)?,
-                })
-            })?
-            .collect::<Result<Vec<_>, _>>()?;
-        Ok(machines)
+/// The master key and its source: [`set_master_key`], then [`security::MASTER_KEY_ENV`],
+/// then the keyring. A keyring failure isn't fatal; it yields [`KeySource::Unavailable`]
+/// with an all-zero key that is never used.
+fn load_master_key(security: &SecurityManager) -> Result<([u8; 32], KeySource)> {
+    if let Some(key) = PROVIDED_MASTER_KEY.get() {
+        return Ok((*key, KeySource::Provided));
     }
-
-    pub fn list_accounts(&self) -> Result<Vec<Account>> {
-        let conn = self.get_conn()?;
-        let mut stmt =
-            conn.prepare("SELECT id, machine_id, username, auth_type, credential FROM accounts")?;
-        let accounts = stmt
-            .query_map([], |row| {
-                Ok(Account {
-                    id: Some(row.get(0)?),
-                    machine_id: row.get(1)?,
-                    username: row.get(2)?,
-                    auth_type: row.get(3)?,
-                    // CRITICAL: AI Hiding Verification (Task 9).
-                    // Do not decrypt or show raw/encrypted credential in general listing to prevent leakage.
-                    credential: "[ENCRYPTED/RESTRICTED]".to_string(),
-                })
-            })?
-            .collect::<Result<Vec<_>, _>>()?;
-        Ok(accounts)
+    if let Ok(hex_key) = std::env::var(security::MASTER_KEY_ENV) {
+        let key = security::parse_master_key(&hex_key)
+            .with_context(|| format!("Invalid {}", security::MASTER_KEY_ENV))?;
+        return Ok((key, KeySource::Provided));
+    }
+    match security.get_or_create_master_key() {
+        Ok(key) => Ok((key, KeySource::Keyring)),
+        Err(e) => {
+            let unavailable = security::KeyringUnavailable {
+                service: app_name(),
+                reason: e.to_string(),
+            };
+            tracing::warn!("{}", unavailable);
+            Ok(([0u8; 32], KeySource::Unavailable(unavailable)))
+        }
     }
+}
 
-    pub fn update_account_credential(&self, account_id: i64, new_credential: &str) -> Result<()> {
-        let encrypted = {
-            let key = self.master_key.read().unwrap();
-            self.security.encrypt(&key, new_credential)?
-        };
+/// Keyring entry holding the key of ownership group `ownership`, next to `master_key`.
+fn ownership_key_entry(ownership: &str) -> String {
+    format!("master_key.{}", ownership)
+}
 
-        let conn = self.get_conn()?;
-        conn.execute(
-            "UPDATE accounts SET credential = ?1 WHERE id = ?2",
-            params![encrypted, account_id],
-        )?;
-        Ok(())
-    }
+static SETTING_DEFAULTS: OnceLock<HashMap<String, String>> = OnceLock::new();
 
-    pub fn list_logs(&self) -> Result<Vec<CommandLog>> {
-        let conn = self.get_conn()?;
-        let mut stmt = conn.prepare(
-            "SELECT l.id, l.machine_id, m.name, l.username, l.command, l.stdout, l.stderr, l.exit_code, l.timestamp 
-             FROM command_logs l
-             JOIN machines m ON l.machine_id = m.id
-             ORDER BY l.timestamp DESC"
-        )?;
-        let logs = stmt
-            .query_map([], |row| {
-                Ok(CommandLog {
-                    id: row.get(0)?,
-                    machine_id: row.get(1)?,
-                    machine_name: row.get(2)?,
-                    username: row.get(3)?,
-                    command: row.get(4)?,
-                    stdout: row.get(5)?,
-                    stderr: row.get(6)?,
-                    exit_code: row.get(7)?,
-                    timestamp: row.get(8)?,
-                })
-            })?
-            .collect::<Result<Vec<_>, _>>()?;
-        Ok(logs)
-    }
+/// Values [`DbHandler::get_setting`] returns for keys the settings table has no row for,
+/// e.g. from the config file. Must be called before settings are read.
+pub fn set_setting_defaults(defaults: HashMap<String, String>) -> Result<()> {
+    SETTING_DEFAULTS
+        .set(defaults)
+        .map_err(|_| anyhow!("Setting defaults are already set"))
+}
 
-    pub fn get_constraints(&self, machine_id: i64) -> Result<Vec<Constraint>> {
-        let conn = self.get_conn()?;
-        let mut stmt = conn
-            .prepare("SELECT id, machine_id, rule_text FROM constraints WHERE machine_id = ?1")?;
-        let rules = stmt
-            .query_map(params![machine_id], |row| {
-                Ok(Constraint {
-                    id: Some(row.get(0)?),
-                    machine_id: row.get(1)?,
-                    rule_text: row.get(2)?,
-                })
-            })?
-            .collect::<Result<Vec<Constraint>, _>>()?;
-        Ok(rules)
-    }
+/// Platform data directory holding the database, backups, and logs.
+pub fn data_dir() -> Result<PathBuf> {
+    let proj_dirs = ProjectDirs::from("com", "veltrea", &app_name())
+        .ok_or_else(|| anyhow!("Could not determine project directories"))?;
+    Ok(proj_dirs.data_dir().to_path_buf())
+}
 
-    pub fn delete_constraint(&self, id: i64) -> Result<()> {
-        let conn = self.get_conn()?;
-        conn.execute("DELETE FROM constraints WHERE id = ?1", params![id])?;
-        Ok(())
+/// Directory the GUI writes automatic backups to.
+pub fn backup_dir() -> Result<PathBuf> {
+    match DB_PATH.get().and_then(|path| path.parent()) {
+        Some(dir) => Ok(dir.join("backups")),
+        None => Ok(data_dir()?.join("backups")),
     }
+}
 
-    pub fn backup_db(&self, backup_path: &std::path::Path) -> Result<()> {
-        let conn = self.get_conn()?;
-        conn.backup(rusqlite::DatabaseName::Main, backup_path, None)?;
-        Ok(())
+/// How long a connection waits on another writer's lock before failing with SQLITE_BUSY.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+/// Attempts to open a database that stays busy past [`BUSY_TIMEOUT`], doubling the delay each time.
+const OPEN_ATTEMPTS: u32 = 3;
+const OPEN_RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// Settings key recording the last migration applied to the database.
+const SCHEMA_VERSION_SETTING: &str = "schema_version";
+/// Newest migration this build knows. Each step runs once, when the stored version is
+/// below it: 1 normalizes OS and auth type spellings, 2 encrypts plaintext credentials,
+/// 3 adds [`CIPHERTEXT_PREFIX`] to existing ciphertext.
+/// Table and column creation stays idempotent and runs on every open.
+const SCHEMA_VERSION: i64 = 3;
+
+/// The stored schema version; 0 for a new database or one from before versioning.
+fn schema_version(conn: &Connection) -> Result<i64> {
+    let has_settings: bool = conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'settings')",
+        [],
+        |row| row.get(0),
+    )?;
+    if !has_settings {
+        return Ok(0);
     }
+    let value: Option<String> = conn
+        .query_row(
+            "SELECT value FROM settings WHERE key = ?1",
+            params![SCHEMA_VERSION_SETTING],
+            |row| row.get(0),
+        )
+        .optional()?;
+    match value {
+        Some(v) => v
+            .parse()
+            .map_err(|_| anyhow!("Invalid schema version in the database: {:?}", v)),
+        None => Ok(0),
+    }
+}
 
-    pub fn rotate_keys(&self) -> Result<()> {
-        let mut conn = self.get_conn()?;
-        let tx = conn.transaction()?;
+/// Acquires a read guard even if another thread panicked while holding the lock.
+/// The key locks are only ever updated by a single assignment, so a poisoned lock
+/// still holds a complete value and one panic must not take down every later DB call.
+fn read_lock<T>(lock: &RwLock<T>) -> RwLockReadGuard<'_, T> {
+    lock.read().unwrap_or_else(|e| e.into_inner())
+}
 
-        // 1. Fetch all accounts
-        let items: Vec<(i64, String)> = {
-            let mut stmt = tx.prepare("SELECT id, credential FROM accounts")?;
-            stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
-                .collect::<Result<Vec<_>, rusqlite::Error>>()?
-        };
+/// Write counterpart of [`read_lock`].
+fn write_lock<T>(lock: &RwLock<T>) -> RwLockWriteGuard<'_, T> {
+    lock.write().unwrap_or_else(|e| e.into_inner())
+}
 
-        // 2. Generate NEW key
-        let new_key = self.security.generate_new_master_key();
+fn sqlite_error_code(error: &anyhow::Error) -> Option<rusqlite::ErrorCode> {
+    error
+        .chain()
+        .find_map(|cause| cause.downcast_ref::<rusqlite::Error>())
+        .and_then(|e| e.sqlite_error_code())
+}
 
-        // 3. Re-encrypt all credentials
-        {
-            let old_key = self.master_key.read().unwrap();
-            for (id, old_cred) in items {
-                // Decrypt with OLD key
-                let plaintext = self
-                    .security
-                    .decrypt(&old_key, &old_cred)
-                    .context(format!("Failed to decrypt credential for account {}", id))?;
-
-                // Encrypt with NEW key
-                let new_cred = self.security.encrypt(&new_key, &plaintext)?;
-
-                // Update DB (in transaction)
-                tx.execute(
-                    "UPDATE accounts SET credential = ?1 WHERE id = ?2",
-                    params![new_cred, id],
-                )?;
-            }
-        }
+/// Whether the database file is damaged or not a database at all.
+pub fn is_corruption(error: &anyhow::Error) -> bool {
+    matches!(
+        sqlite_error_code(error),
+        Some(rusqlite::ErrorCode::DatabaseCorrupt | rusqlite::ErrorCode::NotADatabase)
+    )
+}
 
-        // 4. Commit DB Transaction
-        tx.commit()
-            .context("Failed to commit database transaction during key rotation")?;
+/// Whether another process is holding the database lock.
+pub fn is_busy(error: &anyhow::Error) -> bool {
+    matches!(
+        sqlite_error_code(error),
+        Some(rusqlite::ErrorCode::DatabaseBusy | rusqlite::ErrorCode::DatabaseLocked)
+    )
+}
 
-        // 5. Update Keyring
-        let hex_key = hex::encode(new_key);
-        if let Err(e) = self.security.store_secret("master_key", &hex_key) {
-            eprintln!("CRITICAL: Database rotated but Keyring update failed!");
-            eprintln!("NEW KEY HEX: {}", hex_key);
-            return Err(anyhow!("Keyring update failed: {}", e));
+/// Newest automatic backup (plain or encrypted), if any.
+pub fn latest_backup() -> Option<PathBuf> {
+    std::fs::read_dir(backup_dir().ok()?)
+        .ok()?
+        .filter_map(|e| e.ok())
+        .filter(|e| {
+            e.path()
+                .extension()
+                .is_some_and(|ext| ext == "db" || ext == "enc")
+        })
+        .filter_map(|e| Some((e.metadata().ok()?.modified().ok()?, e.path())))
+        .max()
+        .map(|(_, path)| path)
+}
+
+/// At-a-glance inventory numbers from [`DbHandler::summary`].
+#[derive(Debug, Clone, Serialize)]
+pub struct Summary {
+    pub machines: i64,
+    pub machines_by_status: std::collections::BTreeMap<String, i64>,
+    pub accounts: i64,
+    pub logs: i64,
+    /// Local modification time of the newest backup, "YYYY-MM-DD HH:MM:SS"
+    pub last_backup: Option<String>,
+    /// See [`SETTING_LAST_KEY_ROTATION`]
+    pub last_key_rotation: Option<String>,
+    /// See [`key_rotation_due`]
+    pub key_rotation_due: bool,
+}
+
+/// Database size before and after [`DbHandler::maintenance`], including any WAL file.
+#[derive(Debug, Clone, Copy)]
+pub struct MaintenanceReport {
+    pub size_before: u64,
+    pub size_after: u64,
+}
+
+/// Directory holding the daily-rotated log files.
+pub fn log_dir() -> Result<PathBuf> {
+    Ok(data_dir()?.join("logs"))
+}
+
+/// Checks that an account's credential is usable for its `auth_type` before it is stored.
+pub fn validate_account(account: &Account) -> Result<()> {
+    if account.username.trim().is_empty() {
+        return Err(anyhow!("username must not be empty"));
+    }
+    match account.auth_type.parse::<AuthType>()? {
+        AuthType::Password => {
+            if account.credential.is_empty() {
+                return Err(anyhow!(
+                    "credential must not be empty for auth_type \"password\""
+                ));
+            }
         }
+        AuthType::Key => {
+            if account.credential.trim().is_empty() {
+                return Err(anyhow!(
+                    "credential must be a key file path for auth_type \"key\""
+                ));
+            }
+            let path = expand_home(&account.credential);
+            if !path.is_file() {
+                return Err(anyhow!("key file not found: {}", path.display()));
+            }
+            std::fs::File::open(&path)
+                .map_err(|e| anyhow!("key file is not readable: {} ({})", path.display(), e))?;
+        }
+        // ssh-agent holds the keys; nothing needs to be stored
+        AuthType::Agent | AuthType::Tpm => {}
+    }
+    Ok(())
+}
 
-        // 6. Update Memory
-        let mut key_guard = self.master_key.write().unwrap();
-        *key_guard = new_key;
+/// Fingerprint of the key a "key" account points at; `None` for other auth types or
+/// when the file can't be read (the account is still stored).
+fn fingerprint_for(auth_type: &str, credential: &str) -> Option<String> {
+    if auth_type.parse::<AuthType>().ok() != Some(AuthType::Key) {
+        return None;
+    }
+    known_hosts::key_fingerprint(&expand_home(credential))
+        .map_err(|e| tracing::warn!("Could not fingerprint key {}: {}", credential, e))
+        .ok()
+}
 
+/// Accepts IPv4/IPv6 literals and RFC 1123 hostnames (letters, digits, and inner hyphens,
+/// up to 63 characters per label and 253 overall; a single trailing dot is allowed).
+pub fn is_valid_host(host: &str) -> bool {
+    if host.parse::<std::net::IpAddr>().is_ok() {
+        return true;
+    }
+    let host = host.strip_suffix('.').unwrap_or(host);
+    if host.is_empty() || host.len() > 253 {
+        return false;
+    }
+    let labels: Vec<&str> = host.split('.').collect();
+    let labels_ok = labels.iter().all(|label| {
+        !label.is_empty()
+            && label.len() <= 63
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+            && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+    });
+    // An all-numeric last label is a malformed IPv4 address (e.g. 192.168.1.300), not a name
+    let tld_ok = labels
+        .last()
+        .is_some_and(|tld| !tld.chars().all(|c| c.is_ascii_digit()));
+    labels_ok && tld_ok
+}
+
+/// Trims surrounding whitespace, unwraps a bracketed IPv6 literal (`[::1]`) and rejects
+/// anything [`is_valid_host`] doesn't accept.
+pub fn normalize_host(host: &str) -> Result<String> {
+    let trimmed = host.trim();
+    let trimmed = trimmed
+        .strip_prefix('[')
+        .and_then(|h| h.strip_suffix(']'))
+        .filter(|h| h.parse::<std::net::Ipv6Addr>().is_ok())
+        .unwrap_or(trimmed);
+    if !is_valid_host(trimmed) {
+        return Err(anyhow!(
+            "invalid host \"{}\": expected an IPv4/IPv6 address or hostname",
+            host
+        ));
+    }
+    Ok(trimmed.to_string())
+}
+
+/// Trims a group name; blank means ungrouped.
+pub fn normalize_group(group: Option<&str>) -> Option<String> {
+    group
+        .map(str::trim)
+        .filter(|g| !g.is_empty())
+        .map(str::to_string)
+}
+
+/// Trims an account note; blank is stored as `NULL`.
+pub fn normalize_note(note: Option<&str>) -> Option<String> {
+    note.map(str::trim)
+        .filter(|n| !n.is_empty())
+        .map(str::to_string)
+}
+
+/// Validates `ssh_options` with [`crate::health::parse_ssh_options`]; blank is stored as
+/// `NULL`.
+pub fn normalize_ssh_options(options: Option<&str>) -> Result<Option<String>> {
+    let Some(options) = options.map(str::trim).filter(|o| !o.is_empty()) else {
+        return Ok(None);
+    };
+    crate::health::parse_ssh_options(options)?;
+    Ok(Some(options.to_string()))
+}
+
+/// Random (version 4) UUID in the usual hyphenated form.
+fn new_uuid() -> String {
+    let mut bytes: [u8; 16] = rand::random();
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex = hex::encode(bytes);
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+/// Validates `env` as a JSON object of string values whose keys are shell variable names
+/// (`[A-Za-z_][A-Za-z0-9_]*`); blank or `{}` is stored as `NULL`.
+pub fn normalize_env(env: Option<&str>) -> Result<Option<String>> {
+    let Some(env) = env.map(str::trim).filter(|e| !e.is_empty()) else {
+        return Ok(None);
+    };
+    let vars: serde_json::Map<String, serde_json::Value> = serde_json::from_str(env)
+        .map_err(|e| anyhow!("Environment must be a JSON object: {}", e))?;
+    for (key, value) in &vars {
+        let mut chars = key.chars();
+        let valid = chars
+            .next()
+            .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid {
+            return Err(anyhow!("Invalid environment variable name \"{}\"", key));
+        }
+        if !value.is_string() {
+            return Err(anyhow!("Environment variable {} must be a string", key));
+        }
+    }
+    if vars.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(serde_json::to_string(&vars)?))
+}
+
+/// `export KEY='value'; ` for each variable of a stored `env`, values shell-quoted, to put
+/// in front of a remote command. Empty when there is none.
+pub fn env_exports(env: Option<&str>) -> Result<String> {
+    let Some(env) = normalize_env(env)? else {
+        return Ok(String::new());
+    };
+    let vars: serde_json::Map<String, serde_json::Value> = serde_json::from_str(&env)?;
+    Ok(vars
+        .iter()
+        .map(|(key, value)| {
+            format!(
+                "export {}={}; ",
+                key,
+                shell_words::quote(value.as_str().unwrap_or_default())
+            )
+        })
+        .collect())
+}
+
+/// Gives every machine without a uuid a new one.
+fn backfill_machine_uuids(conn: &Connection) -> Result<()> {
+    let ids: Vec<i64> = conn
+        .prepare("SELECT id FROM machines WHERE uuid IS NULL")?
+        .query_map([], |row| row.get(0))?
+        .collect::<Result<_, _>>()?;
+    for id in ids {
+        conn.execute(
+            "UPDATE machines SET uuid = ?1 WHERE id = ?2",
+            params![new_uuid(), id],
+        )?;
+    }
+    Ok(())
+}
+
+/// Most jump hosts followed for one connection; also bounds the cycle check.
+pub const MAX_JUMP_HOPS: usize = 8;
+
+/// A jump host that doesn't exist, would make a cycle, or makes the chain too long;
+/// callers can downcast to report it as invalid input.
+#[derive(Debug)]
+pub struct InvalidJumpHost(pub String);
+
+impl std::fmt::Display for InvalidJumpHost {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidJumpHost {}
+
+/// Rejects making `jump_host_id` the jump host of machine `id` (`None` for a machine not
+/// inserted yet) when that machine is itself on the jump chain, a hop doesn't exist, or
+/// the chain is longer than [`MAX_JUMP_HOPS`].
+fn check_jump_host(conn: &Connection, id: Option<i64>, jump_host_id: Option<i64>) -> Result<()> {
+    let invalid = |msg: String| anyhow::Error::from(InvalidJumpHost(msg));
+    let mut next = jump_host_id;
+    for _ in 0..MAX_JUMP_HOPS {
+        let Some(hop) = next else {
+            return Ok(());
+        };
+        if Some(hop) == id {
+            return Err(invalid(format!(
+                "Machine {} can't be its own jump host (directly or through another)",
+                hop
+            )));
+        }
+        next = conn
+            .query_row(
+                "SELECT jump_host_id FROM machines WHERE id = ?1",
+                params![hop],
+                |row| row.get(0),
+            )
+            .optional()?
+            .ok_or_else(|| invalid(format!("Jump host {} not found", hop)))?;
+    }
+    match next {
+        Some(_) => Err(invalid(format!(
+            "Jump chain longer than {} hops",
+            MAX_JUMP_HOPS
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// Expands a leading `~/` to the user's home directory.
+pub fn expand_home(path: &str) -> PathBuf {
+    if let Some(rest) = path.strip_prefix("~/")
+        && let Some(user_dirs) = directories::UserDirs::new()
+    {
+        return user_dirs.home_dir().join(rest);
+    }
+    PathBuf::from(path)
+}
+
+const MACHINE_COLUMNS: &str = "id, name, ip_address, purpose, ownership, os_type, status, group_name, ssh_options, jump_host_id, requires_vpn, env, uuid";
+
+/// Tables holding per-machine rows, with their column definitions. Deleting a machine
+/// cascades to everything except `command_logs`, which deliberately has no foreign key:
+/// logs outlive the machine and are reattached when it is restored.
+const CHILD_TABLES: [(&str, &str); 5] = [
+    (
+        "accounts",
+        "id INTEGER PRIMARY KEY AUTOINCREMENT,
+         machine_id INTEGER NOT NULL,
+         username TEXT NOT NULL,
+         auth_type TEXT NOT NULL,
+         credential TEXT NOT NULL,
+         key_fingerprint TEXT,
+         note TEXT,
+         FOREIGN KEY(machine_id) REFERENCES machines(id) ON DELETE CASCADE",
+    ),
+    (
+        "command_logs",
+        "id INTEGER PRIMARY KEY AUTOINCREMENT,
+         machine_id INTEGER NOT NULL,
+         username TEXT NOT NULL,
+         command TEXT NOT NULL,
+         stdout TEXT,
+         stderr TEXT,
+         exit_code INTEGER,
+         timestamp DATETIME DEFAULT CURRENT_TIMESTAMP,
+         stderr_raw BLOB",
+    ),
+    (
+        "connection_stats",
+        "machine_id INTEGER PRIMARY KEY,
+         connections INTEGER NOT NULL DEFAULT 0,
+         last_connected_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+         FOREIGN KEY(machine_id) REFERENCES machines(id) ON DELETE CASCADE",
+    ),
+    (
+        "constraints",
+        "id INTEGER PRIMARY KEY AUTOINCREMENT,
+         machine_id INTEGER NOT NULL,
+         rule_text TEXT NOT NULL,
+         FOREIGN KEY(machine_id) REFERENCES machines(id) ON DELETE CASCADE",
+    ),
+    (
+        "diagnostics",
+        "id INTEGER PRIMARY KEY AUTOINCREMENT,
+         machine_id INTEGER NOT NULL,
+         timestamp DATETIME DEFAULT CURRENT_TIMESTAMP,
+         success INTEGER NOT NULL,
+         matched_pattern_id TEXT,
+         stderr_excerpt TEXT,
+         FOREIGN KEY(machine_id) REFERENCES machines(id) ON DELETE CASCADE",
+    ),
+];
+
+/// Recreates [`CHILD_TABLES`] created by older versions, whose foreign keys didn't cascade
+/// (and were never enforced). Rows pointing at a machine that no longer exists are dropped,
+/// except command logs.
+fn rebuild_child_tables(conn: &Connection) -> Result<()> {
+    let mut outdated = Vec::new();
+    for (table, columns) in CHILD_TABLES {
+        let foreign_keys: Vec<String> = conn
+            .prepare(&format!(
+                "SELECT on_delete FROM pragma_foreign_key_list('{}')",
+                table
+            ))?
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<_, _>>()?;
+        let cascades = columns.contains("ON DELETE CASCADE");
+        let expected: &[&str] = if cascades { &["CASCADE"] } else { &[] };
+        if foreign_keys != expected {
+            outdated.push((table, columns, cascades));
+        }
+    }
+    if outdated.is_empty() {
+        return Ok(());
+    }
+
+    // Can't be changed inside a transaction; off so dropping the old tables checks nothing
+    conn.execute_batch("PRAGMA foreign_keys = OFF")?;
+    let result = (|| -> Result<()> {
+        let tx = conn.unchecked_transaction()?;
+        for (table, columns, cascades) in outdated {
+            tracing::info!("Rebuilding table {} with cascading foreign keys", table);
+            tx.execute(&format!("CREATE TABLE {}_new ({})", table, columns), [])?;
+            let names: Vec<String> = tx
+                .prepare(&format!(
+                    "SELECT name FROM pragma_table_info('{}_new')",
+                    table
+                ))?
+                .query_map([], |row| row.get(0))?
+                .collect::<Result<_, _>>()?;
+            let names = names.join(", ");
+            let filter = if cascades {
+                " WHERE machine_id IN (SELECT id FROM machines)"
+            } else {
+                ""
+            };
+            tx.execute(
+                &format!(
+                    "INSERT INTO {t}_new ({n}) SELECT {n} FROM {t}{f}",
+                    t = table,
+                    n = names,
+                    f = filter
+                ),
+                [],
+            )?;
+            tx.execute(&format!("DROP TABLE {}", table), [])?;
+            tx.execute(&format!("ALTER TABLE {t}_new RENAME TO {t}", t = table), [])?;
+        }
+        tx.commit()?;
+        Ok(())
+    })();
+    conn.execute_batch("PRAGMA foreign_keys = ON")?;
+    result
+}
+
+/// Adds a column to a table created by an older version of the schema.
+fn add_column_if_missing(conn: &Connection, table: &str, column: &str, decl: &str) -> Result<()> {
+    let exists = conn
+        .prepare(&format!(
+            "SELECT 1 FROM pragma_table_info('{}') WHERE name = ?1",
+            table
+        ))?
+        .exists(params![column])?;
+    if !exists {
+        conn.execute(
+            &format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, decl),
+            [],
+        )?;
+    }
+    Ok(())
+}
+
+fn machine_from_row(row: &rusqlite::Row) -> rusqlite::Result<Machine> {
+    Ok(Machine {
+        id: Some(row.get(0)?),
+        name: row.get(1)?,
+        ip_address: row.get(2)?,
+        purpose: row.get(3)?,
+        ownership: row.get(4)?,
+        os_type: row.get(5)?,
+        status: row.get(6)?,
+        group: row.get(7)?,
+        ssh_options: row.get(8)?,
+        jump_host_id: row.get(9)?,
+        requires_vpn: row.get(10)?,
+        env: row.get(11)?,
+        uuid: row.get(12)?,
+    })
+}
+
+const ACCOUNT_COLUMNS: &str = "id, machine_id, username, auth_type, key_fingerprint, note";
+
+/// Selected from `command_logs l LEFT JOIN machines m`; logs of deleted machines keep "#<id>".
+const LOG_COLUMNS: &str = "l.id, l.machine_id, COALESCE(m.name, '#' || l.machine_id), l.username, \
+     l.command, l.stdout, l.stderr, l.exit_code, l.timestamp, l.stderr_raw";
+
+fn log_from_row(row: &rusqlite::Row) -> rusqlite::Result<CommandLog> {
+    Ok(CommandLog {
+        id: row.get(0)?,
+        machine_id: row.get(1)?,
+        machine_name: row.get(2)?,
+        username: row.get(3)?,
+        command: row.get(4)?,
+        stdout: row.get(5)?,
+        stderr: row.get(6)?,
+        exit_code: row.get(7)?,
+        timestamp: row.get(8)?,
+        stderr_raw: row.get(9)?,
+    })
+}
+
+const STATS_COLUMNS: &str = "machine_id, connections, last_connected_at";
+
+fn stats_from_row(row: &rusqlite::Row) -> rusqlite::Result<ConnectionStats> {
+    Ok(ConnectionStats {
+        machine_id: row.get(0)?,
+        connections: row.get(1)?,
+        last_connected_at: row.get(2)?,
+    })
+}
+
+fn redacted_account_from_row(row: &rusqlite::Row) -> rusqlite::Result<Account> {
+    Ok(Account {
+        id: Some(row.get(0)?),
+        machine_id: row.get(1)?,
+        username: row.get(2)?,
+        auth_type: row.get(3)?,
+        // CRITICAL: AI Hiding Verification (Task 9).
+        // Do not decrypt or show raw/encrypted credential in general listing to prevent leakage.
+        credential: "[ENCRYPTED/RESTRICTED]".to_string(),
+        key_fingerprint: row.get(4)?,
+        note: row.get(5)?,
+    })
+}
+
+/// A machine name is already taken; callers can downcast to offer editing the existing one.
+#[derive(Debug)]
+pub struct DuplicateMachineName(pub String);
+
+impl std::fmt::Display for DuplicateMachineName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "a machine named \"{}\" already exists", self.0)
+    }
+}
+
+impl std::error::Error for DuplicateMachineName {}
+
+fn duplicate_machine_error(name: &str) -> anyhow::Error {
+    DuplicateMachineName(name.to_string()).into()
+}
+
+/// Id, stored credential, and machine ownership of every account.
+fn credentials_with_ownership(conn: &Connection) -> Result<Vec<(i64, String, String)>> {
+    let mut stmt = conn.prepare(
+        "SELECT a.id, a.credential, m.ownership FROM accounts a JOIN machines m ON m.id = a.machine_id",
+    )?;
+    let rows = stmt
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(rows)
+}
+
+/// Inserts `machine` with its host, OS type, and SSH options normalized, under a fresh uuid
+/// (any the machine carries belongs to another row); returns the new id.
+fn insert_machine(conn: &Connection, machine: &Machine) -> Result<i64> {
+    insert_machine_with_uuid(conn, machine, &new_uuid())
+}
+
+/// [`insert_machine`] under the given `uuid`, which only a vault import keeps.
+fn insert_machine_with_uuid(conn: &Connection, machine: &Machine, uuid: &str) -> Result<i64> {
+    let ip_address = normalize_host(&machine.ip_address)?;
+    let os_type = machine.os_type.parse::<OsType>()?.to_string();
+    let ssh_options = normalize_ssh_options(machine.ssh_options.as_deref())?;
+    let env = normalize_env(machine.env.as_deref())?;
+    check_jump_host(conn, None, machine.jump_host_id)?;
+    conn.execute(
+        "INSERT INTO machines (name, ip_address, purpose, ownership, os_type, status, group_name, ssh_options, jump_host_id, requires_vpn, env, uuid)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+        params![
+            machine.name,
+            ip_address,
+            machine.purpose,
+            machine.ownership,
+            os_type,
+            machine.status,
+            normalize_group(machine.group.as_deref()),
+            ssh_options,
+            machine.jump_host_id,
+            machine.requires_vpn,
+            env,
+            uuid,
+        ],
+    )
+    .map_err(|e| map_machine_name_conflict(e, &machine.name))?;
+    Ok(conn.last_insert_rowid())
+}
+
+/// Turns a UNIQUE violation on `machines.name` into a readable error; any other error,
+/// including other constraint violations, is passed on as is.
+fn map_machine_name_conflict(e: rusqlite::Error, name: &str) -> anyhow::Error {
+    match &e {
+        rusqlite::Error::SqliteFailure(err, Some(message))
+            if err.extended_code == rusqlite::ffi::SQLITE_CONSTRAINT_UNIQUE
+                && message.contains("machines.name") =>
+        {
+            duplicate_machine_error(name)
+        }
+        _ => e.into(),
+    }
+}
+
+const BACKUP_PASSPHRASE_SETTING: &str = "backup.passphrase";
+/// Private half of a software device identity key, sealed under the master key
+const IDENTITY_PRIVATE_KEY_SETTING: &str = "identity.private_key";
+const PASSPHRASE_SALT_SETTING: &str = "security.passphrase_salt";
+const PASSPHRASE_CHECK_SETTING: &str = "security.passphrase_check";
+// Encrypted under the effective key so a wrong passphrase is detected before any credential is touched
+const PASSPHRASE_CHECK_VALUE: &str = "mcp-ssh-manager";
+/// JSON array of the ownership values whose credentials are encrypted under their own key
+/// instead of the master key. Unset on databases that never separated a group.
+const OWNERSHIP_KEYS_SETTING: &str = "security.ownership_keys";
+/// UTC time of the last master key rotation, "YYYY-MM-DD HH:MM:SS"; unset if never rotated.
+pub const SETTING_LAST_KEY_ROTATION: &str = "security.last_key_rotation";
+/// Days after which a rotation is due; 0 turns the reminder off.
+pub const SETTING_ROTATION_REMINDER_DAYS: &str = "rotation.reminder_days";
+pub const DEFAULT_ROTATION_REMINDER_DAYS: u64 = 90;
+/// Minimum seconds between MCP-triggered SSH spawns (diagnose/run_command) per machine; 0 disables.
+pub const SETTING_SSH_RATE_LIMIT_SECS: &str = "mcp.ssh_rate_limit_secs";
+pub const DEFAULT_SSH_RATE_LIMIT_SECS: u64 = 10;
+/// Local directory `upload_file` / `download_file` are confined to; unset allows any path
+/// outside the protected ones (see [`crate::transfer::check_local_path`]).
+pub const SETTING_TRANSFER_DIR: &str = "mcp.transfer_dir";
+
+/// Whether a rotation is due `reminder_days` after `last_rotation` (UTC). A key that was
+/// never rotated is always due unless the reminder is off.
+pub fn key_rotation_due(
+    last_rotation: Option<&str>,
+    reminder_days: u64,
+    now: chrono::NaiveDateTime,
+) -> bool {
+    if reminder_days == 0 {
+        return false;
+    }
+    let Some(last) = last_rotation else {
+        return true;
+    };
+    match chrono::NaiveDateTime::parse_from_str(last, "%Y-%m-%d %H:%M:%S") {
+        Ok(last) => (now - last).num_days() >= reminder_days as i64,
+        Err(_) => true,
+    }
+}
+
+/// Where [`DbHandler`]'s master key came from.
+#[derive(Debug, Clone)]
+pub enum KeySource {
+    /// The OS keyring (the default)
+    Keyring,
+    /// `--key-file` or [`security::MASTER_KEY_ENV`]; the keyring is never touched
+    Provided,
+    /// Neither was available: credentials can't be encrypted or decrypted
+    Unavailable(security::KeyringUnavailable),
+}
+
+pub struct DbHandler {
+    path: PathBuf,
+    security: SecurityManager,
+    key_source: KeySource,
+    /// Effective key used for credentials (keyring key, combined with the passphrase key if enabled)
+    master_key: RwLock<[u8; 32]>,
+    /// Argon2id-derived passphrase key, present only in passphrase mode
+    passphrase_key: RwLock<Option<[u8; 32]>>,
+    /// Effective keys of the ownership groups listed in [`OWNERSHIP_KEYS_SETTING`]; `None`
+    /// when the group's keyring entry couldn't be read
+    ownership_keys: RwLock<HashMap<String, Option<[u8; 32]>>>,
+    /// Set by [`Self::lock`]: the keys above are zeroed until [`Self::unlock`]
+    locked: AtomicBool,
+    /// Open connection keeping an in-memory database alive between [`Self::get_conn`] calls
+    _memory_anchor: Option<Mutex<Connection>>,
+}
+
+/// Credential stored for TPM accounts, whose key never leaves the hardware.
+pub const TPM_CREDENTIAL_MARKER: &str = "TPM_HARDWARE_BOUND";
+
+/// Result of [`DbHandler::plan_key_rotation`].
+#[derive(Debug, Default, PartialEq)]
+pub struct RotationPlan {
+    /// Accounts that would be re-encrypted
+    pub accounts: Vec<i64>,
+    /// Accounts whose credential doesn't decrypt with the current key; the rotation would fail
+    pub undecryptable: Vec<i64>,
+}
+
+/// Whether an account's stored credential is usable, from [`DbHandler::credential_status`].
+/// Computed by decrypting internally; the plaintext is never handed out.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CredentialStatus {
+    /// Decrypts to a non-empty value
+    Set,
+    /// Decrypts to an empty value (expected for ssh-agent accounts)
+    Empty,
+    /// The [`TPM_CREDENTIAL_MARKER`] placeholder
+    HardwareBound,
+    /// Doesn't decrypt with the current master key
+    Corrupt,
+}
+
+/// What [`DbHandler::migrate_credentials`] does (or would do) with a stored credential.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CredentialMigration {
+    /// Carries [`CIPHERTEXT_PREFIX`] and decrypts with the current key; left as is.
+    Current,
+    /// Our ciphertext from before [`CIPHERTEXT_PREFIX`]: decrypts, but lacks the prefix.
+    /// Gets the prefix added; the ciphertext itself is kept.
+    AddPrefix,
+    /// Has no prefix, doesn't decrypt, and looks like plaintext; gets encrypted.
+    Encrypt,
+    /// Doesn't decrypt but carries the prefix or is shaped like ciphertext, e.g. sealed
+    /// under a different master key. Left untouched: encrypting it again would make it
+    /// unrecoverable.
+    Undecryptable,
+}
+
+/// One account's entry in a credential migration plan.
+#[derive(Debug, Clone)]
+pub struct MigrationEntry {
+    pub account_id: i64,
+    pub username: String,
+    pub auth_type: String,
+    pub action: CredentialMigration,
+}
+
+/// Marks a value as our ciphertext, so migrations can tell it from plaintext without
+/// trying to decrypt it. The number versions the format.
+const CIPHERTEXT_PREFIX: &str = "msm1:";
+
+/// Whether `value` has the shape of our ciphertext: `sample` (the encryption of an empty
+/// string) gives the minimum length and the encoding, hex or base64.
+fn looks_like_ciphertext(value: &str, sample: &str) -> bool {
+    let is_hex = |c: char| c.is_ascii_hexdigit();
+    let is_base64 = |c: char| c.is_ascii_alphanumeric() || matches!(c, '+' | '/' | '-' | '_' | '=');
+    if value.len() < sample.len() {
+        return false;
+    }
+    if sample.chars().all(is_hex) {
+        value.chars().all(is_hex)
+    } else {
+        value.chars().all(is_base64)
+    }
+}
+
+impl DbHandler {
+    /// Opens (creating if needed) the database, retrying with backoff while another
+    /// process keeps it locked.
+    pub fn new() -> Result<Self> {
+        Self::with_path(Self::get_db_path()?)
+    }
+
+    /// Like [`Self::new`], but for the database at `path` instead of the data directory.
+    /// The master key still comes from the keyring.
+    pub fn with_path(path: impl Into<PathBuf>) -> Result<Self> {
+        Self::open_retrying(&path.into(), true, true)
+    }
+
+    /// Like [`Self::new`], but leaves stored credentials untouched so
+    /// [`Self::plan_credential_migration`] can report on them first.
+    pub fn new_without_migration() -> Result<Self> {
+        Self::open_retrying(&Self::get_db_path()?, false, true)
+    }
+
+    /// Like [`Self::new`], but never prompts on the terminal: in passphrase mode without
+    /// [`security::PASSPHRASE_ENV`] the database opens locked, for the caller to ask for the
+    /// passphrase and [`Self::unlock`] it. For the GUI, which has no terminal in release
+    /// builds. The credential migration then waits for the next open.
+    pub fn new_without_prompt() -> Result<Self> {
+        Self::open_retrying(&Self::get_db_path()?, true, false)
+    }
+
+    /// A database held in memory with `key` as its master key, for tests that must not
+    /// touch the OS keyring or the disk. Every [`Self::get_conn`] sees the same database
+    /// (SQLite's `memdb` VFS); it is freed when the handler is dropped. Rotate with
+    /// [`Self::rotate_keys_to`], since [`Self::rotate_keys`] refuses a provided key.
+    #[cfg(test)]
+    pub fn new_in_memory_with_key(key: [u8; 32]) -> Result<Self> {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let path = PathBuf::from(format!(
+            "file:/mcp-ssh-manager-{}-{}?vfs=memdb",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        let anchor = Connection::open(&path)?;
+        let handler = DbHandler {
+            path,
+            security: SecurityManager::new("mcp-ssh-manager-test"),
+            key_source: KeySource::Provided,
+            master_key: RwLock::new(key),
+            passphrase_key: RwLock::new(None),
+            ownership_keys: RwLock::default(),
+            locked: AtomicBool::new(false),
+            _memory_anchor: Some(Mutex::new(anchor)),
+        };
+        handler.init_schema(&handler.get_conn()?)?;
+        handler.set_schema_version(SCHEMA_VERSION)?;
+        Ok(handler)
+    }
+
+    fn open_retrying(path: &Path, migrate: bool, prompt: bool) -> Result<Self> {
+        let mut delay = OPEN_RETRY_DELAY;
+        let mut attempt = 1;
+        loop {
+            match Self::open(path, migrate, prompt) {
+                Err(e) if is_busy(&e) && attempt < OPEN_ATTEMPTS => {
+                    tracing::warn!(
+                        "Database is busy (attempt {}/{}), retrying in {:?}",
+                        attempt,
+                        OPEN_ATTEMPTS,
+                        delay
+                    );
+                    std::thread::sleep(delay);
+                    delay *= 2;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    fn open(path: &Path, migrate: bool, prompt: bool) -> Result<Self> {
+        let conn = Connection::open(path)?;
+        conn.busy_timeout(BUSY_TIMEOUT)?;
+        conn.execute_batch("PRAGMA foreign_keys = ON")?;
+        let security = SecurityManager::new(&app_name());
+        let (master_key, key_source) = load_master_key(&security)?;
+        let secrets = !matches!(key_source, KeySource::Unavailable(_));
+
+        let handler = DbHandler {
+            path: path.to_path_buf(),
+            security,
+            key_source,
+            master_key: RwLock::new(master_key),
+            passphrase_key: RwLock::new(None),
+            ownership_keys: RwLock::default(),
+            locked: AtomicBool::new(false),
+            _memory_anchor: None,
+        };
+        let version = schema_version(&conn)?;
+        if version > SCHEMA_VERSION {
+            return Err(anyhow!(
+                "The database at {} was written by a newer version of {} (schema {}, this build understands up to {}); please upgrade",
+                path.display(),
+                APP_NAME,
+                version,
+                SCHEMA_VERSION
+            ));
+        }
+        handler.init_schema(&conn)?;
+        if version < 1 {
+            handler.normalize_os_types()?;
+            handler.normalize_auth_types()?;
+            handler.set_schema_version(1)?;
+        }
+        if !secrets {
+            // Nothing below can work without the key; the version stays put for the next open
+            return Ok(handler);
+        }
+        if !prompt
+            && handler.get_setting(PASSPHRASE_SALT_SETTING)?.is_some()
+            && std::env::var(security::PASSPHRASE_ENV).is_err()
+        {
+            // Marks passphrase mode for unlock(); the key itself comes with the passphrase
+            *write_lock(&handler.passphrase_key) = Some([0u8; 32]);
+            handler.lock();
+            return Ok(handler);
+        }
+        handler.unlock_with_passphrase()?;
+        handler.load_ownership_keys()?;
+        // Without `migrate` the version stays put, so the next regular open still encrypts
+        // One pass covers both 2 and 3: it encrypts plaintext and tags older ciphertext
+        if version < 3 && migrate {
+            handler.migrate_credentials()?; // Phase 11 Task 5
+            handler.set_schema_version(3)?;
+        }
+        if let Err(e) = handler.backfill_key_fingerprints() {
+            tracing::warn!("Failed to record key fingerprints: {}", e);
+        }
+        Ok(handler)
+    }
+
+    fn set_schema_version(&self, version: i64) -> Result<()> {
+        tracing::info!("Database migrated to schema version {}", version);
+        self.set_setting(SCHEMA_VERSION_SETTING, &version.to_string())
+    }
+
+    /// In passphrase mode, prompts for the passphrase and switches to the combined key.
+    /// Without a configured salt this is a no-op, keeping keyring-only installs unchanged.
+    fn unlock_with_passphrase(&self) -> Result<()> {
+        if self.get_setting(PASSPHRASE_SALT_SETTING)?.is_none() {
+            return Ok(());
+        }
+        let passphrase = security::read_passphrase("Master passphrase: ")?;
+        let base_key = *self.master_key()?;
+        self.apply_passphrase(&base_key, &passphrase)
+    }
+
+    /// Checks `passphrase` against the stored verifier and switches to `base_key` combined
+    /// with the key derived from it.
+    fn apply_passphrase(&self, base_key: &[u8; 32], passphrase: &str) -> Result<()> {
+        let salt_hex = self
+            .get_setting(PASSPHRASE_SALT_SETTING)?
+            .ok_or_else(|| anyhow!("No passphrase is configured"))?;
+        let salt = hex::decode(salt_hex).context("Corrupt passphrase salt in settings")?;
+        let check = self
+            .get_setting(PASSPHRASE_CHECK_SETTING)?
+            .ok_or_else(|| anyhow!("Passphrase verifier missing from settings"))?;
+
+        let passphrase_key = security::derive_passphrase_key(passphrase, &salt)?;
+        let effective = security::combine_keys(base_key, &passphrase_key)?;
+
+        if self.unseal(&effective, &check).ok().as_deref() != Some(PASSPHRASE_CHECK_VALUE) {
+            let legacy = security::legacy_combine_keys(base_key, &passphrase_key);
+            if self.unseal(&legacy, &check).ok().as_deref() != Some(PASSPHRASE_CHECK_VALUE) {
+                return Err(anyhow!("Incorrect passphrase"));
+            }
+            self.upgrade_legacy_passphrase(base_key, &passphrase_key)?;
+        }
+
+        *write_lock(&self.master_key) = effective;
+        *write_lock(&self.passphrase_key) = Some(passphrase_key);
+        Ok(())
+    }
+
+    /// Re-encrypts credentials from [`security::legacy_combine_keys`] to
+    /// [`security::combine_keys`] once the passphrase has checked out against the former. A
+    /// group whose key isn't in the keyring is left as it was.
+    fn upgrade_legacy_passphrase(
+        &self,
+        base_key: &[u8; 32],
+        passphrase_key: &[u8; 32],
+    ) -> Result<()> {
+        // The in-memory group keys aren't loaded yet at startup, so go by the settings
+        let groups = self.ownership_key_groups()?;
+        let mut keyring_keys = vec![(None, *base_key)];
+        for ownership in &groups {
+            match self.read_ownership_key(ownership) {
+                Ok(key) => keyring_keys.push((Some(ownership), key)),
+                Err(e) => tracing::warn!(
+                    "Key of ownership group \"{}\" is unavailable; its credentials keep the old passphrase key: {}",
+                    ownership,
+                    e
+                ),
+            }
+        }
+
+        let mut conn = self.get_conn()?;
+        let tx = conn.transaction()?;
+        let credentials = credentials_with_ownership(&tx)?;
+        for (group, keyring_key) in keyring_keys {
+            let items = credentials
+                .iter()
+                .filter(|(_, _, ownership)| match group {
+                    Some(group) => ownership == group,
+                    None => !groups.contains(ownership),
+                })
+                .map(|(id, cred, _)| (*id, cred.clone()))
+                .collect();
+            self.reencrypt_accounts(
+                &tx,
+                items,
+                &security::legacy_combine_keys(&keyring_key, passphrase_key),
+                &security::combine_keys(&keyring_key, passphrase_key)?,
+                &mut |_, _| {},
+            )?;
+        }
+        self.reencrypt_settings(
+            &tx,
+            &security::legacy_combine_keys(base_key, passphrase_key),
+            &security::combine_keys(base_key, passphrase_key)?,
+        )?;
+        tx.commit()?;
+        tracing::info!("Upgraded the passphrase key derivation");
+        Ok(())
+    }
+
+    /// Overwrites the master, passphrase, and ownership group keys in memory with zeros, e.g.
+    /// when the GUI has been idle. Until [`Self::unlock`], anything needing a credential fails;
+    /// the rest of the database stays usable. A no-op in no-secrets mode.
+    pub fn lock(&self) {
+        if matches!(self.key_source, KeySource::Unavailable(_)) {
+            return;
+        }
+        self.locked.store(true, Ordering::SeqCst);
+        *write_lock(&self.master_key) = [0u8; 32];
+        // Zeroed in place rather than removed, so passphrase mode is still known
+        if let Some(key) = write_lock(&self.passphrase_key).as_mut() {
+            *key = [0u8; 32];
+        }
+        for key in write_lock(&self.ownership_keys).values_mut().flatten() {
+            *key = [0u8; 32];
+        }
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.locked.load(Ordering::SeqCst)
+    }
+
+    /// Undoes [`Self::lock`]: reads the master key again from where it was loaded at startup
+    /// (the keyring, `--key-file`, or the environment), then applies `passphrase` in
+    /// passphrase mode.
+    pub fn unlock(&self, passphrase: Option<&str>) -> Result<()> {
+        if !self.is_locked() {
+            return Ok(());
+        }
+        self.unlock_with(self.read_master_key()?, passphrase)
+    }
+
+    /// The master key as stored where it was loaded from at startup, without the passphrase
+    /// key.
+    fn read_master_key(&self) -> Result<[u8; 32]> {
+        match &self.key_source {
+            KeySource::Keyring => self
+                .security
+                .get_secret("master_key")
+                .map_err(|e| anyhow!("{}", e))
+                .and_then(|hex_key| security::parse_master_key(&hex_key)),
+            _ => Ok(load_master_key(&self.security)?.0),
+        }
+    }
+
+    /// [`Self::unlock`] with the master key as read from its source.
+    fn unlock_with(&self, base_key: [u8; 32], passphrase: Option<&str>) -> Result<()> {
+        if self.passphrase_enabled() {
+            let passphrase = passphrase.ok_or_else(|| anyhow!("The passphrase is required"))?;
+            self.apply_passphrase(&base_key, passphrase)?;
+        } else {
+            *write_lock(&self.master_key) = base_key;
+        }
+        if let Err(e) = self.load_ownership_keys() {
+            self.lock();
+            return Err(e);
+        }
+        self.locked.store(false, Ordering::SeqCst);
+        tracing::info!("Unlocked");
+        Ok(())
+    }
+
+    /// Reads the keys of the groups in [`OWNERSHIP_KEYS_SETTING`] from the keyring, combined
+    /// with the passphrase key in passphrase mode. A group whose key can't be read stays
+    /// listed, so its credentials fail to decrypt instead of falling back to the master key.
+    fn load_ownership_keys(&self) -> Result<()> {
+        let passphrase_key = *read_lock(&self.passphrase_key);
+        let mut keys = HashMap::new();
+        for ownership in self.ownership_key_groups()? {
+            let key = self
+                .read_ownership_key(&ownership)
+                .and_then(|key| match passphrase_key {
+                    Some(passphrase_key) => security::combine_keys(&key, &passphrase_key),
+                    None => Ok(key),
+                });
+            let key = match key {
+                Ok(key) => Some(key),
+                Err(e) => {
+                    tracing::warn!(
+                        "Key of ownership group \"{}\" is unavailable; its credentials can't be used: {}",
+                        ownership,
+                        e
+                    );
+                    None
+                }
+            };
+            keys.insert(ownership, key);
+        }
+        *write_lock(&self.ownership_keys) = keys;
+        Ok(())
+    }
+
+    /// The key of ownership group `ownership` as stored in the keyring, without the
+    /// passphrase key.
+    fn read_ownership_key(&self, ownership: &str) -> Result<[u8; 32]> {
+        match &self.key_source {
+            // The keyring is never touched with a provided key
+            KeySource::Provided => Err(anyhow!(
+                "the master key was provided, not read from the keyring"
+            )),
+            _ => self
+                .security
+                .get_secret(&ownership_key_entry(ownership))
+                .map_err(|e| anyhow!("{}", e))
+                .and_then(|hex_key| security::parse_master_key(&hex_key)),
+        }
+    }
+
+    /// Ownership values whose credentials have a key of their own, from
+    /// [`Self::enable_ownership_key`].
+    pub fn ownership_key_groups(&self) -> Result<Vec<String>> {
+        match self.get_setting(OWNERSHIP_KEYS_SETTING)? {
+            Some(list) => {
+                serde_json::from_str(&list).context("Corrupt ownership key list in settings")
+            }
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Key for the credentials of machines owned by `ownership`: the group's own key when it
+    /// has one, otherwise the master key.
+    fn key_for_ownership(&self, ownership: &str) -> Result<[u8; 32]> {
+        let master_key = *self.master_key()?;
+        match read_lock(&self.ownership_keys).get(ownership) {
+            None => Ok(master_key),
+            Some(Some(key)) => Ok(*key),
+            Some(None) => Err(anyhow!(
+                "The key of ownership group \"{}\" is not in the keyring",
+                ownership
+            )),
+        }
+    }
+
+    /// [`Self::key_for_ownership`] of the machine's ownership.
+    fn key_for_machine(&self, conn: &Connection, machine_id: i64) -> Result<[u8; 32]> {
+        let ownership: String = conn
+            .query_row(
+                "SELECT ownership FROM machines WHERE id = ?1",
+                params![machine_id],
+                |row| row.get(0),
+            )
+            .context(format!("Machine {} not found", machine_id))?;
+        self.key_for_ownership(&ownership)
+    }
+
+    /// An account's stored credential and the ownership of its machine, which picks the key.
+    fn sealed_credential(&self, conn: &Connection, account_id: i64) -> Result<(String, String)> {
+        conn.query_row(
+            "SELECT a.credential, m.ownership FROM accounts a JOIN machines m ON m.id = a.machine_id
+             WHERE a.id = ?1",
+            params![account_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .context(format!("Account {} not found", account_id))
+    }
+
+    pub fn passphrase_enabled(&self) -> bool {
+        read_lock(&self.passphrase_key).is_some()
+    }
+
+    /// Turns on passphrase mode: re-encrypts every credential under the keyring key combined
+    /// with an Argon2id key derived from `passphrase`, and stores the salt in `settings`.
+    pub fn enable_passphrase(&self, passphrase: &str) -> Result<()> {
+        if self.passphrase_enabled() {
+            return Err(anyhow!("A passphrase is already configured"));
+        }
+        if passphrase.is_empty() {
+            return Err(anyhow!("Passphrase must not be empty"));
+        }
+
+        let salt = security::generate_salt();
+        let passphrase_key = security::derive_passphrase_key(passphrase, &salt)?;
+        let old_key = *self.master_key()?;
+        let new_key = security::combine_keys(&old_key, &passphrase_key)?;
+
+        let mut conn = self.get_conn()?;
+        let tx = conn.transaction()?;
+        self.reencrypt_all(&tx, &old_key, &new_key, &mut |_, _| {})?;
+        let ownership_keys = self.reencrypt_ownership_groups(&tx, Some(&passphrase_key))?;
+        tx.execute(
+            "INSERT OR REPLACE INTO settings (key, value) VALUES (?1, ?2)",
+            params![PASSPHRASE_SALT_SETTING, hex::encode(salt)],
+        )?;
+        tx.execute(
+            "INSERT OR REPLACE INTO settings (key, value) VALUES (?1, ?2)",
+            params![
+                PASSPHRASE_CHECK_SETTING,
+                self.seal(&new_key, PASSPHRASE_CHECK_VALUE)?
+            ],
+        )?;
+        tx.commit()?;
+
+        *write_lock(&self.master_key) = new_key;
+        *write_lock(&self.passphrase_key) = Some(passphrase_key);
+        *write_lock(&self.ownership_keys) = ownership_keys;
+        Ok(())
+    }
+
+    /// Leaves passphrase mode, re-encrypting every credential under the keyring key alone.
+    /// The combined key can't be split, so the keyring keys are read again.
+    pub fn disable_passphrase(&self) -> Result<()> {
+        if !self.passphrase_enabled() {
+            return Err(anyhow!("No passphrase is configured"));
+        }
+        let old_key = *self.master_key()?;
+        let new_key = self.read_master_key()?;
+
+        let mut conn = self.get_conn()?;
+        let tx = conn.transaction()?;
+        self.reencrypt_all(&tx, &old_key, &new_key, &mut |_, _| {})?;
+        let ownership_keys = self.reencrypt_ownership_groups(&tx, None)?;
+        tx.execute(
+            "DELETE FROM settings WHERE key IN (?1, ?2)",
+            params![PASSPHRASE_SALT_SETTING, PASSPHRASE_CHECK_SETTING],
+        )?;
+        tx.commit()?;
+
+        *write_lock(&self.master_key) = new_key;
+        *write_lock(&self.passphrase_key) = None;
+        *write_lock(&self.ownership_keys) = ownership_keys;
+        Ok(())
+    }
+
+    /// Re-encrypts each ownership group with its own key under that key combined with
+    /// `passphrase_key`, or under its keyring key alone when `None`. Returns the new keys.
+    fn reencrypt_ownership_groups(
+        &self,
+        tx: &rusqlite::Transaction,
+        passphrase_key: Option<&[u8; 32]>,
+    ) -> Result<HashMap<String, Option<[u8; 32]>>> {
+        let mut new_keys = HashMap::new();
+        for (ownership, key) in read_lock(&self.ownership_keys).iter() {
+            let old_key = key.ok_or_else(|| {
+                anyhow!(
+                    "The key of ownership group \"{}\" is not in the keyring",
+                    ownership
+                )
+            })?;
+            let new_key = match passphrase_key {
+                Some(passphrase_key) => security::combine_keys(&old_key, passphrase_key)?,
+                None => self.read_ownership_key(ownership)?,
+            };
+            self.reencrypt_group(tx, ownership, &old_key, &new_key, &mut |_, _| {})?;
+            new_keys.insert(ownership.clone(), Some(new_key));
+        }
+        Ok(new_keys)
+    }
+
+    /// The stored value of `key`, or its default from [`set_setting_defaults`].
+    pub fn get_setting(&self, key: &str) -> Result<Option<String>> {
+        let conn = self.get_conn()?;
+        let mut stmt = conn.prepare("SELECT value FROM settings WHERE key = ?1")?;
+        let mut rows = stmt.query(params![key])?;
+        match rows.next()? {
+            Some(row) => Ok(Some(row.get(0)?)),
+            None => Ok(SETTING_DEFAULTS
+                .get()
+                .and_then(|defaults| defaults.get(key).cloned())),
+        }
+    }
+
+    pub fn set_setting(&self, key: &str, value: &str) -> Result<()> {
+        let conn = self.get_conn()?;
+        conn.execute(
+            "INSERT OR REPLACE INTO settings (key, value) VALUES (?1, ?2)",
+            params![key, value],
+        )?;
+        Ok(())
+    }
+
+    /// Rewrites recognizable `os_type` variants ("Linux", "ubuntu", ...) to their canonical
+    /// form so filtering works. Unrecognized values are left alone.
+    fn normalize_os_types(&self) -> Result<()> {
+        let mut conn = self.get_conn()?;
+        let tx = conn.transaction()?;
+
+        let items: Vec<(i64, String)> = {
+            let mut stmt = tx.prepare("SELECT id, os_type FROM machines")?;
+            stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+                .collect::<Result<Vec<_>, rusqlite::Error>>()?
+        };
+
+        for (id, os_type) in items {
+            if let Ok(parsed) = os_type.parse::<OsType>() {
+                let canonical = parsed.to_string();
+                if canonical != os_type {
+                    tx.execute(
+                        "UPDATE machines SET os_type = ?1 WHERE id = ?2",
+                        params![canonical, id],
+                    )?;
+                }
+            }
+        }
+
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Rewrites recognizable `auth_type` variants ("Password", "ssh-key", ...) to their
+    /// canonical form so connect paths match them. Unrecognized values are left alone.
+    fn normalize_auth_types(&self) -> Result<()> {
+        let mut conn = self.get_conn()?;
+        let tx = conn.transaction()?;
+
+        let items: Vec<(i64, String)> = {
+            let mut stmt = tx.prepare("SELECT id, auth_type FROM accounts")?;
+            stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+                .collect::<Result<Vec<_>, rusqlite::Error>>()?
+        };
+
+        for (id, auth_type) in items {
+            match auth_type.parse::<AuthType>() {
+                Ok(parsed) => {
+                    let canonical = parsed.to_string();
+                    if canonical != auth_type {
+                        tx.execute(
+                            "UPDATE accounts SET auth_type = ?1 WHERE id = ?2",
+                            params![canonical, id],
+                        )?;
+                    }
+                }
+                Err(_) => tracing::warn!(
+                    "Account {} has an unknown auth_type \"{}\"; it can't be used to connect",
+                    id,
+                    auth_type
+                ),
+            }
+        }
+
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Classifies every stored credential without writing anything.
+    pub fn plan_credential_migration(&self) -> Result<Vec<MigrationEntry>> {
+        let conn = self.get_conn()?;
+        Ok(self
+            .classify_credentials(&conn)?
+            .into_iter()
+            .map(|(entry, _, _)| entry)
+            .collect())
+    }
+
+    /// Classifies every stored credential, returned with the stored value and the ownership
+    /// of its machine, whose key it is sealed under.
+    fn classify_credentials(
+        &self,
+        conn: &Connection,
+    ) -> Result<Vec<(MigrationEntry, String, String)>> {
+        let master_key = *self.master_key()?;
+        let sample = self.security.encrypt(&master_key, "")?;
+        let mut stmt = conn.prepare(
+            "SELECT a.id, a.username, a.auth_type, a.credential, m.ownership
+             FROM accounts a JOIN machines m ON m.id = a.machine_id ORDER BY a.id",
+        )?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok((
+                    row.get(0)?,
+                    row.get(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                    row.get(4)?,
+                ))
+            })?
+            .collect::<Result<Vec<(i64, String, String, String, String)>, _>>()?;
+        Ok(rows
+            .into_iter()
+            .map(|(account_id, username, auth_type, cred, ownership)| {
+                let key = self.key_for_ownership(&ownership);
+                let decrypts = |value: &str| {
+                    key.as_ref()
+                        .is_ok_and(|key| self.unseal(key, value).is_ok())
+                };
+                let action = if let Some(sealed) = cred.strip_prefix(CIPHERTEXT_PREFIX) {
+                    if decrypts(sealed) {
+                        CredentialMigration::Current
+                    } else {
+                        CredentialMigration::Undecryptable
+                    }
+                } else if decrypts(&cred) {
+                    CredentialMigration::AddPrefix
+                } else if looks_like_ciphertext(&cred, &sample) {
+                    CredentialMigration::Undecryptable
+                } else {
+                    CredentialMigration::Encrypt
+                };
+                let entry = MigrationEntry {
+                    account_id,
+                    username,
+                    auth_type,
+                    action,
+                };
+                (entry, cred, ownership)
+            })
+            .collect())
+    }
+
+    /// Encrypts credentials still stored as plaintext and adds [`CIPHERTEXT_PREFIX`] to
+    /// our older, unprefixed ciphertext (including the encrypted settings). Values that
+    /// don't decrypt but look like ciphertext are only reported, never encrypted a second
+    /// time.
+    fn migrate_credentials(&self) -> Result<()> {
+        let mut conn = self.get_conn()?;
+        let tx = conn.transaction()?;
+
+        for (entry, cred, ownership) in self.classify_credentials(&tx)? {
+            match entry.action {
+                CredentialMigration::Current => {}
+                CredentialMigration::AddPrefix => {
+                    tx.execute(
+                        "UPDATE accounts SET credential = ?1 WHERE id = ?2",
+                        params![format!("{}{}", CIPHERTEXT_PREFIX, cred), entry.account_id],
+                    )?;
+                }
+                CredentialMigration::Encrypt => {
+                    // Sealed under the machine's group key, as reveal_credential opens it
+                    let key = match self.key_for_ownership(&ownership) {
+                        Ok(key) => key,
+                        Err(e) => {
+                            tracing::warn!(
+                                "Leaving the plaintext credential of account {} ({}) as is: {}",
+                                entry.account_id,
+                                entry.username,
+                                e
+                            );
+                            continue;
+                        }
+                    };
+                    tracing::info!(
+                        "Encrypting plaintext credential of account {} ({}, {})",
+                        entry.account_id,
+                        entry.username,
+                        entry.auth_type
+                    );
+                    let encrypted = self.seal(&key, &cred)?;
+                    tx.execute(
+                        "UPDATE accounts SET credential = ?1 WHERE id = ?2",
+                        params![encrypted, entry.account_id],
+                    )?;
+                }
+                CredentialMigration::Undecryptable => {
+                    tracing::warn!(
+                        "Credential of account {} ({}) looks encrypted but does not decrypt with the current master key; leaving it untouched",
+                        entry.account_id,
+                        entry.username
+                    );
+                }
+            }
+        }
+
+        // The passphrase verifier is sealed under the combined key, so only the prefix is
+        // added; its content isn't checked here
+        for setting in [PASSPHRASE_CHECK_SETTING, BACKUP_PASSPHRASE_SETTING] {
+            tx.execute(
+                "UPDATE settings SET value = ?1 || value WHERE key = ?2 AND value NOT LIKE ?3",
+                params![
+                    CIPHERTEXT_PREFIX,
+                    setting,
+                    format!("{}%", CIPHERTEXT_PREFIX)
+                ],
+            )?;
+        }
+
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn db_path(&self) -> &std::path::Path {
+        &self.path
+    }
+
+    fn get_conn(&self) -> Result<Connection> {
+        let conn = Connection::open(&self.path)?;
+        conn.busy_timeout(BUSY_TIMEOUT)?;
+        // Per connection in SQLite; makes deleting a machine cascade to its records
+        conn.execute_batch("PRAGMA foreign_keys = ON")?;
+        Ok(conn)
+    }
+
+    /// `manager.db` in the data directory, or the path given to [`set_db_path`].
+    fn get_db_path() -> Result<PathBuf> {
+        if let Some(path) = DB_PATH.get() {
+            if let Some(dir) = path.parent() {
+                std::fs::create_dir_all(dir)?;
+            }
+            return Ok(path.clone());
+        }
+        let db_dir = data_dir()?;
+        std::fs::create_dir_all(&db_dir)?;
+        Ok(db_dir.join("manager.db"))
+    }
+
+    fn init_schema(&self, conn: &Connection) -> Result<()> {
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS machines (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                name TEXT NOT NULL UNIQUE,
+                ip_address TEXT NOT NULL,
+                purpose TEXT NOT NULL,
+                ownership TEXT NOT NULL,
+                os_type TEXT NOT NULL,
+                status TEXT NOT NULL DEFAULT 'active',
+                group_name TEXT,
+                ssh_options TEXT,
+                jump_host_id INTEGER REFERENCES machines(id) ON DELETE SET NULL,
+                requires_vpn INTEGER NOT NULL DEFAULT 0,
+                env TEXT,
+                uuid TEXT
+            )",
+            [],
+        )?;
+        add_column_if_missing(conn, "machines", "group_name", "TEXT")?;
+        add_column_if_missing(conn, "machines", "ssh_options", "TEXT")?;
+        add_column_if_missing(
+            conn,
+            "machines",
+            "jump_host_id",
+            "INTEGER REFERENCES machines(id) ON DELETE SET NULL",
+        )?;
+        add_column_if_missing(
+            conn,
+            "machines",
+            "requires_vpn",
+            "INTEGER NOT NULL DEFAULT 0",
+        )?;
+        add_column_if_missing(conn, "machines", "env", "TEXT")?;
+        add_column_if_missing(conn, "machines", "uuid", "TEXT")?;
+        // Rows from before uuids existed, or written by an older build since; cheap when none
+        backfill_machine_uuids(conn)?;
+        conn.execute(
+            "CREATE UNIQUE INDEX IF NOT EXISTS idx_machines_uuid ON machines(uuid)",
+            [],
+        )?;
+
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS settings (
+                key TEXT PRIMARY KEY,
+                value TEXT NOT NULL
+            )",
+            [],
+        )?;
+
+        for (table, columns) in CHILD_TABLES {
+            conn.execute(
+                &format!("CREATE TABLE IF NOT EXISTS {} ({})", table, columns),
+                [],
+            )?;
+        }
+        add_column_if_missing(conn, "accounts", "key_fingerprint", "TEXT")?;
+        add_column_if_missing(conn, "accounts", "note", "TEXT")?;
+        add_column_if_missing(conn, "command_logs", "stderr_raw", "BLOB")?;
+        rebuild_child_tables(conn)?;
+
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_accounts_machine_id ON accounts(machine_id)",
+            [],
+        )?;
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_diagnostics_machine_id ON diagnostics(machine_id)",
+            [],
+        )?;
+
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS host_keys (
+                host TEXT PRIMARY KEY,
+                fingerprint TEXT NOT NULL,
+                confirmed_at DATETIME DEFAULT CURRENT_TIMESTAMP
+            )",
+            [],
+        )?;
+        Ok(())
+    }
+
+    pub fn machine_exists(&self, name: &str) -> Result<bool> {
+        let conn = self.get_conn()?;
+        let count: i64 = conn.query_row(
+            "SELECT COUNT(*) FROM machines WHERE name = ?1",
+            params![name],
+            |row| row.get(0),
+        )?;
+        Ok(count > 0)
+    }
+
+    pub fn add_machine(&self, machine: Machine) -> Result<i64> {
+        normalize_host(&machine.ip_address)?;
+        machine.os_type.parse::<OsType>()?;
+        normalize_ssh_options(machine.ssh_options.as_deref())?;
+        normalize_env(machine.env.as_deref())?;
+        if self.machine_exists(&machine.name)? {
+            return Err(duplicate_machine_error(&machine.name));
+        }
+        // A name conflict is still possible if another process inserts it after the check
+        insert_machine(&self.get_conn()?, &machine)
+    }
+
+    pub fn update_machine(&self, machine: &Machine) -> Result<()> {
+        let id = machine
+            .id
+            .ok_or_else(|| anyhow!("Cannot update a machine without an id"))?;
+        let ip_address = normalize_host(&machine.ip_address)?;
+        let os_type = machine.os_type.parse::<OsType>()?.to_string();
+        let ssh_options = normalize_ssh_options(machine.ssh_options.as_deref())?;
+        let env = normalize_env(machine.env.as_deref())?;
+        let mut conn = self.get_conn()?;
+        let tx = conn.transaction()?;
+        check_jump_host(&tx, Some(id), machine.jump_host_id)?;
+        let old_ownership: String = tx
+            .query_row(
+                "SELECT ownership FROM machines WHERE id = ?1",
+                params![id],
+                |row| row.get(0),
+            )
+            .context(format!("Machine {} not found", id))?;
+        // Moving between groups with different keys takes the credentials along
+        if old_ownership != machine.ownership {
+            let groups = read_lock(&self.ownership_keys);
+            let rekey =
+                groups.contains_key(&old_ownership) || groups.contains_key(&machine.ownership);
+            drop(groups);
+            if rekey {
+                let old_key = self.key_for_ownership(&old_ownership)?;
+                let new_key = self.key_for_ownership(&machine.ownership)?;
+                let items = {
+                    let mut stmt =
+                        tx.prepare("SELECT id, credential FROM accounts WHERE machine_id = ?1")?;
+                    stmt.query_map(params![id], |row| Ok((row.get(0)?, row.get(1)?)))?
+                        .collect::<Result<Vec<_>, rusqlite::Error>>()?
+                };
+                self.reencrypt_accounts(&tx, items, &old_key, &new_key, &mut |_, _| {})?;
+            }
+        }
+        let updated = tx
+            .execute(
+                "UPDATE machines
+                 SET name = ?1, ip_address = ?2, purpose = ?3, ownership = ?4, os_type = ?5, status = ?6,
+                     group_name = ?7, ssh_options = ?8, jump_host_id = ?9, requires_vpn = ?10, env = ?11
+                 WHERE id = ?12",
+                params![
+                    machine.name,
+                    ip_address,
+                    machine.purpose,
+                    machine.ownership,
+                    os_type,
+                    machine.status,
+                    normalize_group(machine.group.as_deref()),
+                    ssh_options,
+                    machine.jump_host_id,
+                    machine.requires_vpn,
+                    env,
+                    id,
+                ],
+            )
+            .map_err(|e| map_machine_name_conflict(e, &machine.name))?;
+        if updated == 0 {
+            return Err(anyhow!("Machine {} not found", id));
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Registers a copy of machine `source_id` as `new_name` at `new_ip`, for a second box
+    /// set up like the first: purpose, ownership, OS type, group, SSH options, jump host,
+    /// VPN flag, environment, and constraints carry over. Accounts don't; the copy starts
+    /// without credentials and as active. Returns the new machine's id.
+    pub fn clone_machine(&self, source_id: i64, new_name: &str, new_ip: &str) -> Result<i64> {
+        let new_name = new_name.trim();
+        if new_name.is_empty() {
+            return Err(anyhow!("The copy needs a name"));
+        }
+        let source = self
+            .get_machine(source_id)?
+            .ok_or_else(|| anyhow!("Machine {} not found", source_id))?;
+        let machine = Machine {
+            id: None,
+            uuid: None,
+            name: new_name.to_string(),
+            ip_address: new_ip.to_string(),
+            status: "active".to_string(),
+            ..source
+        };
+        let mut conn = self.get_conn()?;
+        let tx = conn.transaction()?;
+        let machine_id = insert_machine(&tx, &machine)?;
+        tx.execute(
+            "INSERT INTO constraints (machine_id, rule_text)
+             SELECT ?1, rule_text FROM constraints WHERE machine_id = ?2 ORDER BY id",
+            params![machine_id, source_id],
+        )?;
+        tx.commit()?;
+        Ok(machine_id)
+    }
+
+    pub fn update_machine_status(&self, id: i64, status: &str) -> Result<()> {
+        let conn = self.get_conn()?;
+        conn.execute(
+            "UPDATE machines SET status = ?1 WHERE id = ?2",
+            params![status, id],
+        )?;
+        Ok(())
+    }
+
+    /// Deletes the machine with its accounts, constraints, and connection stats, and returns
+    /// what was removed for [`Self::restore_machine_snapshot`]. Command logs are kept.
+    pub fn delete_machine(&self, id: i64) -> Result<DeletedSnapshot> {
+        let mut conn = self.get_conn()?;
+        let tx = conn.transaction()?;
+
+        let machine = tx
+            .query_row(
+                &format!("SELECT {} FROM machines WHERE id = ?1", MACHINE_COLUMNS),
+                params![id],
+                machine_from_row,
+            )
+            .optional()?
+            .ok_or_else(|| anyhow!("Machine {} not found", id))?;
+        let accounts = tx
+            .prepare(&format!(
+                "SELECT {}, credential FROM accounts WHERE machine_id = ?1 ORDER BY id",
+                ACCOUNT_COLUMNS
+            ))?
+            .query_map(params![id], |row| {
+                Ok(Account {
+                    credential: row.get(6)?,
+                    ..redacted_account_from_row(row)?
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        let constraints = tx
+            .prepare("SELECT id, machine_id, rule_text FROM constraints WHERE machine_id = ?1")?
+            .query_map(params![id], |row| {
+                Ok(Constraint {
+                    id: Some(row.get(0)?),
+                    machine_id: row.get(1)?,
+                    rule_text: row.get(2)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        let stats = tx
+            .query_row(
+                &format!(
+                    "SELECT {} FROM connection_stats WHERE machine_id = ?1",
+                    STATS_COLUMNS
+                ),
+                params![id],
+                stats_from_row,
+            )
+            .optional()?;
+        let jump_dependents = tx
+            .prepare("SELECT id FROM machines WHERE jump_host_id = ?1 ORDER BY id")?
+            .query_map(params![id], |row| row.get(0))?
+            .collect::<Result<Vec<i64>, _>>()?;
+
+        // Accounts, constraints, and stats go with it (ON DELETE CASCADE)
+        tx.execute("DELETE FROM machines WHERE id = ?1", params![id])?;
+        tx.commit()?;
+
+        Ok(DeletedSnapshot {
+            machine,
+            accounts,
+            constraints,
+            stats,
+            jump_dependents,
+        })
+    }
+
+    /// Re-inserts a deleted machine and its records under their original ids, which also
+    /// reattaches its command logs, and points the machines that jumped through it back at it
+    /// unless they were given another jump host since. Fails if the name has been taken
+    /// since, or if the master key changed and the saved credentials could no longer be
+    /// decrypted.
+    pub fn restore_machine_snapshot(&self, snapshot: &DeletedSnapshot) -> Result<()> {
+        {
+            let key = self.key_for_ownership(&snapshot.machine.ownership)?;
+            for a in &snapshot.accounts {
+                self.unseal(&key, &a.credential).context(format!(
+                    "The credential of account {} can no longer be decrypted (was the key rotated?)",
+                    a.id.unwrap_or_default()
+                ))?;
+            }
+        }
+        let m = &snapshot.machine;
+        let mut conn = self.get_conn()?;
+        let tx = conn.transaction()?;
+        tx.execute(
+            // The jump host may have been deleted in the meantime
+            "INSERT INTO machines (id, name, ip_address, purpose, ownership, os_type, status, group_name, ssh_options, jump_host_id, requires_vpn, env, uuid)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, (SELECT id FROM machines WHERE id = ?10), ?11, ?12, ?13)",
+            params![
+                m.id,
+                m.name,
+                m.ip_address,
+                m.purpose,
+                m.ownership,
+                m.os_type,
+                m.status,
+                m.group,
+                m.ssh_options,
+                m.jump_host_id,
+                m.requires_vpn,
+                m.env,
+                m.uuid,
+            ],
+        )
+        .map_err(|e| map_machine_name_conflict(e, &m.name))?;
+        for a in &snapshot.accounts {
+            tx.execute(
+                "INSERT INTO accounts (id, machine_id, username, auth_type, credential, key_fingerprint, note)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                params![
+                    a.id,
+                    a.machine_id,
+                    a.username,
+                    a.auth_type,
+                    a.credential,
+                    a.key_fingerprint,
+                    a.note,
+                ],
+            )?;
+        }
+        for c in &snapshot.constraints {
+            tx.execute(
+                "INSERT INTO constraints (id, machine_id, rule_text) VALUES (?1, ?2, ?3)",
+                params![c.id, c.machine_id, c.rule_text],
+            )?;
+        }
+        if let Some(s) = &snapshot.stats {
+            tx.execute(
+                "INSERT INTO connection_stats (machine_id, connections, last_connected_at)
+                 VALUES (?1, ?2, ?3)",
+                params![s.machine_id, s.connections, s.last_connected_at],
+            )?;
+        }
+        for dependent in &snapshot.jump_dependents {
+            tx.execute(
+                "UPDATE machines SET jump_host_id = ?1 WHERE id = ?2 AND jump_host_id IS NULL",
+                params![m.id, dependent],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn add_account(&self, mut account: Account) -> Result<i64> {
+        validate_account(&account)?;
+
+        let conn = self.get_conn()?;
+        // Encrypt the credential before saving
+        let encrypted = {
+            let key = self.key_for_machine(&conn, account.machine_id)?;
+            self.seal(&key, &account.credential)?
+        };
+        let fingerprint = fingerprint_for(&account.auth_type, &account.credential);
+        account.credential = encrypted;
+        let auth_type = account.auth_type.parse::<AuthType>()?.to_string();
+
+        conn.execute(
+            "INSERT INTO accounts (machine_id, username, auth_type, credential, key_fingerprint, note)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                account.machine_id,
+                account.username,
+                auth_type,
+                account.credential,
+                fingerprint,
+                normalize_note(account.note.as_deref()),
+            ],
+        )?;
+        Ok(conn.last_insert_rowid())
+    }
+
+    /// Inserts a machine together with its first account in one transaction, so a failing
+    /// account insert doesn't leave the machine behind. Returns the new machine id.
+    pub fn register_machine_with_account(&self, machine: Machine, account: Account) -> Result<i64> {
+        drop(self.master_key()?);
+        let mut conn = self.get_conn()?;
+        let tx = conn.transaction()?;
+        let machine_id = self.insert_machine_with_account(&tx, machine, account)?;
+        tx.commit()?;
+        Ok(machine_id)
+    }
+
+    /// Inserts each machine together with its first account in a single transaction.
+    /// A failing entry is rolled back on its own and reported in its slot of the result;
+    /// the rest of the batch is still committed.
+    pub fn add_machines_with_accounts(
+        &self,
+        entries: Vec<(Machine, Account)>,
+    ) -> Result<Vec<Result<i64>>> {
+        drop(self.master_key()?);
+        let mut conn = self.get_conn()?;
+        let mut tx = conn.transaction()?;
+        let mut results = Vec::with_capacity(entries.len());
+        for (machine, account) in entries {
+            // Dropping the savepoint without committing rolls back just this entry
+            let sp = tx.savepoint()?;
+            let result = self.insert_machine_with_account(&sp, machine, account);
+            if result.is_ok() {
+                sp.commit()?;
+            }
+            results.push(result);
+        }
+        tx.commit()?;
+        Ok(results)
+    }
+
+    fn insert_machine_with_account(
+        &self,
+        conn: &Connection,
+        machine: Machine,
+        account: Account,
+    ) -> Result<i64> {
+        validate_account(&account)?;
+        let machine_id = insert_machine(conn, &machine)?;
+        self.insert_account(conn, machine_id, &account)?;
+        Ok(machine_id)
+    }
+
+    /// Inserts `account` under `machine_id`, encrypting its plaintext credential with the
+    /// machine's key. A fingerprint carried by the account is kept; otherwise it is computed
+    /// for key files.
+    fn insert_account(&self, conn: &Connection, machine_id: i64, account: &Account) -> Result<i64> {
+        let key = self.key_for_machine(conn, machine_id)?;
+        let auth_type = account.auth_type.parse::<AuthType>()?.to_string();
+        let fingerprint = account
+            .key_fingerprint
+            .clone()
+            .or_else(|| fingerprint_for(&account.auth_type, &account.credential));
+        conn.execute(
+            "INSERT INTO accounts (machine_id, username, auth_type, credential, key_fingerprint, note)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                machine_id,
+                account.username,
+                auth_type,
+                self.seal(&key, &account.credential)?,
+                fingerprint,
+                normalize_note(account.note.as_deref()),
+            ],
+        )?;
+        Ok(conn.last_insert_rowid())
+    }
+
+    /// Every machine with its accounts (credentials decrypted) and constraints, for
+    /// [`Self::import_vault`] on another installation. Seal the result before writing it.
+    pub fn export_vault(&self) -> Result<Vault> {
+        let mut machines = Vec::new();
+        for machine in self.list_machines()? {
+            let Some(id) = machine.id else { continue };
+            let mut accounts = self.list_accounts_for_machine(id)?;
+            for account in &mut accounts {
+                if let Some(account_id) = account.id {
+                    account.credential = self.reveal_credential(account_id)?;
+                }
+            }
+            let constraints = self.get_constraints(id)?;
+            machines.push(VaultMachine {
+                machine,
+                accounts,
+                constraints,
+            });
+        }
+        Ok(Vault {
+            format_version: VAULT_FORMAT_VERSION,
+            exported_at: chrono::Utc::now().to_rfc3339(),
+            machines,
+        })
+    }
+
+    /// Adds the machines of `vault` in one transaction, encrypting credentials under this
+    /// database's master key. Machines already present (same uuid) or whose name is taken
+    /// are skipped; jump hosts are remapped to the new ids. Returns the number imported
+    /// and the skipped names.
+    pub fn import_vault(&self, vault: &Vault) -> Result<(usize, Vec<String>)> {
+        if vault.format_version > VAULT_FORMAT_VERSION {
+            return Err(anyhow!(
+                "Vault format {} is newer than this build supports ({}); please upgrade",
+                vault.format_version,
+                VAULT_FORMAT_VERSION
+            ));
+        }
+        drop(self.master_key()?);
+        let mut conn = self.get_conn()?;
+        let tx = conn.transaction()?;
+        let mut new_ids = HashMap::new();
+        let mut skipped = Vec::new();
+        for entry in &vault.machines {
+            let exists: bool = tx.query_row(
+                "SELECT EXISTS (SELECT 1 FROM machines WHERE name = ?1 OR uuid = ?2)",
+                params![entry.machine.name, entry.machine.uuid],
+                |row| row.get(0),
+            )?;
+            if exists {
+                skipped.push(entry.machine.name.clone());
+                continue;
+            }
+            let machine = Machine {
+                jump_host_id: None,
+                ..entry.machine.clone()
+            };
+            // The uuid identifies the machine across databases, so the import keeps it
+            let machine_id = match &machine.uuid {
+                Some(uuid) => insert_machine_with_uuid(&tx, &machine, uuid)?,
+                None => insert_machine(&tx, &machine)?,
+            };
+            for account in &entry.accounts {
+                self.insert_account(&tx, machine_id, account)?;
+            }
+            for constraint in &entry.constraints {
+                tx.execute(
+                    "INSERT INTO constraints (machine_id, rule_text) VALUES (?1, ?2)",
+                    params![machine_id, constraint.rule_text],
+                )?;
+            }
+            if let Some(old_id) = entry.machine.id {
+                new_ids.insert(old_id, machine_id);
+            }
+        }
+        for entry in &vault.machines {
+            let (Some(old_id), Some(old_jump)) = (entry.machine.id, entry.machine.jump_host_id)
+            else {
+                continue;
+            };
+            if let (Some(id), Some(jump)) = (new_ids.get(&old_id), new_ids.get(&old_jump)) {
+                tx.execute(
+                    "UPDATE machines SET jump_host_id = ?1 WHERE id = ?2",
+                    params![jump, id],
+                )?;
+            }
+        }
+        tx.commit()?;
+        Ok((new_ids.len(), skipped))
+    }
+
+    pub fn list_machines(&self) -> Result<Vec<Machine>> {
+        let conn = self.get_conn()?;
+        let mut stmt = conn.prepare(&format!("SELECT {} FROM machines", MACHINE_COLUMNS))?;
+        let machines = stmt
+            .query_map([], machine_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(machines)
+    }
+
+    pub fn list_machines_by_status(&self, status: &str) -> Result<Vec<Machine>> {
+        let conn = self.get_conn()?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM machines WHERE status = ?1",
+            MACHINE_COLUMNS
+        ))?;
+        let machines = stmt
+            .query_map(params![status], machine_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(machines)
+    }
+
+    /// Machines matching every filter given; `None` leaves that column unfiltered. `group`
+    /// is compared after trimming, as stored.
+    pub fn list_machines_filtered(
+        &self,
+        status: Option<&str>,
+        ownership: Option<&str>,
+        group: Option<&str>,
+    ) -> Result<Vec<Machine>> {
+        let conn = self.get_conn()?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM machines
+             WHERE (?1 IS NULL OR status = ?1) AND (?2 IS NULL OR ownership = ?2)
+               AND (?3 IS NULL OR group_name = ?3)
+             ORDER BY id",
+            MACHINE_COLUMNS
+        ))?;
+        let machines = stmt
+            .query_map(
+                params![status, ownership, group.map(str::trim)],
+                machine_from_row,
+            )?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(machines)
+    }
+
+    pub fn get_machine_by_uuid(&self, uuid: &str) -> Result<Option<Machine>> {
+        let conn = self.get_conn()?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM machines WHERE uuid = ?1",
+            MACHINE_COLUMNS
+        ))?;
+        let mut rows = stmt.query(params![uuid])?;
+        match rows.next()? {
+            Some(row) => Ok(Some(machine_from_row(row)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn get_machine(&self, id: i64) -> Result<Option<Machine>> {
+        let conn = self.get_conn()?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM machines WHERE id = ?1",
+            MACHINE_COLUMNS
+        ))?;
+        let mut rows = stmt.query(params![id])?;
+        match rows.next()? {
+            Some(row) => Ok(Some(machine_from_row(row)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Looks up one account; the credential is redacted like in the listings.
+    pub fn get_account(&self, id: i64) -> Result<Option<Account>> {
+        let conn = self.get_conn()?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM accounts WHERE id = ?1",
+            ACCOUNT_COLUMNS
+        ))?;
+        let mut rows = stmt.query(params![id])?;
+        match rows.next()? {
+            Some(row) => Ok(Some(redacted_account_from_row(row)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn list_accounts(&self) -> Result<Vec<Account>> {
+        let conn = self.get_conn()?;
+        let mut stmt = conn.prepare(&format!("SELECT {} FROM accounts", ACCOUNT_COLUMNS))?;
+        let accounts = stmt
+            .query_map([], redacted_account_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(accounts)
+    }
+
+    pub fn list_accounts_for_machine(&self, machine_id: i64) -> Result<Vec<Account>> {
+        let conn = self.get_conn()?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM accounts WHERE machine_id = ?1 ORDER BY id",
+            ACCOUNT_COLUMNS
+        ))?;
+        let accounts = stmt
+            .query_map(params![machine_id], redacted_account_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(accounts)
+    }
+
+    pub fn update_account_credential(&self, account_id: i64, new_credential: &str) -> Result<()> {
+        let conn = self.get_conn()?;
+        let (auth_type, ownership): (String, String) = conn
+            .query_row(
+                "SELECT a.auth_type, m.ownership FROM accounts a JOIN machines m ON m.id = a.machine_id
+                 WHERE a.id = ?1",
+                params![account_id],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .context(format!("Account {} not found", account_id))?;
+        let encrypted = {
+            let key = self.key_for_ownership(&ownership)?;
+            self.seal(&key, new_credential)?
+        };
+        conn.execute(
+            "UPDATE accounts SET credential = ?1, key_fingerprint = ?2 WHERE id = ?3",
+            params![
+                encrypted,
+                fingerprint_for(&auth_type, new_credential),
+                account_id
+            ],
+        )?;
+        Ok(())
+    }
+
+    pub fn update_account_note(&self, account_id: i64, note: Option<&str>) -> Result<()> {
+        let conn = self.get_conn()?;
+        let updated = conn.execute(
+            "UPDATE accounts SET note = ?1 WHERE id = ?2",
+            params![normalize_note(note), account_id],
+        )?;
+        if updated == 0 {
+            return Err(anyhow!("Account {} not found", account_id));
+        }
+        Ok(())
+    }
+
+    /// Machines with a "key" account whose recorded fingerprint is `fingerprint`
+    /// ("SHA256:xxxx"; anything after the first whitespace, such as a key type, is ignored).
+    pub fn find_accounts_by_key_fingerprint(
+        &self,
+        fingerprint: &str,
+    ) -> Result<Vec<(Machine, Account)>> {
+        let fingerprint = fingerprint.split_whitespace().next().unwrap_or("");
+        let conn = self.get_conn()?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM accounts WHERE key_fingerprint = ?1 ORDER BY machine_id, id",
+            ACCOUNT_COLUMNS
+        ))?;
+        let accounts = stmt
+            .query_map(params![fingerprint], redacted_account_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut matches = Vec::with_capacity(accounts.len());
+        for account in accounts {
+            if let Some(machine) = self.get_machine(account.machine_id)? {
+                matches.push((machine, account));
+            }
+        }
+        Ok(matches)
+    }
+
+    /// Records fingerprints for "key" accounts stored before fingerprints were tracked.
+    /// Keys that can't be read are skipped and retried on the next start.
+    fn backfill_key_fingerprints(&self) -> Result<()> {
+        let conn = self.get_conn()?;
+        let missing: Vec<(i64, String, String)> = {
+            let mut stmt = conn.prepare(
+                "SELECT a.id, a.credential, m.ownership FROM accounts a JOIN machines m ON m.id = a.machine_id
+                 WHERE a.auth_type = 'key' AND a.key_fingerprint IS NULL",
+            )?;
+            stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+                .collect::<Result<Vec<_>, rusqlite::Error>>()?
+        };
+        for (id, encrypted, ownership) in missing {
+            let Ok(key) = self.key_for_ownership(&ownership) else {
+                continue;
+            };
+            let Ok(path) = self.unseal(&key, &encrypted) else {
+                continue;
+            };
+            if let Some(fingerprint) = fingerprint_for("key", &path) {
+                conn.execute(
+                    "UPDATE accounts SET key_fingerprint = ?1 WHERE id = ?2",
+                    params![fingerprint, id],
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Decrypts an account's stored credential for internal use (e.g. handing it to the SSH engine).
+    /// The plaintext must never be returned to MCP clients or rendered in the GUI.
+    pub fn reveal_credential(&self, account_id: i64) -> Result<String> {
+        let conn = self.get_conn()?;
+        let (encrypted, ownership) = self.sealed_credential(&conn, account_id)?;
+        let key = self.key_for_ownership(&ownership)?;
+        self.unseal(&key, &encrypted).context(format!(
+            "Failed to decrypt credential for account {}",
+            account_id
+        ))
+    }
+
+    /// Whether the account's credential is set, empty, hardware bound, or undecryptable,
+    /// without returning it.
+    pub fn credential_status(&self, account_id: i64) -> Result<CredentialStatus> {
+        drop(self.master_key()?);
+        let conn = self.get_conn()?;
+        let (encrypted, ownership) = self.sealed_credential(&conn, account_id)?;
+        Ok(self.classify_credential(&ownership, &encrypted))
+    }
+
+    /// [`Self::credential_status`] of every account, by account id.
+    pub fn credential_statuses(&self) -> Result<HashMap<i64, CredentialStatus>> {
+        drop(self.master_key()?);
+        let conn = self.get_conn()?;
+        Ok(credentials_with_ownership(&conn)?
+            .into_iter()
+            .map(|(id, encrypted, ownership)| {
+                (id, self.classify_credential(&ownership, &encrypted))
+            })
+            .collect())
+    }
+
+    /// Encrypts `plaintext` under `key`, tagged with [`CIPHERTEXT_PREFIX`].
+    fn seal(&self, key: &[u8; 32], plaintext: &str) -> Result<String> {
+        Ok(format!(
+            "{}{}",
+            CIPHERTEXT_PREFIX,
+            self.security.encrypt(key, plaintext)?
+        ))
+    }
+
+    /// Decrypts a value from [`Self::seal`]. Ciphertext from before the prefix is still
+    /// accepted until the migration has tagged it.
+    fn unseal(&self, key: &[u8; 32], stored: &str) -> Result<String> {
+        let sealed = stored.strip_prefix(CIPHERTEXT_PREFIX).unwrap_or(stored);
+        self.security.decrypt(key, sealed)
+    }
+
+    /// Callers have checked the master key is available; a missing group key counts as
+    /// [`CredentialStatus::Corrupt`].
+    fn classify_credential(&self, ownership: &str, encrypted: &str) -> CredentialStatus {
+        let Ok(key) = self.key_for_ownership(ownership) else {
+            return CredentialStatus::Corrupt;
+        };
+        match self.unseal(&key, encrypted) {
+            Ok(plain) if plain == TPM_CREDENTIAL_MARKER => CredentialStatus::HardwareBound,
+            Ok(plain) if plain.is_empty() => CredentialStatus::Empty,
+            Ok(_) => CredentialStatus::Set,
+            Err(_) => CredentialStatus::Corrupt,
+        }
+    }
+
+    /// Output is stored with ANSI escapes stripped and line endings normalized; the raw
+    /// stderr bytes go to `stderr_raw` when that cleaning changed anything.
+    pub fn add_log(
+        &self,
+        machine_id: i64,
+        username: &str,
+        command: &str,
+        stdout: Option<&str>,
+        stderr: Option<&[u8]>,
+        exit_code: Option<i32>,
+    ) -> Result<i64> {
+        let stdout = stdout.map(terminal_text::clean);
+        let cleaned = stderr.map(terminal_text::decode);
+        let raw = stderr.filter(|raw| cleaned.as_deref().map(str::as_bytes) != Some(*raw));
+        let conn = self.get_conn()?;
+        conn.execute(
+            "INSERT INTO command_logs (machine_id, username, command, stdout, stderr, exit_code, stderr_raw)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![machine_id, username, command, stdout, cleaned, exit_code, raw],
+        )?;
+        Ok(conn.last_insert_rowid())
+    }
+
+    pub fn list_logs(&self) -> Result<Vec<CommandLog>> {
+        let conn = self.get_conn()?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM command_logs l
+             LEFT JOIN machines m ON l.machine_id = m.id
+             ORDER BY l.timestamp DESC",
+            LOG_COLUMNS
+        ))?;
+        let logs = stmt
+            .query_map([], log_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(logs)
+    }
+
+    /// Newest-first page of logs, optionally for one machine and/or at or after `since`
+    /// (UTC, "YYYY-MM-DD HH:MM:SS" or any prefix of it such as a date).
+    pub fn list_logs_paged(
+        &self,
+        machine_id: Option<i64>,
+        since: Option<&str>,
+        limit: usize,
+        offset: usize,
+    ) -> Result<Vec<CommandLog>> {
+        let conn = self.get_conn()?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM command_logs l
+             LEFT JOIN machines m ON l.machine_id = m.id
+             WHERE (?1 IS NULL OR l.machine_id = ?1) AND (?2 IS NULL OR l.timestamp >= ?2)
+             ORDER BY l.timestamp DESC, l.id DESC
+             LIMIT ?3 OFFSET ?4",
+            LOG_COLUMNS
+        ))?;
+        let logs = stmt
+            .query_map(
+                params![machine_id, since, limit as i64, offset as i64],
+                log_from_row,
+            )?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(logs)
+    }
+
+    /// Logs oldest first for exporting, optionally only those at or after `since` (as for
+    /// [`Self::list_logs_paged`]) and at most `limit` of them.
+    pub fn export_logs(
+        &self,
+        since: Option<&str>,
+        limit: Option<usize>,
+    ) -> Result<Vec<CommandLog>> {
+        let conn = self.get_conn()?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM command_logs l
+             LEFT JOIN machines m ON l.machine_id = m.id
+             WHERE ?1 IS NULL OR l.timestamp >= ?1
+             ORDER BY l.timestamp, l.id
+             LIMIT ?2",
+            LOG_COLUMNS
+        ))?;
+        // A negative LIMIT means none
+        let limit = limit.map_or(-1, |n| n as i64);
+        let logs = stmt
+            .query_map(params![since, limit], log_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(logs)
+    }
+
+    pub fn get_constraints(&self, machine_id: i64) -> Result<Vec<Constraint>> {
+        let conn = self.get_conn()?;
+        let mut stmt = conn
+            .prepare("SELECT id, machine_id, rule_text FROM constraints WHERE machine_id = ?1")?;
+        let rules = stmt
+            .query_map(params![machine_id], |row| {
+                Ok(Constraint {
+                    id: Some(row.get(0)?),
+                    machine_id: row.get(1)?,
+                    rule_text: row.get(2)?,
+                })
+            })?
+            .collect::<Result<Vec<Constraint>, _>>()?;
+        Ok(rules)
+    }
+
+    /// Case-insensitive (for ASCII) substring search over machine names, hosts, purposes
+    /// and groups, account usernames and notes, logged commands, and constraint rules.
+    /// Each category is capped at [`SEARCH_RESULTS_PER_CATEGORY`]; a blank query finds
+    /// nothing.
+    pub fn global_search(&self, query: &str) -> Result<SearchResults> {
+        let query = query.trim();
+        if query.is_empty() {
+            return Ok(SearchResults::default());
+        }
+        let pattern = like_pattern(query);
+        let limit = SEARCH_RESULTS_PER_CATEGORY as i64;
+        let conn = self.get_conn()?;
+
+        let machines = conn
+            .prepare(&format!(
+                "SELECT {} FROM machines
+                 WHERE name LIKE ?1 ESCAPE '\\' OR ip_address LIKE ?1 ESCAPE '\\'
+                    OR purpose LIKE ?1 ESCAPE '\\' OR group_name LIKE ?1 ESCAPE '\\'
+                 ORDER BY name LIMIT ?2",
+                MACHINE_COLUMNS
+            ))?
+            .query_map(params![pattern, limit], machine_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+        let accounts = conn
+            .prepare(&format!(
+                "SELECT {} FROM accounts
+                 WHERE username LIKE ?1 ESCAPE '\\' OR note LIKE ?1 ESCAPE '\\'
+                 ORDER BY id LIMIT ?2",
+                ACCOUNT_COLUMNS
+            ))?
+            .query_map(params![pattern, limit], redacted_account_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+        let logs = conn
+            .prepare(&format!(
+                "SELECT {} FROM command_logs l
+                 LEFT JOIN machines m ON l.machine_id = m.id
+                 WHERE l.command LIKE ?1 ESCAPE '\\' OR l.username LIKE ?1 ESCAPE '\\'
+                 ORDER BY l.timestamp DESC, l.id DESC LIMIT ?2",
+                LOG_COLUMNS
+            ))?
+            .query_map(params![pattern, limit], log_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+        let constraints = conn
+            .prepare(
+                "SELECT id, machine_id, rule_text FROM constraints
+                 WHERE rule_text LIKE ?1 ESCAPE '\\'
+                 ORDER BY machine_id, id LIMIT ?2",
+            )?
+            .query_map(params![pattern, limit], |row| {
+                Ok(Constraint {
+                    id: Some(row.get(0)?),
+                    machine_id: row.get(1)?,
+                    rule_text: row.get(2)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(SearchResults {
+            machines,
+            accounts,
+            logs,
+            constraints,
+        })
+    }
+
+    pub fn delete_constraint(&self, id: i64) -> Result<()> {
+        let conn = self.get_conn()?;
+        conn.execute("DELETE FROM constraints WHERE id = ?1", params![id])?;
+        Ok(())
+    }
+
+    /// Cheap liveness probe: verifies the database can be opened and queried.
+    pub fn ping(&self) -> Result<()> {
+        let conn = self.get_conn()?;
+        conn.query_row("SELECT 1", [], |row| row.get::<_, i64>(0))?;
+        Ok(())
+    }
+
+    /// Returns `(machines, accounts)` row counts.
+    pub fn record_counts(&self) -> Result<(i64, i64)> {
+        let conn = self.get_conn()?;
+        let counts = conn.query_row(
+            "SELECT (SELECT COUNT(*) FROM machines), (SELECT COUNT(*) FROM accounts)",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        Ok(counts)
+    }
+
+    /// Machine counts per status, account and log totals, and the newest backup's time.
+    pub fn summary(&self) -> Result<Summary> {
+        let conn = self.get_conn()?;
+        let machines_by_status = conn
+            .prepare("SELECT status, COUNT(*) FROM machines GROUP BY status")?
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<std::collections::BTreeMap<String, i64>, _>>()?;
+        let (accounts, logs) = conn.query_row(
+            "SELECT (SELECT COUNT(*) FROM accounts), (SELECT COUNT(*) FROM command_logs)",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        let last_backup = latest_backup()
+            .and_then(|path| path.metadata().ok()?.modified().ok())
+            .map(|modified| {
+                chrono::DateTime::<chrono::Local>::from(modified)
+                    .format("%Y-%m-%d %H:%M:%S")
+                    .to_string()
+            });
+        let (last_key_rotation, key_rotation_due) = self.key_rotation_status()?;
+        Ok(Summary {
+            machines: machines_by_status.values().sum(),
+            machines_by_status,
+            accounts,
+            logs,
+            last_backup,
+            last_key_rotation,
+            key_rotation_due,
+        })
+    }
+
+    /// Time of the last master key rotation and whether the next one is due, per
+    /// [`SETTING_ROTATION_REMINDER_DAYS`].
+    pub fn key_rotation_status(&self) -> Result<(Option<String>, bool)> {
+        let last_rotation = self.get_setting(SETTING_LAST_KEY_ROTATION)?;
+        let reminder_days = self
+            .get_setting(SETTING_ROTATION_REMINDER_DAYS)?
+            .and_then(|v| v.trim().parse().ok())
+            .unwrap_or(DEFAULT_ROTATION_REMINDER_DAYS);
+        let due = key_rotation_due(
+            last_rotation.as_deref(),
+            reminder_days,
+            chrono::Utc::now().naive_utc(),
+        );
+        Ok((last_rotation, due))
+    }
+
+    pub fn key_source(&self) -> &KeySource {
+        &self.key_source
+    }
+
+    /// The effective key, or [`security::KeyringUnavailable`] in no-secrets mode.
+    fn master_key(&self) -> Result<RwLockReadGuard<'_, [u8; 32]>> {
+        match &self.key_source {
+            KeySource::Unavailable(e) => Err(e.clone().into()),
+            _ if self.is_locked() => Err(anyhow!(
+                "The app is locked; unlock it to use stored credentials"
+            )),
+            _ => Ok(read_lock(&self.master_key)),
+        }
+    }
+
+    pub fn master_key_loaded(&self) -> bool {
+        read_lock(&self.master_key).iter().any(|b| *b != 0)
+    }
+
+    /// Counts a successful connection (terminal launch or remote command) to the machine.
+    pub fn record_connection(&self, machine_id: i64) -> Result<()> {
+        let conn = self.get_conn()?;
+        conn.execute(
+            "INSERT INTO connection_stats (machine_id, connections, last_connected_at)
+             VALUES (?1, 1, CURRENT_TIMESTAMP)
+             ON CONFLICT(machine_id) DO UPDATE SET
+                connections = connections + 1,
+                last_connected_at = CURRENT_TIMESTAMP",
+            params![machine_id],
+        )?;
+        Ok(())
+    }
+
+    /// Stats for one machine; `None` if it has never been connected to.
+    pub fn connection_stats(&self, machine_id: i64) -> Result<Option<ConnectionStats>> {
+        let conn = self.get_conn()?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM connection_stats WHERE machine_id = ?1",
+            STATS_COLUMNS
+        ))?;
+        let mut rows = stmt.query(params![machine_id])?;
+        match rows.next()? {
+            Some(row) => Ok(Some(stats_from_row(row)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Records a diagnose probe, keeping the last [`DIAGNOSTIC_EXCERPT_CHARS`] characters
+    /// of `stderr` and at most [`MAX_DIAGNOSTICS_PER_MACHINE`] entries for the machine.
+    pub fn add_diagnostic(
+        &self,
+        machine_id: i64,
+        success: bool,
+        matched_pattern_id: Option<&str>,
+        stderr: &str,
+    ) -> Result<i64> {
+        let stderr = stderr.trim();
+        let skip = stderr
+            .chars()
+            .count()
+            .saturating_sub(DIAGNOSTIC_EXCERPT_CHARS);
+        let excerpt: String = stderr.chars().skip(skip).collect();
+        let conn = self.get_conn()?;
+        conn.execute(
+            "INSERT INTO diagnostics (machine_id, success, matched_pattern_id, stderr_excerpt)
+             VALUES (?1, ?2, ?3, ?4)",
+            params![
+                machine_id,
+                success,
+                matched_pattern_id,
+                Some(excerpt).filter(|e| !e.is_empty())
+            ],
+        )?;
+        let id = conn.last_insert_rowid();
+        conn.execute(
+            "DELETE FROM diagnostics WHERE machine_id = ?1 AND id NOT IN
+                (SELECT id FROM diagnostics WHERE machine_id = ?1 ORDER BY id DESC LIMIT ?2)",
+            params![machine_id, MAX_DIAGNOSTICS_PER_MACHINE],
+        )?;
+        Ok(id)
+    }
+
+    /// Recorded diagnose probes of the machine, newest first.
+    pub fn list_diagnostics(&self, machine_id: i64) -> Result<Vec<Diagnostic>> {
+        let conn = self.get_conn()?;
+        let mut stmt = conn.prepare(
+            "SELECT id, machine_id, timestamp, success, matched_pattern_id, stderr_excerpt
+             FROM diagnostics WHERE machine_id = ?1 ORDER BY id DESC",
+        )?;
+        let diagnostics = stmt
+            .query_map(params![machine_id], |row| {
+                Ok(Diagnostic {
+                    id: row.get(0)?,
+                    machine_id: row.get(1)?,
+                    timestamp: row.get(2)?,
+                    success: row.get(3)?,
+                    matched_pattern_id: row.get(4)?,
+                    stderr_excerpt: row.get(5)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(diagnostics)
+    }
+
+    pub fn list_connection_stats(&self) -> Result<Vec<ConnectionStats>> {
+        let conn = self.get_conn()?;
+        let mut stmt = conn.prepare(&format!("SELECT {} FROM connection_stats", STATS_COLUMNS))?;
+        let stats = stmt
+            .query_map([], stats_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(stats)
+    }
+
+    /// Records that the user confirmed `host`'s key fingerprint(s).
+    pub fn confirm_host_key(&self, host: &str, fingerprint: &str) -> Result<()> {
+        let conn = self.get_conn()?;
+        conn.execute(
+            "INSERT OR REPLACE INTO host_keys (host, fingerprint) VALUES (?1, ?2)",
+            params![host, fingerprint],
+        )?;
+        Ok(())
+    }
+
+    pub fn confirmed_host_key(&self, host: &str) -> Result<Option<String>> {
+        let conn = self.get_conn()?;
+        let mut stmt = conn.prepare("SELECT fingerprint FROM host_keys WHERE host = ?1")?;
+        let mut rows = stmt.query(params![host])?;
+        match rows.next()? {
+            Some(row) => Ok(Some(row.get(0)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Bytes used by the database file plus its `-wal` file, if any.
+    fn disk_size(&self) -> u64 {
+        let mut wal = self.path.as_os_str().to_owned();
+        wal.push("-wal");
+        [self.path.as_os_str(), wal.as_os_str()]
+            .iter()
+            .filter_map(|p| std::fs::metadata(p).ok())
+            .map(|m| m.len())
+            .sum()
+    }
+
+    /// Folds the WAL back into the database (a no-op outside WAL mode) and rebuilds the
+    /// file with `VACUUM` to drop free pages and fragmentation.
+    pub fn maintenance(&self) -> Result<MaintenanceReport> {
+        let size_before = self.disk_size();
+        let conn = self.get_conn()?;
+        conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()))?;
+        conn.execute_batch("VACUUM")?;
+        drop(conn);
+        Ok(MaintenanceReport {
+            size_before,
+            size_after: self.disk_size(),
+        })
+    }
+
+    /// Cheap checkpoint for clean shutdown: copies what it can from the WAL without waiting
+    /// on other connections.
+    pub fn checkpoint(&self) -> Result<()> {
+        let conn = self.get_conn()?;
+        conn.query_row("PRAGMA wal_checkpoint(PASSIVE)", [], |_| Ok(()))?;
+        Ok(())
+    }
+
+    pub fn backup_db(&self, backup_path: &std::path::Path) -> Result<()> {
+        let conn = self.get_conn()?;
+        conn.backup(rusqlite::DatabaseName::Main, backup_path, None)?;
+        Ok(())
+    }
+
+    /// Re-encrypts every credential under the master key (and the passphrase verifier) from
+    /// `old_key` to `new_key` inside the caller's transaction; ownership groups with their own
+    /// key are left alone. `progress(done, total)` is called after each account.
+    fn reencrypt_all(
+        &self,
+        tx: &rusqlite::Transaction,
+        old_key: &[u8; 32],
+        new_key: &[u8; 32],
+        progress: &mut dyn FnMut(usize, usize),
+    ) -> Result<()> {
+        // 1. Fetch the accounts under the master key
+        let items: Vec<(i64, String)> = {
+            let groups = read_lock(&self.ownership_keys);
+            credentials_with_ownership(tx)?
+                .into_iter()
+                .filter(|(_, _, ownership)| !groups.contains_key(ownership))
+                .map(|(id, cred, _)| (id, cred))
+                .collect()
+        };
+
+        // 2. Re-encrypt them
+        self.reencrypt_accounts(tx, items, old_key, new_key, progress)?;
+
+        // 3. Keep encrypted settings (passphrase verifier, backup passphrase) decryptable
+        self.reencrypt_settings(tx, old_key, new_key)
+    }
+
+    /// Re-encrypts the settings sealed with the master key from `old_key` to `new_key`.
+    fn reencrypt_settings(
+        &self,
+        tx: &rusqlite::Transaction,
+        old_key: &[u8; 32],
+        new_key: &[u8; 32],
+    ) -> Result<()> {
+        for setting in [
+            PASSPHRASE_CHECK_SETTING,
+            BACKUP_PASSPHRASE_SETTING,
+            IDENTITY_PRIVATE_KEY_SETTING,
+        ] {
+            let value: Option<String> = tx
+                .query_row(
+                    "SELECT value FROM settings WHERE key = ?1",
+                    params![setting],
+                    |row| row.get(0),
+                )
+                .ok();
+            if let Some(value) = value {
+                let plaintext = self.unseal(old_key, &value)?;
+                tx.execute(
+                    "UPDATE settings SET value = ?1 WHERE key = ?2",
+                    params![self.seal(new_key, &plaintext)?, setting],
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Re-encrypts the credentials of machines owned by `ownership` from `old_key` to
+    /// `new_key` inside the caller's transaction.
+    fn reencrypt_group(
+        &self,
+        tx: &rusqlite::Transaction,
+        ownership: &str,
+        old_key: &[u8; 32],
+        new_key: &[u8; 32],
+        progress: &mut dyn FnMut(usize, usize),
+    ) -> Result<()> {
+        let items = credentials_with_ownership(tx)?
+            .into_iter()
+            .filter(|(_, _, o)| o == ownership)
+            .map(|(id, cred, _)| (id, cred))
+            .collect();
+        self.reencrypt_accounts(tx, items, old_key, new_key, progress)
+    }
+
+    fn reencrypt_accounts(
+        &self,
+        conn: &Connection,
+        items: Vec<(i64, String)>,
+        old_key: &[u8; 32],
+        new_key: &[u8; 32],
+        progress: &mut dyn FnMut(usize, usize),
+    ) -> Result<()> {
+        let total = items.len();
+        for (done, (id, old_cred)) in items.into_iter().enumerate() {
+            // Decrypt with OLD key
+            let plaintext = self
+                .unseal(old_key, &old_cred)
+                .context(format!("Failed to decrypt credential for account {}", id))?;
+
+            // Encrypt with NEW key
+            let new_cred = self.seal(new_key, &plaintext)?;
+
+            // Update DB (in transaction)
+            conn.execute(
+                "UPDATE accounts SET credential = ?1 WHERE id = ?2",
+                params![new_cred, id],
+            )?;
+            progress(done + 1, total);
+        }
+        Ok(())
+    }
+
+    /// Writes a backup sealed with ChaCha20-Poly1305 under a key derived from `passphrase`.
+    pub fn backup_db_encrypted(
+        &self,
+        backup_path: &std::path::Path,
+        passphrase: &str,
+    ) -> Result<()> {
+        // SQLite's online backup needs a file target; keep the plaintext copy only briefly
+        let tmp_path = backup_path.with_extension("tmp");
+        self.backup_db(&tmp_path)?;
+        let result = std::fs::read(&tmp_path)
+            .map_err(anyhow::Error::from)
+            .and_then(|bytes| security::seal_with_passphrase(passphrase, &bytes))
+            .and_then(|sealed| Ok(std::fs::write(backup_path, sealed)?));
+        let _ = std::fs::remove_file(&tmp_path);
+        result
+    }
+
+    /// Decrypts a backup written by [`DbHandler::backup_db_encrypted`] into a plain SQLite file.
+    pub fn restore_encrypted_backup(
+        encrypted_path: &std::path::Path,
+        output_path: &std::path::Path,
+        passphrase: &str,
+    ) -> Result<()> {
+        let sealed = std::fs::read(encrypted_path)?;
+        let bytes = security::open_with_passphrase(passphrase, &sealed)?;
+        std::fs::write(output_path, bytes)?;
+        Ok(())
+    }
+
+    /// Replaces the database with `backup`, moving the damaged file aside (never deleting it).
+    /// Encrypted backups use `passphrase`, or ask for it when `None`. Returns where the old
+    /// file was moved.
+    pub fn restore_from_backup(
+        backup: &std::path::Path,
+        passphrase: Option<&str>,
+    ) -> Result<PathBuf> {
+        let db_path = Self::get_db_path()?;
+        let stamp = chrono::Local::now().format("%Y%m%d-%H%M%S");
+        let quarantined = db_path.with_extension(format!("db.corrupt-{}", stamp));
+        std::fs::rename(&db_path, &quarantined)
+            .with_context(|| format!("Failed to move {} aside", db_path.display()))?;
+
+        let restored = if backup.extension().is_some_and(|ext| ext == "enc") {
+            passphrase
+                .map(|pass| Ok(pass.to_string()))
+                .unwrap_or_else(|| {
+                    security::read_secret(security::BACKUP_PASSPHRASE_ENV, "Backup passphrase: ")
+                })
+                .and_then(|pass| Self::restore_encrypted_backup(backup, &db_path, &pass))
+        } else {
+            std::fs::copy(backup, &db_path)
+                .map(|_| ())
+                .map_err(anyhow::Error::from)
+        };
+        if let Err(e) = restored {
+            // Put the original back so nothing is lost if the restore itself failed
+            let _ = std::fs::remove_file(&db_path);
+            let _ = std::fs::rename(&quarantined, &db_path);
+            return Err(e.context(format!("Failed to restore from {}", backup.display())));
+        }
+        Ok(quarantined)
+    }
+
+    /// Passphrase used for automatic encrypted backups, stored encrypted under the master key.
+    pub fn backup_passphrase(&self) -> Result<Option<String>> {
+        match self.get_setting(BACKUP_PASSPHRASE_SETTING)? {
+            Some(encrypted) => {
+                let key = self.master_key()?;
+                Ok(Some(self.unseal(&key, &encrypted)?))
+            }
+            None => Ok(None),
+        }
+    }
+
+    pub fn set_backup_passphrase(&self, passphrase: &str) -> Result<()> {
+        let encrypted = {
+            let key = self.master_key()?;
+            self.seal(&key, passphrase)?
+        };
+        self.set_setting(BACKUP_PASSPHRASE_SETTING, &encrypted)
+    }
+
+    /// OpenSSH private key of the software device identity key; `None` when none was
+    /// generated or the identity key is TPM-bound.
+    pub fn identity_private_key(&self) -> Result<Option<String>> {
+        match self.get_setting(IDENTITY_PRIVATE_KEY_SETTING)? {
+            Some(encrypted) => {
+                let key = self.master_key()?;
+                Ok(Some(self.unseal(&key, &encrypted)?))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Seals `private_key` under the master key, or forgets the stored one with `None`.
+    pub fn set_identity_private_key(&self, private_key: Option<&str>) -> Result<()> {
+        let Some(private_key) = private_key else {
+            self.get_conn()?.execute(
+                "DELETE FROM settings WHERE key = ?1",
+                params![IDENTITY_PRIVATE_KEY_SETTING],
+            )?;
+            return Ok(());
+        };
+        let encrypted = {
+            let key = self.master_key()?;
+            self.seal(&key, private_key)?
+        };
+        self.set_setting(IDENTITY_PRIVATE_KEY_SETTING, &encrypted)
+    }
+
+    /// What [`Self::rotate_keys`], or with `ownership` [`Self::rotate_ownership_key`], would
+    /// re-encrypt, without changing anything. Each credential is decrypted the way the
+    /// rotation would; one that fails would abort it.
+    pub fn plan_key_rotation(&self, ownership: Option<&str>) -> Result<RotationPlan> {
+        let key = match ownership {
+            None => {
+                self.refuse_master_rotation()?;
+                *self.master_key()?
+            }
+            Some(ownership) => {
+                self.refuse_provided_key()?;
+                if !read_lock(&self.ownership_keys).contains_key(ownership) {
+                    return Err(anyhow!(
+                        "Ownership group \"{}\" has no key of its own; its credentials use the master key",
+                        ownership
+                    ));
+                }
+                self.key_for_ownership(ownership)?
+            }
+        };
+        let conn = self.get_conn()?;
+        let groups = read_lock(&self.ownership_keys);
+        let mut plan = RotationPlan::default();
+        for (id, cred, owner) in credentials_with_ownership(&conn)? {
+            let affected = match ownership {
+                None => !groups.contains_key(&owner),
+                Some(ownership) => owner == ownership,
+            };
+            if !affected {
+                continue;
+            }
+            if self.unseal(&key, &cred).is_ok() {
+                plan.accounts.push(id);
+            } else {
+                plan.undecryptable.push(id);
+            }
+        }
+        Ok(plan)
+    }
+
+    fn refuse_master_rotation(&self) -> Result<()> {
+        if let KeySource::Provided = self.key_source {
+            return Err(anyhow!(
+                "The master key was supplied with --key-file or {}; rotation would move it into the keyring",
+                security::MASTER_KEY_ENV
+            ));
+        }
+        Ok(())
+    }
+
+    /// Replaces the master key and re-encrypts everything under it in one transaction.
+    /// `progress(done, total)` reports each re-encrypted account.
+    pub fn rotate_keys(&self, progress: &mut dyn FnMut(usize, usize)) -> Result<()> {
+        self.refuse_master_rotation()?;
+        let new_keyring_key = self.security.generate_new_master_key();
+        self.rotate_keys_to(new_keyring_key, progress, |hex_key| {
+            self.security
+                .store_secret("master_key", hex_key)
+                .map(|_| ())
+                .map_err(|e| anyhow!("{}", e))
+        })
+    }
+
+    /// [`Self::rotate_keys`] with the new keyring key and the keyring write supplied by
+    /// the caller, so tests can rotate without touching the OS keyring.
+    fn rotate_keys_to(
+        &self,
+        new_keyring_key: [u8; 32],
+        progress: &mut dyn FnMut(usize, usize),
+        store_keyring_key: impl FnOnce(&str) -> Result<()>,
+    ) -> Result<()> {
+        let mut conn = self.get_conn()?;
+        let tx = conn.transaction()?;
+
+        // 1. Combine the new keyring key with the passphrase key in passphrase mode
+        let new_key = self.with_passphrase_key(new_keyring_key)?;
+
+        // 2. Re-encrypt all credentials
+        {
+            let old_key = self.master_key()?;
+            self.reencrypt_all(&tx, &old_key, &new_key, progress)?;
+        }
+        tx.execute(
+            "INSERT OR REPLACE INTO settings (key, value) VALUES (?1, CURRENT_TIMESTAMP)",
+            params![SETTING_LAST_KEY_ROTATION],
+        )?;
+
+        // 3. Commit DB Transaction
+        tx.commit()
+            .context("Failed to commit database transaction during key rotation")?;
+
+        // 4. Update Keyring
+        let hex_key = hex::encode(new_keyring_key);
+        if let Err(e) = store_keyring_key(&hex_key) {
+            tracing::error!("CRITICAL: Database rotated but Keyring update failed!");
+            // Console only: the key must never end up in the persistent log file or the
+            // in-app panel, which keeps it for the whole session
+            eprintln!("NEW KEY HEX: {}", hex_key);
+            return Err(anyhow!("Keyring update failed: {}", e));
+        }
+
+        tracing::info!("Master key rotated");
+
+        // 5. Update Memory
+        let mut key_guard = write_lock(&self.master_key);
+        *key_guard = new_key;
+
+        Ok(())
+    }
+
+    /// Gives the machines owned by `ownership` a key of their own: generates it, moves their
+    /// credentials from the master key to it, and stores it in the keyring. Machines moved
+    /// into the group later are re-encrypted on save. Returns the number of accounts moved.
+    pub fn enable_ownership_key(&self, ownership: &str) -> Result<usize> {
+        self.refuse_provided_key()?;
+        let new_keyring_key = self.security.generate_new_master_key();
+        self.enable_ownership_key_to(ownership, new_keyring_key, |hex_key| {
+            self.store_ownership_key(ownership, hex_key)
+        })
+    }
+
+    /// [`Self::enable_ownership_key`] with the key and the keyring write supplied by the
+    /// caller, like [`Self::rotate_keys_to`].
+    fn enable_ownership_key_to(
+        &self,
+        ownership: &str,
+        new_keyring_key: [u8; 32],
+        store_keyring_key: impl FnOnce(&str) -> Result<()>,
+    ) -> Result<usize> {
+        if ownership.trim().is_empty() {
+            return Err(anyhow!("Ownership must not be empty"));
+        }
+        if read_lock(&self.ownership_keys).contains_key(ownership) {
+            return Err(anyhow!(
+                "Ownership group \"{}\" already has its own key",
+                ownership
+            ));
+        }
+        let master_key = *self.master_key()?;
+        let new_key = self.with_passphrase_key(new_keyring_key)?;
+
+        let mut groups = self.ownership_key_groups()?;
+        groups.push(ownership.to_string());
+        let mut moved = 0;
+        let mut conn = self.get_conn()?;
+        let tx = conn.transaction()?;
+        self.reencrypt_group(&tx, ownership, &master_key, &new_key, &mut |done, _| {
+            moved = done
+        })?;
+        tx.execute(
+            "INSERT OR REPLACE INTO settings (key, value) VALUES (?1, ?2)",
+            params![OWNERSHIP_KEYS_SETTING, serde_json::to_string(&groups)?],
+        )?;
+        tx.commit()
+            .context("Failed to commit database transaction while separating the key")?;
+
+        self.finish_ownership_key_change(ownership, new_keyring_key, new_key, store_keyring_key)?;
+        tracing::info!(
+            "Ownership group \"{}\" now has its own key ({} accounts moved)",
+            ownership,
+            moved
+        );
+        Ok(moved)
+    }
+
+    /// [`Self::rotate_keys`] for one ownership group with its own key; the master key and
+    /// the other groups are left alone.
+    pub fn rotate_ownership_key(
+        &self,
+        ownership: &str,
+        progress: &mut dyn FnMut(usize, usize),
+    ) -> Result<()> {
+        self.refuse_provided_key()?;
+        let new_keyring_key = self.security.generate_new_master_key();
+        self.rotate_ownership_key_to(ownership, new_keyring_key, progress, |hex_key| {
+            self.store_ownership_key(ownership, hex_key)
+        })
+    }
+
+    fn rotate_ownership_key_to(
+        &self,
+        ownership: &str,
+        new_keyring_key: [u8; 32],
+        progress: &mut dyn FnMut(usize, usize),
+        store_keyring_key: impl FnOnce(&str) -> Result<()>,
+    ) -> Result<()> {
+        if !read_lock(&self.ownership_keys).contains_key(ownership) {
+            return Err(anyhow!(
+                "Ownership group \"{}\" has no key of its own; its credentials use the master key",
+                ownership
+            ));
+        }
+        let old_key = self.key_for_ownership(ownership)?;
+        let new_key = self.with_passphrase_key(new_keyring_key)?;
+
+        let mut conn = self.get_conn()?;
+        let tx = conn.transaction()?;
+        self.reencrypt_group(&tx, ownership, &old_key, &new_key, progress)?;
+        tx.commit()
+            .context("Failed to commit database transaction during key rotation")?;
+
+        self.finish_ownership_key_change(ownership, new_keyring_key, new_key, store_keyring_key)?;
+        tracing::info!("Key of ownership group \"{}\" rotated", ownership);
+        Ok(())
+    }
+
+    /// Stores a group's new keyring key once the database has been committed, then switches
+    /// to the new effective key. Failing here leaves the database ahead of the keyring, so
+    /// the key is shown the same way [`Self::rotate_keys_to`] does.
+    fn finish_ownership_key_change(
+        &self,
+        ownership: &str,
+        new_keyring_key: [u8; 32],
+        new_key: [u8; 32],
+        store_keyring_key: impl FnOnce(&str) -> Result<()>,
+    ) -> Result<()> {
+        let hex_key = hex::encode(new_keyring_key);
+        // Switch first: the database already needs the new key
+        write_lock(&self.ownership_keys).insert(ownership.to_string(), Some(new_key));
+        if let Err(e) = store_keyring_key(&hex_key) {
+            tracing::error!(
+                "CRITICAL: Database updated but the keyring entry of ownership group \"{}\" was not!",
+                ownership
+            );
+            eprintln!("NEW KEY HEX ({}): {}", ownership, hex_key);
+            return Err(anyhow!("Keyring update failed: {}", e));
+        }
+        Ok(())
+    }
+
+    fn store_ownership_key(&self, ownership: &str, hex_key: &str) -> Result<()> {
+        self.security
+            .store_secret(&ownership_key_entry(ownership), hex_key)
+            .map(|_| ())
+            .map_err(|e| anyhow!("{}", e))
+    }
+
+    /// Group keys live in the keyring, which a provided master key is meant to keep out of.
+    fn refuse_provided_key(&self) -> Result<()> {
+        if let KeySource::Provided = self.key_source {
+            return Err(anyhow!(
+                "The master key was supplied with --key-file or {}; ownership keys are kept in the keyring",
+                security::MASTER_KEY_ENV
+            ));
+        }
         Ok(())
     }
+
+    /// `keyring_key` combined with the passphrase key in passphrase mode, as credentials use it.
+    fn with_passphrase_key(&self, keyring_key: [u8; 32]) -> Result<[u8; 32]> {
+        match *read_lock(&self.passphrase_key) {
+            Some(passphrase_key) => security::combine_keys(&keyring_key, &passphrase_key),
+            None => Ok(keyring_key),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Database file in the temp dir, removed (with its WAL files) on drop.
+    struct TempDb(PathBuf);
+
+    impl TempDb {
+        fn new() -> Self {
+            static NEXT: AtomicUsize = AtomicUsize::new(0);
+            TempDb(std::env::temp_dir().join(format!(
+                "mcp-ssh-manager-test-{}-{}.db",
+                std::process::id(),
+                NEXT.fetch_add(1, Ordering::Relaxed)
+            )))
+        }
+    }
+
+    impl Drop for TempDb {
+        fn drop(&mut self) {
+            for suffix in ["", "-wal", "-shm"] {
+                let mut path = self.0.clone().into_os_string();
+                path.push(suffix);
+                let _ = std::fs::remove_file(path);
+            }
+        }
+    }
+
+    /// Opens `db` with a fixed master key instead of the keyring, without the startup
+    /// credential migration.
+    fn open_test_db(db: &TempDb) -> DbHandler {
+        let handler = DbHandler {
+            path: db.0.clone(),
+            security: SecurityManager::new("mcp-ssh-manager-test"),
+            key_source: KeySource::Provided,
+            master_key: RwLock::new([42u8; 32]),
+            passphrase_key: RwLock::new(None),
+            ownership_keys: RwLock::default(),
+            locked: AtomicBool::new(false),
+            _memory_anchor: None,
+        };
+        handler.init_schema(&handler.get_conn().unwrap()).unwrap();
+        handler
+    }
+
+    fn add_test_machine(db: &DbHandler) -> i64 {
+        db.add_machine(Machine {
+            id: None,
+            uuid: None,
+            name: "web-01".to_string(),
+            ip_address: "10.0.0.1".to_string(),
+            purpose: "test".to_string(),
+            ownership: "personal".to_string(),
+            os_type: "linux".to_string(),
+            status: "active".to_string(),
+            group: None,
+            ssh_options: None,
+            jump_host_id: None,
+            requires_vpn: false,
+            env: None,
+        })
+        .unwrap()
+    }
+
+    fn stored_credential(db: &DbHandler, account_id: i64) -> String {
+        db.get_conn()
+            .unwrap()
+            .query_row(
+                "SELECT credential FROM accounts WHERE id = ?1",
+                params![account_id],
+                |row| row.get(0),
+            )
+            .unwrap()
+    }
+
+    #[test]
+    fn test_credentials_round_trip_and_survive_rotation() {
+        let file = TempDb::new();
+        let db = open_test_db(&file);
+        let machine_id = add_test_machine(&db);
+        let account_id = db
+            .add_account(Account {
+                id: None,
+                machine_id,
+                username: "alice".to_string(),
+                auth_type: "password".to_string(),
+                credential: "hunter2".to_string(),
+                key_fingerprint: None,
+                note: None,
+            })
+            .unwrap();
+
+        let listed = db.list_accounts().unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].credential, "[ENCRYPTED/RESTRICTED]");
+        assert_ne!(stored_credential(&db, account_id), "hunter2");
+        assert_eq!(db.reveal_credential(account_id).unwrap(), "hunter2");
+
+        let before = stored_credential(&db, account_id);
+        let mut stored_key = None;
+        db.rotate_keys_to([7u8; 32], &mut |_, _| {}, |hex_key| {
+            stored_key = Some(hex_key.to_string());
+            Ok(())
+        })
+        .unwrap();
+
+        assert_eq!(stored_key.as_deref(), Some(hex::encode([7u8; 32]).as_str()));
+        assert_eq!(*read_lock(&db.master_key), [7u8; 32]);
+        assert_ne!(stored_credential(&db, account_id), before);
+        assert_eq!(db.reveal_credential(account_id).unwrap(), "hunter2");
+        assert!(db.get_setting(SETTING_LAST_KEY_ROTATION).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_in_memory_register_migrate_rotate_reveal() {
+        let db = DbHandler::new_in_memory_with_key([9u8; 32]).unwrap();
+        let machine_id = db
+            .register_machine_with_account(
+                Machine {
+                    id: None,
+                    uuid: None,
+                    name: "mem-01".to_string(),
+                    ip_address: "10.0.0.2".to_string(),
+                    purpose: "test".to_string(),
+                    ownership: "personal".to_string(),
+                    os_type: "linux".to_string(),
+                    status: "active".to_string(),
+                    group: None,
+                    ssh_options: None,
+                    jump_host_id: None,
+                    requires_vpn: false,
+                    env: None,
+                },
+                Account {
+                    id: None,
+                    machine_id: 0,
+                    username: "bob".to_string(),
+                    auth_type: "password".to_string(),
+                    credential: "s3cret".to_string(),
+                    key_fingerprint: None,
+                    note: None,
+                },
+            )
+            .unwrap();
+        let account_id = db.list_accounts_for_machine(machine_id).unwrap()[0]
+            .id
+            .unwrap();
+
+        // A plaintext row left by an old version is planned for encryption, then encrypted
+        db.get_conn()
+            .unwrap()
+            .execute(
+                "INSERT INTO accounts (machine_id, username, auth_type, credential)
+                 VALUES (?1, 'legacy', 'password', 'plain')",
+                params![machine_id],
+            )
+            .unwrap();
+        let plan = db.plan_credential_migration().unwrap();
+        assert_eq!(
+            plan.iter().map(|e| e.action).collect::<Vec<_>>(),
+            [CredentialMigration::Current, CredentialMigration::Encrypt]
+        );
+        db.migrate_credentials().unwrap();
+
+        db.rotate_keys_to([3u8; 32], &mut |_, _| {}, |_| Ok(()))
+            .unwrap();
+        assert_eq!(db.reveal_credential(account_id).unwrap(), "s3cret");
+        assert_eq!(db.reveal_credential(account_id + 1).unwrap(), "plain");
+        assert!(db.rotate_keys(&mut |_, _| {}).is_err());
+
+        // Each handler has its own database
+        let other = DbHandler::new_in_memory_with_key([9u8; 32]).unwrap();
+        assert!(other.list_machines().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_identity_private_key_is_sealed_and_follows_rotation() {
+        let db = DbHandler::new_in_memory_with_key([9u8; 32]).unwrap();
+        assert!(db.identity_private_key().unwrap().is_none());
+        db.set_identity_private_key(Some("PRIVATE KEY")).unwrap();
+        let stored = db
+            .get_setting(IDENTITY_PRIVATE_KEY_SETTING)
+            .unwrap()
+            .unwrap();
+        assert!(!stored.contains("PRIVATE KEY"));
+
+        db.rotate_keys_to([3u8; 32], &mut |_, _| {}, |_| Ok(()))
+            .unwrap();
+        assert_eq!(
+            db.identity_private_key().unwrap().as_deref(),
+            Some("PRIVATE KEY")
+        );
+        db.set_identity_private_key(None).unwrap();
+        assert!(db.identity_private_key().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_ownership_key_separates_and_follows_machines() {
+        let db = DbHandler::new_in_memory_with_key([9u8; 32]).unwrap();
+        let personal_id = add_test_machine(&db);
+        let company_id = db
+            .add_machine(Machine {
+                id: None,
+                uuid: None,
+                name: "corp-01".to_string(),
+                ownership: "company".to_string(),
+                ..db.get_machine(personal_id).unwrap().unwrap()
+            })
+            .unwrap();
+        let add = |machine_id, credential: &str| {
+            db.add_account(Account {
+                id: None,
+                machine_id,
+                username: "alice".to_string(),
+                auth_type: "password".to_string(),
+                credential: credential.to_string(),
+                key_fingerprint: None,
+                note: None,
+            })
+            .unwrap()
+        };
+        let personal = add(personal_id, "home");
+        let company = add(company_id, "work");
+
+        let mut stored_key = None;
+        let moved = db
+            .enable_ownership_key_to("company", [4u8; 32], |hex_key| {
+                stored_key = Some(hex_key.to_string());
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(moved, 1);
+        assert_eq!(stored_key.as_deref(), Some(hex::encode([4u8; 32]).as_str()));
+        assert_eq!(db.ownership_key_groups().unwrap(), ["company"]);
+        // The master key alone no longer opens the company credential
+        assert!(
+            db.unseal(&[9u8; 32], &stored_credential(&db, company))
+                .is_err()
+        );
+        assert_eq!(db.reveal_credential(company).unwrap(), "work");
+        assert!(
+            db.enable_ownership_key_to("company", [5u8; 32], |_| Ok(()))
+                .is_err()
+        );
+
+        // Rotating the master key leaves the group alone, and the other way round
+        let company_before = stored_credential(&db, company);
+        db.rotate_keys_to([7u8; 32], &mut |_, _| {}, |_| Ok(()))
+            .unwrap();
+        assert_eq!(stored_credential(&db, company), company_before);
+        let personal_before = stored_credential(&db, personal);
+        db.rotate_ownership_key_to("company", [6u8; 32], &mut |_, _| {}, |_| Ok(()))
+            .unwrap();
+        assert_eq!(stored_credential(&db, personal), personal_before);
+        assert_ne!(stored_credential(&db, company), company_before);
+        assert!(
+            db.rotate_ownership_key_to("personal", [8u8; 32], &mut |_, _| {}, |_| Ok(()))
+                .is_err()
+        );
+
+        // New accounts and machines moved into the group use its key
+        let second = add(company_id, "work2");
+        assert!(
+            db.unseal(&[6u8; 32], &stored_credential(&db, second))
+                .is_ok()
+        );
+        let mut machine = db.get_machine(personal_id).unwrap().unwrap();
+        machine.ownership = "company".to_string();
+        db.update_machine(&machine).unwrap();
+        assert!(
+            db.unseal(&[6u8; 32], &stored_credential(&db, personal))
+                .is_ok()
+        );
+        assert_eq!(db.reveal_credential(personal).unwrap(), "home");
+        assert_eq!(
+            db.credential_statuses()
+                .unwrap()
+                .values()
+                .collect::<Vec<_>>(),
+            [&CredentialStatus::Set; 3]
+        );
+    }
+
+    #[test]
+    fn test_plan_key_rotation_lists_accounts_without_writing() {
+        let mut db = DbHandler::new_in_memory_with_key([9u8; 32]).unwrap();
+        assert!(db.plan_key_rotation(None).is_err());
+        db.key_source = KeySource::Keyring;
+
+        let machine_id = add_test_machine(&db);
+        let account_id = db
+            .add_account(Account {
+                id: None,
+                machine_id,
+                username: "alice".to_string(),
+                auth_type: "password".to_string(),
+                credential: "hunter2".to_string(),
+                key_fingerprint: None,
+                note: None,
+            })
+            .unwrap();
+        let broken_id = db
+            .add_account(Account {
+                username: "bob".to_string(),
+                ..db.list_accounts_for_machine(machine_id).unwrap()[0].clone()
+            })
+            .unwrap();
+        db.get_conn()
+            .unwrap()
+            .execute(
+                "UPDATE accounts SET credential = ?1 WHERE id = ?2",
+                params![db.seal(&[1u8; 32], "x").unwrap(), broken_id],
+            )
+            .unwrap();
+        let before = stored_credential(&db, account_id);
+
+        assert_eq!(
+            db.plan_key_rotation(None).unwrap(),
+            RotationPlan {
+                accounts: vec![account_id],
+                undecryptable: vec![broken_id],
+            }
+        );
+        assert_eq!(stored_credential(&db, account_id), before);
+        assert!(db.plan_key_rotation(Some("personal")).is_err());
+    }
+
+    #[test]
+    fn test_account_note_round_trip() {
+        let file = TempDb::new();
+        let db = open_test_db(&file);
+        let machine_id = add_test_machine(&db);
+        let account_id = db
+            .add_account(Account {
+                id: None,
+                machine_id,
+                username: "deploy".to_string(),
+                auth_type: "agent".to_string(),
+                credential: String::new(),
+                key_fingerprint: None,
+                note: Some("  deploy user, don't rotate its key ".to_string()),
+            })
+            .unwrap();
+        let note = |db: &DbHandler| db.get_account(account_id).unwrap().unwrap().note;
+        assert_eq!(
+            note(&db).as_deref(),
+            Some("deploy user, don't rotate its key")
+        );
+
+        db.update_account_note(account_id, Some("   ")).unwrap();
+        assert_eq!(note(&db), None);
+        assert!(db.update_account_note(account_id + 1, Some("x")).is_err());
+    }
+
+    #[test]
+    fn test_vault_round_trip_between_databases() {
+        let source_file = TempDb::new();
+        let source = open_test_db(&source_file);
+        let bastion = add_test_machine(&source);
+        let machine_id = source
+            .add_machine(Machine {
+                id: None,
+                uuid: None,
+                name: "db-01".to_string(),
+                ip_address: "10.0.1.5".to_string(),
+                purpose: "test".to_string(),
+                ownership: "company".to_string(),
+                os_type: "linux".to_string(),
+                status: "active".to_string(),
+                group: Some("prod".to_string()),
+                ssh_options: None,
+                jump_host_id: None,
+                requires_vpn: true,
+                env: None,
+            })
+            .unwrap();
+        let mut machine = source.get_machine(machine_id).unwrap().unwrap();
+        machine.jump_host_id = Some(bastion);
+        source.update_machine(&machine).unwrap();
+        source
+            .add_account(Account {
+                id: None,
+                machine_id,
+                username: "postgres".to_string(),
+                auth_type: "password".to_string(),
+                credential: "s3cret".to_string(),
+                key_fingerprint: None,
+                note: Some("replication".to_string()),
+            })
+            .unwrap();
+        source
+            .get_conn()
+            .unwrap()
+            .execute(
+                "INSERT INTO constraints (machine_id, rule_text) VALUES (?1, 'read-only')",
+                params![machine_id],
+            )
+            .unwrap();
+        let vault = source.export_vault().unwrap();
+        let json = serde_json::to_string(&vault).unwrap();
+
+        // A different master key, with one machine already present
+        let dest_file = TempDb::new();
+        let mut dest = open_test_db(&dest_file);
+        dest.master_key = RwLock::new([7u8; 32]);
+        add_test_machine(&dest);
+        let (imported, skipped) = dest
+            .import_vault(&serde_json::from_str(&json).unwrap())
+            .unwrap();
+        assert_eq!((imported, skipped), (1, vec!["web-01".to_string()]));
+
+        let copy = dest
+            .list_machines()
+            .unwrap()
+            .into_iter()
+            .find(|m| m.name == "db-01")
+            .unwrap();
+        assert!(copy.requires_vpn);
+        // Its jump host was skipped, so the link is not carried over
+        assert_eq!(copy.jump_host_id, None);
+        let accounts = dest.list_accounts_for_machine(copy.id.unwrap()).unwrap();
+        assert_eq!(accounts[0].note.as_deref(), Some("replication"));
+        assert_eq!(
+            dest.reveal_credential(accounts[0].id.unwrap()).unwrap(),
+            "s3cret"
+        );
+        assert_eq!(dest.get_constraints(copy.id.unwrap()).unwrap().len(), 1);
+
+        // Into an empty database the jump host comes along and is remapped
+        let fresh_file = TempDb::new();
+        let fresh = open_test_db(&fresh_file);
+        assert_eq!(fresh.import_vault(&vault).unwrap().0, 2);
+        let machines = fresh.list_machines().unwrap();
+        let web = machines.iter().find(|m| m.name == "web-01").unwrap();
+        let db = machines.iter().find(|m| m.name == "db-01").unwrap();
+        assert_eq!(db.jump_host_id, web.id);
+
+        let newer = Vault {
+            format_version: VAULT_FORMAT_VERSION + 1,
+            ..vault
+        };
+        assert!(fresh.import_vault(&newer).is_err());
+    }
+
+    #[test]
+    fn test_diagnostics_are_capped_per_machine() {
+        let file = TempDb::new();
+        let db = open_test_db(&file);
+        let machine_id = add_test_machine(&db);
+        let long_output = format!("{}Permission denied (publickey).", "x".repeat(1000));
+        db.add_diagnostic(machine_id, false, Some("auth_failed"), &long_output)
+            .unwrap();
+        for _ in 0..MAX_DIAGNOSTICS_PER_MACHINE {
+            db.add_diagnostic(machine_id, true, None, "").unwrap();
+        }
+        let diagnostics = db.list_diagnostics(machine_id).unwrap();
+        assert_eq!(diagnostics.len() as i64, MAX_DIAGNOSTICS_PER_MACHINE);
+        assert!(diagnostics.iter().all(|d| d.success));
+        assert_eq!(diagnostics[0].stderr_excerpt, None);
+
+        db.add_diagnostic(machine_id, false, Some("auth_failed"), &long_output)
+            .unwrap();
+        let latest = &db.list_diagnostics(machine_id).unwrap()[0];
+        assert!(!latest.success);
+        assert_eq!(latest.matched_pattern_id.as_deref(), Some("auth_failed"));
+        let excerpt = latest.stderr_excerpt.as_deref().unwrap();
+        assert_eq!(excerpt.chars().count(), DIAGNOSTIC_EXCERPT_CHARS);
+        assert!(excerpt.ends_with("Permission denied (publickey)."));
+    }
+
+    #[test]
+    fn test_no_secrets_mode_keeps_machines_usable() {
+        let file = TempDb::new();
+        let mut db = open_test_db(&file);
+        db.key_source = KeySource::Unavailable(security::KeyringUnavailable {
+            service: "mcp-ssh-manager-test".to_string(),
+            reason: "no Secret Service".to_string(),
+        });
+        let machine_id = add_test_machine(&db);
+        assert_eq!(db.list_machines().unwrap().len(), 1);
+
+        let err = db
+            .add_account(Account {
+                id: None,
+                machine_id,
+                username: "alice".to_string(),
+                auth_type: "password".to_string(),
+                credential: "pw".to_string(),
+                key_fingerprint: None,
+                note: None,
+            })
+            .unwrap_err();
+        assert!(err.downcast_ref::<security::KeyringUnavailable>().is_some());
+        assert!(db.credential_statuses().is_err());
+    }
+
+    #[test]
+    fn test_env_validation_and_exports() {
+        assert_eq!(normalize_env(Some("  ")).unwrap(), None);
+        assert_eq!(normalize_env(Some("{}")).unwrap(), None);
+        assert!(normalize_env(Some("[1]")).is_err());
+        assert!(normalize_env(Some(r#"{"1BAD": "x"}"#)).is_err());
+        assert!(normalize_env(Some(r#"{"A-B": "x"}"#)).is_err());
+        assert!(normalize_env(Some(r#"{"N": 1}"#)).is_err());
+        assert_eq!(
+            env_exports(Some(
+                r#"{"KUBECONFIG": "/etc/kube/config", "_MSG": "it's $HOME"}"#
+            ))
+            .unwrap(),
+            "export KUBECONFIG=/etc/kube/config; export _MSG='it'\\''s $HOME'; "
+        );
+        assert_eq!(env_exports(None).unwrap(), "");
+    }
+
+    #[test]
+    fn test_machine_uuid_survives_rename_and_is_backfilled() {
+        let file = TempDb::new();
+        let db = open_test_db(&file);
+        let machine_id = add_test_machine(&db);
+        let mut machine = db.get_machine(machine_id).unwrap().unwrap();
+        let uuid = machine.uuid.clone().unwrap();
+        assert_eq!(uuid.len(), 36);
+        assert_eq!(&uuid[14..15], "4");
+
+        machine.name = "web-01-renamed".to_string();
+        machine.uuid = Some("ignored".to_string());
+        db.update_machine(&machine).unwrap();
+        let renamed = db.get_machine_by_uuid(&uuid).unwrap().unwrap();
+        assert_eq!(renamed.name, "web-01-renamed");
+
+        let conn = db.get_conn().unwrap();
+        conn.execute("UPDATE machines SET uuid = NULL", []).unwrap();
+        db.init_schema(&conn).unwrap();
+        let backfilled = db.get_machine(machine_id).unwrap().unwrap().uuid;
+        assert!(backfilled.is_some_and(|u| u != uuid));
+    }
+
+    #[test]
+    fn test_credential_status() {
+        let file = TempDb::new();
+        let db = open_test_db(&file);
+        let machine_id = add_test_machine(&db);
+        let add = |auth_type: &str, credential: &str| {
+            db.add_account(Account {
+                id: None,
+                machine_id,
+                username: "alice".to_string(),
+                auth_type: auth_type.to_string(),
+                credential: credential.to_string(),
+                key_fingerprint: None,
+                note: None,
+            })
+            .unwrap()
+        };
+        let password = add("password", "hunter2");
+        let agent = add("agent", "");
+        let tpm = add("tpm", TPM_CREDENTIAL_MARKER);
+        let foreign = add("password", "x");
+        let sealed = db.security.encrypt(&[1u8; 32], "elsewhere").unwrap();
+        db.get_conn()
+            .unwrap()
+            .execute(
+                "UPDATE accounts SET credential = ?1 WHERE id = ?2",
+                params![sealed, foreign],
+            )
+            .unwrap();
+
+        assert_eq!(
+            db.credential_status(password).unwrap(),
+            CredentialStatus::Set
+        );
+        assert_eq!(
+            db.credential_status(agent).unwrap(),
+            CredentialStatus::Empty
+        );
+        assert_eq!(
+            db.credential_status(tpm).unwrap(),
+            CredentialStatus::HardwareBound
+        );
+        assert_eq!(
+            db.credential_status(foreign).unwrap(),
+            CredentialStatus::Corrupt
+        );
+        assert!(db.credential_status(foreign + 1).is_err());
+        assert_eq!(db.credential_statuses().unwrap().len(), 4);
+    }
+
+    #[test]
+    fn test_delete_machine_cascades_to_accounts_and_constraints() {
+        let file = TempDb::new();
+        let db = open_test_db(&file);
+        let machine_id = add_test_machine(&db);
+        db.add_account(Account {
+            id: None,
+            machine_id,
+            username: "alice".to_string(),
+            auth_type: "password".to_string(),
+            credential: "hunter2".to_string(),
+            key_fingerprint: None,
+            note: None,
+        })
+        .unwrap();
+        let conn = db.get_conn().unwrap();
+        conn.execute(
+            "INSERT INTO constraints (machine_id, rule_text) VALUES (?1, 'deny-write /etc')",
+            params![machine_id],
+        )
+        .unwrap();
+        conn.execute(
+            "INSERT INTO command_logs (machine_id, username, command) VALUES (?1, 'alice', 'ls')",
+            params![machine_id],
+        )
+        .unwrap();
+
+        let snapshot = db.delete_machine(machine_id).unwrap();
+        assert_eq!(snapshot.accounts.len(), 1);
+        assert_eq!(snapshot.constraints.len(), 1);
+        let count = |table: &str| -> i64 {
+            conn.query_row(&format!("SELECT COUNT(*) FROM {}", table), [], |row| {
+                row.get(0)
+            })
+            .unwrap()
+        };
+        assert_eq!(count("accounts"), 0);
+        assert_eq!(count("constraints"), 0);
+        // Logs are kept for a later restore
+        assert_eq!(count("command_logs"), 1);
+    }
+
+    #[test]
+    fn test_lock_wipes_keys_until_unlocked_with_the_passphrase() {
+        let file = TempDb::new();
+        let db = open_test_db(&file);
+        let machine_id = add_test_machine(&db);
+        let account_id = db
+            .add_account(Account {
+                id: None,
+                machine_id,
+                username: "alice".to_string(),
+                auth_type: "password".to_string(),
+                credential: "hunter2".to_string(),
+                key_fingerprint: None,
+                note: None,
+            })
+            .unwrap();
+
+        db.lock();
+        assert!(db.is_locked());
+        assert!(!db.master_key_loaded());
+        assert!(db.reveal_credential(account_id).is_err());
+        // Everything without a credential still works
+        assert_eq!(db.list_machines().unwrap().len(), 1);
+        db.unlock_with([42u8; 32], None).unwrap();
+        assert_eq!(db.reveal_credential(account_id).unwrap(), "hunter2");
+
+        db.enable_passphrase("correct horse").unwrap();
+        db.lock();
+        assert!(db.passphrase_enabled());
+        assert!(db.unlock_with([42u8; 32], None).is_err());
+        assert!(db.unlock_with([42u8; 32], Some("wrong")).is_err());
+        assert!(db.is_locked());
+        db.unlock_with([42u8; 32], Some("correct horse")).unwrap();
+        assert!(!db.is_locked());
+        assert_eq!(db.reveal_credential(account_id).unwrap(), "hunter2");
+    }
+
+    #[test]
+    fn test_passphrase_from_xor_combined_keys_is_upgraded_on_unlock() {
+        let file = TempDb::new();
+        let db = open_test_db(&file);
+        let machine_id = add_test_machine(&db);
+        let account_id = db
+            .add_account(Account {
+                id: None,
+                machine_id,
+                username: "alice".to_string(),
+                auth_type: "password".to_string(),
+                credential: "hunter2".to_string(),
+                key_fingerprint: None,
+                note: None,
+            })
+            .unwrap();
+        // Passphrase mode as the first release set it up
+        let salt = security::generate_salt();
+        let passphrase_key = security::derive_passphrase_key("correct horse", &salt).unwrap();
+        let legacy = security::legacy_combine_keys(&[42u8; 32], &passphrase_key);
+        let mut conn = db.get_conn().unwrap();
+        let tx = conn.transaction().unwrap();
+        db.reencrypt_all(&tx, &[42u8; 32], &legacy, &mut |_, _| {})
+            .unwrap();
+        tx.commit().unwrap();
+        db.set_setting(PASSPHRASE_SALT_SETTING, &hex::encode(salt))
+            .unwrap();
+        db.set_setting(
+            PASSPHRASE_CHECK_SETTING,
+            &db.seal(&legacy, PASSPHRASE_CHECK_VALUE).unwrap(),
+        )
+        .unwrap();
+
+        assert!(db.apply_passphrase(&[42u8; 32], "wrong").is_err());
+        db.apply_passphrase(&[42u8; 32], "correct horse").unwrap();
+        assert_eq!(db.reveal_credential(account_id).unwrap(), "hunter2");
+        let effective = security::combine_keys(&[42u8; 32], &passphrase_key).unwrap();
+        assert_eq!(
+            db.unseal(&effective, &stored_credential(&db, account_id))
+                .unwrap(),
+            "hunter2"
+        );
+        let check = db.get_setting(PASSPHRASE_CHECK_SETTING).unwrap().unwrap();
+        assert!(db.unseal(&legacy, &check).is_err());
+    }
+
+    #[test]
+    fn test_clone_machine_copies_settings_and_constraints_but_not_accounts() {
+        let file = TempDb::new();
+        let db = open_test_db(&file);
+        let source_id = add_test_machine(&db);
+        let mut source = db.get_machine(source_id).unwrap().unwrap();
+        source.group = Some("cluster".to_string());
+        source.ssh_options = Some("-p 2222".to_string());
+        source.status = "broken".to_string();
+        db.update_machine(&source).unwrap();
+        db.add_account(Account {
+            id: None,
+            machine_id: source_id,
+            username: "alice".to_string(),
+            auth_type: "password".to_string(),
+            credential: "hunter2".to_string(),
+            key_fingerprint: None,
+            note: None,
+        })
+        .unwrap();
+        db.get_conn()
+            .unwrap()
+            .execute(
+                "INSERT INTO constraints (machine_id, rule_text) VALUES (?1, 'deny-write /etc')",
+                params![source_id],
+            )
+            .unwrap();
+
+        let clone_id = db.clone_machine(source_id, "web-02", "10.0.0.2").unwrap();
+        let clone = db.get_machine(clone_id).unwrap().unwrap();
+        assert_eq!(clone.name, "web-02");
+        assert_eq!(clone.ip_address, "10.0.0.2");
+        assert_eq!(clone.purpose, source.purpose);
+        assert_eq!(clone.ownership, source.ownership);
+        assert_eq!(clone.group.as_deref(), Some("cluster"));
+        assert_eq!(clone.ssh_options.as_deref(), Some("-p 2222"));
+        assert_eq!(clone.status, "active");
+        assert_ne!(clone.uuid, source.uuid);
+        let rules: Vec<String> = db
+            .get_constraints(clone_id)
+            .unwrap()
+            .into_iter()
+            .map(|c| c.rule_text)
+            .collect();
+        assert_eq!(rules, ["deny-write /etc"]);
+        assert!(db.list_accounts_for_machine(clone_id).unwrap().is_empty());
+
+        let err = db
+            .clone_machine(source_id, &source.name, "10.0.0.3")
+            .unwrap_err();
+        assert!(err.downcast_ref::<DuplicateMachineName>().is_some());
+        assert!(db.clone_machine(source_id, " ", "10.0.0.3").is_err());
+        assert!(db.clone_machine(9999, "web-03", "10.0.0.3").is_err());
+    }
+
+    #[test]
+    fn test_jump_host_rejects_cycles_and_clears_on_delete() {
+        let file = TempDb::new();
+        let db = open_test_db(&file);
+        let bastion_id = add_test_machine(&db);
+        let mut target = db.get_machine(bastion_id).unwrap().unwrap();
+        target.id = None;
+        target.name = "db-01".to_string();
+        target.jump_host_id = Some(bastion_id);
+        let mut orphan = target.clone();
+        orphan.name = "db-02".to_string();
+        orphan.jump_host_id = Some(9999);
+        let err = db.add_machine(orphan).unwrap_err();
+        assert!(err.downcast_ref::<InvalidJumpHost>().is_some());
+        let target_id = db.add_machine(target).unwrap();
+        // The copied uuid isn't reused
+        assert_ne!(
+            db.get_machine(target_id).unwrap().unwrap().uuid,
+            db.get_machine(bastion_id).unwrap().unwrap().uuid
+        );
+
+        let mut bastion = db.get_machine(bastion_id).unwrap().unwrap();
+        bastion.jump_host_id = Some(target_id);
+        assert!(db.update_machine(&bastion).is_err());
+        bastion.jump_host_id = Some(bastion_id);
+        assert!(db.update_machine(&bastion).is_err());
+
+        let snapshot = db.delete_machine(bastion_id).unwrap();
+        let target = db.get_machine(target_id).unwrap().unwrap();
+        assert_eq!(target.jump_host_id, None);
+        assert_eq!(snapshot.jump_dependents, [target_id]);
+
+        db.restore_machine_snapshot(&snapshot).unwrap();
+        let target = db.get_machine(target_id).unwrap().unwrap();
+        assert_eq!(target.jump_host_id, Some(bastion_id));
+    }
+
+    #[test]
+    fn test_rebuild_child_tables_adds_cascade_and_drops_orphans() {
+        let file = TempDb::new();
+        let conn = Connection::open(&file.0).unwrap();
+        // Old databases got their orphans while foreign keys weren't enforced
+        conn.execute_batch(
+            "PRAGMA foreign_keys = OFF;
+             CREATE TABLE machines (id INTEGER PRIMARY KEY AUTOINCREMENT, name TEXT NOT NULL UNIQUE,
+                ip_address TEXT NOT NULL, purpose TEXT NOT NULL, ownership TEXT NOT NULL,
+                os_type TEXT NOT NULL, status TEXT NOT NULL DEFAULT 'active', group_name TEXT);
+             CREATE TABLE accounts (id INTEGER PRIMARY KEY AUTOINCREMENT, machine_id INTEGER NOT NULL,
+                username TEXT NOT NULL, auth_type TEXT NOT NULL, credential TEXT NOT NULL,
+                FOREIGN KEY(machine_id) REFERENCES machines(id));
+             INSERT INTO machines (id, name, ip_address, purpose, ownership, os_type)
+                VALUES (1, 'web-01', '10.0.0.1', 'test', 'personal', 'linux');
+             INSERT INTO accounts (machine_id, username, auth_type, credential)
+                VALUES (1, 'alice', 'agent', ''), (99, 'orphan', 'agent', '');",
+        )
+        .unwrap();
+        drop(conn);
+
+        let db = open_test_db(&file);
+        let conn = db.get_conn().unwrap();
+        let on_delete: String = conn
+            .query_row(
+                "SELECT on_delete FROM pragma_foreign_key_list('accounts')",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(on_delete, "CASCADE");
+        let users: Vec<String> = conn
+            .prepare("SELECT username FROM accounts")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(users, ["alice"]);
+
+        conn.execute("DELETE FROM machines WHERE id = 1", [])
+            .unwrap();
+        assert!(db.list_accounts().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_register_machine_with_account_rolls_back_machine() {
+        let file = TempDb::new();
+        let db = open_test_db(&file);
+        let machine = Machine {
+            id: None,
+            uuid: None,
+            name: "web-01".to_string(),
+            ip_address: "10.0.0.1".to_string(),
+            purpose: "test".to_string(),
+            ownership: "personal".to_string(),
+            os_type: "linux".to_string(),
+            status: "active".to_string(),
+            group: None,
+            ssh_options: None,
+            jump_host_id: None,
+            requires_vpn: false,
+            env: None,
+        };
+        let account = Account {
+            id: None,
+            machine_id: 0,
+            username: "alice".to_string(),
+            auth_type: "password".to_string(),
+            credential: "hunter2".to_string(),
+            key_fingerprint: None,
+            note: None,
+        };
+        // Fails the account insert after the machine row has been written
+        db.get_conn()
+            .unwrap()
+            .execute_batch(
+                "CREATE TRIGGER reject_accounts BEFORE INSERT ON accounts
+                 BEGIN SELECT RAISE(ABORT, 'rejected'); END;",
+            )
+            .unwrap();
+        assert!(
+            db.register_machine_with_account(machine.clone(), account.clone())
+                .is_err()
+        );
+        assert!(db.list_machines().unwrap().is_empty());
+
+        db.get_conn()
+            .unwrap()
+            .execute_batch("DROP TRIGGER reject_accounts;")
+            .unwrap();
+        let machine_id = db.register_machine_with_account(machine, account).unwrap();
+        let accounts = db.list_accounts().unwrap();
+        assert_eq!(accounts.len(), 1);
+        assert_eq!(accounts[0].machine_id, machine_id);
+    }
+
+    #[test]
+    fn test_key_rotation_due() {
+        let now = chrono::NaiveDateTime::parse_from_str("2024-06-30 12:00:00", "%Y-%m-%d %H:%M:%S")
+            .unwrap();
+        assert!(key_rotation_due(None, 90, now));
+        assert!(!key_rotation_due(None, 0, now));
+        assert!(!key_rotation_due(Some("2024-04-02 12:00:01"), 90, now));
+        assert!(key_rotation_due(Some("2024-04-01 12:00:00"), 90, now));
+        assert!(key_rotation_due(Some("garbage"), 90, now));
+    }
+
+    #[test]
+    fn test_migrate_credentials_is_idempotent() {
+        let file = TempDb::new();
+        let db = open_test_db(&file);
+        let machine_id = add_test_machine(&db);
+        let conn = db.get_conn().unwrap();
+        conn.execute(
+            "INSERT INTO accounts (machine_id, username, auth_type, credential)
+             VALUES (?1, 'alice', 'password', 'hunter2')",
+            params![machine_id],
+        )
+        .unwrap();
+        let plaintext_id = conn.last_insert_rowid();
+        // Sealed under some other master key: must never be encrypted a second time
+        let foreign = db.security.encrypt(&[1u8; 32], "elsewhere").unwrap();
+        conn.execute(
+            "INSERT INTO accounts (machine_id, username, auth_type, credential)
+             VALUES (?1, 'bob', 'password', ?2)",
+            params![machine_id, foreign],
+        )
+        .unwrap();
+        let foreign_id = conn.last_insert_rowid();
+
+        db.migrate_credentials().unwrap();
+        let first = stored_credential(&db, plaintext_id);
+        assert_ne!(first, "hunter2");
+        assert_eq!(db.reveal_credential(plaintext_id).unwrap(), "hunter2");
+
+        db.migrate_credentials().unwrap();
+        assert_eq!(stored_credential(&db, plaintext_id), first);
+        assert_eq!(db.reveal_credential(plaintext_id).unwrap(), "hunter2");
+        assert_eq!(stored_credential(&db, foreign_id), foreign);
+    }
+
+    #[test]
+    fn test_migration_encrypts_under_the_group_key() {
+        let file = TempDb::new();
+        let db = open_test_db(&file);
+        let machine_id = add_test_machine(&db);
+        let ownership = db.get_machine(machine_id).unwrap().unwrap().ownership;
+        write_lock(&db.ownership_keys).insert(ownership, Some([7u8; 32]));
+        let conn = db.get_conn().unwrap();
+        conn.execute(
+            "INSERT INTO accounts (machine_id, username, auth_type, credential)
+             VALUES (?1, 'alice', 'password', 'hunter2')",
+            params![machine_id],
+        )
+        .unwrap();
+        let account_id = conn.last_insert_rowid();
+
+        db.migrate_credentials().unwrap();
+        assert_eq!(db.reveal_credential(account_id).unwrap(), "hunter2");
+        let plan = db.plan_credential_migration().unwrap();
+        assert_eq!(plan[0].action, CredentialMigration::Current);
+    }
+
+    #[test]
+    fn test_migration_tags_unprefixed_ciphertext() {
+        let file = TempDb::new();
+        let db = open_test_db(&file);
+        let machine_id = add_test_machine(&db);
+        let key = *db.master_key().unwrap();
+        assert!(db.seal(&key, "x").unwrap().starts_with(CIPHERTEXT_PREFIX));
+
+        // Written before the prefix existed: ours, but untagged
+        let legacy = db.security.encrypt(&key, "hunter2").unwrap();
+        let conn = db.get_conn().unwrap();
+        conn.execute(
+            "INSERT INTO accounts (machine_id, username, auth_type, credential)
+             VALUES (?1, 'alice', 'password', ?2)",
+            params![machine_id, legacy],
+        )
+        .unwrap();
+        let legacy_id = conn.last_insert_rowid();
+        assert_eq!(db.reveal_credential(legacy_id).unwrap(), "hunter2");
+        let plan = db.plan_credential_migration().unwrap();
+        assert_eq!(plan[0].action, CredentialMigration::AddPrefix);
+
+        db.migrate_credentials().unwrap();
+        assert_eq!(
+            stored_credential(&db, legacy_id),
+            format!("{}{}", CIPHERTEXT_PREFIX, legacy)
+        );
+        assert_eq!(db.reveal_credential(legacy_id).unwrap(), "hunter2");
+        let plan = db.plan_credential_migration().unwrap();
+        assert_eq!(plan[0].action, CredentialMigration::Current);
+    }
+
+    #[test]
+    fn test_global_search_spans_tables() {
+        let file = TempDb::new();
+        let db = open_test_db(&file);
+        let machine_id = add_test_machine(&db);
+        db.add_account(Account {
+            id: None,
+            machine_id,
+            username: "deploy".to_string(),
+            auth_type: "password".to_string(),
+            credential: "web-secret".to_string(),
+            key_fingerprint: None,
+            note: Some("used by CI".to_string()),
+        })
+        .unwrap();
+        db.add_log(
+            machine_id,
+            "deploy",
+            "systemctl restart web",
+            None,
+            None,
+            Some(0),
+        )
+        .unwrap();
+        db.get_conn()
+            .unwrap()
+            .execute(
+                "INSERT INTO constraints (machine_id, rule_text) VALUES (?1, 'no restarts 100%')",
+                params![machine_id],
+            )
+            .unwrap();
+
+        let results = db.global_search("WEB").unwrap();
+        assert_eq!(results.machines.len(), 1);
+        assert_eq!(results.logs.len(), 1);
+        // Credentials aren't searched
+        assert!(results.accounts.is_empty());
+        let results = db.global_search("deploy").unwrap();
+        assert_eq!(results.accounts.len(), 1);
+        assert_eq!(results.accounts[0].credential, "[ENCRYPTED/RESTRICTED]");
+        assert_eq!(results.logs.len(), 1);
+        assert!(results.machines.is_empty());
+        assert_eq!(db.global_search("100%").unwrap().constraints.len(), 1);
+        assert!(db.global_search("0_").unwrap().is_empty());
+        assert!(db.global_search("  ").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_export_logs_oldest_first_since() {
+        let file = TempDb::new();
+        let db = open_test_db(&file);
+        let machine_id = add_test_machine(&db);
+        let conn = db.get_conn().unwrap();
+        for (command, timestamp) in [
+            ("uptime", "2024-05-02 08:00:00"),
+            ("ls", "2024-05-01 09:00:00"),
+            ("df -h", "2024-04-30 23:59:59"),
+        ] {
+            conn.execute(
+                "INSERT INTO command_logs (machine_id, username, command, timestamp)
+                 VALUES (?1, 'alice', ?2, ?3)",
+                params![machine_id, command, timestamp],
+            )
+            .unwrap();
+        }
+
+        let commands = |logs: Vec<CommandLog>| -> Vec<String> {
+            logs.into_iter().map(|l| l.command).collect()
+        };
+        assert_eq!(
+            commands(db.export_logs(None, None).unwrap()),
+            ["df -h", "ls", "uptime"]
+        );
+        assert_eq!(
+            commands(db.export_logs(Some("2024-05-01"), None).unwrap()),
+            ["ls", "uptime"]
+        );
+        let limited = db.export_logs(None, Some(1)).unwrap();
+        assert_eq!(limited.len(), 1);
+        assert_eq!(limited[0].machine_name, "web-01");
+    }
+
+    #[test]
+    fn test_schema_version() {
+        let file = TempDb::new();
+        let conn = Connection::open(&file.0).unwrap();
+        assert_eq!(schema_version(&conn).unwrap(), 0);
+
+        let db = open_test_db(&file);
+        assert_eq!(schema_version(&conn).unwrap(), 0);
+        db.set_schema_version(SCHEMA_VERSION).unwrap();
+        assert_eq!(schema_version(&conn).unwrap(), SCHEMA_VERSION);
+        db.set_setting(SCHEMA_VERSION_SETTING, "two").unwrap();
+        assert!(schema_version(&conn).is_err());
+    }
+
+    #[test]
+    fn test_poisoned_lock_is_still_usable() {
+        let lock = std::sync::Arc::new(RwLock::new([7u8; 32]));
+        let poisoner = std::sync::Arc::clone(&lock);
+        let _ = std::thread::spawn(move || {
+            let _guard = poisoner.write().unwrap();
+            panic!("poison the lock");
+        })
+        .join();
+        assert!(lock.is_poisoned());
+
+        assert_eq!(*read_lock(&lock), [7u8; 32]);
+        *write_lock(&lock) = [9u8; 32];
+        assert_eq!(*read_lock(&lock), [9u8; 32]);
+    }
+
+    #[test]
+    fn test_looks_like_ciphertext() {
+        let hex_sample = "00".repeat(28);
+        assert!(looks_like_ciphertext(&"ab".repeat(40), &hex_sample));
+        assert!(!looks_like_ciphertext("hunter2", &hex_sample));
+        assert!(!looks_like_ciphertext(
+            "/home/alice/.ssh/id_ed25519_work_laptop_2024_rotation",
+            &hex_sample
+        ));
+
+        let b64_sample = "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA==";
+        assert!(looks_like_ciphertext(
+            "q2VyeS1sb25nLWNpcGhlcnRleHQtYmxvYi1oZXJlLXRvbw==",
+            b64_sample
+        ));
+        assert!(!looks_like_ciphertext("TPM_HARDWARE_BOUND", b64_sample));
+        assert!(!looks_like_ciphertext(
+            "~/.ssh/id_ed25519 with a long enough path to pass",
+            b64_sample
+        ));
+    }
+
+    #[test]
+    fn test_is_valid_host() {
+        for host in [
+            "192.168.1.10",
+            "::1",
+            "fe80::1",
+            "example.com",
+            "web-01.internal",
+            "localhost",
+            "host.example.",
+        ] {
+            assert!(is_valid_host(host), "{} should be accepted", host);
+        }
+        for host in [
+            "",
+            "htttp://host",
+            "host name",
+            "-bad.example",
+            "bad-.example",
+            "a..b",
+            "192.168.1.300",
+            "user@host",
+        ] {
+            assert!(!is_valid_host(host), "{} should be rejected", host);
+        }
+    }
+
+    #[test]
+    fn test_os_type_normalizes_variants() {
+        assert_eq!("Linux".parse::<OsType>().unwrap(), OsType::Linux);
+        assert_eq!("ubuntu".parse::<OsType>().unwrap(), OsType::Linux);
+        assert_eq!(" Windows ".parse::<OsType>().unwrap(), OsType::Windows);
+        assert_eq!("darwin".parse::<OsType>().unwrap(), OsType::MacOS);
+        assert!("beos".parse::<OsType>().is_err());
+        for os in OsType::ALL {
+            assert_eq!(os.to_string().parse::<OsType>().unwrap(), os);
+        }
+    }
+
+    #[test]
+    fn test_auth_type_normalizes_variants() {
+        assert_eq!("Password".parse::<AuthType>().unwrap(), AuthType::Password);
+        assert_eq!(" ssh-key ".parse::<AuthType>().unwrap(), AuthType::Key);
+        assert_eq!("SSH-Agent".parse::<AuthType>().unwrap(), AuthType::Agent);
+        assert!("kerberos".parse::<AuthType>().is_err());
+        for auth in [
+            AuthType::Password,
+            AuthType::Key,
+            AuthType::Agent,
+            AuthType::Tpm,
+        ] {
+            assert_eq!(auth.to_string().parse::<AuthType>().unwrap(), auth);
+        }
+    }
+
+    #[test]
+    fn test_normalize_host_trims() {
+        assert_eq!(normalize_host("  10.0.0.1 \n").unwrap(), "10.0.0.1");
+        assert!(normalize_host("bad host").is_err());
+        assert_eq!(normalize_host("[fe80::1]").unwrap(), "fe80::1");
+        assert!(normalize_host("[example.com]").is_err());
+    }
 }
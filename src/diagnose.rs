@@ -0,0 +1,552 @@
+use crate::db::{Account, AuthType, DbHandler, Machine};
+use crate::health;
+use crate::knowledge::{self, Suggestion};
+use crate::known_hosts;
+use crate::ssh_worker;
+use crate::terminal_text;
+use anyhow::{Result, anyhow};
+use std::fmt::Write;
+use std::time::Duration;
+
+/// `ConnectTimeout` used for the diagnose probe.
+const DIAGNOSE_CONNECT_TIMEOUT_SECS: u64 = 5;
+/// Prefix of the command recorded in `command_logs` for each diagnose probe; the probe
+/// command itself follows.
+const PROBE_LOG_PREFIX: &str = "[diagnose] ssh -v -o BatchMode=yes";
+/// Command recorded in `command_logs` for the optional sudo check.
+const SUDO_LOG_COMMAND: &str = "[diagnose] sudo -n true";
+/// Most accounts [`run_accounts`] probes for one machine.
+pub const MAX_ACCOUNTS: usize = 5;
+/// Limit for [`test_credentials`] as a whole: connecting, logging in, and the probe.
+pub const CREDENTIAL_TEST_TIMEOUT_SECS: u64 = 10;
+
+pub enum Outcome {
+    Success,
+    /// The host key isn't trusted yet; the fingerprints need confirming first.
+    HostKeyUnknown {
+        fingerprints: Vec<String>,
+    },
+    /// Any other failure, with the matching knowledge-base hint if there is one.
+    Failed {
+        suggestion: Option<Suggestion>,
+    },
+}
+
+/// Whether passwordless sudo works for the account, checked once the login succeeded.
+pub struct SudoCheck {
+    pub passwordless: bool,
+    /// stderr of `sudo -n true`
+    pub output: String,
+    /// Knowledge-base hint when sudo failed
+    pub suggestion: Option<Suggestion>,
+}
+
+/// Result of a verbose ssh probe against one machine/account.
+pub struct Diagnosis {
+    pub machine: Machine,
+    pub account_id: Option<i64>,
+    pub username: String,
+    /// Full `ssh -v` stderr.
+    pub stderr: String,
+    pub outcome: Outcome,
+    /// Present when the sudo check was requested and the connection succeeded
+    pub sudo: Option<SudoCheck>,
+    /// Present when the password hint was requested, a key account was rejected, and a
+    /// password account exists for the same user on the machine
+    pub password_fallback: Option<Suggestion>,
+}
+
+/// Probes `accounts` one after another (at most [`MAX_ACCOUNTS`]) until one connects.
+/// Stops early on an untrusted host key, which every account would run into alike.
+pub fn run_accounts(
+    db: &DbHandler,
+    machine: &Machine,
+    accounts: &[Account],
+    check_sudo: bool,
+    password_hint: bool,
+) -> Result<Vec<Diagnosis>> {
+    let mut results = Vec::new();
+    for account in accounts.iter().take(MAX_ACCOUNTS) {
+        let diagnosis = run(db, machine.clone(), account, check_sudo, password_hint)?;
+        let conclusive = !matches!(diagnosis.outcome, Outcome::Failed { .. });
+        results.push(diagnosis);
+        if conclusive {
+            break;
+        }
+    }
+    Ok(results)
+}
+
+/// Probes the machine and records the attempt in `command_logs` as an audit trail and in
+/// the machine's diagnose history.
+/// With `check_sudo`, a successful login is followed by `sudo -n true`. With
+/// `password_hint`, a rejected key account gets a [`CATEGORY_KEY_REJECTED`] hint when a
+/// password is stored for the same user; the password itself is never tried.
+///
+/// [`CATEGORY_KEY_REJECTED`]: knowledge::CATEGORY_KEY_REJECTED
+pub fn run(
+    db: &DbHandler,
+    machine: Machine,
+    account: &Account,
+    check_sudo: bool,
+    password_hint: bool,
+) -> Result<Diagnosis> {
+    tracing::info!(
+        "Diagnosing connection to {}@{}",
+        account.username,
+        machine.ip_address
+    );
+    health::check_vpn(&machine)?;
+
+    let known_hosts = known_hosts::active_file(db);
+    let output = health::ssh_probe(
+        &account.username,
+        &machine.ip_address,
+        &machine.os_type,
+        true,
+        DIAGNOSE_CONNECT_TIMEOUT_SECS,
+        known_hosts.as_deref(),
+        &health::machine_ssh_args(db, &machine)?,
+    )
+    .map_err(|e| anyhow!("Failed to execute ssh command: {}", e))?;
+    let stderr = terminal_text::decode(&output.stderr);
+
+    log_probe(
+        db,
+        &machine,
+        account,
+        &format!(
+            "{} {}",
+            PROBE_LOG_PREFIX,
+            health::probe_command_for(&machine.os_type).join(" ")
+        ),
+        &output.stderr,
+        output.status.code(),
+    );
+
+    let outcome = if output.status.success() {
+        Outcome::Success
+    } else if known_hosts::is_host_key_failure(&stderr) {
+        let scanned = known_hosts::scan_host_key(&machine.ip_address, health::ssh_port(&machine)?)?;
+        Outcome::HostKeyUnknown {
+            fingerprints: scanned.fingerprints,
+        }
+    } else {
+        let signing_key = db.get_setting(knowledge::SETTING_SIGNING_KEY)?;
+        let patterns = knowledge::load_troubleshooting_patterns(signing_key.as_deref());
+        Outcome::Failed {
+            suggestion: knowledge::match_error_pattern(&stderr, &machine.os_type, &patterns),
+        }
+    };
+
+    record_diagnostic(db, &machine, &outcome, &stderr);
+
+    let sudo = match outcome {
+        Outcome::Success if check_sudo => Some(run_sudo_check(db, &machine, account)?),
+        _ => None,
+    };
+    let password_fallback = match outcome {
+        Outcome::Failed { .. }
+            if password_hint && has_password_fallback(db, &machine, account)? =>
+        {
+            let signing_key = db.get_setting(knowledge::SETTING_SIGNING_KEY)?;
+            let patterns = knowledge::load_troubleshooting_patterns(signing_key.as_deref());
+            knowledge::match_key_rejected_pattern(&stderr, &machine.os_type, &patterns)
+        }
+        _ => None,
+    };
+
+    Ok(Diagnosis {
+        machine,
+        account_id: account.id,
+        username: account.username.clone(),
+        stderr,
+        outcome,
+        sudo,
+        password_fallback,
+    })
+}
+
+/// Result of [`test_credentials`].
+pub enum CredentialTest {
+    Success,
+    /// Login or the probe failed, with a knowledge-base hint if one matches
+    Failed {
+        message: String,
+        suggestion: Option<Suggestion>,
+    },
+    /// Nothing came back within [`CREDENTIAL_TEST_TIMEOUT_SECS`]
+    TimedOut,
+}
+
+/// Logs in with a credential that hasn't been saved yet (`account.credential` is
+/// plaintext) and runs the probe, so a typo'd password or username shows up while the
+/// form is still open. Goes through the SSH engine like `run_command`, since OpenSSH's
+/// batch mode can't take a password. Nothing is logged or recorded.
+pub fn test_credentials(
+    db: &DbHandler,
+    machine: &Machine,
+    account: &Account,
+) -> Result<CredentialTest> {
+    health::check_vpn(machine)?;
+    let (key_path, password) = match account.auth_type.parse::<AuthType>()? {
+        AuthType::Password => (None, Some(account.credential.clone())),
+        AuthType::Key => {
+            let path = crate::db::expand_home(&account.credential);
+            if known_hosts::key_needs_passphrase(&path) {
+                return Err(anyhow!(
+                    "Key file {} is passphrase-protected and can't be tested here",
+                    path.display()
+                ));
+            }
+            (Some(path.to_string_lossy().into_owned()), None)
+        }
+        AuthType::Agent | AuthType::Tpm => (None, None),
+    };
+    let mut job = ssh_worker::Job {
+        host: machine.ip_address.clone(),
+        port: health::DEFAULT_SSH_PORT,
+        username: account.username.clone(),
+        key_path,
+        password,
+        command: health::probe_command_for(&machine.os_type).join(" "),
+    };
+    let result = ssh_worker::run(&job, Duration::from_secs(CREDENTIAL_TEST_TIMEOUT_SECS));
+    if let Some(password) = job.password.as_mut() {
+        password.clear();
+    }
+
+    let message = match result {
+        Ok(None) => return Ok(CredentialTest::TimedOut),
+        Ok(Some(output)) if output.exit_code == 0 => return Ok(CredentialTest::Success),
+        Ok(Some(output)) => format!("exit code {}: {}", output.exit_code, output.stderr.trim()),
+        // The engine reports failed logins as errors
+        Err(e) => e.to_string(),
+    };
+    let signing_key = db.get_setting(knowledge::SETTING_SIGNING_KEY)?;
+    let patterns = knowledge::load_troubleshooting_patterns(signing_key.as_deref());
+    Ok(CredentialTest::Failed {
+        suggestion: knowledge::match_error_pattern(&message, &machine.os_type, &patterns),
+        message,
+    })
+}
+
+/// Whether `account` authenticates with a key and the machine also has a password
+/// account for the same user.
+fn has_password_fallback(db: &DbHandler, machine: &Machine, account: &Account) -> Result<bool> {
+    if !matches!(
+        account.auth_type.parse::<AuthType>(),
+        Ok(AuthType::Key | AuthType::Agent)
+    ) {
+        return Ok(false);
+    }
+    let Some(machine_id) = machine.id else {
+        return Ok(false);
+    };
+    Ok(db.list_accounts_for_machine(machine_id)?.iter().any(|a| {
+        a.username == account.username
+            && a.auth_type.parse::<AuthType>().ok() == Some(AuthType::Password)
+    }))
+}
+
+fn run_sudo_check(db: &DbHandler, machine: &Machine, account: &Account) -> Result<SudoCheck> {
+    let known_hosts = known_hosts::active_file(db);
+    let output = health::ssh_batch(
+        &account.username,
+        &machine.ip_address,
+        false,
+        DIAGNOSE_CONNECT_TIMEOUT_SECS,
+        known_hosts.as_deref(),
+        &health::machine_ssh_args(db, machine)?,
+        &["sudo", "-n", "true"],
+    )
+    .map_err(|e| anyhow!("Failed to execute ssh command: {}", e))?;
+    let stderr = terminal_text::decode(&output.stderr);
+    log_probe(
+        db,
+        machine,
+        account,
+        SUDO_LOG_COMMAND,
+        &output.stderr,
+        output.status.code(),
+    );
+
+    let passwordless = output.status.success();
+    let suggestion = if passwordless {
+        None
+    } else {
+        let signing_key = db.get_setting(knowledge::SETTING_SIGNING_KEY)?;
+        let patterns = knowledge::load_troubleshooting_patterns(signing_key.as_deref());
+        knowledge::match_sudo_pattern(&stderr, &machine.os_type, &patterns)
+    };
+    Ok(SudoCheck {
+        passwordless,
+        output: stderr,
+        suggestion,
+    })
+}
+
+fn record_diagnostic(db: &DbHandler, machine: &Machine, outcome: &Outcome, stderr: &str) {
+    let Some(machine_id) = machine.id else {
+        return;
+    };
+    let pattern_id = match outcome {
+        Outcome::Failed {
+            suggestion: Some(s),
+        } => s.pattern_id.as_deref(),
+        _ => None,
+    };
+    let success = matches!(outcome, Outcome::Success);
+    if let Err(e) = db.add_diagnostic(machine_id, success, pattern_id, stderr) {
+        tracing::warn!(
+            "Failed to record diagnostic for machine {}: {}",
+            machine_id,
+            e
+        );
+    }
+}
+
+fn log_probe(
+    db: &DbHandler,
+    machine: &Machine,
+    account: &Account,
+    command: &str,
+    stderr: &[u8],
+    exit_code: Option<i32>,
+) {
+    let Some(machine_id) = machine.id else {
+        return;
+    };
+    let logged = db.add_log(
+        machine_id,
+        &account.username,
+        command,
+        None,
+        Some(stderr),
+        exit_code,
+    );
+    if let Err(e) = logged {
+        tracing::warn!(
+            "Failed to log diagnose probe for machine {}: {}",
+            machine_id,
+            e
+        );
+    }
+}
+
+/// Addresses ssh resolved the host to, from `ssh -v`'s "Connecting to host [addr] port N".
+fn resolved_addresses(stderr: &str) -> Vec<&str> {
+    stderr
+        .lines()
+        .filter_map(|line| {
+            line.split_once("Connecting to ")?
+                .1
+                .split_once('[')?
+                .1
+                .split_once(']')
+        })
+        .map(|(addr, _)| addr)
+        .filter(|addr| !addr.is_empty())
+        .collect()
+}
+
+/// Replaces `word` in `text` only where it isn't part of a longer name, so the machine
+/// `web` leaves `web-01` and `webhook` alone.
+fn replace_word(text: &str, word: &str, placeholder: &str) -> String {
+    let is_name_char = |c: char| c.is_alphanumeric() || c == '_' || c == '-';
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(pos) = rest.find(word) {
+        let end = pos + word.len();
+        let bounded = !rest[..pos].chars().next_back().is_some_and(is_name_char)
+            && !rest[end..].chars().next().is_some_and(is_name_char);
+        out.push_str(&rest[..pos]);
+        out.push_str(if bounded { placeholder } else { word });
+        rest = &rest[end..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Renders a self-contained Markdown report for humans. With `redact`, the username,
+/// host (and the addresses ssh resolved it to), and machine name are replaced everywhere,
+/// including the ssh output.
+pub fn render_markdown(diagnosis: &Diagnosis, redact: bool) -> String {
+    let m = &diagnosis.machine;
+    let addresses = resolved_addresses(&diagnosis.stderr);
+    let scrub = |text: &str| -> String {
+        if !redact {
+            return text.to_string();
+        }
+        let mut text = text.to_string();
+        for secret in std::iter::once(m.ip_address.as_str()).chain(addresses.iter().copied()) {
+            if !secret.is_empty() {
+                text = text.replace(secret, "<host>");
+            }
+        }
+        if !m.name.is_empty() {
+            text = replace_word(&text, &m.name, "<machine>");
+        }
+        if !diagnosis.username.is_empty() {
+            text = text.replace(diagnosis.username.as_str(), "<user>");
+        }
+        text
+    };
+
+    let mut md = String::new();
+    let _ = writeln!(md, "# Connection report: {}\n", scrub(&m.name));
+    let _ = writeln!(md, "| Field | Value |\n| --- | --- |");
+    let _ = writeln!(md, "| Host | {} |", scrub(&m.ip_address));
+    let _ = writeln!(md, "| User | {} |", scrub(&diagnosis.username));
+    let _ = writeln!(md, "| OS | {} |", m.os_type);
+    let _ = writeln!(md, "| Purpose | {} |", scrub(&m.purpose));
+    let _ = writeln!(md, "| Ownership | {} |", m.ownership);
+    let _ = writeln!(md, "| Status | {} |", m.status);
+    let _ = writeln!(
+        md,
+        "| Generated | {} |\n",
+        chrono::Local::now().format("%Y-%m-%d %H:%M:%S")
+    );
+
+    let _ = writeln!(md, "## Result\n");
+    match &diagnosis.outcome {
+        Outcome::Success => {
+            let _ = writeln!(md, "Connection successful. No issues detected.\n");
+        }
+        Outcome::HostKeyUnknown { fingerprints } => {
+            let _ = writeln!(
+                md,
+                "The host key is not trusted yet. Verify one of these fingerprints with the server owner:\n"
+            );
+            for fp in fingerprints {
+                let _ = writeln!(md, "- `{}`", scrub(fp));
+            }
+            md.push('\n');
+        }
+        Outcome::Failed { suggestion } => {
+            let _ = writeln!(md, "SSH connection failed.\n");
+            match suggestion {
+                Some(s) => {
+                    let _ = writeln!(md, "### Hint\n\n{}\n", scrub(&s.message));
+                    if let Some(cmd) = &s.command_hint {
+                        let _ = writeln!(md, "Suggested command:\n\n```sh\n{}\n```\n", scrub(cmd));
+                    }
+                    if let Some(script) = &s.script_path {
+                        let _ = writeln!(md, "Suggested script: `{}`\n", script);
+                    }
+                }
+                None => {
+                    let _ = writeln!(md, "No specific troubleshooting hint found.\n");
+                }
+            }
+            if let Some(s) = &diagnosis.password_fallback {
+                let _ = writeln!(md, "### Password fallback\n\n{}\n", scrub(&s.message));
+            }
+        }
+    }
+
+    if let Some(sudo) = &diagnosis.sudo {
+        let _ = writeln!(md, "## Sudo\n");
+        if sudo.passwordless {
+            let _ = writeln!(md, "Passwordless sudo works (`sudo -n true` succeeded).\n");
+        } else {
+            let _ = writeln!(md, "Passwordless sudo failed (`sudo -n true`).\n");
+            let _ = writeln!(md, "```text\n{}\n```\n", scrub(sudo.output.trim_end()));
+            match &sudo.suggestion {
+                Some(s) => {
+                    let _ = writeln!(md, "### Sudo hint\n\n{}\n", scrub(&s.message));
+                    if let Some(cmd) = &s.command_hint {
+                        let _ = writeln!(md, "Suggested command:\n\n```sh\n{}\n```\n", scrub(cmd));
+                    }
+                }
+                None => {
+                    let _ = writeln!(md, "No specific sudo hint found.\n");
+                }
+            }
+        }
+    }
+
+    let _ = writeln!(
+        md,
+        "## ssh -v output\n\n```text\n{}\n```",
+        scrub(diagnosis.stderr.trim_end())
+    );
+    md
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_markdown_redacts_identity() {
+        let diagnosis = Diagnosis {
+            machine: Machine {
+                id: Some(1),
+                uuid: None,
+                name: "build-box".to_string(),
+                ip_address: "10.1.2.3".to_string(),
+                purpose: "CI".to_string(),
+                ownership: "company".to_string(),
+                os_type: "linux".to_string(),
+                status: "broken".to_string(),
+                group: None,
+                ssh_options: None,
+                jump_host_id: None,
+                requires_vpn: false,
+                env: None,
+            },
+            account_id: Some(1),
+            username: "alice".to_string(),
+            stderr: "debug1: Connecting to 10.1.2.3 [10.1.2.3] port 22.\n\
+                     alice@10.1.2.3: Permission denied"
+                .to_string(),
+            outcome: Outcome::Failed { suggestion: None },
+            sudo: None,
+            password_fallback: None,
+        };
+
+        let report = render_markdown(&diagnosis, true);
+        assert!(!report.contains("10.1.2.3"));
+        assert!(!report.contains("alice"));
+        assert!(!report.contains("build-box"));
+        assert!(report.contains("<user>@<host>: Permission denied"));
+
+        let report = render_markdown(&diagnosis, false);
+        assert!(report.contains("alice@10.1.2.3"));
+    }
+
+    #[test]
+    fn test_render_markdown_redacts_resolved_address_and_whole_names_only() {
+        let diagnosis = Diagnosis {
+            machine: Machine {
+                id: Some(1),
+                uuid: None,
+                name: "web".to_string(),
+                ip_address: "web.internal".to_string(),
+                purpose: "web and webhook relay".to_string(),
+                ownership: "company".to_string(),
+                os_type: "linux".to_string(),
+                status: "broken".to_string(),
+                group: None,
+                ssh_options: None,
+                jump_host_id: None,
+                requires_vpn: false,
+                env: None,
+            },
+            account_id: Some(1),
+            username: "deploy".to_string(),
+            stderr: "debug1: Connecting to web.internal [10.0.0.5] port 22.\n\
+                     debug1: connect to address 10.0.0.5 port 22: Connection refused"
+                .to_string(),
+            outcome: Outcome::Failed { suggestion: None },
+            sudo: None,
+            password_fallback: None,
+        };
+
+        let report = render_markdown(&diagnosis, true);
+        assert!(!report.contains("10.0.0.5"));
+        assert!(report.contains("Connecting to <host> [<host>] port 22."));
+        assert!(report.contains("| Purpose | <machine> and webhook relay |"));
+    }
+}
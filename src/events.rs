@@ -0,0 +1,104 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, MutexGuard};
+
+/// A change made through MCP that the GUI should show right away, instead of waiting for
+/// its database file check to notice.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    MachineAdded {
+        name: String,
+    },
+    /// `register_machines` added this many machines
+    MachinesAdded {
+        count: usize,
+    },
+    CredentialUpdated {
+        account_id: i64,
+    },
+    KeysRotated,
+    HostKeyTrusted {
+        host: String,
+    },
+    IdentityKeyGenerated,
+    /// Logs, statuses, or diagnose history changed; worth a reload but not a toast
+    DataChanged,
+}
+
+/// Events queued by the MCP thread until the GUI drains them on its next frame. Nothing is
+/// queued until [`Self::subscribe`], so a headless server doesn't collect them forever.
+#[derive(Default)]
+pub struct Events {
+    subscribed: AtomicBool,
+    queue: Mutex<Vec<Event>>,
+    /// Wakes the GUI so an idle window still picks the event up
+    waker: Mutex<Option<Box<dyn Fn() + Send + Sync>>>,
+}
+
+impl Events {
+    fn queue(&self) -> MutexGuard<'_, Vec<Event>> {
+        self.queue.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Starts queueing; called by the GUI, the only consumer.
+    pub fn subscribe(&self) {
+        self.subscribed.store(true, Ordering::SeqCst);
+    }
+
+    pub fn emit(&self, event: Event) {
+        if !self.subscribed.load(Ordering::SeqCst) {
+            return;
+        }
+        self.queue().push(event);
+        if let Some(wake) = &*self.waker.lock().unwrap_or_else(|e| e.into_inner()) {
+            wake();
+        }
+    }
+
+    /// Everything emitted since the last call, oldest first.
+    pub fn drain(&self) -> Vec<Event> {
+        std::mem::take(&mut *self.queue())
+    }
+
+    /// Sets the callback run after each [`Self::emit`], e.g. a GUI repaint request.
+    pub fn set_waker(&self, wake: impl Fn() + Send + Sync + 'static) {
+        *self.waker.lock().unwrap_or_else(|e| e.into_inner()) = Some(Box::new(wake));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::sync::atomic::AtomicUsize;
+
+    #[test]
+    fn test_emit_drains_in_order_and_wakes() {
+        let events = Events::default();
+        events.emit(Event::DataChanged);
+        assert!(events.drain().is_empty());
+
+        events.subscribe();
+        events.emit(Event::KeysRotated);
+
+        let wakes = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&wakes);
+        events.set_waker(move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+        events.emit(Event::MachineAdded {
+            name: "web-01".to_string(),
+        });
+
+        assert_eq!(
+            events.drain(),
+            [
+                Event::KeysRotated,
+                Event::MachineAdded {
+                    name: "web-01".to_string()
+                }
+            ]
+        );
+        assert!(events.drain().is_empty());
+        assert_eq!(wakes.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,147 @@
+use crate::db::Machine;
+use crate::gui::ManagerApp;
+use eframe::egui;
+
+/// Matches listed under the query; Enter connects to the first.
+const MAX_RESULTS: usize = 8;
+
+/// Score of `query` as a case-insensitive subsequence of `text`, or `None` if it isn't one.
+/// Consecutive matches and matches at the start of a word (after `-`, `.`, `_`, `/` or a
+/// space) count extra and skipped characters count against it, so "web1" prefers
+/// "web-01" over "dev-web-backup-1".
+fn fuzzy_score(query: &str, text: &str) -> Option<i64> {
+    let text: Vec<char> = text.to_lowercase().chars().collect();
+    let mut score = 0;
+    let mut pos = 0;
+    let mut previous = None;
+    for q in query.to_lowercase().chars().filter(|c| !c.is_whitespace()) {
+        let found = pos + text[pos..].iter().position(|&c| c == q)?;
+        score += 1 - (found - pos) as i64;
+        if previous.is_some_and(|p| p + 1 == found) {
+            score += 5;
+        }
+        if found == 0 || matches!(text[found - 1], '-' | '.' | '_' | '/' | ' ') {
+            score += 3;
+        }
+        previous = Some(found);
+        pos = found + 1;
+    }
+    // Between equal matches, the shorter text is the closer one
+    Some(score * 100 - text.len() as i64)
+}
+
+/// Machines matching `query` in name, host, or group, best match first; ties keep the
+/// order of `machines`. An empty query matches everything.
+pub fn fuzzy_rank<'a>(query: &str, machines: &'a [Machine]) -> Vec<&'a Machine> {
+    if query.trim().is_empty() {
+        return machines.iter().collect();
+    }
+    let mut ranked: Vec<(i64, &Machine)> = machines
+        .iter()
+        .filter_map(|m| {
+            [Some(&m.name), Some(&m.ip_address), m.group.as_ref()]
+                .into_iter()
+                .flatten()
+                .filter_map(|field| fuzzy_score(query, field))
+                .max()
+                .map(|score| (score, m))
+        })
+        .collect();
+    ranked.sort_by_key(|(score, _)| std::cmp::Reverse(*score));
+    ranked.into_iter().map(|(_, m)| m).collect()
+}
+
+/// Ctrl+K quick connect: type part of a name, host, or group and press Enter to connect
+/// to the top match. Escape closes it.
+pub fn show(app: &mut ManagerApp, ctx: &egui::Context) {
+    let Some(mut query) = app.command_palette.take() else {
+        return;
+    };
+    let mut open = true;
+    let mut chosen = None;
+
+    egui::Window::new("クイック接続")
+        .open(&mut open)
+        .collapsible(false)
+        .resizable(false)
+        .title_bar(false)
+        .anchor(egui::Align2::CENTER_TOP, [0.0, 80.0])
+        .show(ctx, |ui| {
+            let response = ui.add_sized(
+                [ManagerApp::FORM_FIELD_WIDTH, 28.0],
+                egui::TextEdit::singleline(&mut query).hint_text("接続先の名前・ホスト・グループ"),
+            );
+            response.request_focus();
+            let matches = fuzzy_rank(&query, &app.machines);
+            if response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+                chosen = matches.first().and_then(|m| m.id);
+            }
+            ui.separator();
+            if matches.is_empty() {
+                ui.weak("一致する接続先がありません");
+            }
+            for (i, machine) in matches.into_iter().take(MAX_RESULTS).enumerate() {
+                let label = format!(
+                    "{}  {}",
+                    ManagerApp::machine_label(machine),
+                    machine.ip_address
+                );
+                if ui.selectable_label(i == 0, label).clicked() {
+                    chosen = machine.id;
+                }
+            }
+        });
+
+    if ctx.input(|i| i.key_pressed(egui::Key::Escape)) {
+        open = false;
+    }
+    if let Some(machine_id) = chosen {
+        app.connect(machine_id);
+    } else if open {
+        app.command_palette = Some(query);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn machine(name: &str, host: &str, group: Option<&str>) -> Machine {
+        Machine {
+            id: None,
+            uuid: None,
+            name: name.to_string(),
+            ip_address: host.to_string(),
+            purpose: String::new(),
+            ownership: "personal".to_string(),
+            os_type: "linux".to_string(),
+            status: "active".to_string(),
+            group: group.map(str::to_string),
+            ssh_options: None,
+            jump_host_id: None,
+            requires_vpn: false,
+            env: None,
+        }
+    }
+
+    #[test]
+    fn test_fuzzy_rank_orders_best_match_first() {
+        let machines = [
+            machine("dev-web-backup-1", "10.0.0.9", None),
+            machine("web-01", "10.0.0.1", None),
+            machine("db-01", "192.168.1.20", Some("prod")),
+        ];
+        let names = |query| -> Vec<&str> {
+            fuzzy_rank(query, &machines)
+                .iter()
+                .map(|m| m.name.as_str())
+                .collect()
+        };
+        assert_eq!(names("web1"), ["web-01", "dev-web-backup-1"]);
+        assert_eq!(names("WEB"), ["web-01", "dev-web-backup-1"]);
+        assert_eq!(names("192.168"), ["db-01"]);
+        assert_eq!(names("prod"), ["db-01"]);
+        assert!(names("xyz").is_empty());
+        assert_eq!(names("").len(), 3);
+    }
+}
@@ -0,0 +1,162 @@
+use crate::gui::{ManagerApp, Tab};
+use eframe::egui;
+
+/// Where a clicked search result leads.
+enum Jump {
+    /// Selects the machine in the Connections list and opens its detail window
+    Machine(i64),
+    /// Highlights the log entry in the Logs tab
+    Log(i64),
+}
+
+/// Search box of the top bar. The database is queried again whenever the text changes.
+pub fn show_box(app: &mut ManagerApp, ui: &mut egui::Ui) {
+    let response = ui.add(
+        egui::TextEdit::singleline(&mut app.global_search_query)
+            .hint_text("🔍 すべてを検索（接続先・アカウント・ログ・制約）")
+            .desired_width(ManagerApp::FORM_FIELD_WIDTH),
+    );
+    if !response.changed() {
+        return;
+    }
+    if app.global_search_query.trim().is_empty() {
+        app.global_search = None;
+        return;
+    }
+    match app.db.global_search(&app.global_search_query) {
+        Ok(results) => app.global_search = Some(results),
+        Err(e) => {
+            app.global_search = None;
+            app.report_error("検索に失敗しました", &e);
+        }
+    }
+}
+
+/// Results of the top-bar search, grouped by category. Clicking one jumps to it; closing
+/// the window or pressing Escape clears the search.
+pub fn show(app: &mut ManagerApp, ctx: &egui::Context) {
+    let Some(results) = app.global_search.take() else {
+        return;
+    };
+    let mut open = true;
+    let mut jump = None;
+    let machine_name = |machine_id: i64| {
+        app.machines
+            .iter()
+            .find(|m| m.id == Some(machine_id))
+            .map(ManagerApp::machine_label)
+            .unwrap_or_else(|| format!("#{}", machine_id))
+    };
+
+    egui::Window::new("検索結果")
+        .open(&mut open)
+        .collapsible(false)
+        .default_width(ManagerApp::FORM_FIELD_WIDTH + 80.0)
+        .anchor(egui::Align2::RIGHT_TOP, [-12.0, 40.0])
+        .show(ctx, |ui| {
+            if results.is_empty() {
+                ui.weak("一致する項目がありません");
+                return;
+            }
+            egui::ScrollArea::vertical()
+                .max_height(420.0)
+                .show(ui, |ui| {
+                    section(ui, "接続先", results.machines.len(), |ui| {
+                        for machine in &results.machines {
+                            let label = format!(
+                                "{}  {}",
+                                ManagerApp::machine_label(machine),
+                                machine.ip_address
+                            );
+                            if ui.link(label).clicked() {
+                                jump = machine.id.map(Jump::Machine);
+                            }
+                        }
+                    });
+                    section(ui, "アカウント", results.accounts.len(), |ui| {
+                        for account in &results.accounts {
+                            let mut label = format!(
+                                "{}@{}",
+                                account.username,
+                                machine_name(account.machine_id)
+                            );
+                            if let Some(note) = &account.note {
+                                label.push_str(&format!("  ({})", note));
+                            }
+                            if ui.link(label).clicked() {
+                                jump = Some(Jump::Machine(account.machine_id));
+                            }
+                        }
+                    });
+                    section(ui, "ログ", results.logs.len(), |ui| {
+                        for log in &results.logs {
+                            ui.horizontal(|ui| {
+                                ui.weak(&log.timestamp);
+                                let label = egui::RichText::new(format!(
+                                    "{}: {}",
+                                    log.machine_name, log.command
+                                ))
+                                .monospace();
+                                if ui.link(label).clicked() {
+                                    jump = Some(Jump::Log(log.id));
+                                }
+                            });
+                        }
+                    });
+                    section(ui, "制約", results.constraints.len(), |ui| {
+                        for constraint in &results.constraints {
+                            let label = format!(
+                                "{}: {}",
+                                machine_name(constraint.machine_id),
+                                constraint.rule_text
+                            );
+                            if ui.link(label).clicked() {
+                                jump = Some(Jump::Machine(constraint.machine_id));
+                            }
+                        }
+                    });
+                });
+        });
+
+    if ctx.input(|i| i.key_pressed(egui::Key::Escape)) {
+        open = false;
+    }
+    let jumped = jump.is_some();
+    match jump {
+        Some(Jump::Machine(machine_id)) => {
+            app.current_tab = Tab::Connections;
+            // The list's own filters could hide the machine
+            app.search_query.clear();
+            app.status_filter = None;
+            app.selected_machine = Some(machine_id);
+            app.scroll_to_selected = true;
+            app.open_machine_detail(machine_id);
+        }
+        Some(Jump::Log(log_id)) => {
+            app.current_tab = Tab::Logs;
+            app.highlighted_log = Some(log_id);
+            app.scroll_to_log = true;
+        }
+        None => {}
+    }
+    if jumped || !open {
+        app.global_search_query.clear();
+    } else {
+        app.global_search = Some(results);
+    }
+}
+
+/// Heading with the match count, then the matches; nothing for an empty category.
+fn section(ui: &mut egui::Ui, title: &str, count: usize, add_contents: impl FnOnce(&mut egui::Ui)) {
+    if count == 0 {
+        return;
+    }
+    let capped = if count >= crate::db::SEARCH_RESULTS_PER_CATEGORY {
+        "+"
+    } else {
+        ""
+    };
+    ui.strong(format!("{} ({}{})", title, count, capped));
+    add_contents(ui);
+    ui.add_space(6.0);
+}
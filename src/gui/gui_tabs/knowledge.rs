@@ -0,0 +1,322 @@
+use crate::db::OsType;
+use crate::gui::ManagerApp;
+use crate::knowledge::{self, CATEGORY_SSH, CATEGORY_SUDO, Pattern, Suggestion};
+use eframe::egui;
+
+/// The knowledge file being edited: the one that is loaded at runtime, or where a new one goes.
+fn knowledge_path() -> &'static str {
+    knowledge::locate_knowledge_file().unwrap_or_else(knowledge::default_knowledge_path)
+}
+
+fn load(app: &mut ManagerApp) {
+    let path = knowledge_path();
+    app.knowledge_selected = None;
+    if !std::path::Path::new(path).is_file() {
+        app.knowledge_patterns = Some(Vec::new());
+        app.knowledge_message = None;
+        return;
+    }
+    match knowledge::read_patterns_file(path) {
+        Ok(patterns) => {
+            app.knowledge_patterns = Some(patterns);
+            app.knowledge_message = None;
+        }
+        Err(e) => {
+            // Keep an empty list so the tab stays usable; saving would replace the broken file
+            app.knowledge_patterns = Some(Vec::new());
+            app.knowledge_message = Some(format!("読み込みに失敗しました: {}", e));
+        }
+    }
+}
+
+fn new_pattern(existing: &[Pattern]) -> Pattern {
+    let id = (1..)
+        .map(|n| format!("NEW_PATTERN_{}", n))
+        .find(|id| existing.iter().all(|p| &p.id != id))
+        .unwrap_or_default();
+    Pattern {
+        id,
+        pattern: String::new(),
+        description: String::new(),
+        suggestion: Suggestion {
+            message: String::new(),
+            action_type: "manual".to_string(),
+            command_hint: None,
+            script_path: None,
+            pattern_id: None,
+        },
+        os_filter: None,
+        category: None,
+    }
+}
+
+/// Text field for an optional value; a blank field is stored as `None`.
+fn optional_field(ui: &mut egui::Ui, value: &mut Option<String>, hint: &str) {
+    let mut text = value.clone().unwrap_or_default();
+    let response = ui.add_sized(
+        [ManagerApp::FORM_FIELD_WIDTH, 28.0],
+        egui::TextEdit::singleline(&mut text).hint_text(hint),
+    );
+    if response.changed() {
+        *value = Some(text).filter(|t| !t.trim().is_empty());
+    }
+}
+
+fn form_label(ui: &mut egui::Ui, label: &str) {
+    ui.add_sized(
+        [ManagerApp::FORM_LABEL_WIDTH, 28.0],
+        egui::Label::new(label),
+    );
+}
+
+fn show_editor(ui: &mut egui::Ui, pattern: &mut Pattern) {
+    egui::Grid::new("knowledge_editor_grid")
+        .num_columns(2)
+        .spacing([8.0, 8.0])
+        .show(ui, |ui| {
+            form_label(ui, "ID");
+            ui.add_sized(
+                [ManagerApp::FORM_FIELD_WIDTH, 28.0],
+                egui::TextEdit::singleline(&mut pattern.id),
+            );
+            ui.end_row();
+
+            form_label(ui, "正規表現");
+            ui.vertical(|ui| {
+                ui.add_sized(
+                    [ManagerApp::FORM_FIELD_WIDTH, 28.0],
+                    egui::TextEdit::singleline(&mut pattern.pattern).code_editor(),
+                );
+                // Compiled on every frame so mistakes show up while typing
+                match regex::Regex::new(&pattern.pattern) {
+                    Ok(_) => ui.colored_label(egui::Color32::GREEN, "✅ 有効な正規表現"),
+                    Err(e) => ui.colored_label(egui::Color32::LIGHT_RED, format!("❌ {}", e)),
+                };
+            });
+            ui.end_row();
+
+            form_label(ui, "説明");
+            ui.add_sized(
+                [ManagerApp::FORM_FIELD_WIDTH, 28.0],
+                egui::TextEdit::singleline(&mut pattern.description),
+            );
+            ui.end_row();
+
+            form_label(ui, "対象");
+            let mut category = pattern
+                .category
+                .clone()
+                .unwrap_or_else(|| CATEGORY_SSH.to_string());
+            egui::ComboBox::from_id_source("knowledge_category")
+                .selected_text(match category.as_str() {
+                    CATEGORY_SUDO => "sudo の出力",
+                    _ => "SSH 接続エラー",
+                })
+                .width(ManagerApp::FORM_FIELD_WIDTH)
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut category, CATEGORY_SSH.to_string(), "SSH 接続エラー");
+                    ui.selectable_value(&mut category, CATEGORY_SUDO.to_string(), "sudo の出力");
+                });
+            // The default category is left implicit so untouched files round-trip unchanged
+            pattern.category = Some(category).filter(|c| c != CATEGORY_SSH);
+            ui.end_row();
+
+            form_label(ui, "OS");
+            egui::ComboBox::from_id_source("knowledge_os_filter")
+                .selected_text(pattern.os_filter.as_deref().unwrap_or("すべて"))
+                .width(ManagerApp::FORM_FIELD_WIDTH)
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut pattern.os_filter, None, "すべて");
+                    for os in OsType::ALL {
+                        ui.selectable_value(
+                            &mut pattern.os_filter,
+                            Some(os.to_string()),
+                            format!("{} {}", os.icon(), os),
+                        );
+                    }
+                });
+            ui.end_row();
+
+            form_label(ui, "メッセージ");
+            ui.add_sized(
+                [ManagerApp::FORM_FIELD_WIDTH, 56.0],
+                egui::TextEdit::multiline(&mut pattern.suggestion.message)
+                    .hint_text("$1 / ${name} でキャプチャを埋め込めます"),
+            );
+            ui.end_row();
+
+            form_label(ui, "アクション種別");
+            ui.add_sized(
+                [ManagerApp::FORM_FIELD_WIDTH, 28.0],
+                egui::TextEdit::singleline(&mut pattern.suggestion.action_type),
+            );
+            ui.end_row();
+
+            form_label(ui, "推奨コマンド");
+            optional_field(ui, &mut pattern.suggestion.command_hint, "なし");
+            ui.end_row();
+
+            form_label(ui, "スクリプト");
+            optional_field(ui, &mut pattern.suggestion.script_path, "なし");
+            ui.end_row();
+        });
+}
+
+fn show_tester(ui: &mut egui::Ui, pattern: &Pattern, sample: &mut String) {
+    ui.label(egui::RichText::new("テスト").strong());
+    ui.add(
+        egui::TextEdit::multiline(sample)
+            .desired_rows(4)
+            .desired_width(f32::INFINITY)
+            .code_editor()
+            .hint_text("ssh -v の出力などを貼り付けてください"),
+    );
+    if sample.trim().is_empty() {
+        return;
+    }
+    match pattern.try_match(sample) {
+        Ok(Some(suggestion)) => {
+            ui.colored_label(egui::Color32::GREEN, "一致しました");
+            ui.label(format!("メッセージ: {}", suggestion.message));
+            if let Some(cmd) = &suggestion.command_hint {
+                ui.label(egui::RichText::new(format!("推奨コマンド: {}", cmd)).monospace());
+            }
+        }
+        Ok(None) => {
+            ui.colored_label(egui::Color32::YELLOW, "一致しませんでした");
+        }
+        Err(_) => {
+            ui.colored_label(
+                egui::Color32::LIGHT_RED,
+                "正規表現が無効なためテストできません",
+            );
+        }
+    }
+}
+
+pub fn show(app: &mut ManagerApp, ui: &mut egui::Ui) {
+    if app.knowledge_patterns.is_none() {
+        load(app);
+    }
+    let path = knowledge_path();
+
+    ui.add_space(ManagerApp::SECTION_GAP);
+    let mut reload = false;
+    let mut save = false;
+    ui.horizontal(|ui| {
+        ui.heading("ナレッジ");
+        ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+            save = ui.button("保存").clicked();
+            reload = ui.button("再読込").clicked();
+        });
+    });
+    ui.label(egui::RichText::new(path).small().monospace());
+    if std::path::Path::new(&knowledge::signature_path(path)).is_file() {
+        ui.colored_label(
+            egui::Color32::YELLOW,
+            "⚠ このファイルは署名されています。保存すると署名が一致しなくなるため、保存後に再署名してください。",
+        );
+    }
+    if let Some(msg) = &app.knowledge_message {
+        ui.label(msg);
+    }
+    ui.separator();
+
+    if reload {
+        load(app);
+        return;
+    }
+    if save {
+        let patterns = app.knowledge_patterns.as_deref().unwrap_or_default();
+        app.knowledge_message = Some(match knowledge::save_patterns_file(path, patterns) {
+            Ok(()) => {
+                tracing::info!("Saved {} knowledge patterns to {}", patterns.len(), path);
+                "保存しました".to_string()
+            }
+            Err(e) => format!("保存に失敗しました: {}", e),
+        });
+    }
+
+    let Some(patterns) = app.knowledge_patterns.as_mut() else {
+        return;
+    };
+
+    // Earlier patterns win, so the list order is the matching order
+    let mut move_up = None;
+    let mut move_down = None;
+    let mut delete = None;
+    egui::ScrollArea::vertical()
+        .id_source("knowledge_list")
+        .max_height(220.0)
+        .show(ui, |ui| {
+            let count = patterns.len();
+            for (i, pattern) in patterns.iter().enumerate() {
+                ui.horizontal(|ui| {
+                    let valid = regex::Regex::new(&pattern.pattern).is_ok();
+                    let mut label = egui::RichText::new(&pattern.id).monospace();
+                    if !valid {
+                        label = label.color(egui::Color32::LIGHT_RED);
+                    }
+                    if ui
+                        .selectable_label(app.knowledge_selected == Some(i), label)
+                        .clicked()
+                    {
+                        app.knowledge_selected = Some(i);
+                    }
+                    ui.label(egui::RichText::new(&pattern.description).weak());
+                    ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                        if ui.small_button("削除").clicked() {
+                            delete = Some(i);
+                        }
+                        if ui
+                            .add_enabled(i + 1 < count, egui::Button::new("↓").small())
+                            .clicked()
+                        {
+                            move_down = Some(i);
+                        }
+                        if ui
+                            .add_enabled(i > 0, egui::Button::new("↑").small())
+                            .clicked()
+                        {
+                            move_up = Some(i);
+                        }
+                    });
+                });
+            }
+        });
+
+    if let Some(i) = move_up {
+        patterns.swap(i, i - 1);
+        app.knowledge_selected = Some(i - 1);
+    }
+    if let Some(i) = move_down {
+        patterns.swap(i, i + 1);
+        app.knowledge_selected = Some(i + 1);
+    }
+    if let Some(i) = delete {
+        patterns.remove(i);
+        app.knowledge_selected = match app.knowledge_selected {
+            Some(s) if s == i => None,
+            Some(s) if s > i => Some(s - 1),
+            other => other,
+        };
+    }
+    if ui.button("パターンを追加").clicked() {
+        let pattern = new_pattern(patterns);
+        patterns.push(pattern);
+        app.knowledge_selected = Some(patterns.len() - 1);
+    }
+
+    ui.separator();
+    let Some(pattern) = app.knowledge_selected.and_then(|i| patterns.get_mut(i)) else {
+        ui.label("編集するパターンを選択してください。");
+        return;
+    };
+    egui::ScrollArea::vertical()
+        .id_source("knowledge_editor")
+        .show(ui, |ui| {
+            show_editor(ui, pattern);
+            ui.add_space(ManagerApp::SECTION_GAP);
+            show_tester(ui, pattern, &mut app.knowledge_sample);
+        });
+}
@@ -1,4 +1,5 @@
 use crate::gui::ManagerApp;
+use crate::terminal_text;
 use eframe::egui;
 
 pub fn show(app: &mut ManagerApp, ui: &mut egui::Ui) {
@@ -13,9 +14,13 @@ pub fn show(app: &mut ManagerApp, ui: &mut egui::Ui) {
     });
     ui.separator();
 
+    let mut reconnect: Option<(i64, String)> = None;
+    let scroll_to_log = std::mem::take(&mut app.scroll_to_log);
     egui::ScrollArea::vertical().show(ui, |ui| {
         for log in &app.logs {
-            ui.group(|ui| {
+            // Logs outlive their machine; deleted ones can't be reconnected
+            let machine_exists = app.machines.iter().any(|m| m.id == Some(log.machine_id));
+            let entry = ui.group(|ui| {
                 ui.horizontal(|ui| {
                     ui.label(&log.timestamp);
                     ui.separator();
@@ -30,19 +35,68 @@ pub fn show(app: &mut ManagerApp, ui: &mut egui::Ui) {
                         ui.label(egui::RichText::new(text).color(color));
                     });
                 });
-                ui.label(egui::RichText::new(&log.command).monospace());
+                ui.horizontal(|ui| {
+                    // Selectable so part of a command or error can be copied with the keyboard
+                    ui.add(
+                        egui::Label::new(egui::RichText::new(&log.command).monospace())
+                            .selectable(true),
+                    );
+                    ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                        let reconnect_button =
+                            ui.add_enabled(machine_exists, egui::Button::new("再接続").small());
+                        if reconnect_button
+                            .on_disabled_hover_text("接続先は削除されています")
+                            .clicked()
+                        {
+                            reconnect = Some((log.machine_id, log.username.clone()));
+                        }
+                        if ui.small_button("コピー").clicked() {
+                            ui.output_mut(|o| o.copied_text = log.command.clone());
+                        }
+                    });
+                });
                 if let Some(err) = &log.stderr {
                     if !err.is_empty() {
                         ui.collapsing("エラー出力", |ui| {
-                            ui.label(
-                                egui::RichText::new(err)
-                                    .color(egui::Color32::LIGHT_RED)
-                                    .monospace(),
+                            // Older rows were stored before escapes were stripped on insert
+                            ui.add(
+                                egui::Label::new(
+                                    egui::RichText::new(terminal_text::clean(err))
+                                        .color(egui::Color32::LIGHT_RED)
+                                        .monospace(),
+                                )
+                                .selectable(true),
                             );
+                            if let Some(raw) = &log.stderr_raw
+                                && ui.small_button("元の出力をコピー").clicked()
+                            {
+                                ui.output_mut(|o| o.copied_text = raw.escape_ascii().to_string());
+                            }
                         });
                     }
                 }
             });
+            // Picked from the search results
+            if app.highlighted_log == Some(log.id) {
+                ui.painter()
+                    .rect_stroke(entry.response.rect, 4.0, ui.visuals().selection.stroke);
+                if scroll_to_log {
+                    entry.response.scroll_to_me(Some(egui::Align::Center));
+                }
+            }
         }
     });
+
+    if let Some((machine_id, username)) = reconnect {
+        // Reuse the account the command ran as; fall back to the usual picker if it's gone
+        let account = app
+            .accounts
+            .iter()
+            .find(|a| a.machine_id == machine_id && a.username == username)
+            .cloned();
+        match account {
+            Some(account) => app.connect_with(machine_id, &account),
+            None => app.connect(machine_id),
+        }
+    }
 }
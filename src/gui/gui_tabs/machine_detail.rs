@@ -0,0 +1,363 @@
+use crate::db::OsType;
+use crate::gui::{ManagerApp, UNGROUPED_LABEL};
+use eframe::egui;
+use std::collections::HashMap;
+
+/// Latest diagnose results listed in the detail window.
+const TIMELINE_ENTRIES: usize = 10;
+
+pub fn show(app: &mut ManagerApp, ctx: &egui::Context) {
+    let usage = app
+        .editing_machine
+        .as_ref()
+        .and_then(|m| m.id)
+        .map(|id| app.usage_label(id));
+    let editing_id = app.editing_machine.as_ref().and_then(|m| m.id);
+    // Jump host candidates; cycles through other machines are rejected on save
+    let jump_hosts: Vec<(i64, String)> = app
+        .machines
+        .iter()
+        .filter_map(|m| Some((m.id?, m.name.clone())))
+        .filter(|(id, _)| Some(*id) != editing_id)
+        .collect();
+    // Looked up before `machine` borrows the app mutably
+    let badges: HashMap<i64, (&str, &str)> = app
+        .accounts
+        .iter()
+        .filter_map(|a| Some((a.id?, app.credential_badge(a)?)))
+        .collect();
+    let diagnostics: Vec<crate::db::Diagnostic> = editing_id
+        .map(|id| app.diagnostics_for(id).to_vec())
+        .unwrap_or_default();
+    let Some(machine) = app.editing_machine.as_mut() else {
+        return;
+    };
+    let machine_id = machine.id;
+
+    let mut open = true;
+    let mut save = false;
+    let mut cancel = false;
+    let mut clone = false;
+    let mut connect_account = None;
+    let mut save_note = false;
+    let mut save_credential = false;
+
+    egui::Window::new("接続先の詳細")
+        .open(&mut open)
+        .collapsible(false)
+        .resizable(false)
+        .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+        .show(ctx, |ui| {
+            egui::Grid::new("machine_detail_grid")
+                .num_columns(2)
+                .spacing([8.0, 8.0])
+                .show(ui, |ui| {
+                    let fields: [(&str, &mut String); 5] = [
+                        ("名前", &mut machine.name),
+                        ("ホスト", &mut machine.ip_address),
+                        ("用途", &mut machine.purpose),
+                        ("所有", &mut machine.ownership),
+                        ("状態", &mut machine.status),
+                    ];
+                    for (label, value) in fields {
+                        ui.add_sized(
+                            [ManagerApp::FORM_LABEL_WIDTH, 28.0],
+                            egui::Label::new(label),
+                        );
+                        ui.add_sized(
+                            [ManagerApp::FORM_FIELD_WIDTH, 28.0],
+                            egui::TextEdit::singleline(value),
+                        );
+                        ui.end_row();
+                    }
+
+                    ui.add_sized(
+                        [ManagerApp::FORM_LABEL_WIDTH, 28.0],
+                        egui::Label::new("グループ"),
+                    );
+                    let mut group = machine.group.clone().unwrap_or_default();
+                    let edited = ui.add_sized(
+                        [ManagerApp::FORM_FIELD_WIDTH, 28.0],
+                        egui::TextEdit::singleline(&mut group).hint_text(UNGROUPED_LABEL),
+                    );
+                    if edited.changed() {
+                        // Blank is stored as ungrouped
+                        machine.group = Some(group);
+                    }
+                    ui.end_row();
+
+                    ui.add_sized(
+                        [ManagerApp::FORM_LABEL_WIDTH, 28.0],
+                        egui::Label::new("SSH オプション"),
+                    );
+                    let mut options = machine.ssh_options.clone().unwrap_or_default();
+                    let edited = ui.add_sized(
+                        [ManagerApp::FORM_FIELD_WIDTH, 28.0],
+                        egui::TextEdit::singleline(&mut options)
+                            .hint_text("例: -J admin@bastion -o IdentitiesOnly=yes"),
+                    );
+                    if edited.changed() {
+                        // Validated on save; blank is stored as none
+                        machine.ssh_options = Some(options);
+                    }
+                    ui.end_row();
+
+                    ui.add_sized(
+                        [ManagerApp::FORM_LABEL_WIDTH, 28.0],
+                        egui::Label::new("環境変数"),
+                    );
+                    let mut env = machine.env.clone().unwrap_or_default();
+                    let edited = ui.add_sized(
+                        [ManagerApp::FORM_FIELD_WIDTH, 28.0],
+                        egui::TextEdit::singleline(&mut env)
+                            .hint_text(r#"例: {"KUBECONFIG": "/etc/kube/config"}"#),
+                    );
+                    if edited.changed() {
+                        // Validated on save; blank is stored as none
+                        machine.env = Some(env);
+                    }
+                    ui.end_row();
+
+                    ui.add_sized(
+                        [ManagerApp::FORM_LABEL_WIDTH, 28.0],
+                        egui::Label::new("踏み台"),
+                    );
+                    let selected_text = machine
+                        .jump_host_id
+                        .map(|id| {
+                            jump_hosts
+                                .iter()
+                                .find(|(jump_id, _)| *jump_id == id)
+                                .map_or_else(|| format!("#{}", id), |(_, name)| name.clone())
+                        })
+                        .unwrap_or_else(|| "なし".to_string());
+                    egui::ComboBox::from_id_source("machine_detail_jump_host")
+                        .selected_text(selected_text)
+                        .width(ManagerApp::FORM_FIELD_WIDTH)
+                        .show_ui(ui, |ui| {
+                            ui.selectable_value(&mut machine.jump_host_id, None, "なし");
+                            for (id, name) in &jump_hosts {
+                                ui.selectable_value(&mut machine.jump_host_id, Some(*id), name);
+                            }
+                        });
+                    ui.end_row();
+
+                    ui.add_sized(
+                        [ManagerApp::FORM_LABEL_WIDTH, 28.0],
+                        egui::Label::new("VPN"),
+                    );
+                    ui.checkbox(&mut machine.requires_vpn, "VPN 接続が必要");
+                    ui.end_row();
+
+                    // Pick from the known values so edits can't reintroduce free-form variants
+                    ui.add_sized([ManagerApp::FORM_LABEL_WIDTH, 28.0], egui::Label::new("OS"));
+                    let selected_text = match machine.os_type.parse::<OsType>() {
+                        Ok(os) => format!("{} {}", os.icon(), os),
+                        Err(_) => machine.os_type.clone(),
+                    };
+                    egui::ComboBox::from_id_source("machine_detail_os")
+                        .selected_text(selected_text)
+                        .width(ManagerApp::FORM_FIELD_WIDTH)
+                        .show_ui(ui, |ui| {
+                            for os in OsType::ALL {
+                                ui.selectable_value(
+                                    &mut machine.os_type,
+                                    os.to_string(),
+                                    format!("{} {}", os.icon(), os),
+                                );
+                            }
+                        });
+                    ui.end_row();
+                });
+
+            if let Some(usage) = &usage {
+                ui.label(usage);
+            }
+
+            ui.add_space(ManagerApp::SECTION_GAP);
+            ui.label(egui::RichText::new("アカウント").strong());
+            for account in app
+                .accounts
+                .iter()
+                .filter(|a| Some(a.machine_id) == machine_id)
+            {
+                ui.horizontal(|ui| {
+                    ui.label(format!("{} ({})", account.username, account.auth_type));
+                    if let Some(&(icon, hint)) = account.id.and_then(|id| badges.get(&id)) {
+                        ui.label(icon).on_hover_text(hint);
+                    }
+                    if let Some(fp) = &account.key_fingerprint {
+                        ui.label(egui::RichText::new(fp).monospace().small());
+                    }
+                    if ui.small_button("接続").clicked() {
+                        connect_account = Some(account.clone());
+                    }
+                    if let Some(id) = account.id {
+                        if ui.small_button("メモ").clicked() {
+                            app.editing_account_note =
+                                Some((id, account.note.clone().unwrap_or_default()));
+                        }
+                        if ui.small_button("認証情報").clicked() {
+                            app.updating_account = Some((id, account.username.clone()));
+                            app.new_credential.clear();
+                            app.reveal_credential = false;
+                        }
+                    }
+                });
+                if app.updating_account.as_ref().map(|(id, _)| *id) == account.id {
+                    ui.horizontal(|ui| {
+                        ManagerApp::credential_input(
+                            ui,
+                            &account.auth_type,
+                            &mut app.new_credential,
+                            &mut app.reveal_credential,
+                        );
+                        if ui.small_button("更新").clicked() {
+                            save_credential = true;
+                        }
+                        if ui.small_button("キャンセル").clicked() {
+                            app.updating_account = None;
+                            app.new_credential.clear();
+                        }
+                    });
+                }
+                match &mut app.editing_account_note {
+                    Some((id, draft)) if Some(*id) == account.id => {
+                        ui.horizontal(|ui| {
+                            ui.add_sized(
+                                [ManagerApp::FORM_FIELD_WIDTH, 28.0],
+                                egui::TextEdit::singleline(draft)
+                                    .hint_text("例: デプロイ用。鍵は変更しない"),
+                            );
+                            if ui.small_button("保存").clicked() {
+                                save_note = true;
+                            }
+                        });
+                    }
+                    _ => {
+                        if let Some(note) = &account.note {
+                            ui.weak(note);
+                        }
+                    }
+                }
+            }
+
+            if !diagnostics.is_empty() {
+                ui.add_space(ManagerApp::SECTION_GAP);
+                ui.label(egui::RichText::new("診断履歴").strong());
+                for diagnostic in diagnostics.iter().take(TIMELINE_ENTRIES) {
+                    ui.horizontal(|ui| {
+                        let (icon, color) = if diagnostic.success {
+                            ("✔", egui::Color32::GREEN)
+                        } else {
+                            ("✖", egui::Color32::RED)
+                        };
+                        ui.colored_label(color, icon);
+                        ui.label(&diagnostic.timestamp);
+                        if let Some(pattern) = &diagnostic.matched_pattern_id {
+                            ui.label(egui::RichText::new(pattern).monospace().small());
+                        }
+                    });
+                    // Shown as selectable text rather than a tooltip so the error can be copied
+                    if let Some(excerpt) = diagnostic
+                        .stderr_excerpt
+                        .as_deref()
+                        .filter(|e| !e.is_empty())
+                    {
+                        egui::CollapsingHeader::new("ssh の出力")
+                            .id_source(("diagnostic_output", diagnostic.id))
+                            .show(ui, |ui| {
+                                ui.add(
+                                    egui::Label::new(
+                                        egui::RichText::new(excerpt)
+                                            .color(egui::Color32::LIGHT_RED)
+                                            .monospace(),
+                                    )
+                                    .selectable(true),
+                                );
+                            });
+                    }
+                }
+            }
+
+            if let Some(err) = &app.edit_error {
+                ui.add_space(8.0);
+                ui.colored_label(egui::Color32::RED, err);
+            }
+
+            ui.add_space(ManagerApp::SECTION_GAP);
+            ui.horizontal(|ui| {
+                if ui.button("保存").clicked() {
+                    save = true;
+                }
+                if ui.button("キャンセル").clicked() {
+                    cancel = true;
+                }
+                if machine_id.is_some()
+                    && ui
+                        .button("複製")
+                        .on_hover_text("設定と制約をコピーした新しい接続先を作成します")
+                        .clicked()
+                {
+                    clone = true;
+                }
+            });
+        });
+
+    if save_note && let Some((id, draft)) = app.editing_account_note.take() {
+        match app.db.update_account_note(id, Some(&draft)) {
+            Ok(()) => app.refresh(),
+            Err(e) => app.edit_error = Some(format!("メモを保存できませんでした: {}", e)),
+        }
+    }
+
+    if save_credential {
+        if let Some((id, username)) = app.updating_account.take() {
+            match app.db.update_account_credential(id, &app.new_credential) {
+                Ok(()) => app.refresh(),
+                Err(e) => {
+                    app.edit_error = Some(format!(
+                        "{} の認証情報を更新できませんでした: {}",
+                        username, e
+                    ))
+                }
+            }
+        }
+        app.new_credential.clear();
+        app.reveal_credential = false;
+    }
+
+    if let (Some(account), Some(id)) = (connect_account, machine_id) {
+        app.connect_with(id, &account);
+    }
+
+    // Unsaved edits in this window are dropped; the copy is made from the stored machine
+    if let Some(id) = machine_id.filter(|_| clone) {
+        app.editing_machine = None;
+        app.edit_error = None;
+        app.open_clone_dialog(id);
+        return;
+    }
+
+    if save {
+        let result = app
+            .editing_machine
+            .as_ref()
+            .map(|m| app.db.update_machine(m))
+            .unwrap_or(Ok(()));
+        match result {
+            Ok(()) => {
+                app.editing_machine = None;
+                app.edit_error = None;
+                app.refresh();
+            }
+            Err(e) => app.edit_error = Some(format!("保存に失敗しました: {}", e)),
+        }
+    } else if cancel || !open {
+        app.editing_machine = None;
+        app.edit_error = None;
+        app.editing_account_note = None;
+        app.updating_account = None;
+        app.new_credential.clear();
+    }
+}
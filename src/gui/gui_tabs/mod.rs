@@ -1,4 +1,9 @@
 pub mod accounts;
+pub mod command_palette;
 pub mod connections;
+pub mod global_search;
+pub mod knowledge;
 pub mod logs;
+pub mod machine_detail;
 pub mod onboarding;
+pub mod settings;
@@ -1,9 +1,138 @@
-use crate::db::{Account, Machine};
+use crate::db::{Account, AuthType, Machine, TPM_CREDENTIAL_MARKER};
+use crate::diagnose::{self, CredentialTest};
 use crate::gui::ManagerApp;
+use crate::security::SecureBootState;
 use anyhow::{Result, anyhow};
 use eframe::egui;
+use std::sync::Arc;
+
+/// The machine the form registers, named after its host.
+fn registration_machine(app: &ManagerApp) -> Machine {
+    Machine {
+        id: None,
+        uuid: None,
+        name: app.reg_host.clone(),
+        ip_address: app.reg_host.clone(),
+        purpose: if app.generated_software_key {
+            "Software-key secure node".to_string()
+        } else {
+            "Hardware-bound secure node".to_string()
+        },
+        ownership: "personal".to_string(),
+        os_type: "linux".to_string(),
+        status: "active".to_string(),
+        group: None,
+        ssh_options: None,
+        jump_host_id: None,
+        requires_vpn: false,
+        env: None,
+    }
+}
+
+/// Whether the host key has to be confirmed before anything is sent to the host.
+fn needs_host_key_confirmation(app: &ManagerApp) -> bool {
+    let file = crate::known_hosts::active_file(&app.db);
+    !crate::known_hosts::is_known(
+        &app.reg_host,
+        crate::health::DEFAULT_SSH_PORT,
+        file.as_deref(),
+    )
+        && app
+            .db
+            .confirmed_host_key(&app.reg_host)
+            .ok()
+            .flatten()
+            .is_none()
+}
+
+/// Starts 接続テスト: logs in with the entered user and password (or ssh-agent) on a
+/// background thread, without saving anything.
+fn start_credential_test(app: &mut ManagerApp, ctx: &egui::Context) {
+    app.credential_test_result = None;
+    match crate::db::normalize_host(&app.reg_host) {
+        Ok(host) => app.reg_host = host,
+        Err(e) => {
+            app.credential_test_result = Some((false, e.to_string()));
+            return;
+        }
+    }
+    if needs_host_key_confirmation(app) {
+        app.credential_test_result = Some((
+            false,
+            "ホスト鍵がまだ確認されていないため、認証情報は送信していません。「登録を実行」でホスト鍵を確認してください。"
+                .to_string(),
+        ));
+        return;
+    }
+    let machine = registration_machine(app);
+    let (auth_type, credential) = if app.reg_use_agent {
+        (AuthType::Agent, String::new())
+    } else {
+        (AuthType::Password, app.reg_pass.clone())
+    };
+    let mut account = Account {
+        id: None,
+        machine_id: 0,
+        username: app.reg_user.clone(),
+        auth_type: auth_type.to_string(),
+        credential,
+        key_fingerprint: None,
+        note: None,
+    };
+    let (tx, rx) = std::sync::mpsc::channel();
+    let db = Arc::clone(&app.db);
+    let ctx = ctx.clone();
+    std::thread::spawn(move || {
+        let result = match diagnose::test_credentials(&db, &machine, &account) {
+            Ok(CredentialTest::Success) => (true, "接続に成功しました。".to_string()),
+            Ok(CredentialTest::Failed {
+                message,
+                suggestion,
+            }) => {
+                let mut text = format!("接続に失敗しました: {}", message);
+                if let Some(suggestion) = suggestion {
+                    text.push_str(&format!("\nヒント: {}", suggestion.message));
+                }
+                (false, text)
+            }
+            Ok(CredentialTest::TimedOut) => (
+                false,
+                format!(
+                    "{} 秒以内に応答がありませんでした。",
+                    diagnose::CREDENTIAL_TEST_TIMEOUT_SECS
+                ),
+            ),
+            Err(e) => (false, format!("接続テストを実行できませんでした: {}", e)),
+        };
+        account.credential.clear();
+        let _ = tx.send(result);
+        ctx.request_repaint();
+    });
+    app.credential_test_rx = Some(rx);
+}
+
+fn poll_credential_test(app: &mut ManagerApp) {
+    let Some(rx) = &app.credential_test_rx else {
+        return;
+    };
+    match rx.try_recv() {
+        Ok(result) => {
+            app.credential_test_result = Some(result);
+            app.credential_test_rx = None;
+        }
+        Err(std::sync::mpsc::TryRecvError::Empty) => {}
+        Err(std::sync::mpsc::TryRecvError::Disconnected) => {
+            app.credential_test_rx = None;
+        }
+    }
+}
 
 fn register_key_to_remote(app: &mut ManagerApp) -> Result<()> {
+    // Catch typos before the one-time password is spent on a bad host
+    app.reg_host = crate::db::normalize_host(&app.reg_host)?;
+    if app.db.machine_exists(&app.reg_host)? {
+        return Err(crate::db::DuplicateMachineName(app.reg_host.clone()).into());
+    }
     let pubkey = app
         .generated_pubkey
         .as_ref()
@@ -14,43 +143,41 @@ fn register_key_to_remote(app: &mut ManagerApp) -> Result<()> {
         pubkey
     );
 
-    println!(
-        "🚀 Using one-time password to register hardware identity on {}...",
-        app.reg_host
+    tracing::info!(
+        host = %app.reg_host,
+        "Using one-time password to register hardware identity"
     );
 
-    let (_stdout, stderr, exit_code) = rust_ssh::run_command(
-        &app.reg_host,
-        22,
-        &app.reg_user,
-        None,
-        Some(&app.reg_pass),
-        &command,
-    )?;
+    // With ssh-agent, pass neither key nor password so the engine uses the agent
+    let password = if app.reg_use_agent {
+        None
+    } else {
+        Some(app.reg_pass.as_str())
+    };
+    let (_stdout, stderr, exit_code) =
+        rust_ssh::run_command(&app.reg_host, 22, &app.reg_user, None, password, &command)?;
 
     app.reg_pass.clear();
 
     if exit_code == 0 {
-        let machine = Machine {
-            id: None,
-            name: app.reg_host.clone(),
-            ip_address: app.reg_host.clone(),
-            purpose: "Hardware-bound secure node".to_string(),
-            ownership: "personal".to_string(),
-            os_type: "linux".to_string(),
-            status: "active".to_string(),
-        };
-
-        let machine_id = app.db.add_machine(machine)?;
+        let machine = registration_machine(app);
 
+        // A software key reaches ssh through the agent, like a TPM-bound one
+        let (auth_type, credential) = if app.generated_software_key {
+            (AuthType::Agent, String::new())
+        } else {
+            (AuthType::Tpm, TPM_CREDENTIAL_MARKER.to_string())
+        };
         let account = Account {
             id: None,
-            machine_id,
+            machine_id: 0, // assigned on insert
             username: app.reg_user.clone(),
-            auth_type: "tpm".to_string(),
-            credential: "TPM_HARDWARE_BOUND".to_string(),
+            auth_type: auth_type.to_string(),
+            credential,
+            key_fingerprint: None,
+            note: None,
         };
-        app.db.add_account(account)?;
+        app.db.register_machine_with_account(machine, account)?;
 
         app.refresh();
         Ok(())
@@ -94,10 +221,16 @@ pub fn show(app: &mut ManagerApp, ui: &mut egui::Ui) {
                         [ManagerApp::FORM_LABEL_WIDTH, 28.0],
                         egui::Label::new("Secure Boot"),
                     );
-                    if app.secure_boot_enabled {
-                        ui.colored_label(egui::Color32::GREEN, "✅ 有効");
-                    } else {
-                        ui.colored_label(egui::Color32::YELLOW, "⚠ 無効 (または診断不可)");
+                    match app.secure_boot {
+                        SecureBootState::Enabled => {
+                            ui.colored_label(egui::Color32::GREEN, "✅ 有効");
+                        }
+                        SecureBootState::Disabled => {
+                            ui.colored_label(egui::Color32::RED, "❌ 無効");
+                        }
+                        SecureBootState::Unknown => {
+                            ui.colored_label(egui::Color32::YELLOW, "⚠ 診断不可");
+                        }
                     }
                 });
 
@@ -107,19 +240,30 @@ pub fn show(app: &mut ManagerApp, ui: &mut egui::Ui) {
             }
         }
         1 => {
-            ui.label("ステップ 2: ハードウェア識別鍵の生成");
-            ui.label("ハードウェア(TPM/Secure Enclave)内で秘密鍵を生成します。");
-            ui.label("生成された秘密鍵はデバイス外に持ち出すことはできません。");
+            if app.tpm_available {
+                ui.label("ステップ 2: ハードウェア識別鍵の生成");
+                ui.label("ハードウェア(TPM/Secure Enclave)内で秘密鍵を生成します。");
+                ui.label("生成された秘密鍵はデバイス外に持ち出すことはできません。");
+            } else {
+                ui.label("ステップ 2: ソフトウェア鍵の生成");
+                ui.label(
+                    "TPM が見つからないため、ed25519 鍵を生成してデータベースに暗号化して保存します。",
+                );
+                ui.label("秘密鍵はファイルに書き出さず、起動時に ssh-agent へ読み込みます。");
+            }
 
             ui.add_space(10.0);
             if ui.button("鍵を生成する").clicked() {
-                match rust_ssh::security::tpm::generate_tpm_key() {
+                app.error_message = None;
+                match crate::security::generate_identity_key(&app.db, app.tpm_available) {
                     Ok(key) => {
-                        app.generated_pubkey = Some(key);
+                        app.generated_pubkey = Some(key.public_key);
+                        app.generated_software_key = key.software;
                         app.onboarding_step = 2;
                     }
                     Err(e) => {
-                        eprintln!("鍵生成に失敗しました: {}", e);
+                        tracing::error!("Key generation failed: {}", e);
+                        app.error_message = Some(format!("鍵生成に失敗しました: {}", e));
                     }
                 }
             }
@@ -160,7 +304,11 @@ pub fn show(app: &mut ManagerApp, ui: &mut egui::Ui) {
             ui.add_space(8.0);
             ui.colored_label(
                 egui::Color32::YELLOW,
-                "⚠ 以降、このPCのハードウェアチップ(TPM)による認証に切り替わります。",
+                if app.generated_software_key {
+                    "⚠ 以降、このPCに保存されたソフトウェア鍵による認証に切り替わります。"
+                } else {
+                    "⚠ 以降、このPCのハードウェアチップ(TPM)による認証に切り替わります。"
+                },
             );
 
             egui::Grid::new("onboarding_register_grid")
@@ -189,23 +337,107 @@ pub fn show(app: &mut ManagerApp, ui: &mut egui::Ui) {
 
                     ui.add_sized(
                         [ManagerApp::FORM_LABEL_WIDTH, 28.0],
-                        egui::Label::new("パスワード"),
-                    );
-                    ui.add_sized(
-                        [ManagerApp::FORM_FIELD_WIDTH, 28.0],
-                        egui::TextEdit::singleline(&mut app.reg_pass).password(true),
+                        egui::Label::new("認証方式"),
                     );
+                    ui.checkbox(&mut app.reg_use_agent, "ssh-agent を使用する");
                     ui.end_row();
+
+                    if !app.reg_use_agent {
+                        ui.add_sized(
+                            [ManagerApp::FORM_LABEL_WIDTH, 28.0],
+                            egui::Label::new("パスワード"),
+                        );
+                        ui.add_sized(
+                            [ManagerApp::FORM_FIELD_WIDTH, 28.0],
+                            egui::TextEdit::singleline(&mut app.reg_pass).password(true),
+                        );
+                        ui.end_row();
+                    }
                 });
 
             ui.add_space(8.0);
-            if ui.button("登録を実行（一回限りのパスワード）").clicked() {
-                match register_key_to_remote(app) {
-                    Ok(_) => {
+            poll_credential_test(app);
+            ui.horizontal(|ui| {
+                if app.credential_test_rx.is_some() {
+                    ui.spinner();
+                    ui.label("接続テスト中…");
+                } else if ui
+                    .button("接続テスト")
+                    .on_hover_text("入力した認証情報でログインできるかを、保存せずに確認します")
+                    .clicked()
+                {
+                    start_credential_test(app, ui.ctx());
+                }
+            });
+            if let Some((ok, message)) = &app.credential_test_result {
+                let color = if *ok {
+                    egui::Color32::GREEN
+                } else {
+                    egui::Color32::RED
+                };
+                ui.add(
+                    egui::Label::new(egui::RichText::new(message).color(color)).selectable(true),
+                );
+            }
+            ui.add_space(8.0);
+            if let Some(scanned) = app.pending_host_key.clone() {
+                ui.group(|ui| {
+                    ui.colored_label(
+                        egui::Color32::YELLOW,
+                        format!("{} のホスト鍵はまだ信頼されていません。", scanned.host),
+                    );
+                    ui.label(
+                        "サーバー管理者に確認したフィンガープリントと一致する場合のみ続行してください。",
+                    );
+                    for fp in &scanned.fingerprints {
+                        ui.label(egui::RichText::new(fp).monospace());
+                    }
+                    ui.horizontal(|ui| {
+                        if ui.button("信頼して続行").clicked() {
+                            app.error_message = None;
+                            app.pending_host_key = None;
+                            let fingerprint = scanned.fingerprints.join(", ");
+                            let file = crate::known_hosts::active_file(&app.db);
+                            let trusted = crate::known_hosts::add_to_known_hosts(
+                                &scanned,
+                                file.as_deref(),
+                            )
+                                .and_then(|_| app.db.confirm_host_key(&scanned.host, &fingerprint));
+                            match trusted.and_then(|_| register_key_to_remote(app)) {
+                                Ok(_) => app.onboarding_step = 4,
+                                Err(e) => {
+                                    tracing::error!("Remote key registration failed: {}", e);
+                                    app.report_error("登録に失敗しました", &e);
+                                }
+                            }
+                        }
+                        if ui.button("キャンセル").clicked() {
+                            app.pending_host_key = None;
+                        }
+                    });
+                });
+            } else if ui.button("登録を実行（一回限りのパスワード）").clicked() {
+                app.error_message = None;
+                let result = if needs_host_key_confirmation(app) {
+                    crate::known_hosts::scan_host_key(
+                        &app.reg_host,
+                        crate::health::DEFAULT_SSH_PORT,
+                    )
+                    .map(|scanned| {
+                        app.pending_host_key = Some(scanned);
+                        false
+                    })
+                } else {
+                    register_key_to_remote(app).map(|_| true)
+                };
+                match result {
+                    Ok(true) => {
                         app.onboarding_step = 4;
                     }
+                    Ok(false) => {}
                     Err(e) => {
-                        eprintln!("登録に失敗しました: {}", e);
+                        tracing::error!("Remote key registration failed: {}", e);
+                        app.report_error("登録に失敗しました", &e);
                     }
                 }
             }
@@ -217,7 +449,13 @@ pub fn show(app: &mut ManagerApp, ui: &mut egui::Ui) {
         4 => {
             ui.label("ワンタイム登録が完了しました。");
             ui.label("パスワードはメモリから即座に破棄されました。");
-            ui.label("データベースには「ハードウェア（TPM）認証」として登録されています。");
+            if app.generated_software_key {
+                ui.label(
+                    "データベースには「ssh-agent 認証（ソフトウェア鍵）」として登録されています。",
+                );
+            } else {
+                ui.label("データベースには「ハードウェア（TPM）認証」として登録されています。");
+            }
             ui.add_space(12.0);
             if ui.button("終了してダッシュボードへ").clicked() {
                 app.current_tab = crate::gui::Tab::Connections;
@@ -0,0 +1,317 @@
+use crate::db;
+use crate::gui::{
+    MacTerminal, ManagerApp, SETTING_AUTO_BACKUP, SETTING_IDLE_LOCK_MINUTES,
+    SETTING_MACOS_TERMINAL, SETTING_RETENTION_COUNT, SETTING_RETENTION_DAYS,
+    SETTING_TERMINAL_COMMAND, open_in_file_manager,
+};
+use crate::known_hosts;
+use eframe::egui;
+use std::path::Path;
+
+fn save_settings(app: &mut ManagerApp) -> Result<(), String> {
+    let count: u64 = app
+        .retention_count_input
+        .trim()
+        .parse()
+        .ok()
+        .filter(|n| *n >= 1)
+        .ok_or_else(|| "保持数には1以上の整数を入力してください".to_string())?;
+    let days = app.retention_days_input.trim();
+    let days: u64 = if days.is_empty() {
+        0
+    } else {
+        days.parse()
+            .map_err(|_| "保持日数には整数を入力してください".to_string())?
+    };
+    let rate_limit: u64 = app
+        .rate_limit_input
+        .trim()
+        .parse()
+        .map_err(|_| "接続間隔には0以上の整数を入力してください".to_string())?;
+    let reminder_days: u64 = app
+        .rotation_reminder_days_input
+        .trim()
+        .parse()
+        .map_err(|_| "ローテーション通知の日数には0以上の整数を入力してください".to_string())?;
+    let idle_lock_minutes: u64 = app
+        .idle_lock_minutes_input
+        .trim()
+        .parse()
+        .map_err(|_| "自動ロックの時間には0以上の整数を入力してください".to_string())?;
+
+    app.db
+        .set_setting(
+            SETTING_AUTO_BACKUP,
+            if app.auto_backup_input {
+                "true"
+            } else {
+                "false"
+            },
+        )
+        .and_then(|_| {
+            app.db
+                .set_setting(SETTING_RETENTION_COUNT, &count.to_string())
+        })
+        .and_then(|_| {
+            app.db
+                .set_setting(SETTING_RETENTION_DAYS, &days.to_string())
+        })
+        .and_then(|_| {
+            app.db
+                .set_setting(db::SETTING_SSH_RATE_LIMIT_SECS, &rate_limit.to_string())
+        })
+        .and_then(|_| {
+            app.db.set_setting(
+                db::SETTING_ROTATION_REMINDER_DAYS,
+                &reminder_days.to_string(),
+            )
+        })
+        .and_then(|_| {
+            app.db
+                .set_setting(SETTING_IDLE_LOCK_MINUTES, &idle_lock_minutes.to_string())
+        })
+        .and_then(|_| {
+            app.db
+                .set_setting(SETTING_TERMINAL_COMMAND, app.terminal_command_input.trim())
+        })
+        .and_then(|_| {
+            app.db.set_setting(
+                SETTING_MACOS_TERMINAL,
+                app.macos_terminal_input.setting_value(),
+            )
+        })
+        .and_then(|_| {
+            app.db.set_setting(
+                known_hosts::SETTING_MANAGED_FILE,
+                if app.managed_known_hosts_input {
+                    "true"
+                } else {
+                    "false"
+                },
+            )
+        })
+        .map_err(|e| format!("保存に失敗しました: {}", e))
+}
+
+/// Read-only path row with a button that opens `dir` in the file manager.
+fn path_row(app: &mut ManagerApp, ui: &mut egui::Ui, label: &str, shown: &Path, dir: &Path) {
+    ui.add_sized(
+        [ManagerApp::FORM_LABEL_WIDTH, 28.0],
+        egui::Label::new(label),
+    );
+    ui.horizontal(|ui| {
+        ui.label(egui::RichText::new(shown.display().to_string()).monospace());
+        if ui.small_button("フォルダを開く").clicked()
+            && let Err(e) = open_in_file_manager(dir)
+        {
+            app.error_message = Some(format!("フォルダを開けませんでした: {}", e));
+        }
+    });
+    ui.end_row();
+}
+
+fn show_locations(app: &mut ManagerApp, ui: &mut egui::Ui) {
+    ui.label(egui::RichText::new("データの場所").strong());
+    let db_path = app.db.db_path().to_path_buf();
+    let db_dir = db_path
+        .parent()
+        .map(Path::to_path_buf)
+        .unwrap_or_else(|| db_path.clone());
+    let backup_dir = db::backup_dir().ok();
+    let log_dir = db::log_dir().ok();
+
+    egui::Grid::new("settings_locations_grid")
+        .num_columns(2)
+        .spacing([8.0, 8.0])
+        .show(ui, |ui| {
+            path_row(app, ui, "データベース", &db_path, &db_dir);
+            if let Some(dir) = &backup_dir {
+                path_row(app, ui, "バックアップ", dir, dir);
+                let count = std::fs::read_dir(dir)
+                    .map(|entries| entries.flatten().filter(|e| e.path().is_file()).count())
+                    .unwrap_or(0);
+                ui.add_sized(
+                    [ManagerApp::FORM_LABEL_WIDTH, 28.0],
+                    egui::Label::new("バックアップ数"),
+                );
+                ui.label(format!("{} 件", count));
+                ui.end_row();
+            }
+            if let Some(dir) = &log_dir {
+                path_row(app, ui, "ログ", dir, dir);
+            }
+        });
+
+    if ui.button("データベースを最適化").clicked() {
+        app.settings_message = Some(match app.db.maintenance() {
+            Ok(report) => format!(
+                "最適化しました: {} KB → {} KB",
+                report.size_before / 1024,
+                report.size_after / 1024
+            ),
+            Err(e) => format!("最適化に失敗しました: {}", e),
+        });
+    }
+}
+
+pub fn show(app: &mut ManagerApp, ui: &mut egui::Ui) {
+    ui.add_space(ManagerApp::SECTION_GAP);
+    ui.heading("設定");
+    ui.separator();
+
+    show_locations(app, ui);
+
+    ui.add_space(ManagerApp::SECTION_GAP);
+
+    ui.label(egui::RichText::new("自動バックアップ").strong());
+    egui::Grid::new("settings_backup_grid")
+        .num_columns(2)
+        .spacing([8.0, 8.0])
+        .show(ui, |ui| {
+            ui.add_sized(
+                [ManagerApp::FORM_LABEL_WIDTH, 28.0],
+                egui::Label::new("有効"),
+            );
+            ui.checkbox(
+                &mut app.auto_backup_input,
+                "起動時に1日1回バックアップを作成する",
+            );
+            ui.end_row();
+
+            ui.add_sized(
+                [ManagerApp::FORM_LABEL_WIDTH, 28.0],
+                egui::Label::new("保持数"),
+            );
+            ui.add_sized(
+                [ManagerApp::FORM_FIELD_WIDTH, 28.0],
+                egui::TextEdit::singleline(&mut app.retention_count_input),
+            );
+            ui.end_row();
+
+            ui.add_sized(
+                [ManagerApp::FORM_LABEL_WIDTH, 28.0],
+                egui::Label::new("保持日数"),
+            );
+            ui.add_sized(
+                [ManagerApp::FORM_FIELD_WIDTH, 28.0],
+                egui::TextEdit::singleline(&mut app.retention_days_input)
+                    .hint_text("空欄 = 保持数で削除 / 指定時は日数のみ"),
+            );
+            ui.end_row();
+        });
+
+    ui.add_space(ManagerApp::SECTION_GAP);
+    ui.label(egui::RichText::new("ターミナル").strong());
+    egui::Grid::new("settings_terminal_grid")
+        .num_columns(2)
+        .spacing([8.0, 8.0])
+        .show(ui, |ui| {
+            ui.add_sized(
+                [ManagerApp::FORM_LABEL_WIDTH, 28.0],
+                egui::Label::new("起動コマンド"),
+            );
+            ui.add_sized(
+                [ManagerApp::FORM_FIELD_WIDTH, 28.0],
+                egui::TextEdit::singleline(&mut app.terminal_command_input)
+                    .hint_text("例: wezterm start -- ssh {host}"),
+            );
+            ui.end_row();
+
+            if cfg!(target_os = "macos") {
+                ui.add_sized(
+                    [ManagerApp::FORM_LABEL_WIDTH, 28.0],
+                    egui::Label::new("macOS のターミナル"),
+                );
+                egui::ComboBox::from_id_source("settings_macos_terminal")
+                    .selected_text(app.macos_terminal_input.label())
+                    .width(ManagerApp::FORM_FIELD_WIDTH)
+                    .show_ui(ui, |ui| {
+                        for choice in MacTerminal::ALL {
+                            ui.selectable_value(
+                                &mut app.macos_terminal_input,
+                                choice,
+                                choice.label(),
+                            );
+                        }
+                    });
+                ui.end_row();
+            }
+
+            ui.add_sized(
+                [ManagerApp::FORM_LABEL_WIDTH, 28.0],
+                egui::Label::new("known_hosts"),
+            );
+            ui.vertical(|ui| {
+                ui.checkbox(
+                    &mut app.managed_known_hosts_input,
+                    "~/.ssh/known_hosts の代わりに専用ファイルを使用する",
+                );
+                if let Ok(path) = known_hosts::managed_path() {
+                    ui.label(egui::RichText::new(path.display().to_string()).small());
+                }
+            });
+            ui.end_row();
+        });
+
+    ui.add_space(ManagerApp::SECTION_GAP);
+    ui.label(egui::RichText::new("セキュリティ").strong());
+    egui::Grid::new("settings_security_grid")
+        .num_columns(2)
+        .spacing([8.0, 8.0])
+        .show(ui, |ui| {
+            ui.add_sized(
+                [ManagerApp::FORM_LABEL_WIDTH, 28.0],
+                egui::Label::new("キーのローテーション通知(日)"),
+            );
+            ui.add_sized(
+                [ManagerApp::FORM_FIELD_WIDTH, 28.0],
+                egui::TextEdit::singleline(&mut app.rotation_reminder_days_input)
+                    .hint_text("前回のローテーションからこの日数で通知。0 = 通知しない"),
+            );
+            ui.end_row();
+
+            ui.add_sized(
+                [ManagerApp::FORM_LABEL_WIDTH, 28.0],
+                egui::Label::new("自動ロック(分)"),
+            );
+            ui.add_sized(
+                [ManagerApp::FORM_FIELD_WIDTH, 28.0],
+                egui::TextEdit::singleline(&mut app.idle_lock_minutes_input)
+                    .hint_text("操作がないとこの時間でロック。0 = ロックしない"),
+            );
+            ui.end_row();
+        });
+
+    ui.add_space(ManagerApp::SECTION_GAP);
+    ui.label(egui::RichText::new("MCP").strong());
+    egui::Grid::new("settings_mcp_grid")
+        .num_columns(2)
+        .spacing([8.0, 8.0])
+        .show(ui, |ui| {
+            ui.add_sized(
+                [ManagerApp::FORM_LABEL_WIDTH, 28.0],
+                egui::Label::new("SSH接続の最小間隔(秒)"),
+            );
+            ui.add_sized(
+                [ManagerApp::FORM_FIELD_WIDTH, 28.0],
+                egui::TextEdit::singleline(&mut app.rate_limit_input)
+                    .hint_text("同じ接続先への診断・コマンド実行の間隔。0 = 制限なし"),
+            );
+            ui.end_row();
+        });
+
+    ui.add_space(8.0);
+    if ui.button("保存").clicked() {
+        app.settings_message = Some(match save_settings(app) {
+            Ok(()) => {
+                // Re-evaluates the rotation reminder against the new interval
+                app.refresh();
+                "保存しました".to_string()
+            }
+            Err(e) => e,
+        });
+    }
+    if let Some(msg) = &app.settings_message {
+        ui.label(msg);
+    }
+}
@@ -1,90 +1,401 @@
-use crate::db::{Account, DbHandler, Machine};
-use anyhow::Result;
+use crate::db::{Account, ConnectionStats, DbHandler, Machine};
+use crate::health;
+use crate::security::SecureBootState;
+use anyhow::{Result, anyhow};
 use eframe::egui;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::sync::Arc;
+use std::sync::mpsc::Receiver;
+use std::time::{Duration, Instant, SystemTime};
 
 mod gui_tabs;
+mod startup_error;
+
+pub use startup_error::show_startup_error;
+
+pub const SETTING_RETENTION_COUNT: &str = "backup.retention_count";
+pub const SETTING_RETENTION_DAYS: &str = "backup.retention_days";
+pub const DEFAULT_RETENTION_COUNT: u64 = 5;
+/// "false" turns the daily automatic backup off; anything else (or unset) leaves it on
+pub const SETTING_AUTO_BACKUP: &str = "backup.auto";
+/// Free space the backup volume must keep beyond the database's own size
+const AUTO_BACKUP_MIN_FREE_BYTES: u64 = 256 * 1024 * 1024;
+/// File name prefix of automatic backups; retention leaves every other file alone
+const AUTO_BACKUP_PREFIX: &str = "auto_backup_";
+pub const SETTING_TERMINAL_COMMAND: &str = "terminal.command";
+/// Terminal app on macOS: "terminal", "iterm2", or "custom" (use `terminal.command`)
+pub const SETTING_MACOS_TERMINAL: &str = "macos.terminal";
+/// Minutes without input after which the window locks and the master key is wiped; 0 disables.
+pub const SETTING_IDLE_LOCK_MINUTES: &str = "gui.idle_lock_minutes";
+pub const DEFAULT_IDLE_LOCK_MINUTES: u64 = 10;
+/// JSON array of the machine groups collapsed in the Connections list ("" is the ungrouped bucket).
+pub const SETTING_COLLAPSED_GROUPS: &str = "connections.collapsed_groups";
+/// Section title for machines without a group.
+pub const UNGROUPED_LABEL: &str = "未分類";
+/// How long closing the window waits for a running MCP request.
+const MCP_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Data reloaded by [`ManagerApp::refresh`], tracked separately so a failed query marks
+/// only the panels showing it.
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
+pub enum LoadedData {
+    Machines,
+    Accounts,
+    CredentialStatus,
+    Logs,
+    ConnectionStats,
+    Summary,
+}
+
+impl LoadedData {
+    pub fn label(self) -> &'static str {
+        match self {
+            LoadedData::Machines => "接続先",
+            LoadedData::Accounts => "アカウント",
+            LoadedData::CredentialStatus => "認証情報の状態",
+            LoadedData::Logs => "ログ",
+            LoadedData::ConnectionStats => "接続統計",
+            LoadedData::Summary => "概要",
+        }
+    }
+
+    /// Data a tab displays, whose load failures are shown above it.
+    fn shown_on(tab: Tab) -> &'static [LoadedData] {
+        match tab {
+            Tab::Connections => &[
+                LoadedData::Machines,
+                LoadedData::Accounts,
+                LoadedData::CredentialStatus,
+                LoadedData::ConnectionStats,
+                LoadedData::Summary,
+            ],
+            Tab::Accounts => &[
+                LoadedData::Machines,
+                LoadedData::Accounts,
+                LoadedData::CredentialStatus,
+            ],
+            Tab::Logs => &[LoadedData::Logs],
+            Tab::Knowledge | Tab::Onboarding | Tab::Settings => &[],
+        }
+    }
+}
+
+/// Terminal app the connect button opens on macOS.
+#[derive(PartialEq, Debug, Clone, Copy, Default)]
+pub enum MacTerminal {
+    #[default]
+    Terminal,
+    ITerm2,
+    /// `terminal.command`; Terminal.app when that is empty
+    Custom,
+}
+
+impl MacTerminal {
+    pub const ALL: [MacTerminal; 3] = [
+        MacTerminal::Terminal,
+        MacTerminal::ITerm2,
+        MacTerminal::Custom,
+    ];
+
+    pub fn setting_value(self) -> &'static str {
+        match self {
+            MacTerminal::Terminal => "terminal",
+            MacTerminal::ITerm2 => "iterm2",
+            MacTerminal::Custom => "custom",
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            MacTerminal::Terminal => "ターミナル.app",
+            MacTerminal::ITerm2 => "iTerm2",
+            MacTerminal::Custom => "起動コマンドを使用",
+        }
+    }
+
+    /// The `macos.terminal` setting; when unset, `Custom` if a `terminal.command` is
+    /// configured (as before the setting existed), else Terminal.app.
+    pub fn from_settings(db: &DbHandler) -> Self {
+        let stored = db.get_setting(SETTING_MACOS_TERMINAL).ok().flatten();
+        match stored.as_deref() {
+            Some("terminal") => MacTerminal::Terminal,
+            Some("iterm2") => MacTerminal::ITerm2,
+            Some("custom") => MacTerminal::Custom,
+            _ => {
+                let command = db.get_setting(SETTING_TERMINAL_COMMAND).ok().flatten();
+                if command.is_some_and(|c| !c.trim().is_empty()) {
+                    MacTerminal::Custom
+                } else {
+                    MacTerminal::Terminal
+                }
+            }
+        }
+    }
+}
+
+/// Name and host for a copy of machine `source_id`, being entered in the 複製 window.
+pub struct CloneDraft {
+    pub source_id: i64,
+    pub name: String,
+    pub host: String,
+    pub error: Option<String>,
+}
 
 #[derive(PartialEq, Debug, Clone, Copy)]
 pub enum Tab {
     Connections,
     Accounts,
     Logs,
+    Knowledge,
     Onboarding,
+    Settings,
 }
 
 pub struct ManagerApp {
     pub db: Arc<DbHandler>,
     pub current_tab: Tab,
+    /// Shown as a red banner above the current tab until dismissed or the action is retried
+    pub error_message: Option<String>,
+    /// Name of an existing machine the last error collided with; the banner offers to edit it
+    pub duplicate_machine: Option<String>,
     pub machines: Vec<Machine>,
     pub accounts: Vec<Account>,
+    /// Checked on refresh so the UI can flag unusable accounts without holding secrets
+    pub credential_status: HashMap<i64, crate::db::CredentialStatus>,
+    pub connection_stats: HashMap<i64, ConnectionStats>,
+    /// Data whose last reload failed, with the error; what is shown for it may be stale
+    pub load_errors: HashMap<LoadedData, String>,
+    /// Counts shown in the Connections tab header, reloaded with the rest of the data
+    pub summary: Option<crate::db::Summary>,
+    /// Groups folded away in the Connections list; new groups start expanded
+    pub collapsed_groups: HashSet<String>,
     pub search_query: String,
+    /// Set by Ctrl+F; the search box takes focus on its next frame
+    pub focus_search: bool,
+    /// Top-bar search across machines, accounts, logs, and constraints
+    pub global_search_query: String,
+    /// Matches of [`Self::global_search_query`]; the results window shows while this is set
+    pub global_search: Option<crate::db::SearchResults>,
+    /// Keyboard selection in the Connections list (Up/Down to move, Enter to connect)
+    pub selected_machine: Option<i64>,
+    /// The selection moved by keyboard and should be scrolled into view
+    pub scroll_to_selected: bool,
+    /// Only machines with this status are listed; `None` shows all
+    pub status_filter: Option<String>,
+    /// List machines needing attention (broken, then maintenance) first within each group
+    pub sort_by_status: bool,
+    /// Most recently deleted machine, offered for "元に戻す" until the next deletion
+    pub last_deleted: Option<crate::db::DeletedSnapshot>,
+    /// The key rotation reminder was closed for this session
+    pub rotation_reminder_dismissed: bool,
+    /// Bottom panel with [`crate::logging::recent_messages`]
+    pub show_recent_messages: bool,
 
     pub adding_machine: Option<Machine>,
+    pub editing_machine: Option<Machine>,
+    /// Machine whose account picker is open (it has more than one account)
+    pub choosing_account_for: Option<i64>,
+    pub cloning_machine: Option<CloneDraft>,
+    /// Query of the Ctrl+K quick connect window while it is open
+    pub command_palette: Option<String>,
+    pub edit_error: Option<String>,
     pub updating_account: Option<(i64, String)>,
+    /// Account whose note is being edited in the machine detail window, with the draft
+    pub editing_account_note: Option<(i64, String)>,
+    /// Diagnose history of the machine in the detail window; loaded on demand, dropped on refresh
+    pub diagnostics: Option<(i64, Vec<crate::db::Diagnostic>)>,
     pub new_credential: String,
+    /// Show `new_credential` in clear text instead of masked
+    pub reveal_credential: bool,
     pub logs: Vec<crate::db::CommandLog>,
+    /// Log entry picked from the search results, highlighted in the Logs tab
+    pub highlighted_log: Option<i64>,
+    /// The highlighted log entry should be scrolled into view
+    pub scroll_to_log: bool,
+
+    /// Last keyboard or pointer input, for [`SETTING_IDLE_LOCK_MINUTES`]
+    pub last_interaction: Instant,
+    /// Idle time before locking, read from the settings on refresh; `None` never locks
+    pub idle_lock_after: Option<Duration>,
+    /// Passphrase typed on the lock screen
+    pub unlock_passphrase: String,
+    pub unlock_error: Option<String>,
+    /// Opened locked in passphrase mode (see [`DbHandler::new_without_prompt`]) and not
+    /// unlocked since
+    pub locked_at_startup: bool,
+
+    // Auto-refresh: reload only when the DB file changed (e.g. MCP thread wrote to it)
+    pub last_refresh_check: Instant,
+    pub db_mtime: Option<SystemTime>,
+
+    // Connectivity sweep running on a background thread
+    pub sweep_rx: Option<Receiver<Vec<(i64, bool)>>>,
+    pub last_sweep: Option<(usize, usize)>, // (reachable, total)
+    /// Latest background TCP check of each machine's SSH port; memory only
+    pub reachability: HashMap<i64, bool>,
+    pub reachability_rx: Option<Receiver<Vec<(i64, bool)>>>,
+    /// VPN check of a connection about to open, run off the UI thread
+    pub vpn_check_rx: Option<Receiver<(Machine, Account, Result<()>)>>,
+
+    /// Stop signal of the MCP server thread, waited on when the window closes
+    pub mcp_shutdown: Option<Arc<crate::shutdown::Shutdown>>,
+    /// Changes made through the MCP server thread, drained every frame
+    pub mcp_events: Option<Arc<crate::events::Events>>,
+    /// Password files handed to launched terminals, removed once expired or on exit
+    pub password_feeds: Vec<crate::askpass::PasswordFeed>,
+    /// Short notices in the bottom-right corner, with when each was shown
+    pub toasts: Vec<(String, Instant)>,
+    /// Automatic backup started after the window opened, joined when it closes
+    pub auto_backup: Option<std::thread::JoinHandle<()>>,
+    /// Automatic backup requested while locked, started once the app is unlocked
+    pub auto_backup_pending: bool,
 
     // Onboarding State
     pub onboarding_step: usize,
     pub tpm_available: bool,
-    pub secure_boot_enabled: bool,
+    pub secure_boot: SecureBootState,
     pub generated_pubkey: Option<String>,
+    /// Whether the generated key is a software key (no TPM was found)
+    pub generated_software_key: bool,
     pub reg_host: String,
     pub reg_user: String,
     pub reg_pass: String,
+    pub reg_use_agent: bool,
+    /// Host key awaiting the user's confirmation before registration continues
+    pub pending_host_key: Option<crate::known_hosts::ScannedHostKey>,
+    /// 接続テスト of the registration form running on a background thread
+    pub credential_test_rx: Option<Receiver<(bool, String)>>,
+    /// Whether the last 接続テスト succeeded, with the message shown under the form
+    pub credential_test_result: Option<(bool, String)>,
+
+    // Settings tab form state (edited as text, validated on save)
+    pub retention_count_input: String,
+    pub retention_days_input: String,
+    pub terminal_command_input: String,
+    pub macos_terminal_input: MacTerminal,
+    pub rate_limit_input: String,
+    pub managed_known_hosts_input: bool,
+    pub auto_backup_input: bool,
+    pub rotation_reminder_days_input: String,
+    pub idle_lock_minutes_input: String,
+    pub settings_message: Option<String>,
+
+    // Knowledge tab editor state; the file is read when the tab is first opened
+    pub knowledge_patterns: Option<Vec<crate::knowledge::Pattern>>,
+    pub knowledge_selected: Option<usize>,
+    pub knowledge_sample: String,
+    pub knowledge_message: Option<String>,
 }
 
 impl ManagerApp {
     pub const FORM_LABEL_WIDTH: f32 = 132.0;
     pub const FORM_FIELD_WIDTH: f32 = 340.0;
     pub const SECTION_GAP: f32 = 12.0;
+    const AUTO_REFRESH_INTERVAL: Duration = Duration::from_secs(2);
+    /// Pause between background reachability checks.
+    const REACHABILITY_INTERVAL: Duration = Duration::from_secs(60);
+    /// How long a toast stays in the corner.
+    const TOAST_DURATION: Duration = Duration::from_secs(5);
 
     pub fn new(_cc: &eframe::CreationContext<'_>, db: Arc<DbHandler>) -> Self {
-        // Keep defaults first; add Japanese font as fallback to avoid oversized/imbalanced text.
-        let mut fonts = egui::FontDefinitions::default();
-        if let Ok(font_data) = std::fs::read("/System/Library/Fonts/Hiragino Sans GB.ttc") {
-            fonts
-                .font_data
-                .insert("Hiragino".to_owned(), egui::FontData::from_owned(font_data));
-            fonts
-                .families
-                .entry(egui::FontFamily::Proportional)
-                .or_default()
-                .push("Hiragino".to_owned());
-            fonts
-                .families
-                .entry(egui::FontFamily::Monospace)
-                .or_default()
-                .push("Hiragino".to_owned());
-        } else {
-            eprintln!("Failed to load system font for Japanese characters.");
-        }
-
-        _cc.egui_ctx.set_fonts(fonts);
+        configure_fonts(&_cc.egui_ctx);
         Self::apply_global_ui_style(&_cc.egui_ctx);
 
         let mut app = Self {
             db,
             current_tab: Tab::Connections,
+            error_message: None,
+            duplicate_machine: None,
             machines: Vec::new(),
             accounts: Vec::new(),
+            credential_status: HashMap::new(),
+            connection_stats: HashMap::new(),
+            load_errors: HashMap::new(),
+            summary: None,
+            collapsed_groups: HashSet::new(),
             search_query: String::new(),
+            focus_search: false,
+            global_search_query: String::new(),
+            global_search: None,
+            selected_machine: None,
+            scroll_to_selected: false,
+            status_filter: None,
+            sort_by_status: false,
+            last_deleted: None,
+            rotation_reminder_dismissed: false,
+            show_recent_messages: false,
 
             adding_machine: None,
+            editing_machine: None,
+            choosing_account_for: None,
+            cloning_machine: None,
+            command_palette: None,
+            edit_error: None,
             updating_account: None,
+            editing_account_note: None,
+            diagnostics: None,
             new_credential: String::new(),
+            reveal_credential: false,
             logs: Vec::new(),
+            highlighted_log: None,
+            scroll_to_log: false,
+            last_interaction: Instant::now(),
+            idle_lock_after: None,
+            unlock_passphrase: String::new(),
+            unlock_error: None,
+            locked_at_startup: false,
+            last_refresh_check: Instant::now(),
+            db_mtime: None,
+            sweep_rx: None,
+            last_sweep: None,
+            reachability: HashMap::new(),
+            reachability_rx: None,
+            vpn_check_rx: None,
+            mcp_shutdown: None,
+            mcp_events: None,
+            password_feeds: Vec::new(),
+            toasts: Vec::new(),
+            auto_backup: None,
+            auto_backup_pending: false,
             onboarding_step: 0,
             tpm_available: false,
-            secure_boot_enabled: false,
+            secure_boot: SecureBootState::Unknown,
             generated_pubkey: None,
+            generated_software_key: false,
             reg_host: String::new(),
             reg_user: String::new(),
             reg_pass: String::new(),
+            reg_use_agent: false,
+            pending_host_key: None,
+            credential_test_rx: None,
+            credential_test_result: None,
+            retention_count_input: String::new(),
+            retention_days_input: String::new(),
+            terminal_command_input: String::new(),
+            macos_terminal_input: MacTerminal::Terminal,
+            rate_limit_input: String::new(),
+            managed_known_hosts_input: false,
+            auto_backup_input: true,
+            rotation_reminder_days_input: String::new(),
+            idle_lock_minutes_input: String::new(),
+            settings_message: None,
+            knowledge_patterns: None,
+            knowledge_selected: None,
+            knowledge_sample: String::new(),
+            knowledge_message: None,
         };
+        app.locked_at_startup = app.db.is_locked();
         app.refresh();
+        app.load_settings_form();
+        app.collapsed_groups = app
+            .db
+            .get_setting(SETTING_COLLAPSED_GROUPS)
+            .ok()
+            .flatten()
+            .and_then(|v| serde_json::from_str(&v).ok())
+            .unwrap_or_default();
         app.check_security_features();
+        app.start_reachability_monitor(&_cc.egui_ctx);
         app
     }
 
@@ -116,61 +427,1116 @@ impl ManagerApp {
         ctx.set_style(style);
     }
 
+    /// Reloads everything from the database. A failed query keeps the previous data and is
+    /// recorded in `load_errors` so the affected tabs show it as stale; auto-refresh keeps
+    /// retrying until every query succeeds.
     pub fn refresh(&mut self) {
-        if let Ok(machines) = self.db.list_machines() {
+        self.db_mtime = self.current_db_mtime();
+        self.diagnostics = None;
+        let db = Arc::clone(&self.db);
+        if let Some(machines) = self.track_load(LoadedData::Machines, db.list_machines()) {
             self.machines = machines;
         }
-        if let Ok(accounts) = self.db.list_accounts() {
+        if let Some(accounts) = self.track_load(LoadedData::Accounts, db.list_accounts()) {
             self.accounts = accounts;
         }
-        if let Ok(logs) = self.db.list_logs() {
+        let statuses = db.credential_statuses();
+        if let Some(statuses) = self.track_load(LoadedData::CredentialStatus, statuses) {
+            self.credential_status = statuses;
+        }
+        if let Some(logs) = self.track_load(LoadedData::Logs, db.list_logs()) {
             self.logs = logs;
         }
+        let stats = db.list_connection_stats();
+        if let Some(stats) = self.track_load(LoadedData::ConnectionStats, stats) {
+            self.connection_stats = stats.into_iter().map(|s| (s.machine_id, s)).collect();
+        }
+        if let Some(summary) = self.track_load(LoadedData::Summary, db.summary()) {
+            self.summary = Some(summary);
+        }
+        self.idle_lock_after =
+            match self.setting_or(SETTING_IDLE_LOCK_MINUTES, DEFAULT_IDLE_LOCK_MINUTES) {
+                0 => None,
+                minutes => Some(Duration::from_secs(minutes * 60)),
+            };
+        if !self.load_errors.is_empty() {
+            // Lets auto-refresh retry on its next check even if the file doesn't change
+            self.db_mtime = None;
+        }
+    }
+
+    fn track_load<T>(&mut self, data: LoadedData, result: Result<T>) -> Option<T> {
+        match result {
+            Ok(value) => {
+                self.load_errors.remove(&data);
+                Some(value)
+            }
+            Err(e) => {
+                tracing::warn!("Failed to load {:?}: {:#}", data, e);
+                self.load_errors.insert(data, format!("{:#}", e));
+                None
+            }
+        }
+    }
+
+    /// Red strip above the current tab listing data that failed to load, with a retry.
+    fn render_load_errors(&mut self, ui: &mut egui::Ui) {
+        let failed: Vec<(LoadedData, &String)> = LoadedData::shown_on(self.current_tab)
+            .iter()
+            .filter_map(|data| Some((*data, self.load_errors.get(data)?)))
+            .collect();
+        if failed.is_empty() {
+            return;
+        }
+        let mut retry = false;
+        egui::Frame::none()
+            .fill(egui::Color32::from_rgb(90, 20, 24))
+            .rounding(egui::Rounding::same(6.0))
+            .inner_margin(egui::Margin::same(8.0))
+            .show(ui, |ui| {
+                ui.horizontal(|ui| {
+                    ui.vertical(|ui| {
+                        ui.label(
+                            egui::RichText::new(
+                                "データ取得に失敗しました（表示は古い可能性があります）",
+                            )
+                            .strong()
+                            .color(egui::Color32::WHITE),
+                        );
+                        for (data, error) in &failed {
+                            ui.label(
+                                egui::RichText::new(format!("{}: {}", data.label(), error))
+                                    .small()
+                                    .color(egui::Color32::WHITE),
+                            );
+                        }
+                    });
+                    ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                        if ui.small_button("再試行").clicked() {
+                            retry = true;
+                        }
+                    });
+                });
+            });
+        if retry {
+            self.refresh();
+        }
+    }
+
+    /// Header strip for the Connections tab: machines per status, accounts, logs, last backup.
+    pub fn show_summary_header(&self, ui: &mut egui::Ui) {
+        let Some(summary) = &self.summary else {
+            return;
+        };
+        egui::Frame::none()
+            .fill(egui::Color32::from_rgb(30, 30, 33))
+            .rounding(egui::Rounding::same(6.0))
+            .inner_margin(egui::Margin::same(8.0))
+            .show(ui, |ui| {
+                ui.horizontal_wrapped(|ui| {
+                    ui.label(egui::RichText::new(format!("接続先 {}", summary.machines)).strong());
+                    for (status, count) in &summary.machines_by_status {
+                        ui.label(
+                            egui::RichText::new(format!("{} {}", status_label(status), count))
+                                .color(status_color(status)),
+                        );
+                    }
+                    ui.separator();
+                    ui.label(format!("アカウント {}", summary.accounts));
+                    ui.label(format!("ログ {}", summary.logs));
+                    ui.separator();
+                    ui.label(format!(
+                        "最終バックアップ: {}",
+                        summary.last_backup.as_deref().unwrap_or("なし")
+                    ));
+                });
+            });
+    }
+
+    /// "最終接続: N日前" plus the connection count, or a note that it was never used.
+    pub fn usage_label(&self, machine_id: i64) -> String {
+        let Some(stats) = self.connection_stats.get(&machine_id) else {
+            return "未接続".to_string();
+        };
+        let days =
+            chrono::NaiveDateTime::parse_from_str(&stats.last_connected_at, "%Y-%m-%d %H:%M:%S")
+                .map(|at| (chrono::Utc::now().naive_utc() - at).num_days());
+        let last = match days {
+            Ok(0) => "今日".to_string(),
+            Ok(days) => format!("{}日前", days),
+            Err(_) => stats.last_connected_at.clone(),
+        };
+        format!("最終接続: {} / {}回", last, stats.connections)
+    }
+
+    /// Diagnose history of `machine_id`, newest first, read from the database the first
+    /// time it is asked for after a refresh.
+    pub fn diagnostics_for(&mut self, machine_id: i64) -> &[crate::db::Diagnostic] {
+        if self.diagnostics.as_ref().map(|(id, _)| *id) != Some(machine_id) {
+            let history = self.db.list_diagnostics(machine_id).unwrap_or_else(|e| {
+                tracing::warn!(
+                    "Failed to load diagnostics for machine {}: {:#}",
+                    machine_id,
+                    e
+                );
+                Vec::new()
+            });
+            self.diagnostics = Some((machine_id, history));
+        }
+        self.diagnostics
+            .as_ref()
+            .map_or(&[], |(_, history)| history)
+    }
+
+    /// Deletes a machine with its accounts and constraints, keeping them for [`Self::undo_delete`].
+    pub fn delete_machine(&mut self, machine_id: i64) {
+        match self.db.delete_machine(machine_id) {
+            Ok(snapshot) => {
+                tracing::info!("Deleted machine {} ({})", machine_id, snapshot.machine.name);
+                self.last_deleted = Some(snapshot);
+            }
+            Err(e) => self.report_error("削除に失敗しました", &e),
+        }
+        self.refresh();
+    }
+
+    /// Restores the most recently deleted machine.
+    pub fn undo_delete(&mut self) {
+        let Some(snapshot) = self.last_deleted.take() else {
+            return;
+        };
+        if let Err(e) = self.db.restore_machine_snapshot(&snapshot) {
+            tracing::error!(
+                "Undoing deletion of {} failed: {}",
+                snapshot.machine.name,
+                e
+            );
+            self.report_error("元に戻せませんでした", &e);
+        }
+        self.refresh();
+    }
+
+    /// Opens the detail/edit window for a machine, pre-filled with its current values.
+    pub fn open_machine_detail(&mut self, machine_id: i64) {
+        self.editing_machine = self
+            .machines
+            .iter()
+            .find(|m| m.id == Some(machine_id))
+            .cloned();
+        self.edit_error = None;
+    }
+
+    /// Opens the 複製 window for a machine, suggesting a free name and its host.
+    pub fn open_clone_dialog(&mut self, machine_id: i64) {
+        let Some(machine) = self.machines.iter().find(|m| m.id == Some(machine_id)) else {
+            return;
+        };
+        let taken = |name: &str| self.machines.iter().any(|m| m.name == name);
+        let name = (2..)
+            .map(|n| format!("{}-{}", machine.name, n))
+            .find(|name| !taken(name))
+            .unwrap_or_default();
+        self.cloning_machine = Some(CloneDraft {
+            source_id: machine_id,
+            name,
+            host: machine.ip_address.clone(),
+            error: None,
+        });
+    }
+
+    /// Opens a terminal for the machine. With a single account this connects right away;
+    /// with several, an account picker is shown first.
+    pub fn connect(&mut self, machine_id: i64) {
+        let accounts: Vec<Account> = self
+            .accounts
+            .iter()
+            .filter(|a| a.machine_id == machine_id)
+            .cloned()
+            .collect();
+        match accounts.as_slice() {
+            [] => self.error_message = Some("この接続先にはアカウントが登録されていません".into()),
+            [account] => self.connect_with(machine_id, account),
+            _ => self.choosing_account_for = Some(machine_id),
+        }
+    }
+
+    /// Opens a terminal on the machine once [`health::check_vpn`], which may wait on a TCP
+    /// connect, has passed on a background thread; see [`Self::poll_vpn_check`].
+    pub fn connect_with(&mut self, machine_id: i64, account: &Account) {
+        if self.vpn_check_rx.is_some() {
+            return;
+        }
+        let Some(machine) = self
+            .machines
+            .iter()
+            .find(|m| m.id == Some(machine_id))
+            .cloned()
+        else {
+            return;
+        };
+        let account = account.clone();
+        let (tx, rx) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            let result = health::check_vpn(&machine);
+            let _ = tx.send((machine, account, result));
+        });
+        self.vpn_check_rx = Some(rx);
+    }
+
+    fn poll_vpn_check(&mut self, ctx: &egui::Context) {
+        let Some(rx) = &self.vpn_check_rx else {
+            return;
+        };
+        match rx.try_recv() {
+            Ok((machine, account, result)) => {
+                self.vpn_check_rx = None;
+                match result {
+                    Ok(()) => self.launch_terminal(machine, &account),
+                    Err(e) => self.toasts.push((e.to_string(), Instant::now())),
+                }
+            }
+            Err(std::sync::mpsc::TryRecvError::Empty) => {
+                ctx.request_repaint_after(Duration::from_millis(100));
+            }
+            Err(std::sync::mpsc::TryRecvError::Disconnected) => {
+                self.vpn_check_rx = None;
+            }
+        }
+    }
+
+    fn launch_terminal(&mut self, machine: Machine, account: &Account) {
+        let Some(machine_id) = machine.id else {
+            return;
+        };
+        let mac_terminal = MacTerminal::from_settings(&self.db);
+        // On macOS an explicitly chosen app wins over terminal.command
+        let terminal_command = match mac_terminal {
+            MacTerminal::Terminal | MacTerminal::ITerm2 if cfg!(target_os = "macos") => None,
+            _ => self.db.get_setting(SETTING_TERMINAL_COMMAND).ok().flatten(),
+        };
+        let known_hosts = crate::known_hosts::active_file(&self.db);
+        let ssh_options = match health::machine_ssh_args(&self.db, &machine) {
+            Ok(ssh_options) => ssh_options,
+            Err(e) => {
+                self.error_message = Some(format!("ターミナルを起動できませんでした: {}", e));
+                return;
+            }
+        };
+        let feed = self.password_feed(account, health::uses_jump_host(&ssh_options));
+        let ssh_command = feed
+            .as_ref()
+            .map_or_else(|| vec!["ssh".to_string()], |feed| feed.command.clone());
+        let launched = launch_ssh_terminal_with(
+            &machine,
+            account,
+            terminal_command.as_deref(),
+            known_hosts.as_deref(),
+            &ssh_options,
+            mac_terminal,
+            &ssh_command,
+        );
+        match launched {
+            Ok(()) => {
+                // ssh reads the password once the terminal is up; on failure drop removes it
+                if let Some(feed) = feed {
+                    self.password_feeds.push(feed);
+                }
+                if let Err(e) = self.db.record_connection(machine_id) {
+                    tracing::warn!(
+                        "Failed to record connection for machine {}: {}",
+                        machine_id,
+                        e
+                    );
+                }
+                self.refresh();
+            }
+            Err(e) => {
+                self.error_message = Some(format!("ターミナルを起動できませんでした: {}", e));
+            }
+        }
     }
 
-    pub fn check_auto_backup(&self) {
-        // Simple logic: check if backup exists for today, if not create one
-        let today = chrono::Local::now().format("%Y-%m-%d").to_string();
-        let proj_dirs =
-            directories::ProjectDirs::from("com", "veltrea", "mcp-ssh-manager").unwrap();
-        let backup_dir = proj_dirs.data_dir().join("backups");
-        let _ = std::fs::create_dir_all(&backup_dir);
+    /// Decrypted password of a password-auth account, ready to hand to ssh. `None` for other
+    /// auth types, and with a toast when the password can't be fed in. Through a jump host
+    /// the first password prompt is the jump host's, so nothing is fed then.
+    fn password_feed(
+        &mut self,
+        account: &Account,
+        through_jump_host: bool,
+    ) -> Option<crate::askpass::PasswordFeed> {
+        use crate::db::AuthType;
+        if account.auth_type.parse::<AuthType>().ok() != Some(AuthType::Password) {
+            return None;
+        }
+        let feed = if through_jump_host {
+            Ok(None)
+        } else {
+            account
+                .id
+                .ok_or_else(|| anyhow!("account has no id"))
+                .and_then(|id| self.db.reveal_credential(id))
+                .and_then(|password| crate::askpass::PasswordFeed::new(&password))
+        };
+        let reason = match feed {
+            Ok(None) if through_jump_host => "踏み台経由の接続".to_string(),
+            Ok(Some(feed)) => return Some(feed),
+            Ok(None) => "この環境では未対応".to_string(),
+            Err(e) => {
+                tracing::warn!("Failed to prepare password for {}: {}", account.username, e);
+                e.to_string()
+            }
+        };
+        self.toasts.push((
+            format!(
+                "パスワードを自動入力できません ({})。ターミナルで入力してください",
+                reason
+            ),
+            Instant::now(),
+        ));
+        None
+    }
 
-        // Cleanup old backups (keep last 5)
-        if let Ok(entries) = std::fs::read_dir(&backup_dir) {
-            let mut backups: Vec<_> = entries
-                .filter_map(|e| e.ok())
-                .filter(|e| e.path().extension().map_or(false, |ext| ext == "db"))
-                .collect();
+    /// Case-insensitive match of the search box against name, host, and purpose.
+    pub fn matches_search(&self, machine: &Machine) -> bool {
+        let query = self.search_query.trim().to_lowercase();
+        query.is_empty()
+            || [&machine.name, &machine.ip_address, &machine.purpose]
+                .iter()
+                .any(|field| field.to_lowercase().contains(&query))
+    }
 
-            backups.sort_by_key(|b| b.metadata().ok().map(|m| m.modified().ok()).flatten());
+    /// Search box for the Connections tab; takes focus when Ctrl+F was pressed.
+    pub fn show_search_box(&mut self, ui: &mut egui::Ui) {
+        let response = ui.add(
+            egui::TextEdit::singleline(&mut self.search_query)
+                .hint_text("🔍 名前・ホスト・用途で検索 (Ctrl+F)")
+                .desired_width(f32::INFINITY),
+        );
+        if std::mem::take(&mut self.focus_search) {
+            response.request_focus();
+        }
+        // Leave the box with Esc or Enter so Up/Down/Enter drive the list again
+        if response.lost_focus() && self.selected_machine.is_none() {
+            self.move_selection(1);
+        }
+    }
 
-            if backups.len() > 5 {
-                for entry in backups.iter().take(backups.len() - 5) {
-                    let _ = std::fs::remove_file(entry.path());
+    /// Machines bucketed by group: named groups in name order, then the ungrouped bucket.
+    /// Honors the search box, the status filter and, when enabled, the status sort.
+    pub fn machine_groups(&self) -> Vec<(Option<String>, Vec<Machine>)> {
+        let mut named: BTreeMap<String, Vec<Machine>> = BTreeMap::new();
+        let mut ungrouped = Vec::new();
+        let visible = self
+            .machines
+            .iter()
+            .filter(|m| self.status_filter.as_ref().is_none_or(|s| &m.status == s))
+            .filter(|m| self.matches_search(m));
+        for machine in visible {
+            match &machine.group {
+                Some(group) => named
+                    .entry(group.clone())
+                    .or_default()
+                    .push(machine.clone()),
+                None => ungrouped.push(machine.clone()),
+            }
+        }
+        let mut groups: Vec<_> = named.into_iter().map(|(g, m)| (Some(g), m)).collect();
+        if !ungrouped.is_empty() {
+            groups.push((None, ungrouped));
+        }
+        if self.sort_by_status {
+            for (_, machines) in &mut groups {
+                machines.sort_by_key(|m| status_rank(&m.status));
+            }
+        }
+        groups
+    }
+
+    /// Status filter and sort toggle shown above the machine list.
+    pub fn show_status_filter(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.label("状態:");
+            egui::ComboBox::from_id_source("status_filter")
+                .selected_text(match &self.status_filter {
+                    Some(status) => status_label(status),
+                    None => "すべて".to_string(),
+                })
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut self.status_filter, None, "すべて");
+                    for status in MACHINE_STATUSES {
+                        ui.selectable_value(
+                            &mut self.status_filter,
+                            Some(status.to_string()),
+                            egui::RichText::new(status_label(status)).color(status_color(status)),
+                        );
+                    }
+                });
+            ui.checkbox(&mut self.sort_by_status, "要対応を先頭に表示");
+        });
+    }
+
+    /// Renders the machines as one collapsible section per group, calling `row` for each
+    /// machine. Folding a section is remembered in [`SETTING_COLLAPSED_GROUPS`].
+    pub fn show_machine_groups(
+        &mut self,
+        ui: &mut egui::Ui,
+        mut row: impl FnMut(&mut Self, &mut egui::Ui, &Machine),
+    ) {
+        for (group, machines) in self.machine_groups() {
+            let key = group.clone().unwrap_or_default();
+            let open = !self.collapsed_groups.contains(&key);
+            let title = format!(
+                "{} ({})",
+                group.as_deref().unwrap_or(UNGROUPED_LABEL),
+                machines.len()
+            );
+            let response = egui::CollapsingHeader::new(title)
+                .id_source(("machine_group", &key))
+                .open(Some(open))
+                .show(ui, |ui| {
+                    for machine in &machines {
+                        let selected = machine.id.is_some() && machine.id == self.selected_machine;
+                        let frame = egui::Frame::none()
+                            .fill(if selected {
+                                egui::Color32::from_rgb(40, 55, 80)
+                            } else {
+                                egui::Color32::TRANSPARENT
+                            })
+                            .rounding(egui::Rounding::same(4.0))
+                            .show(ui, |ui| {
+                                ui.horizontal(|ui| {
+                                    if let Some(id) = machine.id {
+                                        self.reachability_dot(ui, id);
+                                    }
+                                    ui.vertical(|ui| row(self, ui, machine));
+                                    let jump = machine.jump_host_id.and_then(|id| {
+                                        self.machines.iter().find(|m| m.id == Some(id))
+                                    });
+                                    if let Some(jump) = jump {
+                                        ui.weak(format!("↪ {}", jump.name)).on_hover_text("踏み台");
+                                    }
+                                });
+                            });
+                        if selected && std::mem::take(&mut self.scroll_to_selected) {
+                            frame.response.scroll_to_me(None);
+                        }
+                    }
+                });
+            if response.header_response.clicked() {
+                if open {
+                    self.collapsed_groups.insert(key);
+                } else {
+                    self.collapsed_groups.remove(&key);
                 }
+                self.save_collapsed_groups();
             }
         }
+    }
 
-        let backup_path = backup_dir.join(format!("auto_backup_{}.db", today));
+    /// Ids in the order the Connections list shows them, skipping folded groups.
+    fn visible_machine_ids(&self) -> Vec<i64> {
+        self.machine_groups()
+            .into_iter()
+            .filter(|(group, _)| {
+                !self
+                    .collapsed_groups
+                    .contains(group.as_deref().unwrap_or_default())
+            })
+            .flat_map(|(_, machines)| machines)
+            .filter_map(|m| m.id)
+            .collect()
+    }
 
-        if !backup_path.exists() {
-            let _ = self.db.backup_db(&backup_path);
+    /// Moves the keyboard selection by `step` rows, starting from the top or bottom when
+    /// nothing (or a machine no longer listed) is selected.
+    fn move_selection(&mut self, step: isize) {
+        let ids = self.visible_machine_ids();
+        if ids.is_empty() {
+            self.selected_machine = None;
+            return;
+        }
+        let current = self
+            .selected_machine
+            .and_then(|id| ids.iter().position(|&i| i == id));
+        let next = match current {
+            Some(pos) => pos.saturating_add_signed(step).min(ids.len() - 1),
+            None if step < 0 => ids.len() - 1,
+            None => 0,
+        };
+        self.selected_machine = Some(ids[next]);
+        self.scroll_to_selected = true;
+    }
+
+    /// Ctrl+F focuses the search box and Ctrl+K opens quick connect; in the Connections list,
+    /// Up/Down move the selection and Enter connects to it. List keys are ignored while a
+    /// text field or dialog has focus.
+    fn handle_shortcuts(&mut self, ctx: &egui::Context) {
+        if ctx.input_mut(|i| i.consume_key(egui::Modifiers::COMMAND, egui::Key::F)) {
+            self.current_tab = Tab::Connections;
+            self.focus_search = true;
+        }
+        if ctx.input_mut(|i| i.consume_key(egui::Modifiers::COMMAND, egui::Key::K)) {
+            self.command_palette = Some(String::new());
+        }
+        let dialog_open = self.adding_machine.is_some()
+            || self.editing_machine.is_some()
+            || self.choosing_account_for.is_some()
+            || self.cloning_machine.is_some()
+            || self.command_palette.is_some();
+        if self.current_tab != Tab::Connections || dialog_open || ctx.wants_keyboard_input() {
+            return;
+        }
+        let (down, up, enter) = ctx.input(|i| {
+            (
+                i.key_pressed(egui::Key::ArrowDown),
+                i.key_pressed(egui::Key::ArrowUp),
+                i.key_pressed(egui::Key::Enter),
+            )
+        });
+        if down {
+            self.move_selection(1);
+        }
+        if up {
+            self.move_selection(-1);
+        }
+        if enter && let Some(machine_id) = self.selected_machine {
+            self.connect(machine_id);
+        }
+    }
+
+    fn save_collapsed_groups(&mut self) {
+        let mut groups: Vec<&String> = self.collapsed_groups.iter().collect();
+        groups.sort();
+        let saved = serde_json::to_string(&groups)
+            .map_err(anyhow::Error::from)
+            .and_then(|v| self.db.set_setting(SETTING_COLLAPSED_GROUPS, &v));
+        if let Err(e) = saved {
+            self.error_message = Some(format!("表示状態を保存できませんでした: {}", e));
+        }
+    }
+
+    /// Icon and explanation for an account's credential status; `None` when there is
+    /// nothing to point out (a set credential, or an empty one on an ssh-agent account).
+    pub fn credential_badge(&self, account: &Account) -> Option<(&'static str, &'static str)> {
+        use crate::db::{AuthType, CredentialStatus};
+        match self.credential_status.get(&account.id?)? {
+            CredentialStatus::Set => None,
+            CredentialStatus::Empty
+                if account.auth_type.parse::<AuthType>().ok() == Some(AuthType::Agent) =>
+            {
+                None
+            }
+            CredentialStatus::Empty => Some(("⚠", "認証情報が設定されていません")),
+            CredentialStatus::HardwareBound => Some(("🔒", "TPM に保存された鍵を使用します")),
+            CredentialStatus::Corrupt => Some((
+                "❌",
+                "認証情報を復号できません（マスターキーが変わった可能性があります）",
+            )),
+        }
+    }
+
+    /// Credential field for an account of `auth_type`: masked until the 👁 toggle is on, with
+    /// a hint for what to enter and a file picker for key accounts. Agent-held keys store
+    /// nothing, so the field is disabled for them.
+    pub fn credential_input(
+        ui: &mut egui::Ui,
+        auth_type: &str,
+        credential: &mut String,
+        reveal: &mut bool,
+    ) {
+        use crate::db::AuthType;
+        let auth_type = auth_type.parse::<AuthType>().ok();
+        let hint = match auth_type {
+            Some(AuthType::Password) => "パスワードを入力",
+            Some(AuthType::Key) => "鍵ファイルのパス",
+            Some(AuthType::Agent | AuthType::Tpm) => "(ssh-agent を使用)",
+            None => "認証情報を入力",
+        };
+        let uses_agent = matches!(auth_type, Some(AuthType::Agent | AuthType::Tpm));
+        ui.add_enabled(
+            !uses_agent,
+            egui::TextEdit::singleline(credential)
+                .hint_text(hint)
+                .password(!*reveal)
+                .desired_width(Self::FORM_FIELD_WIDTH),
+        );
+        if uses_agent {
+            return;
+        }
+        ui.toggle_value(reveal, "👁").on_hover_text("入力内容を表示");
+        if auth_type == Some(AuthType::Key)
+            && ui
+                .small_button("📂")
+                .on_hover_text("鍵ファイルを選択")
+                .clicked()
+        {
+            let mut dialog = rfd::FileDialog::new();
+            if let Some(ssh_dir) = directories::BaseDirs::new().map(|d| d.home_dir().join(".ssh")) {
+                dialog = dialog.set_directory(ssh_dir);
+            }
+            if let Some(path) = dialog.pick_file() {
+                *credential = path.display().to_string();
+            }
+        }
+    }
+
+    /// Machine name prefixed with its OS icon, as shown in machine lists.
+    pub fn machine_label(machine: &Machine) -> String {
+        let icon = machine
+            .os_type
+            .parse::<crate::db::OsType>()
+            .map(|os| os.icon())
+            .unwrap_or("❔");
+        format!("{} {}", icon, machine.name)
+    }
+
+    fn render_account_picker(&mut self, ctx: &egui::Context) {
+        let Some(machine_id) = self.choosing_account_for else {
+            return;
+        };
+        let mut open = true;
+        let mut chosen = None;
+        egui::Window::new("アカウントを選択")
+            .open(&mut open)
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+            .show(ctx, |ui| {
+                if let Some(machine) = self.machines.iter().find(|m| m.id == Some(machine_id)) {
+                    ui.label(egui::RichText::new(Self::machine_label(machine)).strong());
+                }
+                for account in self.accounts.iter().filter(|a| a.machine_id == machine_id) {
+                    let button = ui.button(format!("{} ({})", account.username, account.auth_type));
+                    let button = match &account.note {
+                        Some(note) => button.on_hover_text(note),
+                        None => button,
+                    };
+                    if button.clicked() {
+                        chosen = Some(account.clone());
+                    }
+                }
+            });
+        if let Some(account) = chosen {
+            self.choosing_account_for = None;
+            self.connect_with(machine_id, &account);
+        } else if !open {
+            self.choosing_account_for = None;
+        }
+    }
+
+    fn render_clone_dialog(&mut self, ctx: &egui::Context) {
+        let Some(draft) = self.cloning_machine.as_mut() else {
+            return;
+        };
+        let source_name = self
+            .machines
+            .iter()
+            .find(|m| m.id == Some(draft.source_id))
+            .map(|m| m.name.clone())
+            .unwrap_or_default();
+        let mut open = true;
+        let mut create = false;
+        let mut cancel = false;
+        egui::Window::new("接続先を複製")
+            .open(&mut open)
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+            .show(ctx, |ui| {
+                ui.label(format!(
+                    "{} の設定と制約をコピーします。アカウントはコピーされません。",
+                    source_name
+                ));
+                egui::Grid::new("clone_machine_grid")
+                    .num_columns(2)
+                    .spacing([8.0, 8.0])
+                    .show(ui, |ui| {
+                        for (label, value) in
+                            [("名前", &mut draft.name), ("ホスト", &mut draft.host)]
+                        {
+                            ui.add_sized([Self::FORM_LABEL_WIDTH, 28.0], egui::Label::new(label));
+                            ui.add_sized(
+                                [Self::FORM_FIELD_WIDTH, 28.0],
+                                egui::TextEdit::singleline(value),
+                            );
+                            ui.end_row();
+                        }
+                    });
+                if let Some(err) = &draft.error {
+                    ui.colored_label(egui::Color32::RED, err);
+                }
+                ui.horizontal(|ui| {
+                    if ui.button("複製").clicked() {
+                        create = true;
+                    }
+                    if ui.button("キャンセル").clicked() {
+                        cancel = true;
+                    }
+                });
+            });
+        if create {
+            match self
+                .db
+                .clone_machine(draft.source_id, &draft.name, &draft.host)
+            {
+                Ok(machine_id) => {
+                    self.cloning_machine = None;
+                    self.refresh();
+                    // Straight to the copy, where its accounts are added
+                    self.open_machine_detail(machine_id);
+                }
+                Err(e) => draft.error = Some(format!("複製できませんでした: {}", e)),
+            }
+        } else if cancel || !open {
+            self.cloning_machine = None;
+        }
+    }
+
+    /// Locks once [`Self::idle_lock_after`] has passed without input; any input restarts the
+    /// clock.
+    fn check_idle_lock(&mut self, ctx: &egui::Context) {
+        if ctx.input(|i| !i.events.is_empty()) {
+            self.last_interaction = Instant::now();
+        }
+        let Some(idle_lock_after) = self.idle_lock_after else {
+            return;
+        };
+        if !self.db.is_locked()
+            && self.db.master_key_loaded()
+            && self.last_interaction.elapsed() >= idle_lock_after
+        {
+            tracing::info!("Locking after {:?} without input", idle_lock_after);
+            self.lock();
+        }
+    }
+
+    /// Wipes the master key (see [`DbHandler::lock`]) and every credential shown or typed
+    /// in the window.
+    pub fn lock(&mut self) {
+        self.db.lock();
+        wipe(&mut self.new_credential);
+        wipe(&mut self.reg_pass);
+        self.password_feeds.clear();
+        // A connection asked for before locking must not open after the unlock
+        self.vpn_check_rx = None;
+        self.reveal_credential = false;
+        self.updating_account = None;
+        self.credential_test_result = None;
+        self.unlock_error = None;
+    }
+
+    /// Full-window unlock screen shown while the database is locked.
+    fn render_lock_screen(&mut self, ctx: &egui::Context) {
+        let needs_passphrase = self.db.passphrase_enabled();
+        let mut unlock = false;
+        egui::CentralPanel::default().show(ctx, |ui| {
+            ui.vertical_centered(|ui| {
+                ui.add_space(120.0);
+                ui.heading("🔒 ロック中");
+                ui.label(if self.locked_at_startup {
+                    "認証情報はマスターパスフレーズで保護されています。"
+                } else {
+                    "しばらく操作がなかったため、マスターキーをメモリから消去しました。"
+                });
+                ui.add_space(Self::SECTION_GAP);
+                if needs_passphrase {
+                    let response = ui.add(
+                        egui::TextEdit::singleline(&mut self.unlock_passphrase)
+                            .password(true)
+                            .hint_text("マスターパスフレーズ")
+                            .desired_width(Self::FORM_FIELD_WIDTH),
+                    );
+                    if response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+                        unlock = true;
+                    }
+                }
+                if ui.button("ロックを解除").clicked() {
+                    unlock = true;
+                }
+                if let Some(err) = &self.unlock_error {
+                    ui.colored_label(egui::Color32::RED, err);
+                }
+            });
+        });
+        if unlock {
+            let result = self
+                .db
+                .unlock(needs_passphrase.then_some(self.unlock_passphrase.as_str()));
+            wipe(&mut self.unlock_passphrase);
+            match result {
+                Ok(()) => {
+                    self.unlock_error = None;
+                    if self.locked_at_startup {
+                        crate::security::load_identity_key(&self.db);
+                    }
+                    self.locked_at_startup = false;
+                    self.last_interaction = Instant::now();
+                    self.refresh();
+                    if self.auto_backup_pending {
+                        self.start_auto_backup();
+                    }
+                }
+                Err(e) => self.unlock_error = Some(format!("ロックを解除できませんでした: {}", e)),
+            }
+        }
+    }
+
+    fn current_db_mtime(&self) -> Option<SystemTime> {
+        std::fs::metadata(self.db.db_path())
+            .and_then(|m| m.modified())
+            .ok()
+    }
+
+    /// Reloads data when the database file has been modified since the last refresh.
+    fn auto_refresh(&mut self, ctx: &egui::Context) {
+        if self.last_refresh_check.elapsed() >= Self::AUTO_REFRESH_INTERVAL {
+            self.last_refresh_check = Instant::now();
+            if self.current_db_mtime() != self.db_mtime {
+                self.refresh();
+            }
+        }
+        ctx.request_repaint_after(Self::AUTO_REFRESH_INTERVAL);
+    }
+
+    /// Reloads right away when the MCP thread changed something, instead of waiting for
+    /// [`Self::auto_refresh`], and tells the user what happened.
+    fn poll_mcp_events(&mut self) {
+        let Some(events) = &self.mcp_events else {
+            return;
+        };
+        let events = events.drain();
+        if events.is_empty() {
+            return;
+        }
+        self.refresh();
+        for event in events {
+            use crate::events::Event;
+            let message = match event {
+                Event::MachineAdded { name } => format!("MCP: 接続先「{}」が登録されました", name),
+                Event::MachinesAdded { count } => {
+                    format!("MCP: {} 件の接続先が登録されました", count)
+                }
+                Event::CredentialUpdated { account_id } => {
+                    format!("MCP: アカウント {} の認証情報が更新されました", account_id)
+                }
+                Event::KeysRotated => "MCP: マスターキーがローテーションされました".to_string(),
+                Event::HostKeyTrusted { host } => {
+                    format!("MCP: {} のホスト鍵が信頼されました", host)
+                }
+                Event::IdentityKeyGenerated => "MCP: 識別鍵が生成されました".to_string(),
+                Event::DataChanged => continue,
+            };
+            self.toasts.push((message, Instant::now()));
+        }
+    }
+
+    /// Stack of recent notices in the bottom-right corner; each fades out on its own.
+    fn render_toasts(&mut self, ctx: &egui::Context) {
+        self.toasts
+            .retain(|(_, shown)| shown.elapsed() < Self::TOAST_DURATION);
+        if self.toasts.is_empty() {
+            return;
+        }
+        egui::Area::new(egui::Id::new("toasts"))
+            .anchor(egui::Align2::RIGHT_BOTTOM, [-12.0, -12.0])
+            .order(egui::Order::Foreground)
+            .show(ctx, |ui| {
+                for (message, _) in &self.toasts {
+                    egui::Frame::popup(ui.style()).show(ui, |ui| {
+                        ui.label(message);
+                    });
+                }
+            });
+        ctx.request_repaint_after(Duration::from_millis(250));
+    }
+
+    /// Runs the daily automatic backup on a background thread so a large database doesn't
+    /// delay the first frame. Does nothing when turned off in the settings, and waits for
+    /// the unlock while locked, since the backup passphrase can't be read before then.
+    pub fn start_auto_backup(&mut self) {
+        if !auto_backup_enabled(&self.db) {
+            tracing::info!("Automatic backup is turned off");
+            return;
+        }
+        if self.db.is_locked() {
+            self.auto_backup_pending = true;
+            return;
+        }
+        self.auto_backup_pending = false;
+        let db = Arc::clone(&self.db);
+        self.auto_backup = Some(std::thread::spawn(move || run_auto_backup(&db)));
+    }
+
+    pub fn load_settings_form(&mut self) {
+        self.retention_count_input = self
+            .setting_or(SETTING_RETENTION_COUNT, DEFAULT_RETENTION_COUNT)
+            .to_string();
+        self.retention_days_input = match self.setting_or(SETTING_RETENTION_DAYS, 0) {
+            0 => String::new(),
+            days => days.to_string(),
+        };
+        self.rate_limit_input = self
+            .setting_or(
+                crate::db::SETTING_SSH_RATE_LIMIT_SECS,
+                crate::db::DEFAULT_SSH_RATE_LIMIT_SECS,
+            )
+            .to_string();
+        self.terminal_command_input = self
+            .db
+            .get_setting(SETTING_TERMINAL_COMMAND)
+            .ok()
+            .flatten()
+            .unwrap_or_default();
+        self.macos_terminal_input = MacTerminal::from_settings(&self.db);
+        self.managed_known_hosts_input = crate::known_hosts::active_file(&self.db).is_some();
+        self.auto_backup_input = auto_backup_enabled(&self.db);
+        self.rotation_reminder_days_input = self
+            .setting_or(
+                crate::db::SETTING_ROTATION_REMINDER_DAYS,
+                crate::db::DEFAULT_ROTATION_REMINDER_DAYS,
+            )
+            .to_string();
+        self.idle_lock_minutes_input = self
+            .setting_or(SETTING_IDLE_LOCK_MINUTES, DEFAULT_IDLE_LOCK_MINUTES)
+            .to_string();
+    }
+
+    /// Reads a numeric setting, falling back to `default` when unset or invalid.
+    fn setting_or(&self, key: &str, default: u64) -> u64 {
+        setting_or(&self.db, key, default)
+    }
+
+    pub fn start_connectivity_sweep(&mut self) {
+        if self.sweep_rx.is_some() {
+            return;
+        }
+        let (tx, rx) = std::sync::mpsc::channel();
+        let db = Arc::clone(&self.db);
+        std::thread::spawn(move || {
+            let _ = tx.send(crate::health::sweep_connectivity(
+                &db,
+                &mut |_| false,
+                &mut |_, _| {},
+            ));
+        });
+        self.sweep_rx = Some(rx);
+    }
+
+    /// Checks every machine's SSH port on a background thread every
+    /// [`Self::REACHABILITY_INTERVAL`] and repaints when results arrive. The thread ends
+    /// once the app (the receiver) is gone.
+    fn start_reachability_monitor(&mut self, ctx: &egui::Context) {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let db = Arc::clone(&self.db);
+        let ctx = ctx.clone();
+        std::thread::spawn(move || {
+            loop {
+                match health::check_reachability(&db) {
+                    Ok(results) => {
+                        if tx.send(results).is_err() {
+                            return;
+                        }
+                        ctx.request_repaint();
+                    }
+                    Err(e) => tracing::warn!("Reachability check failed: {}", e),
+                }
+                std::thread::sleep(Self::REACHABILITY_INTERVAL);
+            }
+        });
+        self.reachability_rx = Some(rx);
+    }
+
+    fn poll_reachability(&mut self) {
+        let Some(rx) = &self.reachability_rx else {
+            return;
+        };
+        for results in rx.try_iter() {
+            self.reachability = results.into_iter().collect();
+        }
+    }
+
+    /// Green dot when the machine's SSH port answered the last background check, grey when
+    /// it didn't or hasn't been checked yet (e.g. under maintenance).
+    fn reachability_dot(&self, ui: &mut egui::Ui, machine_id: i64) {
+        let (color, hint) = match self.reachability.get(&machine_id) {
+            Some(true) => (egui::Color32::GREEN, "SSH ポートに到達できます"),
+            Some(false) => (egui::Color32::GRAY, "SSH ポートに到達できません"),
+            None => (egui::Color32::DARK_GRAY, "未確認"),
+        };
+        ui.label(egui::RichText::new("●").color(color))
+            .on_hover_text(hint);
+    }
+
+    fn poll_connectivity_sweep(&mut self, ctx: &egui::Context) {
+        let Some(rx) = &self.sweep_rx else {
+            return;
+        };
+        match rx.try_recv() {
+            Ok(results) => {
+                let reachable = results.iter().filter(|(_, ok)| *ok).count();
+                self.last_sweep = Some((reachable, results.len()));
+                self.sweep_rx = None;
+                self.refresh();
+            }
+            Err(std::sync::mpsc::TryRecvError::Empty) => {
+                ctx.request_repaint_after(Duration::from_millis(250));
+            }
+            Err(std::sync::mpsc::TryRecvError::Disconnected) => {
+                self.sweep_rx = None;
+            }
         }
     }
 
     fn check_security_features(&mut self) {
-        // Mock check (Task 7)
         self.tpm_available = rust_ssh::security::tpm::is_tpm_available();
-        self.secure_boot_enabled = true; // Placeholder
+        self.secure_boot = crate::security::secure_boot_state();
     }
 }
 
 impl eframe::App for ManagerApp {
+    fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
+        // Let an in-flight MCP request commit before the process exits; an abandoned
+        // transaction is what leaves "database is locked" behind for the next launch
+        if let Some(shutdown) = &self.mcp_shutdown
+            && !shutdown.request_and_wait(MCP_SHUTDOWN_TIMEOUT)
+        {
+            tracing::warn!(
+                "MCP request still running after {}s; exiting anyway",
+                MCP_SHUTDOWN_TIMEOUT.as_secs()
+            );
+        }
+        // A backup cut off half-way would leave a truncated file that counts as today's
+        if let Some(backup) = self.auto_backup.take() {
+            let _ = backup.join();
+        }
+        if let Err(e) = self.db.checkpoint() {
+            tracing::warn!("Checkpoint on exit failed: {}", e);
+        }
+        self.password_feeds.clear();
+    }
+
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        self.check_idle_lock(ctx);
+        self.password_feeds.retain(|feed| !feed.expired());
+        if !self.password_feeds.is_empty() {
+            ctx.request_repaint_after(Duration::from_secs(1));
+        }
+        if self.db.is_locked() {
+            self.render_lock_screen(ctx);
+            return;
+        }
+        self.poll_connectivity_sweep(ctx);
+        self.poll_reachability();
+        self.poll_vpn_check(ctx);
+        self.auto_refresh(ctx);
+        self.poll_mcp_events();
+        self.handle_shortcuts(ctx);
+
         egui::TopBottomPanel::top("top_panel").show(ctx, |ui| {
             ui.horizontal(|ui| {
                 ui.label(egui::RichText::new("MCP-SSH Manager").strong());
+                ui.add_space(16.0);
+                gui_tabs::global_search::show_box(self, ui);
                 ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
                     ui.label(egui::RichText::new("v0.2.0").small());
                     if let Some(user_dir) = directories::UserDirs::new() {
@@ -195,25 +1561,223 @@ impl eframe::App for ManagerApp {
                     self.render_tab(ui, Tab::Connections, "接続先");
                     self.render_tab(ui, Tab::Accounts, "アカウント");
                     self.render_tab(ui, Tab::Logs, "ログ");
+                    self.render_tab(ui, Tab::Knowledge, "ナレッジ");
                     self.render_tab(ui, Tab::Onboarding, "セキュリティ設定");
+                    self.render_tab(ui, Tab::Settings, "設定");
 
                     ui.add_space(8.0);
                     if ui.button("データ再読込").clicked() {
                         self.refresh();
                     }
+
+                    if self.sweep_rx.is_some() {
+                        ui.horizontal(|ui| {
+                            ui.spinner();
+                            ui.label("接続確認中…");
+                        });
+                    } else if ui.button("一括接続確認").clicked() {
+                        self.start_connectivity_sweep();
+                    }
+                    if let Some((reachable, total)) = self.last_sweep {
+                        ui.label(
+                            egui::RichText::new(format!("到達可能: {}/{}", reachable, total))
+                                .small(),
+                        );
+                    }
+
+                    ui.add_space(8.0);
+                    ui.toggle_value(&mut self.show_recent_messages, "最近のメッセージ");
                 });
             });
 
-        egui::CentralPanel::default().show(ctx, |ui| match self.current_tab {
-            Tab::Connections => gui_tabs::connections::show(self, ui, ctx),
-            Tab::Accounts => gui_tabs::accounts::show(self, ui),
-            Tab::Logs => gui_tabs::logs::show(self, ui),
-            Tab::Onboarding => gui_tabs::onboarding::show(self, ui),
+        self.render_recent_messages(ctx);
+        self.render_toasts(ctx);
+
+        egui::CentralPanel::default().show(ctx, |ui| {
+            self.render_error_banner(ui);
+            self.render_load_errors(ui);
+            self.render_undo_banner(ui);
+            self.render_rotation_banner(ui);
+            match self.current_tab {
+                Tab::Connections => gui_tabs::connections::show(self, ui, ctx),
+                Tab::Accounts => gui_tabs::accounts::show(self, ui),
+                Tab::Logs => gui_tabs::logs::show(self, ui),
+                Tab::Onboarding => gui_tabs::onboarding::show(self, ui),
+                Tab::Knowledge => gui_tabs::knowledge::show(self, ui),
+                Tab::Settings => gui_tabs::settings::show(self, ui),
+            }
         });
+
+        gui_tabs::machine_detail::show(self, ctx);
+        gui_tabs::command_palette::show(self, ctx);
+        gui_tabs::global_search::show(self, ctx);
+        self.render_account_picker(ctx);
+        self.render_clone_dialog(ctx);
     }
 }
 
 impl ManagerApp {
+    /// Shows `error` in the banner, remembering a duplicate machine name so it can be edited.
+    pub fn report_error(&mut self, context: &str, error: &anyhow::Error) {
+        self.error_message = Some(format!("{}: {}", context, error));
+        self.duplicate_machine = error
+            .downcast_ref::<crate::db::DuplicateMachineName>()
+            .map(|d| d.0.clone());
+    }
+
+    fn render_error_banner(&mut self, ui: &mut egui::Ui) {
+        let Some(message) = &self.error_message else {
+            self.duplicate_machine = None;
+            return;
+        };
+        let mut dismissed = false;
+        let mut edit_existing = false;
+        egui::Frame::none()
+            .fill(egui::Color32::from_rgb(90, 20, 24))
+            .rounding(egui::Rounding::same(6.0))
+            .inner_margin(egui::Margin::same(8.0))
+            .show(ui, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label(egui::RichText::new(message).color(egui::Color32::WHITE));
+                    ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                        if ui.small_button("✕").clicked() {
+                            dismissed = true;
+                        }
+                        if self.duplicate_machine.is_some()
+                            && ui.small_button("既存の接続先を編集").clicked()
+                        {
+                            edit_existing = true;
+                        }
+                    });
+                });
+            });
+        if edit_existing {
+            let existing = self.duplicate_machine.as_ref().and_then(|name| {
+                self.machines
+                    .iter()
+                    .find(|m| &m.name == name)
+                    .and_then(|m| m.id)
+            });
+            if let Some(id) = existing {
+                self.open_machine_detail(id);
+            }
+        }
+        if dismissed || edit_existing {
+            self.error_message = None;
+            self.duplicate_machine = None;
+        }
+    }
+
+    fn render_undo_banner(&mut self, ui: &mut egui::Ui) {
+        let Some(snapshot) = &self.last_deleted else {
+            return;
+        };
+        let mut undo = false;
+        let mut dismissed = false;
+        egui::Frame::none()
+            .fill(egui::Color32::from_rgb(40, 40, 46))
+            .rounding(egui::Rounding::same(6.0))
+            .inner_margin(egui::Margin::same(8.0))
+            .show(ui, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label(format!(
+                        "「{}」を削除しました（アカウント {} 件）",
+                        snapshot.machine.name,
+                        snapshot.accounts.len()
+                    ));
+                    ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                        if ui.small_button("✕").clicked() {
+                            dismissed = true;
+                        }
+                        if ui.small_button("元に戻す").clicked() {
+                            undo = true;
+                        }
+                    });
+                });
+            });
+        if undo {
+            self.undo_delete();
+        } else if dismissed {
+            self.last_deleted = None;
+        }
+    }
+
+    /// Bottom panel mirroring stderr, which release builds on Windows have no console for.
+    fn render_recent_messages(&mut self, ctx: &egui::Context) {
+        if !self.show_recent_messages {
+            return;
+        }
+        let messages = crate::logging::recent_messages();
+        egui::TopBottomPanel::bottom("recent_messages")
+            .resizable(true)
+            .default_height(160.0)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label(egui::RichText::new("最近のメッセージ").strong());
+                    ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                        if ui.small_button("✕").clicked() {
+                            self.show_recent_messages = false;
+                        }
+                        if ui.small_button("コピー").clicked() {
+                            ui.output_mut(|o| o.copied_text = messages.join("\n"));
+                        }
+                    });
+                });
+                egui::ScrollArea::vertical()
+                    .auto_shrink([false, false])
+                    .stick_to_bottom(true)
+                    .show(ui, |ui| {
+                        if messages.is_empty() {
+                            ui.label("メッセージはありません");
+                        }
+                        for line in &messages {
+                            let color = if line.contains("ERROR") {
+                                egui::Color32::LIGHT_RED
+                            } else if line.contains("WARN") {
+                                egui::Color32::YELLOW
+                            } else {
+                                egui::Color32::from_gray(200)
+                            };
+                            ui.label(egui::RichText::new(line).monospace().small().color(color));
+                        }
+                    });
+            });
+    }
+
+    fn render_rotation_banner(&mut self, ui: &mut egui::Ui) {
+        if self.rotation_reminder_dismissed {
+            return;
+        }
+        let Some(summary) = self.summary.as_ref().filter(|s| s.key_rotation_due) else {
+            return;
+        };
+        let last = match &summary.last_key_rotation {
+            Some(at) => format!("前回: {} (UTC)", at),
+            None => "まだ一度も行っていません".to_string(),
+        };
+        let mut dismissed = false;
+        egui::Frame::none()
+            .fill(egui::Color32::from_rgb(60, 50, 20))
+            .rounding(egui::Rounding::same(6.0))
+            .inner_margin(egui::Margin::same(8.0))
+            .show(ui, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label(format!(
+                        "🔑 マスターキーのローテーション時期です（{}）。MCP の rotate_keys で更新できます",
+                        last
+                    ));
+                    ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                        if ui.small_button("✕").clicked() {
+                            dismissed = true;
+                        }
+                    });
+                });
+            });
+        if dismissed {
+            self.rotation_reminder_dismissed = true;
+        }
+    }
+
     fn render_tab(&mut self, ui: &mut egui::Ui, tab: Tab, label: &str) {
         let is_active = self.current_tab == tab;
 
@@ -256,36 +1820,428 @@ impl ManagerApp {
     }
 }
 
+/// Known machine statuses, in the order the status sort lists them.
+pub const MACHINE_STATUSES: [&str; 3] = ["broken", "maintenance", "active"];
+
+/// Position of `status` in [`MACHINE_STATUSES`]; unknown statuses sort last.
+fn status_rank(status: &str) -> usize {
+    MACHINE_STATUSES
+        .iter()
+        .position(|s| *s == status)
+        .unwrap_or(MACHINE_STATUSES.len())
+}
+
+/// Green for active, red for broken, yellow for maintenance, gray otherwise.
+pub fn status_color(status: &str) -> egui::Color32 {
+    match status {
+        "active" => egui::Color32::GREEN,
+        "broken" => egui::Color32::RED,
+        "maintenance" => egui::Color32::YELLOW,
+        _ => egui::Color32::GRAY,
+    }
+}
+
+/// Status name with a dot for machine lists, e.g. "● 接続不可"; color it with [`status_color`].
+pub fn status_label(status: &str) -> String {
+    let name = match status {
+        "active" => "稼働中",
+        "broken" => "接続不可",
+        "maintenance" => "メンテナンス中",
+        other => other,
+    };
+    format!("● {}", name)
+}
+
+/// Overwrites `text` in place before emptying it, so a secret doesn't linger in the freed
+/// buffer.
+fn wipe(text: &mut String) {
+    let len = text.len();
+    text.replace_range(.., &"\0".repeat(len));
+    text.clear();
+}
+
+/// Reads a numeric setting, falling back to `default` when unset or invalid.
+fn setting_or(db: &DbHandler, key: &str, default: u64) -> u64 {
+    db.get_setting(key)
+        .ok()
+        .flatten()
+        .and_then(|v| v.trim().parse().ok())
+        .unwrap_or(default)
+}
+
+fn auto_backup_enabled(db: &DbHandler) -> bool {
+    db.get_setting(SETTING_AUTO_BACKUP)
+        .ok()
+        .flatten()
+        .is_none_or(|v| v != "false")
+}
+
+/// Creates today's automatic backup unless one already exists, then prunes old backups.
+/// Skipped (with a warning in the recent messages) when the backup volume is low on space.
+fn run_auto_backup(db: &DbHandler) {
+    let Ok(backup_dir) = crate::db::backup_dir() else {
+        return;
+    };
+    let _ = std::fs::create_dir_all(&backup_dir);
+
+    // Encrypt automatic backups when a backup passphrase has been stored. A passphrase
+    // that can't be read must not turn into an unencrypted backup.
+    let passphrase = match db.backup_passphrase() {
+        Ok(passphrase) => passphrase,
+        Err(e) => {
+            tracing::warn!("Skipping automatic backup: {}", e);
+            return;
+        }
+    };
+    let ext = if passphrase.is_some() { "db.enc" } else { "db" };
+    let today = chrono::Local::now().date_naive();
+    let backup_path = backup_dir.join(format!(
+        "{}{}.{}",
+        AUTO_BACKUP_PREFIX,
+        today.format("%Y-%m-%d"),
+        ext
+    ));
+
+    if !written_on(&backup_path, today) {
+        let db_size = std::fs::metadata(db.db_path()).map_or(0, |m| m.len());
+        match fs2::available_space(&backup_dir) {
+            Ok(free) if free < db_size + AUTO_BACKUP_MIN_FREE_BYTES => {
+                tracing::warn!(
+                    "Skipping automatic backup: only {} MB free in {}",
+                    free / (1024 * 1024),
+                    backup_dir.display()
+                );
+                return;
+            }
+            _ => {}
+        }
+        // A same-named file from another day (e.g. restored from elsewhere) is replaced
+        let _ = std::fs::remove_file(&backup_path);
+        let result = match &passphrase {
+            Some(p) => db.backup_db_encrypted(&backup_path, p),
+            None => db.backup_db(&backup_path),
+        };
+        if let Err(e) = result {
+            tracing::warn!("Automatic backup failed: {}", e);
+        }
+    }
+
+    let retention_count = setting_or(db, SETTING_RETENTION_COUNT, DEFAULT_RETENTION_COUNT).max(1);
+    let retention_days = setting_or(db, SETTING_RETENTION_DAYS, 0);
+    prune_backups(&backup_dir, retention_count, retention_days);
+}
+
+/// Whether `path` exists and was last modified on local date `day`.
+fn written_on(path: &std::path::Path, day: chrono::NaiveDate) -> bool {
+    std::fs::metadata(path)
+        .and_then(|m| m.modified())
+        .is_ok_and(|modified| chrono::DateTime::<chrono::Local>::from(modified).date_naive() == day)
+}
+
+/// Deletes the automatic backups [`expired_backups`] picks. Backups made by hand share the
+/// directory and are never deleted, nor are files whose mtime can't be read.
+fn prune_backups(backup_dir: &std::path::Path, keep: u64, max_age_days: u64) {
+    let Ok(entries) = std::fs::read_dir(backup_dir) else {
+        return;
+    };
+    let backups: Vec<(std::path::PathBuf, SystemTime)> = entries
+        .filter_map(|e| e.ok())
+        .filter(|e| {
+            e.file_name()
+                .to_str()
+                .is_some_and(|name| name.starts_with(AUTO_BACKUP_PREFIX))
+                && e.path()
+                    .extension()
+                    .is_some_and(|ext| ext == "db" || ext == "enc")
+        })
+        .filter_map(|e| {
+            let modified = e.metadata().ok()?.modified().ok()?;
+            Some((e.path(), modified))
+        })
+        .collect();
+
+    for path in expired_backups(backups, keep, max_age_days, SystemTime::now()) {
+        let _ = std::fs::remove_file(path);
+    }
+}
+
+/// Backups older than `max_age_days` as of `now` when that is non-zero, and otherwise
+/// those beyond the newest `keep`.
+fn expired_backups(
+    mut backups: Vec<(std::path::PathBuf, SystemTime)>,
+    keep: u64,
+    max_age_days: u64,
+    now: SystemTime,
+) -> Vec<std::path::PathBuf> {
+    if max_age_days > 0 {
+        let max_age = Duration::from_secs(max_age_days * 24 * 60 * 60);
+        return backups
+            .into_iter()
+            .filter(|(_, modified)| now.duration_since(*modified).is_ok_and(|age| age > max_age))
+            .map(|(path, _)| path)
+            .collect();
+    }
+    // Newest first, so everything past `keep` is the oldest
+    backups.sort_by_key(|(_, modified)| std::cmp::Reverse(*modified));
+    backups
+        .into_iter()
+        .skip(keep as usize)
+        .map(|(path, _)| path)
+        .collect()
+}
+
+/// Keeps the default fonts first and adds a Japanese font as fallback, to avoid
+/// oversized/imbalanced text.
+fn configure_fonts(ctx: &egui::Context) {
+    let mut fonts = egui::FontDefinitions::default();
+    if let Ok(font_data) = std::fs::read("/System/Library/Fonts/Hiragino Sans GB.ttc") {
+        fonts
+            .font_data
+            .insert("Hiragino".to_owned(), egui::FontData::from_owned(font_data));
+        fonts
+            .families
+            .entry(egui::FontFamily::Proportional)
+            .or_default()
+            .push("Hiragino".to_owned());
+        fonts
+            .families
+            .entry(egui::FontFamily::Monospace)
+            .or_default()
+            .push("Hiragino".to_owned());
+    } else {
+        tracing::warn!("Failed to load system font for Japanese characters.");
+    }
+
+    ctx.set_fonts(fonts);
+}
+
+/// Opens a directory in the platform file manager.
+pub fn open_in_file_manager(path: &std::path::Path) -> Result<()> {
+    #[cfg(target_os = "macos")]
+    let program = "open";
+    #[cfg(target_os = "windows")]
+    let program = "explorer";
+    #[cfg(not(any(target_os = "macos", target_os = "windows")))]
+    let program = "xdg-open";
+
+    std::fs::create_dir_all(path)?;
+    std::process::Command::new(program)
+        .arg(path)
+        .spawn()
+        .map_err(|e| anyhow!("Failed to run {}: {}", program, e))?;
+    Ok(())
+}
+
 pub fn launch_ssh_terminal(machine: &Machine, account: &Account) -> Result<()> {
-    let target = format!("{}@{}", account.username, machine.ip_address);
+    let ssh_options = health::machine_ssh_options(machine);
+    launch_ssh_terminal_with(
+        machine,
+        account,
+        None,
+        None,
+        &ssh_options,
+        MacTerminal::default(),
+        &["ssh".to_string()],
+    )
+}
+
+/// Opens `ssh user@host` in a terminal. `terminal_command` (the `terminal.command` setting)
+/// overrides the platform default; `{host}` (or `{target}`) in it is replaced with the
+/// `user@host` target. `known_hosts` overrides the user's known_hosts file; `ssh_options`
+/// come from [`health::machine_ssh_args`]. `mac_terminal` picks the app on macOS.
+/// `ssh_command` stands in for `ssh`, e.g. the wrapper from [`crate::askpass::PasswordFeed`];
+/// Windows always runs plain ssh.
+pub fn launch_ssh_terminal_with(
+    machine: &Machine,
+    account: &Account,
+    terminal_command: Option<&str>,
+    known_hosts: Option<&std::path::Path>,
+    ssh_options: &[String],
+    mac_terminal: MacTerminal,
+    ssh_command: &[String],
+) -> Result<()> {
+    let (target, extra_args) = health::format_ssh_target(
+        &account.username,
+        &machine.ip_address,
+        health::DEFAULT_SSH_PORT,
+    );
+    // Everything that follows "ssh" on the command line
+    let ssh_args: Vec<String> = crate::known_hosts::ssh_options(known_hosts)
+        .into_iter()
+        .chain(ssh_options.iter().cloned())
+        .chain(extra_args)
+        .chain([target.clone()])
+        .collect();
+
+    if let Some(template) = terminal_command.filter(|t| !t.trim().is_empty()) {
+        // Split like a shell would, so quoted arguments and paths with spaces stay whole
+        let words = shell_words::split(template)
+            .map_err(|e| anyhow!("terminal.command can't be parsed: {}", e))?;
+        // A standalone {host} expands to the full argument list so port options survive
+        let mut parts = words.into_iter().flat_map(|part| {
+            if part == "{host}" || part == "{target}" {
+                ssh_args.clone()
+            } else if part == "ssh" {
+                ssh_command.to_vec()
+            } else {
+                vec![part.replace("{host}", &target).replace("{target}", &target)]
+            }
+        });
+        let program = parts
+            .next()
+            .ok_or_else(|| anyhow!("terminal.command is empty"))?;
+        std::process::Command::new(&program)
+            .args(parts)
+            .spawn()
+            .map_err(|e| anyhow!("Failed to run terminal.command ({}): {}", program, e))?;
+        return Ok(());
+    }
+
     #[cfg(target_os = "macos")]
     {
-        let script = format!(
-            "tell application \"Terminal\" to do script \"ssh {}\"",
-            target
-        );
-        std::process::Command::new("osascript")
-            .arg("-e")
-            .arg(script)
-            .spawn()?;
+        // Single-quote each argument for the shell (the known_hosts path has spaces),
+        // then escape the result for the AppleScript string
+        let command = ssh_command
+            .iter()
+            .chain(&ssh_args)
+            .map(|arg| format!("'{}'", arg.replace('\'', "'\\''")))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let command = command.replace('\\', "\\\\").replace('"', "\\\"");
+        let script: Vec<String> = match mac_terminal {
+            MacTerminal::ITerm2 => vec![
+                "tell application \"iTerm\"".to_string(),
+                "activate".to_string(),
+                "set newWindow to (create window with default profile)".to_string(),
+                format!(
+                    "tell current session of newWindow to write text \"{}\"",
+                    command
+                ),
+                "end tell".to_string(),
+            ],
+            MacTerminal::Terminal | MacTerminal::Custom => vec![format!(
+                "tell application \"Terminal\" to do script \"{}\"",
+                command
+            )],
+        };
+        let mut osascript = std::process::Command::new("osascript");
+        for line in &script {
+            osascript.arg("-e").arg(line);
+        }
+        osascript.spawn()?;
     }
+    #[cfg(not(target_os = "macos"))]
+    let _ = mac_terminal;
     #[cfg(target_os = "windows")]
     {
+        let _ = ssh_command;
         std::process::Command::new("cmd")
-            .args(&["/C", "start", "ssh", &target])
+            .args(["/C", "start", "ssh"])
+            .args(&ssh_args)
             .spawn()?;
     }
     #[cfg(target_os = "linux")]
     {
-        if std::process::Command::new("gnome-terminal")
-            .arg("--")
-            .arg("ssh")
-            .arg(&target)
-            .spawn()
-            .is_err()
-        {
-            eprintln!("Linux terminal launch not fully implemented for all distros");
+        // (program, arguments placed before "ssh <target>")
+        const TERMINALS: [(&str, &[&str]); 7] = [
+            ("gnome-terminal", &["--"]),
+            ("x-terminal-emulator", &["-e"]),
+            ("konsole", &["-e"]),
+            ("xfce4-terminal", &["-x"]),
+            ("alacritty", &["-e"]),
+            ("kitty", &[]),
+            ("xterm", &["-e"]),
+        ];
+        let launched = TERMINALS.iter().any(|(program, args)| {
+            std::process::Command::new(program)
+                .args(*args)
+                .args(ssh_command)
+                .args(&ssh_args)
+                .spawn()
+                .is_ok()
+        });
+        if !launched {
+            return Err(anyhow!(
+                "No terminal emulator found. Install one of {} or set terminal.command (e.g. \"wezterm start -- ssh {{host}}\")",
+                TERMINALS
+                    .iter()
+                    .map(|(p, _)| *p)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
         }
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+    /// Backups named after their age in days as of `now`.
+    fn backups_aged(now: SystemTime, days: &[u64]) -> Vec<(PathBuf, SystemTime)> {
+        days.iter()
+            .map(|d| (PathBuf::from(format!("{}.db", d)), now - DAY * *d as u32))
+            .collect()
+    }
+
+    fn names(paths: Vec<PathBuf>) -> Vec<String> {
+        let mut names: Vec<String> = paths.iter().map(|p| p.display().to_string()).collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn test_expired_backups_keeps_the_newest_by_count() {
+        let now = SystemTime::now();
+        let backups = backups_aged(now, &[3, 0, 10, 1, 5]);
+        assert_eq!(
+            names(expired_backups(backups.clone(), 3, 0, now)),
+            ["10.db", "5.db"]
+        );
+        assert!(expired_backups(backups, 5, 0, now).is_empty());
+    }
+
+    #[test]
+    fn test_prune_backups_leaves_manual_backups() {
+        let dir = std::env::temp_dir().join(format!(
+            "mcp-ssh-manager-test-prune-{}-{:016x}",
+            std::process::id(),
+            rand::random::<u64>()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        for name in [
+            "auto_backup_2026-01-01.db",
+            "auto_backup_2026-01-02.db.enc",
+            "manual.db",
+            "before-upgrade.db.enc",
+        ] {
+            std::fs::write(dir.join(name), "").unwrap();
+        }
+
+        prune_backups(&dir, 1, 0);
+        let mut left: Vec<String> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        left.sort();
+        assert_eq!(left.len(), 3);
+        assert_eq!(left[1..], ["before-upgrade.db.enc", "manual.db"]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_expired_backups_goes_by_age_alone_when_days_are_set() {
+        let now = SystemTime::now();
+        let backups = backups_aged(now, &[0, 1, 2, 3, 4, 8, 30]);
+        // The count would leave only one; with days set it doesn't apply
+        assert_eq!(
+            names(expired_backups(backups.clone(), 1, 7, now)),
+            ["30.db", "8.db"]
+        );
+        assert!(expired_backups(backups, 1, 60, now).is_empty());
+    }
+}
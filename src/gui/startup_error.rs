@@ -0,0 +1,100 @@
+use eframe::egui;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+/// The user's choice to restore the backup offered by [`show_startup_error`].
+pub struct Restore {
+    /// Typed for an encrypted (`.enc`) backup; `None` when left empty
+    pub passphrase: Option<String>,
+}
+
+/// Minimal window shown when the database can't be opened, so the windowed build
+/// reports the problem instead of exiting silently.
+struct StartupErrorApp {
+    message: String,
+    backup: Option<PathBuf>,
+    /// Backup passphrase typed into the window, since there is no terminal to ask on
+    passphrase: String,
+    restore: Arc<Mutex<Option<Restore>>>,
+}
+
+impl StartupErrorApp {
+    fn backup_is_encrypted(&self) -> bool {
+        self.backup
+            .as_ref()
+            .and_then(|path| path.extension())
+            .is_some_and(|ext| ext == "enc")
+    }
+}
+
+impl eframe::App for StartupErrorApp {
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        egui::CentralPanel::default().show(ctx, |ui| {
+            ui.heading("データベースを開けませんでした");
+            ui.add_space(8.0);
+            egui::ScrollArea::vertical()
+                .max_height(160.0)
+                .show(ui, |ui| {
+                    ui.label(egui::RichText::new(&self.message).monospace());
+                });
+            ui.add_space(12.0);
+
+            if let Some(backup) = &self.backup {
+                ui.label("データベースが破損している可能性があります。最新の自動バックアップから復元できます。");
+                ui.label(egui::RichText::new(backup.display().to_string()).monospace());
+                ui.label("現在のファイルは削除されず、別名で保存されます。");
+                if self.backup_is_encrypted() {
+                    ui.add(
+                        egui::TextEdit::singleline(&mut self.passphrase)
+                            .password(true)
+                            .hint_text("バックアップのパスフレーズ"),
+                    );
+                }
+                ui.add_space(8.0);
+            }
+
+            ui.horizontal(|ui| {
+                if self.backup.is_some() && ui.button("バックアップから復元").clicked() {
+                    let passphrase = std::mem::take(&mut self.passphrase);
+                    *self.restore.lock().unwrap_or_else(|e| e.into_inner()) = Some(Restore {
+                        passphrase: (!passphrase.is_empty()).then_some(passphrase),
+                    });
+                    ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+                }
+                if ui.button("終了").clicked() {
+                    ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+                }
+            });
+        });
+    }
+}
+
+/// Shows `message` in a window and returns the user's choice if they chose to restore
+/// `backup`.
+pub fn show_startup_error(
+    message: &str,
+    backup: Option<PathBuf>,
+) -> anyhow::Result<Option<Restore>> {
+    let restore = Arc::new(Mutex::new(None));
+    let app = StartupErrorApp {
+        message: message.to_string(),
+        backup,
+        passphrase: String::new(),
+        restore: Arc::clone(&restore),
+    };
+    let options = eframe::NativeOptions {
+        viewport: egui::ViewportBuilder::default().with_inner_size([520.0, 320.0]),
+        ..Default::default()
+    };
+    eframe::run_native(
+        "MCP-SSH Manager",
+        options,
+        Box::new(|cc| {
+            super::configure_fonts(&cc.egui_ctx);
+            Box::new(app)
+        }),
+    )
+    .map_err(|e| anyhow::anyhow!("GUI error: {}", e))?;
+    let restore = restore.lock().unwrap_or_else(|e| e.into_inner()).take();
+    Ok(restore)
+}
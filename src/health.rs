@@ -0,0 +1,696 @@
+use crate::db::{DbHandler, MAX_JUMP_HOPS, Machine, OsType};
+use anyhow::{Result, anyhow};
+use std::collections::HashSet;
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+/// Number of hosts probed at the same time during a sweep.
+const SWEEP_CONCURRENCY: usize = 8;
+/// `ConnectTimeout` passed to ssh for sweep probes.
+const SWEEP_CONNECT_TIMEOUT_SECS: u64 = 5;
+
+/// Port ssh connects to when none is given.
+pub const DEFAULT_SSH_PORT: u16 = 22;
+/// Connect timeout of the background reachability check.
+const REACHABILITY_TIMEOUT: Duration = Duration::from_secs(2);
+/// ssh flags allowed in a machine's `ssh_options` that take a value.
+const SSH_OPTION_FLAGS_WITH_VALUE: [&str; 4] = ["-o", "-J", "-p", "-i"];
+/// ssh flags allowed in a machine's `ssh_options` on their own.
+const SSH_OPTION_FLAGS: [&str; 3] = ["-4", "-6", "-C"];
+/// `-o` keywords refused because they run local programs, load shared libraries, or replace
+/// the remote command.
+const FORBIDDEN_SSH_KEYWORDS: [&str; 8] = [
+    "proxycommand",
+    "localcommand",
+    "permitlocalcommand",
+    "knownhostscommand",
+    "remotecommand",
+    "match",
+    "pkcs11provider",
+    "securitykeyprovider",
+];
+
+/// Builds the ssh destination for `user` on `host:port`, plus the arguments that must
+/// precede it. IPv6 literals are passed bare (`user@::1`): ssh splits the destination at
+/// the last `@` and can't resolve the bracketed `[::1]`, so surrounding brackets are
+/// stripped. A non-default port becomes `-p PORT`.
+pub fn format_ssh_target(user: &str, host: &str, port: u16) -> (String, Vec<String>) {
+    let host = host
+        .strip_prefix('[')
+        .and_then(|h| h.strip_suffix(']'))
+        .unwrap_or(host);
+    let extra_args = if port == DEFAULT_SSH_PORT {
+        Vec::new()
+    } else {
+        vec!["-p".to_string(), port.to_string()]
+    };
+    (format!("{}@{}", user, host), extra_args)
+}
+
+/// Splits a machine's `ssh_options` the way a shell would (quotes and backslashes) and
+/// checks each argument against the allowed flags, so the stored text can't add a
+/// destination, a remote command, or an option that runs local programs. Returns the
+/// arguments to place before the destination.
+pub fn parse_ssh_options(text: &str) -> Result<Vec<String>> {
+    let words = shell_words::split(text).map_err(|e| anyhow!("Invalid ssh_options: {}", e))?;
+    let mut args = Vec::with_capacity(words.len());
+    let mut words = words.into_iter();
+    while let Some(word) = words.next() {
+        if SSH_OPTION_FLAGS.contains(&word.as_str()) {
+            args.push(word);
+            continue;
+        }
+        let Some(flag) = SSH_OPTION_FLAGS_WITH_VALUE
+            .iter()
+            .find(|flag| word.starts_with(**flag))
+        else {
+            return Err(anyhow!(
+                "ssh_options: unsupported argument \"{}\" (allowed: {} VALUE, {})",
+                word,
+                SSH_OPTION_FLAGS_WITH_VALUE.join(" VALUE, "),
+                SSH_OPTION_FLAGS.join(", ")
+            ));
+        };
+        // Both "-o Key=value" and "-oKey=value"
+        let value = if word.len() > flag.len() {
+            word[flag.len()..].to_string()
+        } else {
+            words
+                .next()
+                .ok_or_else(|| anyhow!("ssh_options: {} needs a value", flag))?
+        };
+        if value.starts_with('-') {
+            return Err(anyhow!(
+                "ssh_options: invalid value \"{}\" for {}",
+                value,
+                flag
+            ));
+        }
+        if *flag == "-o" {
+            let keyword = split_ssh_option(&value).0.to_ascii_lowercase();
+            if FORBIDDEN_SSH_KEYWORDS.contains(&keyword.as_str()) {
+                return Err(anyhow!("ssh_options: {} is not allowed", keyword));
+            }
+        }
+        args.push(flag.to_string());
+        args.push(value);
+    }
+    Ok(args)
+}
+
+/// The machine's `ssh_options` as arguments; empty when unset. Stored values are validated
+/// on save, so an invalid one is only logged and ignored.
+pub fn machine_ssh_options(machine: &Machine) -> Vec<String> {
+    let Some(text) = machine.ssh_options.as_deref() else {
+        return Vec::new();
+    };
+    parse_ssh_options(text).unwrap_or_else(|e| {
+        tracing::warn!("Ignoring ssh options of machine {}: {}", machine.name, e);
+        Vec::new()
+    })
+}
+
+/// Splits an `-o` value into its keyword and value: `Port=2200`, `Port 2200`, or
+/// `Port = 2200`. Leading whitespace and `=` are skipped first, as ssh does.
+pub fn split_ssh_option(option: &str) -> (&str, &str) {
+    let option = option.trim_start_matches(|c: char| c == '=' || c.is_whitespace());
+    match option.split_once(|c: char| c == '=' || c.is_whitespace()) {
+        Some((keyword, value)) => (
+            keyword,
+            value.trim_start_matches(|c: char| c == '=' || c.is_whitespace()),
+        ),
+        None => (option, ""),
+    }
+}
+
+/// The port ssh connects to for `machine`: a `-p` (or `-o Port=`) in its ssh options, or
+/// [`DEFAULT_SSH_PORT`]. The last one given wins, as with ssh's command line.
+pub fn ssh_port(machine: &Machine) -> Result<u16> {
+    let parse_port = |value: &str| {
+        value
+            .trim()
+            .parse::<u16>()
+            .map_err(|_| anyhow!("Invalid port \"{}\" in the ssh options", value))
+    };
+    let mut port = DEFAULT_SSH_PORT;
+    let options = machine_ssh_options(machine);
+    let mut args = options.iter().map(String::as_str);
+    while let Some(arg) = args.next() {
+        match (arg, args.clone().next()) {
+            ("-p", Some(value)) => port = parse_port(value)?,
+            ("-o", Some(option)) => {
+                let (keyword, value) = split_ssh_option(option);
+                if keyword.eq_ignore_ascii_case("port") {
+                    port = parse_port(value)?;
+                }
+            }
+            _ => continue,
+        }
+        args.next();
+    }
+    Ok(port)
+}
+
+/// The `-J` value reaching `machine` through its jump host, outermost hop first, e.g.
+/// `admin@bastion,ops@10.0.1.5`; `None` when it is reached directly. Each hop uses the
+/// first account registered on that jump host.
+pub fn jump_chain(db: &DbHandler, machine: &Machine) -> Result<Option<String>> {
+    let mut hops = Vec::new();
+    let mut seen: HashSet<i64> = machine.id.into_iter().collect();
+    let mut next = machine.jump_host_id;
+    while let Some(id) = next {
+        if !seen.insert(id) || hops.len() == MAX_JUMP_HOPS {
+            return Err(anyhow!(
+                "Jump chain of {} loops or is too long",
+                machine.name
+            ));
+        }
+        let jump = db
+            .get_machine(id)?
+            .ok_or_else(|| anyhow!("Jump host {} of {} not found", id, machine.name))?;
+        let account = db
+            .list_accounts_for_machine(id)?
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow!("Jump host {} has no account", jump.name))?;
+        hops.push(jump_hop(
+            &account.username,
+            &jump.ip_address,
+            ssh_port(&jump)?,
+        ));
+        next = jump.jump_host_id;
+    }
+    hops.reverse();
+    Ok((!hops.is_empty()).then(|| hops.join(",")))
+}
+
+/// One `-J` hop: `user@host`, with `:port` when it isn't the default and IPv6 literals
+/// bracketed.
+fn jump_hop(user: &str, host: &str, port: u16) -> String {
+    let host = host
+        .strip_prefix('[')
+        .and_then(|h| h.strip_suffix(']'))
+        .unwrap_or(host);
+    let host = if host.contains(':') {
+        format!("[{}]", host)
+    } else {
+        host.to_string()
+    };
+    if port == DEFAULT_SSH_PORT {
+        format!("{}@{}", user, host)
+    } else {
+        format!("{}@{}:{}", user, host, port)
+    }
+}
+
+/// Everything ssh needs to reach `machine` besides the destination: `-J` for its jump
+/// chain followed by its own `ssh_options`.
+pub fn machine_ssh_args(db: &DbHandler, machine: &Machine) -> Result<Vec<String>> {
+    let mut args = Vec::new();
+    if let Some(chain) = jump_chain(db, machine)? {
+        args.push("-J".to_string());
+        args.push(chain);
+    }
+    args.extend(machine_ssh_options(machine));
+    Ok(args)
+}
+
+/// Whether ssh `args` (see [`machine_ssh_args`]) connect through a jump host, with `-J` or
+/// `-o ProxyJump`.
+pub fn uses_jump_host(args: &[String]) -> bool {
+    args.iter().zip(args.iter().skip(1)).any(|(flag, value)| {
+        flag == "-J"
+            || (flag == "-o" && split_ssh_option(value).0.eq_ignore_ascii_case("proxyjump"))
+    })
+}
+
+/// Remote command of the connection probe for a machine with `os_type`. Windows goes
+/// through `cmd /c`, which works whether sshd starts cmd or PowerShell; everything else
+/// (including an unrecognized type) gets a POSIX shell's `echo`.
+pub fn probe_command_for(os_type: &str) -> &'static [&'static str] {
+    match os_type.parse::<OsType>() {
+        Ok(OsType::Windows) => &["cmd", "/c", "echo", "connection_success"],
+        _ => &["echo", "connection_success"],
+    }
+}
+
+/// Runs the non-interactive [`probe_command_for`] `os_type` used by diagnose and the sweep.
+/// `verbose` adds `-v` so stderr carries the handshake details diagnose analyzes.
+/// The process is killed if it outlives twice the connect timeout. `known_hosts` overrides
+/// the user's known_hosts file (see [`crate::known_hosts::active_file`]); `options` are the
+/// machine's own (see [`machine_ssh_args`]).
+pub fn ssh_probe(
+    user: &str,
+    host: &str,
+    os_type: &str,
+    verbose: bool,
+    connect_timeout_secs: u64,
+    known_hosts: Option<&Path>,
+    options: &[String],
+) -> Result<Output> {
+    ssh_batch(
+        user,
+        host,
+        verbose,
+        connect_timeout_secs,
+        known_hosts,
+        options,
+        probe_command_for(os_type),
+    )
+}
+
+/// Runs `remote_command` over a non-interactive ssh connection, with the same options and
+/// timeout as [`ssh_probe`].
+pub fn ssh_batch(
+    user: &str,
+    host: &str,
+    verbose: bool,
+    connect_timeout_secs: u64,
+    known_hosts: Option<&Path>,
+    options: &[String],
+    remote_command: &[&str],
+) -> Result<Output> {
+    let (target, extra_args) = format_ssh_target(user, host, DEFAULT_SSH_PORT);
+    let mut cmd = Command::new("ssh");
+    if verbose {
+        cmd.arg("-v");
+    }
+    // Use BatchMode=yes to avoid interactivity, ConnectTimeout to avoid hanging
+    cmd.args([
+        "-o",
+        "BatchMode=yes",
+        "-o",
+        &format!("ConnectTimeout={}", connect_timeout_secs),
+    ]);
+    cmd.args(crate::known_hosts::ssh_options(known_hosts))
+        .args(options)
+        .args(&extra_args)
+        .arg(&target)
+        .args(remote_command);
+    output_with_timeout(cmd, Duration::from_secs(connect_timeout_secs * 2))
+}
+
+/// Like `Command::output`, but kills (and reaps) the child if it runs longer than `timeout`.
+pub fn output_with_timeout(cmd: Command, timeout: Duration) -> Result<Output> {
+    run_with_timeout(cmd, None, timeout)?
+        .ok_or_else(|| anyhow!("timed out after {}s", timeout.as_secs()))
+}
+
+/// Runs `cmd` with `input` (if any) on its stdin and collects its output. A child still
+/// running after `timeout` is killed and reaped, and `Ok(None)` is returned.
+pub fn run_with_timeout(
+    mut cmd: Command,
+    input: Option<&[u8]>,
+    timeout: Duration,
+) -> Result<Option<Output>> {
+    let mut child = cmd
+        .stdin(if input.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| anyhow!("Failed to execute {:?}: {}", cmd.get_program(), e))?;
+    if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+        // Dropping stdin afterwards closes it so the child sees EOF
+        if let Err(e) = stdin.write_all(input) {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(anyhow!("Failed to write to {:?}: {}", cmd.get_program(), e));
+        }
+    }
+
+    // Drain the pipes on their own threads so a chatty child can't block on a full pipe
+    let mut stdout = child
+        .stdout
+        .take()
+        .ok_or_else(|| anyhow!("stdout not captured"))?;
+    let mut stderr = child
+        .stderr
+        .take()
+        .ok_or_else(|| anyhow!("stderr not captured"))?;
+    let stdout_reader = std::thread::spawn(move || {
+        let mut buf = Vec::new();
+        let _ = stdout.read_to_end(&mut buf);
+        buf
+    });
+    let stderr_reader = std::thread::spawn(move || {
+        let mut buf = Vec::new();
+        let _ = stderr.read_to_end(&mut buf);
+        buf
+    });
+
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Ok(None);
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    };
+
+    Ok(Some(Output {
+        status,
+        stdout: stdout_reader.join().unwrap_or_default(),
+        stderr: stderr_reader.join().unwrap_or_default(),
+    }))
+}
+
+/// Probes every machine not under maintenance and records the outcome in its `status`
+/// ("active" when the host answered, "broken" on a connect failure or timeout). The probe
+/// runs in batch mode with the default keys and ssh-agent, so an authentication failure
+/// (e.g. an account that only has a password) still counts as reachable. Machines without
+/// an account are skipped.
+/// Machines for which `skip(machine_id)` is true are left out and keep their status.
+/// `progress(done, total)` is called on the calling thread as each probe finishes.
+pub fn sweep_connectivity(
+    db: &DbHandler,
+    skip: &mut dyn FnMut(i64) -> bool,
+    progress: &mut dyn FnMut(usize, usize),
+) -> Vec<(i64, bool)> {
+    let targets = match sweep_targets(db) {
+        Ok(mut t) => {
+            t.retain(|target| !skip(target.machine_id));
+            t
+        }
+        Err(e) => {
+            tracing::error!("Connectivity sweep failed to load machines: {}", e);
+            return Vec::new();
+        }
+    };
+
+    let known_hosts = crate::known_hosts::active_file(db);
+    let next = AtomicUsize::new(0);
+    let total = targets.len();
+    let mut results = Vec::with_capacity(total);
+    std::thread::scope(|s| {
+        let (tx, rx) = mpsc::channel();
+        for _ in 0..SWEEP_CONCURRENCY.min(total) {
+            let tx = tx.clone();
+            let (next, targets, known_hosts) = (&next, &targets, known_hosts.as_deref());
+            s.spawn(move || {
+                while let Some(target) = targets.get(next.fetch_add(1, Ordering::SeqCst)) {
+                    let reachable = ssh_probe(
+                        &target.username,
+                        &target.host,
+                        &target.os_type,
+                        false,
+                        SWEEP_CONNECT_TIMEOUT_SECS,
+                        known_hosts,
+                        &target.options,
+                    )
+                    .map(|o| host_answered(&o))
+                    .unwrap_or(false);
+                    let _ = tx.send((target.machine_id, reachable));
+                }
+            });
+        }
+        // Workers hold the remaining senders, so the loop ends when the last one finishes
+        drop(tx);
+        for result in rx {
+            results.push(result);
+            progress(results.len(), total);
+        }
+    });
+
+    results.sort_by_key(|(id, _)| *id);
+
+    for (id, reachable) in &results {
+        let status = if *reachable { "active" } else { "broken" };
+        if let Err(e) = db.update_machine_status(*id, status) {
+            tracing::warn!("Failed to update status for machine {}: {}", id, e);
+        }
+    }
+    results
+}
+
+/// Whether a sweep probe reached the host: it succeeded, or ssh got as far as
+/// authentication and was refused there.
+fn host_answered(output: &Output) -> bool {
+    if output.status.success() {
+        return true;
+    }
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    stderr.contains("Permission denied") || stderr.contains("Too many authentication failures")
+}
+
+/// Whether a TCP connection to `host:port` opens within `timeout`; no SSH handshake.
+pub fn tcp_reachable(host: &str, port: u16, timeout: Duration) -> bool {
+    let host = host
+        .strip_prefix('[')
+        .and_then(|h| h.strip_suffix(']'))
+        .unwrap_or(host);
+    let Ok(addrs) = (host, port).to_socket_addrs() else {
+        return false;
+    };
+    addrs
+        .into_iter()
+        .any(|addr| TcpStream::connect_timeout(&addr, timeout).is_ok())
+}
+
+/// Shown instead of a connection error when a `requires_vpn` machine doesn't answer.
+pub const VPN_REQUIRED_HINT: &str = "この接続先はVPNが必要です。VPN接続を確認してください";
+
+/// For a machine marked `requires_vpn`, a quick TCP check of its SSH port that fails with
+/// [`VPN_REQUIRED_HINT`]. Machines behind a jump host aren't reached directly, so they
+/// are not checked.
+pub fn check_vpn(machine: &Machine) -> Result<()> {
+    if !machine.requires_vpn || machine.jump_host_id.is_some() {
+        return Ok(());
+    }
+    let port = ssh_port(machine)?;
+    if tcp_reachable(&machine.ip_address, port, REACHABILITY_TIMEOUT) {
+        Ok(())
+    } else {
+        Err(anyhow!(VPN_REQUIRED_HINT))
+    }
+}
+
+/// TCP-connects to the SSH port of every machine not under maintenance, with the sweep's
+/// concurrency. Nothing is written to the database.
+pub fn check_reachability(db: &DbHandler) -> Result<Vec<(i64, bool)>> {
+    let targets: Vec<(i64, String, u16)> = db
+        .list_machines()?
+        .into_iter()
+        .filter(|m| m.status != "maintenance")
+        .filter_map(|m| {
+            let port = ssh_port(&m).unwrap_or(DEFAULT_SSH_PORT);
+            Some((m.id?, m.ip_address, port))
+        })
+        .collect();
+    let next = AtomicUsize::new(0);
+    let mut results = Vec::with_capacity(targets.len());
+    std::thread::scope(|s| {
+        let (tx, rx) = mpsc::channel();
+        for _ in 0..SWEEP_CONCURRENCY.min(targets.len()) {
+            let tx = tx.clone();
+            let (next, targets) = (&next, &targets);
+            s.spawn(move || {
+                while let Some((id, host, port)) = targets.get(next.fetch_add(1, Ordering::SeqCst))
+                {
+                    let reachable = tcp_reachable(host, *port, REACHABILITY_TIMEOUT);
+                    let _ = tx.send((*id, reachable));
+                }
+            });
+        }
+        drop(tx);
+        results.extend(rx);
+    });
+    Ok(results)
+}
+
+/// Machines [`sweep_connectivity`] would probe, by id, and how long it could take at most:
+/// every probe running into the connect timeout, [`SWEEP_CONCURRENCY`] at a time.
+pub fn plan_sweep(db: &DbHandler) -> Result<(Vec<i64>, Duration)> {
+    let ids: Vec<i64> = sweep_targets(db)?
+        .into_iter()
+        .map(|t| t.machine_id)
+        .collect();
+    let rounds = ids.len().div_ceil(SWEEP_CONCURRENCY) as u64;
+    Ok((
+        ids,
+        Duration::from_secs(rounds * SWEEP_CONNECT_TIMEOUT_SECS),
+    ))
+}
+
+/// A machine the sweep probes, with what the probe needs to reach it.
+struct SweepTarget {
+    machine_id: i64,
+    username: String,
+    host: String,
+    os_type: String,
+    /// The machine's ssh options and jump chain as arguments
+    options: Vec<String>,
+}
+
+/// Every machine the sweep should probe.
+fn sweep_targets(db: &DbHandler) -> Result<Vec<SweepTarget>> {
+    let accounts = db.list_accounts()?;
+    let targets = db
+        .list_machines()?
+        .into_iter()
+        .filter(|m| m.status != "maintenance")
+        .filter_map(|m| {
+            let id = m.id?;
+            let account = accounts.iter().find(|a| a.machine_id == id)?;
+            let options = machine_ssh_args(db, &m)
+                .map_err(|e| tracing::warn!("Skipping {} in the sweep: {}", m.name, e))
+                .ok()?;
+            Some(SweepTarget {
+                machine_id: id,
+                username: account.username.clone(),
+                host: m.ip_address,
+                os_type: m.os_type,
+                options,
+            })
+        })
+        .collect();
+    Ok(targets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_probe_command_for() {
+        assert_eq!(probe_command_for("linux"), ["echo", "connection_success"]);
+        assert_eq!(probe_command_for("macos"), ["echo", "connection_success"]);
+        assert_eq!(
+            probe_command_for("Windows"),
+            ["cmd", "/c", "echo", "connection_success"]
+        );
+        assert_eq!(probe_command_for("win11"), probe_command_for("windows"));
+        assert_eq!(probe_command_for("plan9"), ["echo", "connection_success"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_host_answered_counts_auth_failures() {
+        use std::os::unix::process::ExitStatusExt;
+        let output = |code: i32, stderr: &str| Output {
+            status: std::process::ExitStatus::from_raw(code << 8),
+            stdout: Vec::new(),
+            stderr: stderr.as_bytes().to_vec(),
+        };
+        assert!(host_answered(&output(0, "")));
+        assert!(host_answered(&output(
+            255,
+            "alice@10.1.2.3: Permission denied (publickey,password)."
+        )));
+        assert!(host_answered(&output(
+            255,
+            "Received disconnect from 10.1.2.3 port 22:2: Too many authentication failures"
+        )));
+        assert!(!host_answered(&output(
+            255,
+            "ssh: connect to host 10.1.2.3 port 22: Connection timed out"
+        )));
+        assert!(!host_answered(&output(
+            255,
+            "ssh: connect to host 10.1.2.3 port 22: Connection refused"
+        )));
+    }
+
+    #[test]
+    fn test_parse_ssh_options() {
+        assert_eq!(
+            parse_ssh_options(
+                "-J admin@bastion -o 'IdentitiesOnly yes' -oStrictHostKeyChecking=accept-new -4"
+            )
+            .unwrap(),
+            [
+                "-J",
+                "admin@bastion",
+                "-o",
+                "IdentitiesOnly yes",
+                "-o",
+                "StrictHostKeyChecking=accept-new",
+                "-4"
+            ]
+        );
+        assert!(parse_ssh_options("").unwrap().is_empty());
+        // Would become the destination or the remote command
+        assert!(parse_ssh_options("evil.example.com").is_err());
+        assert!(parse_ssh_options("-o ProxyCommand='nc %h %p'").is_err());
+        assert!(parse_ssh_options("-oLocalCommand=touch /tmp/x").is_err());
+        assert!(parse_ssh_options("-J -oProxyCommand=x").is_err());
+        // ssh skips leading whitespace before the keyword
+        assert!(parse_ssh_options("-o ' ProxyCommand=touch /tmp/x'").is_err());
+        assert!(parse_ssh_options("-o '\tLocalCommand=touch /tmp/x'").is_err());
+        assert!(parse_ssh_options("-o '=ProxyCommand=x'").is_err());
+        // Would load a shared library into ssh
+        assert!(parse_ssh_options("-o PKCS11Provider=/tmp/x.so").is_err());
+        assert!(parse_ssh_options("-oSecurityKeyProvider=/tmp/x.so").is_err());
+        assert!(parse_ssh_options("-o").is_err());
+        assert!(parse_ssh_options("-o 'unterminated").is_err());
+    }
+
+    #[test]
+    fn test_jump_hop_adds_the_bastion_port() {
+        assert_eq!(jump_hop("admin", "bastion", 22), "admin@bastion");
+        assert_eq!(jump_hop("admin", "bastion", 2222), "admin@bastion:2222");
+        assert_eq!(jump_hop("admin", "fe80::1", 22), "admin@[fe80::1]");
+        assert_eq!(jump_hop("admin", "[fe80::1]", 2222), "admin@[fe80::1]:2222");
+    }
+
+    #[test]
+    fn test_uses_jump_host() {
+        let args = |text: &str| parse_ssh_options(text).unwrap();
+        assert!(uses_jump_host(&args("-J admin@bastion")));
+        assert!(uses_jump_host(&args("-C -oProxyJump=bastion")));
+        assert!(!uses_jump_host(&args(
+            "-C -p 2222 -o ServerAliveInterval=30"
+        )));
+        assert!(!uses_jump_host(&[]));
+    }
+
+    #[test]
+    fn test_tcp_reachable() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        assert!(tcp_reachable("127.0.0.1", port, Duration::from_secs(1)));
+        drop(listener);
+        assert!(!tcp_reachable("127.0.0.1", port, Duration::from_secs(1)));
+        assert!(!tcp_reachable("bad host", port, Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn test_format_ssh_target() {
+        let none: Vec<String> = Vec::new();
+        assert_eq!(
+            format_ssh_target("admin", "192.168.1.10", DEFAULT_SSH_PORT),
+            ("admin@192.168.1.10".to_string(), none.clone())
+        );
+        assert_eq!(
+            format_ssh_target("admin", "server.example.com", DEFAULT_SSH_PORT),
+            ("admin@server.example.com".to_string(), none.clone())
+        );
+        assert_eq!(
+            format_ssh_target("admin", "fe80::1", DEFAULT_SSH_PORT),
+            ("admin@fe80::1".to_string(), none.clone())
+        );
+        assert_eq!(
+            format_ssh_target("admin", "[::1]", DEFAULT_SSH_PORT),
+            ("admin@::1".to_string(), none)
+        );
+        assert_eq!(
+            format_ssh_target("admin", "::1", 2222),
+            (
+                "admin@::1".to_string(),
+                vec!["-p".to_string(), "2222".to_string()]
+            )
+        );
+    }
+}
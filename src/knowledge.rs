@@ -1,4 +1,9 @@
+use crate::db::OsType;
+use anyhow::anyhow;
 use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Pattern {
@@ -6,6 +11,46 @@ pub struct Pattern {
     pub pattern: String,
     pub description: String,
     pub suggestion: Suggestion,
+    /// Only applies to machines with this `os_type` ("windows", "linux", ...); any OS when unset
+    #[serde(default)]
+    pub os_filter: Option<String>,
+    /// Output the pattern is matched against: [`CATEGORY_SSH`] (the default), [`CATEGORY_SUDO`],
+    /// or [`CATEGORY_KEY_REJECTED`]
+    #[serde(default)]
+    pub category: Option<String>,
+}
+
+/// Patterns matched against `ssh -v` stderr of a failed connection.
+pub const CATEGORY_SSH: &str = "ssh";
+/// Patterns matched against the output of `sudo -n true` on a reachable machine.
+pub const CATEGORY_SUDO: &str = "sudo";
+/// Patterns matched against `ssh -v` stderr when a key account failed but a password is
+/// stored for the same user.
+pub const CATEGORY_KEY_REJECTED: &str = "key_rejected";
+
+impl Pattern {
+    /// Matches the pattern against `text`, returning its suggestion with placeholders filled.
+    /// Fails only if the regex doesn't compile.
+    pub fn try_match(&self, text: &str) -> Result<Option<Suggestion>, regex::Error> {
+        let re = regex::Regex::new(&self.pattern)?;
+        Ok(re
+            .captures(text)
+            .map(|caps| expand_suggestion(&self.suggestion, &caps)))
+    }
+
+    fn in_category(&self, category: &str) -> bool {
+        self.category.as_deref().unwrap_or(CATEGORY_SSH) == category
+    }
+
+    fn applies_to(&self, os_type: &str) -> bool {
+        let Some(filter) = &self.os_filter else {
+            return true;
+        };
+        match (filter.parse::<OsType>(), os_type.parse::<OsType>()) {
+            (Ok(filter), Ok(os)) => filter == os,
+            _ => filter.trim().eq_ignore_ascii_case(os_type.trim()),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -14,41 +59,426 @@ pub struct Suggestion {
     pub action_type: String,
     pub command_hint: Option<String>,
     pub script_path: Option<String>,
+    /// Id of the [`Pattern`] that produced this suggestion; filled in when matching
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pattern_id: Option<String>,
 }
 
-pub fn load_troubleshooting_patterns() -> Vec<Pattern> {
-    let mut patterns = Vec::new();
+// Candidate locations of the knowledge file relative to the executable or project root
+const KNOWLEDGE_PATHS: [&str; 3] = [
+    "knowledge/troubleshooting.json",
+    "../knowledge/troubleshooting.json",
+    "../../knowledge/troubleshooting.json",
+];
 
-    // Attempt to locate the knowledge file relative to the executable or project root
-    let paths = [
-        "knowledge/troubleshooting.json",
-        "../knowledge/troubleshooting.json",
-        "../../knowledge/troubleshooting.json",
-    ];
+/// Returns the first knowledge file location that exists, if any.
+pub fn locate_knowledge_file() -> Option<&'static str> {
+    KNOWLEDGE_PATHS
+        .into_iter()
+        .find(|path| std::path::Path::new(path).is_file())
+}
 
-    for path in paths {
-        if let Ok(content) = std::fs::read_to_string(path) {
-            if let Ok(loaded) = serde_json::from_str::<Vec<Pattern>>(&content) {
-                patterns = loaded;
-                break;
-            }
+/// Where a knowledge file is created when none exists yet.
+pub fn default_knowledge_path() -> &'static str {
+    KNOWLEDGE_PATHS[0]
+}
+
+/// Path of the detached signature checked for the knowledge file at `path`.
+pub fn signature_path(path: &str) -> String {
+    format!("{}.sig", path)
+}
+
+/// Reads a knowledge file for editing. Unlike [`load_troubleshooting_patterns`] this skips
+/// the signature check, and a parse error is returned instead of ignored.
+pub fn read_patterns_file(path: &str) -> anyhow::Result<Vec<Pattern>> {
+    let content = std::fs::read_to_string(path)?;
+    serde_json::from_str(&content)
+        .map_err(|e| anyhow!("{} is not a valid knowledge file: {}", path, e))
+}
+
+/// Writes `patterns` to `path` as pretty-printed JSON, after checking that every regex
+/// compiles and every id is set and unique. An existing signature no longer matches afterwards.
+pub fn save_patterns_file(path: &str, patterns: &[Pattern]) -> anyhow::Result<()> {
+    let mut ids = std::collections::HashSet::new();
+    for p in patterns {
+        if p.id.trim().is_empty() {
+            return Err(anyhow!("a pattern has no id"));
         }
+        if !ids.insert(p.id.as_str()) {
+            return Err(anyhow!("duplicate pattern id \"{}\"", p.id));
+        }
+        regex::Regex::new(&p.pattern).map_err(|e| anyhow!("pattern \"{}\": {}", p.id, e))?;
+    }
+    if let Some(dir) = std::path::Path::new(path).parent() {
+        std::fs::create_dir_all(dir)?;
     }
+    std::fs::write(path, serde_json::to_string_pretty(patterns)? + "\n")?;
+    Ok(())
+}
 
-    patterns
+/// Setting holding the OpenSSH public key (`ssh-ed25519 AAAA...`) that signs the knowledge
+/// file. Overrides the key baked in at build time through `MCP_SSH_MANAGER_KNOWLEDGE_KEY`.
+pub const SETTING_SIGNING_KEY: &str = "knowledge.signing_key";
+/// `ssh-keygen -Y sign -n` namespace the knowledge file signature must be made with.
+pub const SIGNATURE_NAMESPACE: &str = "mcp-ssh-manager-knowledge";
+const BUILTIN_SIGNING_KEY: Option<&str> = option_env!("MCP_SSH_MANAGER_KNOWLEDGE_KEY");
+
+/// Set from `--strict-knowledge`: refuse knowledge files that aren't verifiably signed.
+static STRICT: AtomicBool = AtomicBool::new(false);
+
+pub fn set_strict(strict: bool) {
+    STRICT.store(strict, Ordering::Relaxed);
 }
 
-pub fn match_error_pattern(stderr: &str, patterns: &[Pattern]) -> Option<Suggestion> {
-    for p in patterns {
-        if let Ok(re) = regex::Regex::new(&p.pattern) {
-            if re.is_match(stderr) {
-                return Some(p.suggestion.clone());
+#[derive(Debug, PartialEq)]
+enum Signature {
+    Verified,
+    /// No `.sig` file next to the knowledge file
+    Missing,
+    /// A signature exists but there's no key to check it against
+    NoKey,
+    Invalid(String),
+}
+
+/// Checks `<path>.sig`, an `ssh-keygen -Y sign` signature over `content`.
+fn check_signature(path: &str, content: &str, signing_key: Option<&str>) -> Signature {
+    let sig_path = signature_path(path);
+    if !std::path::Path::new(&sig_path).is_file() {
+        return Signature::Missing;
+    }
+    let Some(key) = signing_key.map(str::trim).filter(|k| !k.is_empty()) else {
+        return Signature::NoKey;
+    };
+    match verify_with_ssh_keygen(&sig_path, content, key) {
+        Ok(()) => Signature::Verified,
+        Err(e) => Signature::Invalid(e.to_string()),
+    }
+}
+
+fn verify_with_ssh_keygen(sig_path: &str, content: &str, key: &str) -> anyhow::Result<()> {
+    const IDENTITY: &str = "knowledge";
+    // A fresh directory only the user can enter, so nobody can plant or swap the file
+    let dir = std::env::temp_dir().join(format!(
+        "mcp-ssh-manager-signers-{}-{:016x}",
+        std::process::id(),
+        rand::random::<u64>()
+    ));
+    let mut builder = std::fs::DirBuilder::new();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::DirBuilderExt;
+        builder.mode(0o700);
+    }
+    builder.create(&dir)?;
+    let allowed_signers = dir.join("allowed_signers");
+
+    let result = (|| {
+        std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&allowed_signers)?
+            .write_all(format!("{} {}\n", IDENTITY, key).as_bytes())?;
+
+        let mut child = Command::new("ssh-keygen")
+            .args([
+                "-Y",
+                "verify",
+                "-I",
+                IDENTITY,
+                "-n",
+                SIGNATURE_NAMESPACE,
+                "-s",
+            ])
+            .arg(sig_path)
+            .arg("-f")
+            .arg(&allowed_signers)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| anyhow!("Failed to execute ssh-keygen: {}", e))?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(content.as_bytes())?;
+        }
+        let output = child.wait_with_output()?;
+        if output.status.success() {
+            Ok(())
+        } else {
+            Err(anyhow!(
+                "{}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ))
+        }
+    })();
+    let _ = std::fs::remove_dir_all(&dir);
+    result
+}
+
+/// Loads the first readable knowledge file. A signature that fails to verify always
+/// rejects the file; an unsigned or unverifiable one is only rejected in strict mode
+/// and otherwise loaded with a warning. `signing_key` overrides the built-in key.
+pub fn load_troubleshooting_patterns(signing_key: Option<&str>) -> Vec<Pattern> {
+    let signing_key = signing_key.or(BUILTIN_SIGNING_KEY);
+    let strict = STRICT.load(Ordering::Relaxed);
+
+    for path in KNOWLEDGE_PATHS {
+        let Ok(content) = std::fs::read_to_string(path) else {
+            continue;
+        };
+        match check_signature(path, &content, signing_key) {
+            Signature::Verified => {}
+            Signature::Invalid(reason) => {
+                tracing::error!(
+                    "Ignoring knowledge file {}: signature check failed: {}",
+                    path,
+                    reason
+                );
+                return Vec::new();
+            }
+            unverified => {
+                let why = if unverified == Signature::Missing {
+                    "it is not signed"
+                } else {
+                    "no signing key is configured"
+                };
+                if strict {
+                    tracing::error!("Ignoring knowledge file {} in strict mode: {}", path, why);
+                    return Vec::new();
+                }
+                tracing::warn!("Knowledge file {} is not verified: {}", path, why);
             }
         }
+        if let Ok(loaded) = serde_json::from_str::<Vec<Pattern>>(&content) {
+            return loaded;
+        }
+    }
+
+    Vec::new()
+}
+
+/// Returns the suggestion of the first pattern for `os_type` matching `stderr`, with `$1` / `${name}`
+/// placeholders in its message, command hint, and script path filled from the pattern's
+/// capture groups (named groups use `(?P<name>...)`). A literal `$`, e.g. a shell
+/// variable in a command hint, must be written `$$`. Captures are shell-quoted in the
+/// command hint and script path unless they only hold `[A-Za-z0-9._:@/-]`.
+pub fn match_error_pattern(
+    stderr: &str,
+    os_type: &str,
+    patterns: &[Pattern],
+) -> Option<Suggestion> {
+    match_in_category(stderr, os_type, CATEGORY_SSH, patterns)
+}
+
+/// Like [`match_error_pattern`] for the output of a failed `sudo -n true`. Patterns from the
+/// knowledge file come first, then the built-in [`default_sudo_patterns`].
+pub fn match_sudo_pattern(output: &str, os_type: &str, patterns: &[Pattern]) -> Option<Suggestion> {
+    match_in_category(output, os_type, CATEGORY_SUDO, patterns)
+        .or_else(|| match_in_category(output, os_type, CATEGORY_SUDO, &default_sudo_patterns()))
+}
+
+/// Like [`match_error_pattern`] for a rejected key when a password is stored for the same
+/// user. Patterns from the knowledge file come first, then [`default_key_rejected_patterns`].
+pub fn match_key_rejected_pattern(
+    stderr: &str,
+    os_type: &str,
+    patterns: &[Pattern],
+) -> Option<Suggestion> {
+    match_in_category(stderr, os_type, CATEGORY_KEY_REJECTED, patterns).or_else(|| {
+        match_in_category(
+            stderr,
+            os_type,
+            CATEGORY_KEY_REJECTED,
+            &default_key_rejected_patterns(),
+        )
+    })
+}
+
+/// Hints for a key the server refused, depending on whether it also offers password login.
+pub fn default_key_rejected_patterns() -> Vec<Pattern> {
+    let pattern = |id: &str, regex: &str, description: &str, message: &str| Pattern {
+        id: id.to_string(),
+        pattern: regex.to_string(),
+        description: description.to_string(),
+        suggestion: Suggestion {
+            message: message.to_string(),
+            action_type: "manual".to_string(),
+            command_hint: None,
+            script_path: None,
+            pattern_id: None,
+        },
+        os_filter: None,
+        category: Some(CATEGORY_KEY_REJECTED.to_string()),
+    };
+    vec![
+        pattern(
+            "KEY_REJECTED_PASSWORD_OFFERED",
+            r"Permission denied \((?P<methods>[^)]*publickey[^)]*password[^)]*|[^)]*password[^)]*publickey[^)]*)\)",
+            "Key rejected, server also accepts passwords",
+            "Key auth failed but password auth might work: the server offers ${methods} and a password is stored for this user. The diagnose probe runs in BatchMode and never sends the password. The key is most likely missing from ~/.ssh/authorized_keys on the server; use the password account once to install it.",
+        ),
+        pattern(
+            "KEY_REJECTED_KEYS_ONLY",
+            r"Permission denied \((?P<methods>[^)]*publickey[^)]*)\)",
+            "Key rejected, server accepts keys only",
+            "Key auth failed and the server only offers ${methods}, so the stored password won't help. Install this account's public key in ~/.ssh/authorized_keys on the server.",
+        ),
+    ]
+}
+
+/// Hints for the common reasons passwordless sudo fails, used when the knowledge file has none.
+pub fn default_sudo_patterns() -> Vec<Pattern> {
+    let pattern =
+        |id: &str, regex: &str, description: &str, message: &str, hint: Option<&str>| Pattern {
+            id: id.to_string(),
+            pattern: regex.to_string(),
+            description: description.to_string(),
+            suggestion: Suggestion {
+                message: message.to_string(),
+                action_type: if hint.is_some() {
+                    "run_command"
+                } else {
+                    "manual"
+                }
+                .to_string(),
+                command_hint: hint.map(str::to_string),
+                script_path: None,
+                pattern_id: None,
+            },
+            os_filter: None,
+            category: Some(CATEGORY_SUDO.to_string()),
+        };
+    vec![
+        pattern(
+            "SUDO_NOT_IN_SUDOERS",
+            r"(?P<user>\S+) is not in the sudoers file",
+            "User has no sudo rights",
+            "${user} is not allowed to use sudo. An administrator must add the user to the sudo (or wheel) group.",
+            Some("usermod -aG sudo ${user}"),
+        ),
+        pattern(
+            "SUDO_PASSWORD_REQUIRED",
+            r"a (terminal|password) is required",
+            "sudo asks for a password",
+            "sudo works but requires a password, so it can't be used non-interactively. Add a NOPASSWD rule for this user if unattended sudo is intended.",
+            // `$$` is a literal `$` in hint templates
+            Some("echo \"$$USER ALL=(ALL) NOPASSWD: ALL\" | sudo tee /etc/sudoers.d/$$USER"),
+        ),
+        pattern(
+            "SUDO_NOT_INSTALLED",
+            r"sudo: (command )?not found",
+            "sudo is not installed",
+            "sudo is not installed on this machine. Install it as root, or use su.",
+            Some("apt-get install sudo"),
+        ),
+        pattern(
+            "SUDO_REQUIRETTY",
+            r"you must have a tty to run sudo",
+            "requiretty is set",
+            "sudoers has `Defaults requiretty`, which blocks sudo over non-interactive SSH. Remove it or exempt this user.",
+            None,
+        ),
+    ]
+}
+
+fn match_in_category(
+    output: &str,
+    os_type: &str,
+    category: &str,
+    patterns: &[Pattern],
+) -> Option<Suggestion> {
+    let candidates = patterns
+        .iter()
+        .filter(|p| p.in_category(category) && p.applies_to(os_type));
+    for p in candidates {
+        // A pattern whose regex doesn't compile never matches
+        if let Ok(Some(suggestion)) = p.try_match(output) {
+            return Some(Suggestion {
+                pattern_id: Some(p.id.clone()),
+                ..suggestion
+            });
+        }
     }
     None
 }
 
+/// Fills the placeholders of a matched pattern's suggestion. Captured text comes from the
+/// server's output, so in the command hint and script path anything beyond
+/// `[A-Za-z0-9._:@/-]` is shell-quoted before it can reach a shell.
+fn expand_suggestion(suggestion: &Suggestion, caps: &regex::Captures) -> Suggestion {
+    let as_is = |value: &str| value.to_string();
+    Suggestion {
+        message: expand_template(&suggestion.message, caps, as_is),
+        action_type: suggestion.action_type.clone(),
+        command_hint: suggestion
+            .command_hint
+            .as_deref()
+            .map(|t| expand_template(t, caps, shell_safe)),
+        script_path: suggestion
+            .script_path
+            .as_deref()
+            .map(|t| expand_template(t, caps, shell_safe)),
+        pattern_id: suggestion.pattern_id.clone(),
+    }
+}
+
+/// `value` unchanged if it only holds `[A-Za-z0-9._:@/-]`, otherwise single-quoted for sh.
+fn shell_safe(value: &str) -> String {
+    let plain = !value.is_empty()
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "._:@/-".contains(c));
+    if plain {
+        value.to_string()
+    } else {
+        shell_words::quote(value).into_owned()
+    }
+}
+
+/// Replaces `$1`, `$name`, and `${name}` in `template` with the captured text passed
+/// through `render`, following [`regex::Captures::expand`]: a bare name runs as far as
+/// `[A-Za-z0-9_]` goes, unknown groups become empty, and `$$` is a literal `$`.
+fn expand_template(
+    template: &str,
+    caps: &regex::Captures,
+    render: impl Fn(&str) -> String,
+) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(pos) = rest.find('$') {
+        out.push_str(&rest[..pos]);
+        rest = &rest[pos + 1..];
+        if let Some(after) = rest.strip_prefix('$') {
+            out.push('$');
+            rest = after;
+            continue;
+        }
+        let (name, after) = match rest.strip_prefix('{') {
+            Some(braced) => match braced.find('}') {
+                Some(end) => (&braced[..end], &braced[end + 1..]),
+                None => ("", rest),
+            },
+            None => {
+                let end = rest
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                    .unwrap_or(rest.len());
+                (&rest[..end], &rest[end..])
+            }
+        };
+        if name.is_empty() {
+            out.push('$');
+            continue;
+        }
+        let value = match name.parse::<usize>() {
+            Ok(index) => caps.get(index),
+            Err(_) => caps.name(name),
+        };
+        out.push_str(&render(value.map_or("", |m| m.as_str())));
+        rest = after;
+    }
+    out.push_str(rest);
+    out
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -64,13 +494,209 @@ mod tests {
                 action_type: "run_script".to_string(),
                 command_hint: None,
                 script_path: Some("scripts/fix_acl.py".to_string()),
+                pattern_id: None,
             },
+            os_filter: None,
+            category: None,
         }];
 
         let stderr_input = "user@host: Permission denied (publickey,keyboard-interactive).";
-        let suggestion = match_error_pattern(stderr_input, &patterns);
+        let suggestion = match_error_pattern(stderr_input, "windows", &patterns);
 
         assert!(suggestion.is_some());
-        assert_eq!(suggestion.unwrap().message, "ACL Error detected");
+        let suggestion = suggestion.unwrap();
+        assert_eq!(suggestion.message, "ACL Error detected");
+        assert_eq!(suggestion.pattern_id.as_deref(), Some("WIN_SSH_ACL_DENIED"));
+    }
+
+    #[test]
+    fn test_key_rejected_hint_depends_on_offered_methods() {
+        let hint = match_key_rejected_pattern(
+            "alice@web01: Permission denied (publickey,password).",
+            "linux",
+            &[],
+        )
+        .unwrap();
+        assert!(
+            hint.message
+                .starts_with("Key auth failed but password auth might work")
+        );
+        assert!(hint.message.contains("publickey,password"));
+
+        let hint =
+            match_key_rejected_pattern("alice@web01: Permission denied (publickey).", "linux", &[])
+                .unwrap();
+        assert!(hint.message.contains("won't help"));
+
+        // Kept out of the regular ssh hints
+        assert!(
+            match_error_pattern(
+                "Permission denied (publickey).",
+                "linux",
+                &default_key_rejected_patterns()
+            )
+            .is_none()
+        );
+    }
+
+    #[test]
+    fn test_sudo_patterns_are_kept_apart() {
+        let output = "sudo: a password is required";
+        let hint = match_sudo_pattern(output, "linux", &[]).unwrap();
+        assert!(hint.message.contains("NOPASSWD"));
+        assert_eq!(
+            hint.command_hint.as_deref(),
+            Some("echo \"$USER ALL=(ALL) NOPASSWD: ALL\" | sudo tee /etc/sudoers.d/$USER")
+        );
+
+        let hint = match_sudo_pattern("bob is not in the sudoers file.", "linux", &[]).unwrap();
+        assert_eq!(hint.command_hint.as_deref(), Some("usermod -aG sudo bob"));
+
+        // Sudo hints never answer an ssh failure
+        assert!(match_error_pattern(output, "linux", &default_sudo_patterns()).is_none());
+    }
+
+    #[test]
+    fn test_unsigned_file_reports_missing_signature() {
+        assert_eq!(
+            check_signature("does/not/exist.json", "[]", Some("ssh-ed25519 AAAA")),
+            Signature::Missing
+        );
+    }
+
+    #[test]
+    fn test_signature_is_verified_against_the_content() {
+        let dir = std::env::temp_dir().join(format!(
+            "mcp-ssh-manager-test-sign-{}-{:016x}",
+            std::process::id(),
+            rand::random::<u64>()
+        ));
+        std::fs::create_dir(&dir).unwrap();
+        let key = dir.join("id_ed25519");
+        let status = Command::new("ssh-keygen")
+            .args(["-q", "-t", "ed25519", "-N", "", "-f"])
+            .arg(&key)
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let content = r#"[{"id":"X"}]"#;
+        let path = dir.join("troubleshooting.json");
+        std::fs::write(&path, content).unwrap();
+        let status = Command::new("ssh-keygen")
+            .args(["-q", "-Y", "sign", "-n", SIGNATURE_NAMESPACE, "-f"])
+            .arg(&key)
+            .arg(&path)
+            .stderr(Stdio::null())
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let path = path.to_str().unwrap();
+        let public_key = std::fs::read_to_string(dir.join("id_ed25519.pub")).unwrap();
+        assert_eq!(
+            check_signature(path, content, Some(&public_key)),
+            Signature::Verified
+        );
+        assert!(matches!(
+            check_signature(path, r#"[{"id":"Y"}]"#, Some(&public_key)),
+            Signature::Invalid(_)
+        ));
+        assert_eq!(check_signature(path, content, None), Signature::NoKey);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_capture_groups_expand_into_hints() {
+        let patterns = vec![Pattern {
+            id: "PUBKEY_DENIED".to_string(),
+            pattern: r"(?P<user>[\w.-]+)@(?P<host>[\w.-]+): Permission denied \((publickey)"
+                .to_string(),
+            description: "Key not authorized".to_string(),
+            suggestion: Suggestion {
+                message: "${host} rejected the key ($3)".to_string(),
+                action_type: "run_command".to_string(),
+                command_hint: Some("ssh-copy-id ${user}@${host}".to_string()),
+                script_path: None,
+                pattern_id: None,
+            },
+            os_filter: None,
+            category: None,
+        }];
+
+        let stderr_input = "admin@db01: Permission denied (publickey,password).";
+        let suggestion = match_error_pattern(stderr_input, "linux", &patterns).unwrap();
+
+        assert_eq!(suggestion.message, "db01 rejected the key (publickey)");
+        assert_eq!(
+            suggestion.command_hint.as_deref(),
+            Some("ssh-copy-id admin@db01")
+        );
+        assert_eq!(suggestion.script_path, None);
+    }
+
+    #[test]
+    fn test_captures_are_shell_quoted_in_commands() {
+        let patterns = vec![Pattern {
+            id: "NOT_SUDOER".to_string(),
+            pattern: r"(?P<user>\S+) is not in the sudoers file".to_string(),
+            description: String::new(),
+            suggestion: Suggestion {
+                message: "${user} can't sudo ($$USER)".to_string(),
+                action_type: "run_command".to_string(),
+                command_hint: Some("usermod -aG sudo ${user} # $$USER $".to_string()),
+                script_path: None,
+                pattern_id: None,
+            },
+            os_filter: None,
+            category: Some(CATEGORY_SUDO.to_string()),
+        }];
+
+        let hint = match_sudo_pattern(
+            "$(rm${IFS}-rf${IFS}~);x is not in the sudoers file.",
+            "linux",
+            &patterns,
+        )
+        .unwrap();
+        assert_eq!(
+            hint.command_hint.as_deref(),
+            Some("usermod -aG sudo '$(rm${IFS}-rf${IFS}~);x' # $USER $")
+        );
+        // Messages are only shown, never run
+        assert_eq!(hint.message, "$(rm${IFS}-rf${IFS}~);x can't sudo ($USER)");
+
+        let hint = match_sudo_pattern("deploy.bot is not in the sudoers file.", "linux", &patterns)
+            .unwrap();
+        assert_eq!(
+            hint.command_hint.as_deref(),
+            Some("usermod -aG sudo deploy.bot # $USER $")
+        );
+    }
+
+    #[test]
+    fn test_os_filter_selects_matching_pattern() {
+        let pattern = |id: &str, os: &str, message: &str| Pattern {
+            id: id.to_string(),
+            pattern: "Permission denied".to_string(),
+            description: String::new(),
+            suggestion: Suggestion {
+                message: message.to_string(),
+                action_type: "info".to_string(),
+                command_hint: None,
+                script_path: None,
+                pattern_id: None,
+            },
+            os_filter: Some(os.to_string()),
+            category: None,
+        };
+        let patterns = vec![
+            pattern("WIN_ACL", "Windows", "fix ACL"),
+            pattern("UNIX_PERMS", "linux", "chmod 600"),
+        ];
+
+        let stderr_input = "Permission denied (publickey).";
+        let on = |os: &str| match_error_pattern(stderr_input, os, &patterns).map(|s| s.message);
+        assert_eq!(on("windows").as_deref(), Some("fix ACL"));
+        assert_eq!(on("ubuntu").as_deref(), Some("chmod 600"));
+        assert_eq!(on("macos"), None);
     }
 }
@@ -0,0 +1,319 @@
+use crate::db::{self, DbHandler};
+use anyhow::{Result, anyhow};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// Setting that, when "true", makes spawned ssh processes use [`managed_path`] instead of
+/// the user's `~/.ssh/known_hosts`, keeping host trust per profile.
+pub const SETTING_MANAGED_FILE: &str = "ssh.managed_known_hosts";
+
+/// known_hosts file kept in the (profile's) data directory.
+pub fn managed_path() -> Result<PathBuf> {
+    Ok(db::data_dir()?.join("known_hosts"))
+}
+
+/// The known_hosts file to use: the managed one when the setting opts in, otherwise `None`
+/// for ssh's default.
+pub fn active_file(db: &DbHandler) -> Option<PathBuf> {
+    let enabled = db
+        .get_setting(SETTING_MANAGED_FILE)
+        .ok()
+        .flatten()
+        .is_some_and(|v| v == "true");
+    if !enabled {
+        return None;
+    }
+    match managed_path() {
+        Ok(path) => Some(path),
+        Err(e) => {
+            tracing::warn!("Falling back to the default known_hosts: {}", e);
+            None
+        }
+    }
+}
+
+/// ssh options selecting `file` as the known_hosts file; empty for the default.
+/// The path is quoted because ssh splits the option value at whitespace.
+pub fn ssh_options(file: Option<&Path>) -> Vec<String> {
+    match file {
+        Some(file) => vec![
+            "-o".to_string(),
+            format!("UserKnownHostsFile=\"{}\"", file.display()),
+        ],
+        None => Vec::new(),
+    }
+}
+
+/// Host keys fetched with `ssh-keyscan`, awaiting user confirmation.
+#[derive(Debug, Clone)]
+pub struct ScannedHostKey {
+    pub host: String,
+    /// Raw `ssh-keyscan` lines, one per key, appended to known_hosts once confirmed
+    pub known_hosts_lines: Vec<String>,
+    /// e.g. "SHA256:abc… (ED25519)", in the order of `known_hosts_lines`
+    pub fingerprints: Vec<String>,
+}
+
+impl ScannedHostKey {
+    /// Just the key whose fingerprint is `fingerprint` ("SHA256:…"; a trailing key type is
+    /// ignored), so confirming one fingerprint trusts no other key.
+    pub fn only(&self, fingerprint: &str) -> Option<ScannedHostKey> {
+        let wanted = fingerprint.split_whitespace().next()?;
+        let index = self
+            .fingerprints
+            .iter()
+            .position(|fp| fp.split_whitespace().next() == Some(wanted))?;
+        Some(ScannedHostKey {
+            host: self.host.clone(),
+            known_hosts_lines: vec![self.known_hosts_lines[index].clone()],
+            fingerprints: vec![self.fingerprints[index].clone()],
+        })
+    }
+}
+
+/// True when ssh refused to connect because the host key is unknown or not trusted.
+pub fn is_host_key_failure(stderr: &str) -> bool {
+    stderr.contains("Host key verification failed")
+        || stderr.contains("No ED25519 host key is known")
+        || stderr.contains("No ECDSA host key is known")
+        || stderr.contains("No RSA host key is known")
+}
+
+/// Name ssh looks `host` up under in known_hosts: the bare host on the default port,
+/// otherwise `[host]:port`.
+pub fn known_hosts_name(host: &str, port: u16) -> String {
+    let host = host
+        .strip_prefix('[')
+        .and_then(|h| h.strip_suffix(']'))
+        .unwrap_or(host);
+    if port == crate::health::DEFAULT_SSH_PORT {
+        host.to_string()
+    } else {
+        format!("[{}]:{}", host, port)
+    }
+}
+
+/// True when `file` (the user's known_hosts when `None`) already has an entry for `host`
+/// on `port`.
+pub fn is_known(host: &str, port: u16, file: Option<&Path>) -> bool {
+    let mut cmd = Command::new("ssh-keygen");
+    cmd.arg("-F").arg(known_hosts_name(host, port));
+    if let Some(file) = file {
+        cmd.arg("-f").arg(file);
+    }
+    cmd.stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .map(|s| s.success())
+        .unwrap_or(false)
+}
+
+/// Fetches the server's host keys on `port` and their SHA256 fingerprints. The lines are
+/// named as ssh looks the host up ([`known_hosts_name`]).
+pub fn scan_host_key(host: &str, port: u16) -> Result<ScannedHostKey> {
+    let bare_host = host
+        .strip_prefix('[')
+        .and_then(|h| h.strip_suffix(']'))
+        .unwrap_or(host);
+    let output = Command::new("ssh-keyscan")
+        .args(["-T", "5", "-p", &port.to_string(), bare_host])
+        .output()
+        .map_err(|e| anyhow!("Failed to execute ssh-keyscan: {}", e))?;
+    let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+    let lines: Vec<&str> = stdout
+        .lines()
+        .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+        .collect();
+    if lines.is_empty() {
+        return Err(anyhow!("ssh-keyscan returned no host keys for {}", host));
+    }
+
+    // One ssh-keygen per line keeps each fingerprint paired with the line it came from
+    let mut known_hosts_lines = Vec::new();
+    let mut fingerprints = Vec::new();
+    let name = known_hosts_name(host, port);
+    for line in lines {
+        let Some((_, key)) = line.split_once(char::is_whitespace) else {
+            continue;
+        };
+        let line = format!("{} {}", name, key.trim_start());
+        if let Some(fingerprint) = host_key_fingerprint(&line)? {
+            known_hosts_lines.push(line);
+            fingerprints.push(fingerprint);
+        }
+    }
+    if fingerprints.is_empty() {
+        return Err(anyhow!(
+            "Could not compute host key fingerprints for {}",
+            host
+        ));
+    }
+
+    Ok(ScannedHostKey {
+        host: host.to_string(),
+        known_hosts_lines,
+        fingerprints,
+    })
+}
+
+/// Fingerprint of one known_hosts line, e.g. "SHA256:xxxx (ED25519)"; `None` when
+/// ssh-keygen doesn't take it for a key.
+fn host_key_fingerprint(line: &str) -> Result<Option<String>> {
+    let mut child = Command::new("ssh-keygen")
+        .args(["-l", "-f", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| anyhow!("Failed to execute ssh-keygen: {}", e))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        writeln!(stdin, "{}", line)?;
+    }
+    let output = child.wait_with_output()?;
+    Ok(parse_host_key_fingerprint(&String::from_utf8_lossy(
+        &output.stdout,
+    )))
+}
+
+/// "256 SHA256:xxxx host (ED25519)" -> "SHA256:xxxx (ED25519)"
+fn parse_host_key_fingerprint(output: &str) -> Option<String> {
+    let parts: Vec<&str> = output.lines().next()?.split_whitespace().collect();
+    match (parts.get(1), parts.last()) {
+        (Some(fp), Some(kind)) if parts.len() >= 3 => Some(format!("{} {}", fp, kind)),
+        _ => None,
+    }
+}
+
+/// SHA256 fingerprint ("SHA256:xxxx") of a key file as printed by `ssh-keygen -l`.
+/// Accepts a private key; ssh-keygen reads the public half from it or from the `.pub` beside it.
+pub fn key_fingerprint(path: &std::path::Path) -> Result<String> {
+    let output = Command::new("ssh-keygen")
+        .arg("-l")
+        .arg("-f")
+        .arg(path)
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| anyhow!("Failed to execute ssh-keygen: {}", e))?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "ssh-keygen could not read {}: {}",
+            path.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    parse_fingerprint(&String::from_utf8_lossy(&output.stdout))
+        .ok_or_else(|| anyhow!("Could not compute the fingerprint of {}", path.display()))
+}
+
+/// True when the private key at `path` is protected by a passphrase: `ssh-keygen -y` can't
+/// derive its public half with an empty one. An unreadable file counts as unprotected so
+/// the real error surfaces from the connection attempt.
+pub fn key_needs_passphrase(path: &std::path::Path) -> bool {
+    Command::new("ssh-keygen")
+        .args(["-y", "-P", ""])
+        .arg("-f")
+        .arg(path)
+        .stdin(Stdio::null())
+        .output()
+        .is_ok_and(|output| {
+            !output.status.success()
+                && String::from_utf8_lossy(&output.stderr).contains("passphrase")
+        })
+}
+
+/// "256 SHA256:xxxx comment (ED25519)" -> "SHA256:xxxx"
+fn parse_fingerprint(output: &str) -> Option<String> {
+    output
+        .lines()
+        .next()?
+        .split_whitespace()
+        .nth(1)
+        .filter(|fp| fp.contains(':'))
+        .map(str::to_string)
+}
+
+/// Appends confirmed host keys to `file`, or to the user's `~/.ssh/known_hosts` when `None`.
+pub fn add_to_known_hosts(scanned: &ScannedHostKey, file: Option<&Path>) -> Result<()> {
+    let path = match file {
+        Some(file) => file.to_path_buf(),
+        None => directories::UserDirs::new()
+            .ok_or_else(|| anyhow!("Could not locate home directory"))?
+            .home_dir()
+            .join(".ssh")
+            .join("known_hosts"),
+    };
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)?;
+    for line in &scanned.known_hosts_lines {
+        writeln!(file, "{}", line)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ssh_options_quote_the_path() {
+        assert!(ssh_options(None).is_empty());
+        assert_eq!(
+            ssh_options(Some(Path::new("/data/Application Support/known_hosts"))),
+            vec![
+                "-o".to_string(),
+                "UserKnownHostsFile=\"/data/Application Support/known_hosts\"".to_string()
+            ]
+        );
+    }
+
+    #[test]
+    fn test_only_keeps_the_confirmed_key() {
+        let scanned = ScannedHostKey {
+            host: "10.0.0.5".to_string(),
+            known_hosts_lines: vec![
+                "10.0.0.5 ssh-rsa AAAArsa".to_string(),
+                "10.0.0.5 ssh-ed25519 AAAAed".to_string(),
+            ],
+            fingerprints: vec![
+                "SHA256:rsa (RSA)".to_string(),
+                "SHA256:ed (ED25519)".to_string(),
+            ],
+        };
+        let confirmed = scanned.only("SHA256:ed").unwrap();
+        assert_eq!(confirmed.known_hosts_lines, ["10.0.0.5 ssh-ed25519 AAAAed"]);
+        assert_eq!(confirmed.fingerprints, ["SHA256:ed (ED25519)"]);
+        assert!(scanned.only("SHA256:rsa (RSA)").is_some());
+        assert!(scanned.only("SHA256:other").is_none());
+        assert!(scanned.only("").is_none());
+
+        assert_eq!(
+            parse_host_key_fingerprint("256 SHA256:ed 10.0.0.5 (ED25519)\n").as_deref(),
+            Some("SHA256:ed (ED25519)")
+        );
+        assert_eq!(parse_host_key_fingerprint(""), None);
+    }
+
+    #[test]
+    fn test_known_hosts_name_brackets_other_ports() {
+        assert_eq!(known_hosts_name("10.0.0.5", 22), "10.0.0.5");
+        assert_eq!(known_hosts_name("10.0.0.5", 2222), "[10.0.0.5]:2222");
+        assert_eq!(known_hosts_name("[fe80::1]", 22), "fe80::1");
+        assert_eq!(known_hosts_name("fe80::1", 2222), "[fe80::1]:2222");
+    }
+
+    #[test]
+    fn test_parse_fingerprint() {
+        assert_eq!(
+            parse_fingerprint("256 SHA256:AbCdEf0123 user@laptop (ED25519)\n").as_deref(),
+            Some("SHA256:AbCdEf0123")
+        );
+        assert_eq!(parse_fingerprint("not a key file\n"), None);
+        assert_eq!(parse_fingerprint(""), None);
+    }
+}
@@ -0,0 +1,121 @@
+use anyhow::{Result, anyhow};
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::EnvFilter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+
+/// Daily log files kept in `<data dir>/logs`.
+const MAX_LOG_FILES: usize = 7;
+/// Lines kept for the GUI's "最近のメッセージ" panel.
+const RECENT_CAPACITY: usize = 200;
+
+/// Copy of what goes to stderr, which release builds on Windows have no console for.
+static RECENT: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+
+fn push_line(buffer: &mut VecDeque<String>, line: String) {
+    if buffer.len() == RECENT_CAPACITY {
+        buffer.pop_front();
+    }
+    buffer.push_back(line);
+}
+
+/// Adds `message` to the in-app panel, one entry per line.
+fn push_recent(message: &str) {
+    let mut buffer = RECENT.lock().unwrap_or_else(|e| e.into_inner());
+    for line in message.lines() {
+        push_line(&mut buffer, line.to_string());
+    }
+}
+
+/// Oldest-first copy of the in-app message buffer.
+pub fn recent_messages() -> Vec<String> {
+    RECENT
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .iter()
+        .cloned()
+        .collect()
+}
+
+/// `fmt` writer feeding [`RECENT`]; it gets one formatted event and flushes it on drop.
+struct RecentWriter(Vec<u8>);
+
+impl std::io::Write for RecentWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for RecentWriter {
+    fn drop(&mut self) {
+        if !self.0.is_empty() {
+            push_recent(&String::from_utf8_lossy(&self.0));
+        }
+    }
+}
+
+/// Sends logs to stderr and to a daily-rotated file in the data dir, so GUI-only users
+/// (no console) still get diagnostics. Nothing is ever written to stdout, which carries
+/// the JSON-RPC stream in stdio MCP mode.
+///
+/// `level` (from `--log-level`) takes precedence over `RUST_LOG`; the default is `info`.
+/// The returned guard must be kept alive to flush the file writer.
+pub fn init(level: Option<&str>) -> Result<WorkerGuard> {
+    let filter = match level {
+        Some(level) => EnvFilter::try_new(level)
+            .map_err(|e| anyhow!("Invalid --log-level '{}': {}", level, e))?,
+        None => EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
+    };
+
+    let log_dir = crate::db::log_dir()?;
+    std::fs::create_dir_all(&log_dir)?;
+    let appender = RollingFileAppender::builder()
+        .rotation(Rotation::DAILY)
+        .filename_prefix("mcp-ssh-manager")
+        .filename_suffix("log")
+        .max_log_files(MAX_LOG_FILES)
+        .build(&log_dir)
+        .map_err(|e| anyhow!("Failed to open log file in {}: {}", log_dir.display(), e))?;
+    let (file_writer, guard) = tracing_appender::non_blocking(appender);
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(tracing_subscriber::fmt::layer().with_writer(std::io::stderr))
+        .with(
+            tracing_subscriber::fmt::layer()
+                .with_ansi(false)
+                .with_writer(|| RecentWriter(Vec::new())),
+        )
+        .with(
+            tracing_subscriber::fmt::layer()
+                .with_ansi(false)
+                .with_writer(file_writer),
+        )
+        .init();
+
+    Ok(guard)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_push_line_drops_oldest_at_capacity() {
+        let mut buffer = VecDeque::new();
+        for i in 0..RECENT_CAPACITY + 3 {
+            push_line(&mut buffer, i.to_string());
+        }
+        assert_eq!(buffer.len(), RECENT_CAPACITY);
+        assert_eq!(buffer.front().map(String::as_str), Some("3"));
+        assert_eq!(buffer.back(), Some(&(RECENT_CAPACITY + 2).to_string()));
+    }
+}
@@ -1,16 +1,37 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
+mod askpass;
+mod config;
 mod db;
+mod diagnose;
+mod events;
 mod gui;
+mod health;
 mod knowledge;
+mod known_hosts;
+mod logging;
+mod mcp_args;
+mod mcp_error;
+mod security;
+mod shutdown;
+mod ssh_config;
+mod ssh_worker;
+mod terminal_text;
+mod transfer;
 
-use crate::db::{Account, DbHandler, Machine};
+use crate::db::{Account, AuthType, DbHandler, Machine};
+use crate::mcp_error::McpError;
 use anyhow::{Result, anyhow};
-use clap::{Parser, Subcommand};
+use clap::{CommandFactory, Parser, Subcommand};
 use eframe::egui;
 use serde::{Deserialize, Serialize};
 use serde_json::{Value, json};
-use std::io::{self, BufRead, Write};
-use std::sync::Arc;
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 #[derive(Parser)]
 #[command(name = "mcp-ssh-manager")]
@@ -18,12 +39,66 @@ use std::sync::Arc;
 struct Cli {
     #[command(subcommand)]
     command: Option<Commands>,
+
+    /// Read defaults from this TOML file instead of config.toml in the config directory
+    #[arg(long, global = true, value_name = "PATH")]
+    config: Option<PathBuf>,
+
+    /// Log filter (e.g. "debug", "mcp_ssh_manager=trace"); overrides RUST_LOG
+    #[arg(long, global = true)]
+    log_level: Option<String>,
+
+    /// Ignore the troubleshooting knowledge file unless its signature verifies
+    #[arg(long, global = true)]
+    strict_knowledge: bool,
+
+    /// Use a separate inventory (data directory and keyring master key) under this name
+    #[arg(long, global = true)]
+    profile: Option<String>,
+
+    /// Read the master key (64 hex digits) from this file instead of the OS keyring
+    #[arg(long, global = true, value_name = "PATH")]
+    key_file: Option<PathBuf>,
+
+    /// Use this database file instead of manager.db in the data directory
+    #[arg(long, global = true, value_name = "PATH")]
+    db_path: Option<PathBuf>,
+
+    /// Report what the startup credential migration would change, without writing, and exit
+    #[arg(long)]
+    verify_migration: bool,
+
+    /// Don't create the daily automatic backup when the GUI starts
+    #[arg(long)]
+    no_auto_backup: bool,
+
+    /// Print the registered machine names, one per line, and exit (for completion scripts)
+    #[arg(long, hide = true)]
+    list_names: bool,
 }
 
+/// `add --owner` when neither the flag nor the config file sets it.
+const DEFAULT_OWNER: &str = "personal";
+/// `add --os` when neither the flag nor the config file sets it.
+const DEFAULT_OS: &str = "windows";
+
 #[derive(Subcommand)]
 enum Commands {
     /// List all registered machines
-    List,
+    List {
+        /// Output format
+        #[arg(long, value_enum, default_value_t = ListFormat::Table)]
+        format: ListFormat,
+        /// Shorthand for --format json
+        #[arg(long, conflicts_with = "format")]
+        json: bool,
+        /// Only list machines with this status (e.g. active, broken, maintenance)
+        #[arg(long)]
+        status: Option<String>,
+        /// Only list machines with this ownership (e.g. personal, company)
+        #[arg(long)]
+        owner: Option<String>,
+    },
     /// Add a new machine
     Add {
         /// Alias name for the machine
@@ -33,20 +108,188 @@ enum Commands {
         /// Purpose of the machine
         #[arg(long)]
         purpose: String,
-        /// Ownership (e.g., personal, company)
-        #[arg(long, default_value = "personal")]
-        owner: String,
-        /// OS Type (linux, windows, macos)
-        #[arg(long, default_value = "windows")]
-        os: String,
+        /// Ownership (e.g., personal, company) [default: personal]
+        #[arg(long)]
+        owner: Option<String>,
+        /// OS Type: linux, windows, macos, or other (case-insensitive; "ubuntu", "win", "darwin" etc. are normalized) [default: windows]
+        #[arg(long)]
+        os: Option<String>,
+        /// Group (folder) to list the machine under
+        #[arg(long)]
+        group: Option<String>,
+        /// Extra ssh arguments, e.g. "-J admin@bastion -o IdentitiesOnly=yes"
+        #[arg(long, allow_hyphen_values = true)]
+        ssh_options: Option<String>,
+        /// ID of the machine to connect through (ssh -J)
+        #[arg(long)]
+        jump_host: Option<i64>,
+        /// Only reachable over a VPN; connecting first checks that the host answers
+        #[arg(long)]
+        requires_vpn: bool,
+        /// Environment variables exported before run_command, as a JSON object,
+        /// e.g. '{"KUBECONFIG": "/etc/kube/config"}'
+        #[arg(long)]
+        env: Option<String>,
+        /// Also register an account with this user name
+        #[arg(long)]
+        username: Option<String>,
+        /// Account auth type: password, key, agent, or tpm (defaults to password)
+        #[arg(long, requires = "username")]
+        auth_type: Option<String>,
+        /// Read the account's credential (password or key path) as one line from stdin
+        /// instead of the MCP_SSH_CREDENTIAL environment variable
+        #[arg(long, requires = "username")]
+        credential_stdin: bool,
+        /// Note on the account, e.g. "deploy user, don't rotate its key"
+        #[arg(long, requires = "username")]
+        note: Option<String>,
     },
     /// Create a database backup immediately
     Backup {
         /// Optional path to save the backup
         path: Option<String>,
+        /// Encrypt the backup with a passphrase (ChaCha20-Poly1305)
+        #[arg(long)]
+        encrypt: bool,
+        /// Also store the passphrase (encrypted) so automatic backups are encrypted too
+        #[arg(long, requires = "encrypt")]
+        remember_passphrase: bool,
+    },
+    /// Decrypt an encrypted backup into a plain SQLite file
+    DecryptBackup {
+        /// Encrypted backup file
+        input: String,
+        /// Where to write the decrypted database
+        output: String,
+    },
+    /// Export machines, accounts (with credentials), and constraints into a
+    /// passphrase-encrypted file for moving to another computer
+    VaultExport {
+        /// Where to write the vault
+        path: String,
+    },
+    /// Add the machines of a vault written by vault-export; existing names are skipped
+    VaultImport {
+        /// Vault file to read
+        path: String,
+    },
+    /// Enable (or with --disable, remove) the optional master passphrase
+    Passphrase {
+        /// Remove the passphrase and go back to keyring-only encryption
+        #[arg(long)]
+        disable: bool,
+    },
+    /// Import hosts from an OpenSSH client config as machines with key or ssh-agent accounts
+    ImportSshConfig {
+        /// Config file to read (defaults to ~/.ssh/config)
+        path: Option<String>,
+        /// Show what would be imported without writing anything
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Diagnose the SSH connection to a machine and print (or save) a Markdown report
+    Diagnose {
+        /// Machine ID
+        machine_id: i64,
+        /// Account ID (by default every account is tried until one connects)
+        #[arg(long)]
+        account: Option<i64>,
+        /// Write the report to this file instead of stdout
+        #[arg(long)]
+        report: Option<PathBuf>,
+        /// Replace the username, host, and machine name in the report
+        #[arg(long)]
+        redact: bool,
+        /// After a successful login, also check whether passwordless sudo works
+        #[arg(long)]
+        check_sudo: bool,
+        /// When a key account is rejected, say whether the stored password for the same
+        /// user might work (the password is never sent)
+        #[arg(long)]
+        password_hint: bool,
+    },
+    /// List the machines whose key accounts use the key with this fingerprint
+    FindKey {
+        /// Fingerprint as printed by `ssh-keygen -l` (e.g. "SHA256:…")
+        fingerprint: String,
+    },
+    /// Export command logs (oldest first) as JSON or CSV, e.g. for a SIEM
+    ExportLogs {
+        /// Where to write the export ("-" for stdout)
+        path: String,
+        /// Output format
+        #[arg(long, value_enum, default_value_t = ExportFormat::Json)]
+        format: ExportFormat,
+        /// Only logs at or after this UTC time ("YYYY-MM-DD HH:MM:SS" or a date)
+        #[arg(long)]
+        since: Option<String>,
+    },
+    /// Checkpoint the WAL and VACUUM the database, reporting the size before and after
+    Maintenance,
+    /// Encrypt the credentials of machines with this ownership under a key of their own,
+    /// kept in the keyring next to the master key
+    OwnerKey {
+        /// Ownership value (e.g. company)
+        ownership: String,
+    },
+    /// Print a shell completion script to stdout
+    Completions {
+        /// Shell to generate the script for
+        #[arg(value_enum)]
+        shell: clap_complete::Shell,
     },
+    /// Internal: runs one SSH command for run_command in a killable child process
+    #[command(name = "ssh-worker", hide = true)]
+    SshWorker,
     /// Run as a headless MCP server (no GUI)
-    Mcp,
+    Mcp {
+        /// Listen on a TCP address (e.g. 127.0.0.1:8765) instead of stdio. There is no
+        /// authentication: anyone who can connect gets every tool, including run_command
+        #[arg(long)]
+        listen: Option<String>,
+        /// With --listen, allow an address other than loopback (e.g. 0.0.0.0), exposing the
+        /// server to the network
+        #[arg(long)]
+        allow_remote: bool,
+        /// Only expose tools that don't change anything (list, get, key lookup, logs, diagnose, health check)
+        #[arg(long)]
+        read_only: bool,
+        /// Largest JSON-RPC message accepted, in bytes; a larger one closes the connection
+        #[arg(long, default_value_t = DEFAULT_MAX_MESSAGE_BYTES)]
+        max_message_bytes: usize,
+        /// With --listen, close a connection that sends nothing for this many seconds (0 = never)
+        #[arg(long, default_value_t = DEFAULT_READ_TIMEOUT_SECS)]
+        read_timeout: u64,
+        /// With --listen, refuse new connections while this many are open (0 = no limit)
+        #[arg(long, default_value_t = DEFAULT_MAX_CONNECTIONS)]
+        max_connections: usize,
+        /// Always answer `initialize` with this MCP protocol version instead of negotiating
+        #[arg(long, value_parser = clap::builder::PossibleValuesParser::new(SUPPORTED_PROTOCOL_VERSIONS))]
+        protocol_version: Option<String>,
+    },
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum ListFormat {
+    Table,
+    Json,
+    Csv,
+}
+
+/// Format of `export-logs` and the `export_logs` tool.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum ExportFormat {
+    Json,
+    Csv,
+}
+
+/// One row of `list` output.
+#[derive(Serialize)]
+struct MachineListing {
+    #[serde(flatten)]
+    machine: Machine,
+    accounts: usize,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -68,22 +311,83 @@ struct JsonRpcResponse {
 #[tokio::main]
 async fn main() -> Result<()> {
     let cli = Cli::parse();
-    let db = Arc::new(DbHandler::new()?);
+    if matches!(cli.command, Some(Commands::SshWorker)) {
+        // Talks only stdin/stdout with the parent; no database, logging, or prompts
+        return ssh_worker::serve();
+    }
+    if let Some(Commands::Completions { shell }) = cli.command {
+        // Needs neither the database nor logging
+        clap_complete::generate(
+            shell,
+            &mut Cli::command(),
+            "mcp-ssh-manager",
+            &mut io::stdout(),
+        );
+        return Ok(());
+    }
+    let config = config::load(cli.config.as_deref())?;
+    db::set_setting_defaults(config.setting_defaults()?)?;
+    // Before logging, which writes into the profile's data directory
+    if let Some(profile) = cli.profile.as_ref().or(config.profile.as_ref()) {
+        db::set_profile(profile)?;
+    }
+    if let Some(path) = cli.db_path.as_ref().or(config.db_path.as_ref()) {
+        db::set_db_path(path)?;
+    }
+    if let Some(path) = cli.key_file.as_ref().or(config.key_file.as_ref()) {
+        db::set_master_key(security::read_key_file(path)?)?;
+    }
+    let _log_guard = logging::init(cli.log_level.as_deref().or(config.log_level.as_deref()))?;
+    knowledge::set_strict(cli.strict_knowledge || config.strict_knowledge == Some(true));
+    if cli.verify_migration {
+        let db = DbHandler::new_without_migration()?;
+        print_migration_plan(&db.plan_credential_migration()?);
+        return Ok(());
+    }
+    if cli.list_names {
+        let db = DbHandler::new_without_migration()?;
+        for machine in db.list_machines()? {
+            println!("{}", machine.name);
+        }
+        return Ok(());
+    }
+    let db = Arc::new(open_db(cli.command.as_ref())?);
+    // The GUI and MCP server connect through ssh-agent; a locked GUI loads it on unlock
+    if matches!(cli.command, None | Some(Commands::Mcp { .. })) && !db.is_locked() {
+        security::load_identity_key(&db);
+    }
 
     if let Some(cmd) = cli.command {
         match cmd {
-            Commands::List => {
-                let machines = db.list_machines()?;
-                println!(
-                    "{:<20} {:<20} {:<10} {:<10}",
-                    "Name", "IP Address", "Status", "OS"
-                );
-                println!("{}", "-".repeat(65));
-                for m in machines {
-                    println!(
-                        "{:<20} {:<20} {:<10} {:<10}",
-                        m.name, m.ip_address, m.status, m.os_type
-                    );
+            Commands::List {
+                format,
+                json,
+                status,
+                owner,
+            } => {
+                let accounts = db.list_accounts()?;
+                let mut machines = match &status {
+                    Some(status) => db.list_machines_by_status(status)?,
+                    None => db.list_machines()?,
+                };
+                if let Some(owner) = &owner {
+                    machines.retain(|m| &m.ownership == owner);
+                }
+                let listings: Vec<MachineListing> = machines
+                    .into_iter()
+                    .map(|machine| MachineListing {
+                        accounts: accounts
+                            .iter()
+                            .filter(|a| Some(a.machine_id) == machine.id)
+                            .count(),
+                        machine,
+                    })
+                    .collect();
+                let format = if json { ListFormat::Json } else { format };
+                match format {
+                    ListFormat::Json => println!("{}", serde_json::to_string_pretty(&listings)?),
+                    ListFormat::Csv => print_machines_csv(&listings),
+                    ListFormat::Table => print_machines_table(&listings),
                 }
                 return Ok(());
             }
@@ -93,48 +397,300 @@ async fn main() -> Result<()> {
                 purpose,
                 owner,
                 os,
+                group,
+                ssh_options,
+                jump_host,
+                requires_vpn,
+                env,
+                username,
+                auth_type,
+                credential_stdin,
+                note,
             } => {
                 let machine = Machine {
                     id: None,
+                    uuid: None,
                     name: name.clone(),
                     ip_address: ip,
                     purpose,
-                    ownership: owner,
-                    os_type: os,
+                    ownership: owner
+                        .or(config.add.owner)
+                        .unwrap_or_else(|| DEFAULT_OWNER.to_string()),
+                    os_type: os
+                        .or(config.add.os)
+                        .unwrap_or_else(|| DEFAULT_OS.to_string()),
                     status: "active".to_string(),
+                    group,
+                    ssh_options,
+                    jump_host_id: jump_host,
+                    requires_vpn,
+                    env,
+                };
+                let id = match username {
+                    Some(username) => {
+                        let account = Account {
+                            id: None,
+                            machine_id: 0,
+                            username,
+                            auth_type: auth_type.unwrap_or_else(|| AuthType::Password.to_string()),
+                            credential: cli_credential(credential_stdin)?,
+                            key_fingerprint: None,
+                            note,
+                        };
+                        db.register_machine_with_account(machine, account)?
+                    }
+                    None => db.add_machine(machine)?,
                 };
-                let id = db.add_machine(machine)?;
                 println!("Machine '{}' added successfully with ID: {}", name, id);
                 return Ok(());
             }
-            Commands::Backup { path } => {
+            Commands::Backup {
+                path,
+                encrypt,
+                remember_passphrase,
+            } => {
+                let ext = if encrypt { "db.enc" } else { "db" };
                 let backup_path = if let Some(p) = path {
                     std::path::PathBuf::from(p)
                 } else {
                     let today = chrono::Local::now().format("%Y-%m-%d_%H%M%S").to_string();
-                    let proj_dirs =
-                        directories::ProjectDirs::from("com", "veltrea", "mcp-ssh-manager")
-                            .unwrap();
-                    let backup_dir = proj_dirs.data_dir().join("backups");
+                    let backup_dir = db::backup_dir()?;
                     let _ = std::fs::create_dir_all(&backup_dir);
-                    backup_dir.join(format!("manual_backup_{}.db", today))
+                    backup_dir.join(format!("manual_backup_{}.{}", today, ext))
                 };
-                db.backup_db(&backup_path)?;
+                if encrypt {
+                    let passphrase = match db.backup_passphrase()? {
+                        Some(p) if !remember_passphrase => p,
+                        _ => security::read_secret(
+                            security::BACKUP_PASSPHRASE_ENV,
+                            "Backup passphrase: ",
+                        )?,
+                    };
+                    if remember_passphrase {
+                        db.set_backup_passphrase(&passphrase)?;
+                    }
+                    db.backup_db_encrypted(&backup_path, &passphrase)?;
+                } else {
+                    db.backup_db(&backup_path)?;
+                }
                 println!("Backup created at: {:?}", backup_path);
                 return Ok(());
             }
-            Commands::Mcp => {
-                println!("Running in headless MCP mode...");
-                run_mcp_loop(db)?;
+            Commands::DecryptBackup { input, output } => {
+                let passphrase =
+                    security::read_secret(security::BACKUP_PASSPHRASE_ENV, "Backup passphrase: ")?;
+                DbHandler::restore_encrypted_backup(
+                    std::path::Path::new(&input),
+                    std::path::Path::new(&output),
+                    &passphrase,
+                )?;
+                println!("Decrypted backup written to: {}", output);
+                return Ok(());
+            }
+            Commands::VaultExport { path } => {
+                let passphrase =
+                    security::read_secret(security::VAULT_PASSPHRASE_ENV, "Vault passphrase: ")?;
+                if std::env::var(security::VAULT_PASSPHRASE_ENV).is_err()
+                    && security::read_secret(
+                        security::VAULT_PASSPHRASE_ENV,
+                        "Confirm passphrase: ",
+                    )? != passphrase
+                {
+                    return Err(anyhow!("Passphrases do not match"));
+                }
+                let vault = db.export_vault()?;
+                let sealed =
+                    security::seal_with_passphrase(&passphrase, &serde_json::to_vec(&vault)?)?;
+                std::fs::write(&path, sealed)
+                    .map_err(|e| anyhow!("Failed to write {}: {}", path, e))?;
+                println!("Exported {} machine(s) to: {}", vault.machines.len(), path);
+                return Ok(());
+            }
+            Commands::VaultImport { path } => {
+                let sealed =
+                    std::fs::read(&path).map_err(|e| anyhow!("Failed to read {}: {}", path, e))?;
+                let passphrase =
+                    security::read_secret(security::VAULT_PASSPHRASE_ENV, "Vault passphrase: ")?;
+                let vault: db::Vault =
+                    serde_json::from_slice(&security::open_with_passphrase(&passphrase, &sealed)?)
+                        .map_err(|e| anyhow!("Vault contents are not valid: {}", e))?;
+                let (imported, skipped) = db.import_vault(&vault)?;
+                println!("Imported {} machine(s) from: {}", imported, path);
+                if !skipped.is_empty() {
+                    println!("Skipped (name already exists): {}", skipped.join(", "));
+                }
+                return Ok(());
+            }
+            Commands::Passphrase { disable } => {
+                if disable {
+                    db.disable_passphrase()?;
+                    println!(
+                        "Passphrase removed. Credentials are now protected by the keyring key only."
+                    );
+                } else {
+                    let passphrase = security::read_passphrase("New passphrase: ")?;
+                    if std::env::var(security::PASSPHRASE_ENV).is_err()
+                        && security::read_passphrase("Confirm passphrase: ")? != passphrase
+                    {
+                        return Err(anyhow!("Passphrases do not match"));
+                    }
+                    db.enable_passphrase(&passphrase)?;
+                    println!("Passphrase enabled. It will be required on every launch.");
+                }
+                return Ok(());
+            }
+            Commands::ImportSshConfig { path, dry_run } => {
+                let path = db::expand_home(path.as_deref().unwrap_or("~/.ssh/config"));
+                let content = std::fs::read_to_string(&path)
+                    .map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e))?;
+                let entries = ssh_config::parse(&content);
+                if entries.is_empty() {
+                    println!("No importable Host entries found in {}", path.display());
+                    return Ok(());
+                }
+                for (alias, outcome) in ssh_config::import(&db, &entries, dry_run) {
+                    println!("{:<20} {}", alias, outcome);
+                }
+                if dry_run {
+                    println!("Dry run: nothing was written.");
+                }
+                return Ok(());
+            }
+            Commands::Diagnose {
+                machine_id,
+                account,
+                report,
+                redact,
+                check_sudo,
+                password_hint,
+            } => {
+                let machine = db
+                    .get_machine(machine_id)?
+                    .ok_or(McpError::MachineNotFound(machine_id))?;
+                let accounts = db.list_accounts_for_machine(machine_id)?;
+                let diagnoses = if account.is_none() && accounts.len() > 1 {
+                    diagnose::run_accounts(&db, &machine, &accounts, check_sudo, password_hint)?
+                } else {
+                    let account = select_account(&db, machine_id, account)?;
+                    vec![diagnose::run(
+                        &db,
+                        machine,
+                        &account,
+                        check_sudo,
+                        password_hint,
+                    )?]
+                };
+                let markdown = diagnoses
+                    .iter()
+                    .map(|d| diagnose::render_markdown(d, redact))
+                    .collect::<Vec<_>>()
+                    .join("\n---\n\n");
+                match report {
+                    Some(path) => {
+                        std::fs::write(&path, markdown)?;
+                        println!("Report written to {}", path.display());
+                    }
+                    None => print!("{}", markdown),
+                }
+                return Ok(());
+            }
+            Commands::FindKey { fingerprint } => {
+                let matches = db.find_accounts_by_key_fingerprint(&fingerprint)?;
+                if matches.is_empty() {
+                    println!("No key accounts use {}", fingerprint);
+                    return Ok(());
+                }
+                println!(
+                    "{:<20} {:<20} {:<16} {:<10}",
+                    "Name", "IP Address", "User", "Account"
+                );
+                println!("{}", "-".repeat(70));
+                for (m, a) in matches {
+                    println!(
+                        "{:<20} {:<20} {:<16} {:<10}",
+                        m.name,
+                        m.ip_address,
+                        a.username,
+                        a.id.unwrap_or_default()
+                    );
+                }
+                return Ok(());
+            }
+            Commands::ExportLogs {
+                path,
+                format,
+                since,
+            } => {
+                let logs = db.export_logs(since.as_deref(), None)?;
+                let exported = render_logs(&logs, format)?;
+                if path == "-" {
+                    print!("{}", exported);
+                } else {
+                    std::fs::write(&path, exported)
+                        .map_err(|e| anyhow!("Failed to write {}: {}", path, e))?;
+                    println!("Exported {} log entries to: {}", logs.len(), path);
+                }
+                return Ok(());
+            }
+            Commands::Maintenance => {
+                let report = db.maintenance()?;
+                println!(
+                    "Database compacted: {} -> {} bytes",
+                    report.size_before, report.size_after
+                );
+                return Ok(());
+            }
+            Commands::OwnerKey { ownership } => {
+                let moved = db.enable_ownership_key(&ownership)?;
+                println!(
+                    "Ownership group \"{}\" now has its own key ({} accounts re-encrypted)",
+                    ownership, moved
+                );
+                return Ok(());
+            }
+            Commands::SshWorker | Commands::Completions { .. } => {
+                unreachable!("handled before the database is opened")
+            }
+            Commands::Mcp {
+                listen,
+                allow_remote,
+                read_only,
+                max_message_bytes,
+                read_timeout,
+                max_connections,
+                protocol_version,
+            } => {
+                let mut ctx = McpContext::new(db, read_only);
+                ctx.max_message_bytes = max_message_bytes;
+                ctx.protocol_version = protocol_version.and_then(|pinned| {
+                    SUPPORTED_PROTOCOL_VERSIONS
+                        .into_iter()
+                        .find(|v| *v == pinned)
+                });
+                ctx.read_timeout = (read_timeout > 0).then(|| Duration::from_secs(read_timeout));
+                if read_only {
+                    tracing::info!("MCP server is read-only");
+                }
+                if let Some(addr) = listen {
+                    check_listen_addr(&addr, allow_remote)?;
+                    run_mcp_tcp(&addr, ctx, max_connections)?;
+                } else {
+                    tracing::info!("Running in headless MCP mode (stdio)");
+                    run_mcp_loop(ctx)?;
+                }
                 return Ok(());
             }
         }
     } else {
         // Default: Launch GUI + Spawn MCP thread
-        let db_for_mcp = Arc::clone(&db);
+        let ctx = McpContext::new(Arc::clone(&db), false);
+        let mcp_shutdown = Arc::clone(&ctx.shutdown);
+        let mcp_events = Arc::clone(&ctx.events);
+        // Before the thread starts, so no change made in the meantime is missed
+        mcp_events.subscribe();
         std::thread::spawn(move || {
-            if let Err(e) = run_mcp_loop(db_for_mcp) {
-                eprintln!("MCP Loop Error: {}", e);
+            if let Err(e) = run_mcp_loop(ctx) {
+                tracing::error!("MCP loop error: {}", e);
             }
         });
 
@@ -142,13 +698,20 @@ async fn main() -> Result<()> {
             viewport: egui::ViewportBuilder::default().with_inner_size([600.0, 400.0]),
             ..Default::default()
         };
+        let auto_backup = !(cli.no_auto_backup || config.no_auto_backup == Some(true));
 
         eframe::run_native(
             "MCP-SSH Manager",
             options,
-            Box::new(|cc| {
-                let app = gui::ManagerApp::new(cc, db);
-                app.check_auto_backup();
+            Box::new(move |cc| {
+                let mut app = gui::ManagerApp::new(cc, db);
+                app.mcp_shutdown = Some(mcp_shutdown);
+                let egui_ctx = cc.egui_ctx.clone();
+                mcp_events.set_waker(move || egui_ctx.request_repaint());
+                app.mcp_events = Some(mcp_events);
+                if auto_backup {
+                    app.start_auto_backup();
+                }
                 Box::new(app)
             }),
         )
@@ -158,224 +721,2354 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
-fn run_mcp_loop(db: Arc<DbHandler>) -> Result<()> {
+fn print_machines_table(listings: &[MachineListing]) {
+    const HEADERS: [&str; 6] = ["ID", "Name", "IP Address", "Status", "OS", "Accounts"];
+    let rows: Vec<[String; 6]> = listings
+        .iter()
+        .map(|l| {
+            let m = &l.machine;
+            [
+                m.id.map(|id| id.to_string()).unwrap_or_default(),
+                m.name.clone(),
+                m.ip_address.clone(),
+                m.status.clone(),
+                m.os_type.clone(),
+                l.accounts.to_string(),
+            ]
+        })
+        .collect();
+    // Size each column to its longest value so long names don't push the others out of line
+    let mut widths = HEADERS.map(|h| h.chars().count());
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let line = |cells: &[&str]| {
+        cells
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect::<Vec<_>>()
+            .join("  ")
+    };
+    println!("{}", line(&HEADERS));
+    println!(
+        "{}",
+        "-".repeat(widths.iter().sum::<usize>() + 2 * (widths.len() - 1))
+    );
+    for row in &rows {
+        println!("{}", line(&row.each_ref().map(String::as_str)));
+    }
+}
+
+/// RFC 4180: quotes a field containing a separator, quote, or line break.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn print_machines_csv(listings: &[MachineListing]) {
+    println!("id,name,ip_address,purpose,ownership,os_type,status,group,accounts");
+    for l in listings {
+        let m = &l.machine;
+        println!(
+            "{},{},{},{},{},{},{},{},{}",
+            m.id.map(|id| id.to_string()).unwrap_or_default(),
+            csv_field(&m.name),
+            csv_field(&m.ip_address),
+            csv_field(&m.purpose),
+            csv_field(&m.ownership),
+            csv_field(&m.os_type),
+            csv_field(&m.status),
+            csv_field(m.group.as_deref().unwrap_or("")),
+            l.accounts
+        );
+    }
+}
+
+/// Logs as `export-logs` writes them: a JSON array, or CSV with a header row. Multi-line
+/// output stays in one quoted CSV field.
+fn render_logs(logs: &[db::CommandLog], format: ExportFormat) -> Result<String> {
+    match format {
+        ExportFormat::Json => Ok(serde_json::to_string_pretty(logs)? + "\n"),
+        ExportFormat::Csv => {
+            let mut out = String::from(
+                "id,timestamp,machine_id,machine_name,username,command,exit_code,stdout,stderr\r\n",
+            );
+            for log in logs {
+                let row = [
+                    log.id.to_string(),
+                    csv_field(&log.timestamp),
+                    log.machine_id.to_string(),
+                    csv_field(&log.machine_name),
+                    csv_field(&log.username),
+                    csv_field(&log.command),
+                    log.exit_code.map(|c| c.to_string()).unwrap_or_default(),
+                    csv_field(log.stdout.as_deref().unwrap_or("")),
+                    csv_field(log.stderr.as_deref().unwrap_or("")),
+                ];
+                out.push_str(&row.join(","));
+                out.push_str("\r\n");
+            }
+            Ok(out)
+        }
+    }
+}
+
+/// Tools that only read state; the only ones available with `mcp --read-only`.
+const READ_ONLY_TOOLS: [&str; 9] = [
+    "list_machines",
+    "summary",
+    "get_machine",
+    "find_machines_by_key",
+    "get_public_key",
+    "get_logs",
+    "export_logs",
+    "diagnose_connection",
+    "health_check",
+];
+
+/// Default `mcp --max-message-bytes`.
+const DEFAULT_MAX_MESSAGE_BYTES: usize = 1024 * 1024;
+/// Default `mcp --read-timeout`.
+const DEFAULT_READ_TIMEOUT_SECS: u64 = 300;
+/// Default `mcp --max-connections`.
+const DEFAULT_MAX_CONNECTIONS: usize = 16;
+/// Pause after a failed accept, doubled on each further failure up to the maximum.
+const ACCEPT_BACKOFF_MIN: Duration = Duration::from_millis(50);
+const ACCEPT_BACKOFF_MAX: Duration = Duration::from_secs(2);
+
+/// MCP protocol versions this server speaks, newest first. They differ only in features
+/// the server doesn't use, so requests are handled the same under each.
+const SUPPORTED_PROTOCOL_VERSIONS: [&str; 3] = ["2025-06-18", "2025-03-26", "2024-11-05"];
+
+/// URI scheme of machine resources: `ssh-manager://machine/<uuid>`. Numeric ids from
+/// before machines had uuids are still accepted.
+const MACHINE_URI_PREFIX: &str = "ssh-manager://machine/";
+
+/// Diagnose results included in `get_machine`, newest first.
+const RECENT_DIAGNOSTICS: usize = 5;
+
+/// Per-server state shared by every MCP session.
+#[derive(Clone)]
+struct McpContext {
+    db: Arc<DbHandler>,
+    /// Hide and reject every tool outside [`READ_ONLY_TOOLS`]
+    read_only: bool,
+    /// Last SSH spawn per machine, shared across sessions so parallel clients can't bypass it
+    last_spawn: Arc<Mutex<HashMap<i64, Instant>>>,
+    /// Set by the GUI on exit; sessions stop taking requests once it is requested
+    shutdown: Arc<shutdown::Shutdown>,
+    /// Changes made by tools, drained by the GUI when it runs alongside
+    events: Arc<events::Events>,
+    /// Largest message (including a partial one still being read) before the session is closed
+    max_message_bytes: usize,
+    /// Idle limit for TCP connections
+    read_timeout: Option<Duration>,
+    /// `mcp --protocol-version`: answer `initialize` with this instead of negotiating
+    protocol_version: Option<&'static str>,
+}
+
+impl McpContext {
+    fn new(db: Arc<DbHandler>, read_only: bool) -> Self {
+        McpContext {
+            db,
+            read_only,
+            last_spawn: Arc::default(),
+            shutdown: Arc::default(),
+            events: Arc::default(),
+            max_message_bytes: DEFAULT_MAX_MESSAGE_BYTES,
+            read_timeout: Some(Duration::from_secs(DEFAULT_READ_TIMEOUT_SECS)),
+            protocol_version: None,
+        }
+    }
+
+    fn allows_tool(&self, name: &str) -> bool {
+        !self.read_only || READ_ONLY_TOOLS.contains(&name)
+    }
+
+    /// Rejects an SSH spawn against `machine_id` if the previous one was too recent, so a
+    /// looping agent can't hammer a server into fail2ban. An interval of 0 disables it.
+    fn check_rate_limit(&self, machine_id: i64) -> Result<()> {
+        let interval = self
+            .db
+            .get_setting(db::SETTING_SSH_RATE_LIMIT_SECS)?
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(db::DEFAULT_SSH_RATE_LIMIT_SECS);
+        if interval == 0 {
+            return Ok(());
+        }
+        let interval = Duration::from_secs(interval);
+
+        let mut last_spawn = self.last_spawn.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        if let Some(elapsed) = last_spawn.get(&machine_id).map(|t| now.duration_since(*t))
+            && elapsed < interval
+        {
+            let retry_after = (interval - elapsed).as_secs_f64().ceil() as u64;
+            return Err(McpError::RateLimited {
+                machine_id,
+                retry_after_secs: retry_after.max(1),
+            }
+            .into());
+        }
+        last_spawn.insert(machine_id, now);
+        Ok(())
+    }
+}
+
+fn run_mcp_loop(ctx: McpContext) -> Result<()> {
     let stdin = io::stdin();
-    let mut reader = stdin.lock();
-    let mut line = String::new();
+    let stdout = io::stdout();
+    run_mcp_session(stdin.lock(), stdout.lock(), &ctx)
+}
 
-    while reader.read_line(&mut line)? > 0 {
-        let req: JsonRpcRequest = match serde_json::from_str(&line) {
-            Ok(r) => r,
-            Err(_) => {
-                line.clear();
+/// Refuses a `--listen` address that resolves to anything but loopback unless
+/// `--allow-remote` is given, since the server has no authentication.
+fn check_listen_addr(addr: &str, allow_remote: bool) -> Result<()> {
+    let addrs: Vec<_> = addr
+        .to_socket_addrs()
+        .map_err(|e| anyhow!("Invalid MCP listen address {}: {}", addr, e))?
+        .collect();
+    if addrs.iter().all(|a| a.ip().is_loopback()) {
+        return Ok(());
+    }
+    if !allow_remote {
+        return Err(anyhow!(
+            "Refusing to listen on {}: it is reachable from other hosts and the MCP server has \
+             no authentication. Use a loopback address such as 127.0.0.1, or pass \
+             --allow-remote",
+            addr
+        ));
+    }
+    tracing::warn!(
+        "MCP server listening on non-loopback address {} without authentication; any host \
+         that can connect can run commands on every registered machine",
+        addr
+    );
+    Ok(())
+}
+
+fn run_mcp_tcp(addr: &str, ctx: McpContext, max_connections: usize) -> Result<()> {
+    let listener = TcpListener::bind(addr)
+        .map_err(|e| anyhow!("Failed to bind MCP listener on {}: {}", addr, e))?;
+    tracing::info!("MCP server listening on {}", listener.local_addr()?);
+
+    let active = Arc::new(AtomicUsize::new(0));
+    let mut accept_backoff = ACCEPT_BACKOFF_MIN;
+    for stream in listener.incoming() {
+        let mut stream = match stream {
+            Ok(s) => s,
+            Err(e) => {
+                // Usually out of file descriptors; retrying at once would spin
+                tracing::warn!("MCP accept error: {}; retrying in {:?}", e, accept_backoff);
+                std::thread::sleep(accept_backoff);
+                accept_backoff = (accept_backoff * 2).min(ACCEPT_BACKOFF_MAX);
                 continue;
             }
         };
+        accept_backoff = ACCEPT_BACKOFF_MIN;
+        let peer = stream
+            .peer_addr()
+            .map(|a| a.to_string())
+            .unwrap_or_else(|_| "unknown".to_string());
 
-        // We use a simple blocking handle in this thread
-        let res = handle_request_sync(req, &db);
-        let res_json = serde_json::to_string(&res)?;
-        println!("{}", res_json);
-        io::stdout().flush()?;
+        if max_connections > 0 && active.load(Ordering::SeqCst) >= max_connections {
+            tracing::warn!(
+                "MCP connection from {} refused: {} connections already open",
+                peer,
+                max_connections
+            );
+            let res = error_response(
+                None,
+                -32000,
+                format!(
+                    "Server busy: at most {} connections are allowed",
+                    max_connections
+                ),
+            );
+            if let Ok(res) = serde_json::to_value(res) {
+                let _ = write_message(&mut stream, &res);
+            }
+            continue;
+        }
 
-        line.clear();
+        let open = active.fetch_add(1, Ordering::SeqCst) + 1;
+        tracing::info!("MCP connection from {} opened ({} active)", peer, open);
+        let slot = ConnectionSlot {
+            active: Arc::clone(&active),
+            peer,
+        };
+        let ctx = ctx.clone();
+        std::thread::spawn(move || {
+            let _span = tracing::info_span!("mcp_connection", peer = %slot.peer).entered();
+            serve_mcp_connection(stream, &slot.peer, &ctx);
+        });
     }
     Ok(())
 }
 
-fn handle_request_sync(req: JsonRpcRequest, db: &DbHandler) -> JsonRpcResponse {
-    let id = req.id.clone();
-    let result = match req.method.as_str() {
-        "initialize" => Ok(json!({
-            "protocolVersion": "2024-11-05",
-            "capabilities": { "tools": {} },
-            "serverInfo": { "name": "mcp-ssh-manager", "version": "0.2.0" }
-        })),
-        "notifications/initialized" => Ok(Value::Null),
-        "tools/list" => Ok(json!({
-            "tools": [
-                {
-                    "name": "register_machine",
-                    "description": "Register a new machine",
-                    "inputSchema": { "type": "object", "properties": { "name": { "type": "string" }, "ip_address": { "type": "string" }, "purpose": { "type": "string" }, "ownership": { "type": "string" }, "os_type": { "type": "string" }, "username": { "type": "string" }, "auth_type": { "type": "string" }, "credential": { "type": "string" } }, "required": ["name", "ip_address", "purpose", "ownership", "os_type", "username", "auth_type", "credential"] }
-                },
-                {
-                    "name": "list_machines",
-                    "description": "List all registered machines",
-                    "inputSchema": { "type": "object", "properties": {} }
-                },
-                {
-                    "name": "diagnose_connection",
-                    "description": "Diagnose SSH connection issues and provide agentic hints",
-                    "inputSchema": { "type": "object", "properties": { "machine_id": { "type": "integer" } }, "required": ["machine_id"] }
-                },
-                {
-                    "name": "rotate_keys",
-                    "description": "Rotate the master encryption key and re-encrypt all stored credentials",
-                    "inputSchema": { "type": "object", "properties": {} }
-                }
-            ]
-        })),
-        "tools/call" => {
-            if let Some(params) = req.params {
-                let name = params.get("name").and_then(|v| v.as_str());
-                let arguments = params.get("arguments");
-                match (name, arguments) {
-                    (Some("register_machine"), Some(args)) => {
-                        handle_register_machine_sync(args, db)
-                    }
-                    (Some("list_machines"), _) => handle_list_machines_sync(db),
-                    (Some("diagnose_connection"), Some(args)) => {
-                        handle_diagnose_connection(args, db)
-                    }
-                    (Some("rotate_keys"), _) => handle_rotate_keys(db),
-                    _ => Err(anyhow!("Unknown tool")),
-                }
-            } else {
-                Err(anyhow!("Missing params"))
-            }
-        }
-        _ => Err(anyhow!("Method not found")),
-    };
+/// Counts a TCP connection as open until dropped, which also covers a session that panicked.
+struct ConnectionSlot {
+    active: Arc<AtomicUsize>,
+    peer: String,
+}
 
-    match result {
-        Ok(res) => JsonRpcResponse {
-            jsonrpc: "2.0".to_string(),
-            result: Some(res),
-            error: None,
-            id,
-        },
-        Err(e) => JsonRpcResponse {
-            jsonrpc: "2.0".to_string(),
-            result: None,
-            error: Some(json!({ "code": -32603, "message": e.to_string() })),
-            id,
-        },
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        let open = self.active.fetch_sub(1, Ordering::SeqCst) - 1;
+        tracing::info!("MCP connection from {} closed ({} active)", self.peer, open);
     }
 }
 
-fn handle_register_machine_sync(args: &Value, db: &DbHandler) -> Result<Value> {
-    let get_str = |key: &str| -> Result<String> {
-        args.get(key)
-            .and_then(|v| v.as_str())
-            .map(|s| s.to_string())
-            .ok_or_else(|| anyhow!("Missing or invalid argument: {}", key))
+/// Runs one TCP session until the client disconnects, goes idle, or breaks the protocol.
+/// Errors end only this connection; the listener keeps accepting.
+fn serve_mcp_connection(stream: TcpStream, peer: &str, ctx: &McpContext) {
+    if let Err(e) = stream.set_read_timeout(ctx.read_timeout) {
+        tracing::warn!("MCP connection {} setup failed: {}", peer, e);
+        return;
+    }
+    let reader = match stream.try_clone() {
+        Ok(s) => BufReader::new(s),
+        Err(e) => {
+            tracing::warn!("MCP connection {} setup failed: {}", peer, e);
+            return;
+        }
     };
+    match run_mcp_session(reader, &stream, ctx) {
+        Ok(()) => {}
+        Err(e)
+            if e.downcast_ref::<io::Error>().is_some_and(|e| {
+                matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                )
+            }) =>
+        {
+            tracing::info!("MCP connection {} closed after being idle", peer);
+        }
+        Err(e)
+            if e.downcast_ref::<io::Error>().is_some_and(|e| {
+                matches!(
+                    e.kind(),
+                    io::ErrorKind::BrokenPipe
+                        | io::ErrorKind::ConnectionReset
+                        | io::ErrorKind::ConnectionAborted
+                        | io::ErrorKind::UnexpectedEof
+                )
+            }) =>
+        {
+            tracing::info!("MCP connection {} dropped by the client: {}", peer, e);
+        }
+        Err(e) => tracing::warn!("MCP connection {} closed with error: {}", peer, e),
+    }
+    // Tell the client right away, even when it stopped reading
+    let _ = stream.shutdown(std::net::Shutdown::Both);
+}
 
-    let machine = Machine {
-        id: None,
-        name: get_str("name")?,
-        ip_address: get_str("ip_address")?,
-        purpose: args
-            .get("purpose")
-            .and_then(|v| v.as_str())
-            .unwrap_or("")
-            .to_string(),
-        ownership: get_str("ownership")?,
-        os_type: get_str("os_type")?,
-        status: "active".to_string(),
+/// Opens the database, offering to restore the newest automatic backup when it is corrupt.
+/// The GUI reports the failure in a window and the CLI asks on the terminal; MCP mode never
+/// prompts because stdin carries the protocol.
+fn open_db(command: Option<&Commands>) -> Result<DbHandler> {
+    // The GUI asks for a master passphrase on its lock screen instead of the terminal
+    let open = || match command {
+        None => DbHandler::new_without_prompt(),
+        Some(_) => DbHandler::new(),
     };
-
-    let machine_id = db
-        .add_machine(machine)
-        .map_err(|e| anyhow!("Failed to add machine: {}", e))?;
-
-    let account = Account {
-        id: None,
-        machine_id,
-        username: get_str("username")?,
-        auth_type: get_str("auth_type")?,
-        credential: get_str("credential")?,
+    let err = match open() {
+        Ok(db) => return Ok(db),
+        Err(e) => e,
     };
+    tracing::error!("Failed to open database: {:#}", err);
 
-    db.add_account(account)
-        .map_err(|e| anyhow!("Failed to add account: {}", e))?;
-
-    Ok(
-        json!({ "content": [{ "type": "text", "text": format!("Machine registered with ID {}", machine_id) }] }),
-    )
+    let backup = if db::is_corruption(&err) {
+        db::latest_backup()
+    } else {
+        None
+    };
+    let mut message = format!("{:#}", err);
+    loop {
+        // Some(passphrase) restores; the CLI asks for an encrypted backup's passphrase itself
+        let restore = match (command, &backup) {
+            (None, _) => {
+                gui::show_startup_error(&message, backup.clone())?.map(|restore| restore.passphrase)
+            }
+            (Some(Commands::Mcp { .. }), _) | (_, None) => None,
+            (Some(_), Some(path)) => confirm(&format!(
+                "The database appears to be corrupt. Restore from {}?",
+                path.display()
+            ))?
+            .then_some(None),
+        };
+        let (Some(path), Some(passphrase)) = (&backup, restore) else {
+            return Err(err);
+        };
+        match DbHandler::restore_from_backup(path, passphrase.as_deref()) {
+            Ok(quarantined) => {
+                tracing::warn!(
+                    "Restored database from {}; the damaged file was kept at {}",
+                    path.display(),
+                    quarantined.display()
+                );
+                return open();
+            }
+            // The window comes back with the reason, e.g. a mistyped passphrase
+            Err(e) if command.is_none() => message = format!("{:#}", e),
+            Err(e) => return Err(e),
+        }
+    }
 }
 
-fn handle_list_machines_sync(db: &DbHandler) -> Result<Value> {
-    let machines = db.list_machines()?;
-    Ok(json!({ "content": [{ "type": "text", "text": serde_json::to_string_pretty(&machines)? }] }))
+fn print_migration_plan(plan: &[db::MigrationEntry]) {
+    println!("Account  User                 Auth       Action");
+    println!("{}", "-".repeat(70));
+    for entry in plan {
+        let action = match entry.action {
+            db::CredentialMigration::Current => "ok (already encrypted)",
+            db::CredentialMigration::AddPrefix => "would tag (encrypted, from before the prefix)",
+            db::CredentialMigration::Encrypt => "would encrypt (looks like plaintext)",
+            db::CredentialMigration::Undecryptable => {
+                "left untouched (looks encrypted, but not with this master key)"
+            }
+        };
+        println!(
+            "{:<8} {:<20} {:<10} {}",
+            entry.account_id, entry.username, entry.auth_type, action
+        );
+    }
+    let count = |action| plan.iter().filter(|e| e.action == action).count();
+    println!(
+        "\n{} to encrypt, {} to tag, {} undecryptable, {} unchanged",
+        count(db::CredentialMigration::Encrypt),
+        count(db::CredentialMigration::AddPrefix),
+        count(db::CredentialMigration::Undecryptable),
+        count(db::CredentialMigration::Current)
+    );
 }
 
-fn handle_diagnose_connection(args: &Value, db: &DbHandler) -> Result<Value> {
-    let machine_id = args
-        .get("machine_id")
-        .and_then(|v| v.as_i64())
-        .ok_or_else(|| anyhow!("machine_id is required"))?;
-
-    // 1. Fetch machine and account info
-    let machines = db.list_machines()?;
-    let machine = machines
-        .into_iter()
-        .find(|m| m.id == Some(machine_id))
-        .ok_or_else(|| anyhow!("Machine not found"))?;
+/// Environment variable `add` reads an account's credential from, keeping it out of the
+/// command line and shell history.
+const CREDENTIAL_ENV: &str = "MCP_SSH_CREDENTIAL";
 
-    let accounts = db.list_accounts()?;
-    let account = accounts
-        .into_iter()
-        .find(|a| a.machine_id == machine_id)
-        .ok_or_else(|| anyhow!("No account found for machine"))?;
-
-    let target = format!("{}@{}", account.username, machine.ip_address);
-    println!("Diagnosing connection to {}...", target);
-
-    // 2. Run SSH command (capturing stderr)
-    // Use BatchMode=yes to avoid interactivity, ConnectTimeout=5 to avoid hanging
-    let output = std::process::Command::new("ssh")
-        .args(&[
-            "-v",
-            "-o",
-            "BatchMode=yes",
-            "-o",
-            "ConnectTimeout=5",
-            &target,
-            "echo",
-            "connection_success",
-        ])
-        .output()
-        .map_err(|e| anyhow!("Failed to execute ssh command: {}", e))?;
-
-    if output.status.success() {
-        return Ok(json!({
-            "content": [{ "type": "text", "text": "Connection successful. No issues detected." }]
-        }));
-    }
-
-    // 3. Analyze failure
-    let stderr = String::from_utf8_lossy(&output.stderr);
-    let patterns = knowledge::load_troubleshooting_patterns();
-
-    let mut response_text = format!("SSH Connection Failed.\n\nSTDERR:\n{}\n\n", stderr);
-    let mut hint_data = serde_json::Map::new();
-
-    if let Some(suggestion) = knowledge::match_error_pattern(&stderr, &patterns) {
-        response_text.push_str(&format!("--- AGENT HINT ---\n{}\n", suggestion.message));
-        if let Some(cmd) = &suggestion.command_hint {
-            response_text.push_str(&format!("Suggested Command: `{}`\n", cmd));
-        }
-        if let Some(script) = &suggestion.script_path {
-            response_text.push_str(&format!("Suggested Script: `{}`\n", script));
-        }
-
-        // Structure for agent
-        hint_data.insert("agent_hint".to_string(), json!(suggestion));
-    } else {
-        response_text.push_str("No specific troubleshooting hint found.");
+/// Credential for `add --username`: one line from stdin with `--credential-stdin`, else
+/// [`CREDENTIAL_ENV`], else empty (enough for agent and tpm accounts).
+fn cli_credential(from_stdin: bool) -> Result<String> {
+    if !from_stdin {
+        return Ok(std::env::var(CREDENTIAL_ENV).unwrap_or_default());
     }
+    let mut line = String::new();
+    io::stdin().read_line(&mut line)?;
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
 
-    Ok(json!({
-        "content": [{ "type": "text", "text": response_text }],
-        "data": hint_data
-    }))
+/// Asks a yes/no question on the terminal (stderr), defaulting to no.
+fn confirm(question: &str) -> Result<bool> {
+    eprint!("{} [y/N] ", question);
+    io::stderr().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
 }
 
-fn handle_rotate_keys(db: &DbHandler) -> Result<Value> {
-    db.rotate_keys()?;
-    Ok(json!({
-        "content": [{ "type": "text", "text": "Master key rotated and all credentials re-encrypted successfully." }]
-    }))
+/// Serves line-delimited JSON-RPC over any reader/writer pair (stdio or a TCP stream).
+/// Input that grows past `ctx.max_message_bytes` without completing a message is answered
+/// with `-32600` and ends the session, since the rest of the stream can't be resynchronized.
+fn run_mcp_session<R: BufRead, W: Write>(
+    mut reader: R,
+    mut writer: W,
+    ctx: &McpContext,
+) -> Result<()> {
+    let mut line = Vec::new();
+    // Accumulates input until it forms complete JSON values, so pretty-printed
+    // requests spanning several lines are handled as well as one-per-line input.
+    let mut buffer = String::new();
+
+    loop {
+        // One byte past the limit is enough to tell that it was exceeded
+        let budget = ctx.max_message_bytes.saturating_sub(buffer.len()) as u64 + 1;
+        // Raw bytes, so a cut at the limit can't split a character into a decode error
+        if (&mut reader).take(budget).read_until(b'\n', &mut line)? == 0 {
+            break;
+        }
+        if buffer.len() + line.len() > ctx.max_message_bytes {
+            tracing::warn!(
+                "Closing MCP session: message exceeds {} bytes",
+                ctx.max_message_bytes
+            );
+            let res = error_response(
+                None,
+                -32600,
+                format!(
+                    "Invalid Request: message exceeds {} bytes",
+                    ctx.max_message_bytes
+                ),
+            );
+            write_message(&mut writer, &serde_json::to_value(res)?)?;
+            return Ok(());
+        }
+        match std::str::from_utf8(&line) {
+            Ok(text) => buffer.push_str(text),
+            // Answered like malformed JSON; any value the line was part of is dropped with it
+            Err(e) => {
+                let res = error_response(None, -32700, format!("Parse error: {}", e));
+                write_message(&mut writer, &serde_json::to_value(res)?)?;
+                buffer.clear();
+            }
+        }
+        line.clear();
+
+        let consumed = {
+            let mut stream = serde_json::Deserializer::from_str(&buffer).into_iter::<Value>();
+            let mut consumed = 0;
+            loop {
+                match stream.next() {
+                    Some(Ok(message)) => {
+                        consumed = stream.byte_offset();
+                        let Some(_in_flight) = ctx.shutdown.begin() else {
+                            tracing::info!("MCP session stopped for shutdown");
+                            return Ok(());
+                        };
+                        // We use a simple blocking handle in this thread
+                        // Progress notifications are written as they happen, ahead of the response
+                        let res = dispatch_message(message, ctx, &mut |notification| {
+                            if let Err(e) = write_message(&mut writer, &notification) {
+                                tracing::warn!("Failed to send notification: {}", e);
+                            }
+                        });
+                        // Notifications (and batches of only notifications) get no reply
+                        if let Some(res) = res {
+                            write_message(&mut writer, &res)?;
+                        }
+                    }
+                    // Incomplete value: wait for more lines
+                    Some(Err(e)) if e.is_eof() => break,
+                    Some(Err(e)) => {
+                        let res = error_response(None, -32700, format!("Parse error: {}", e));
+                        write_message(&mut writer, &serde_json::to_value(res)?)?;
+                        consumed = buffer.len();
+                        break;
+                    }
+                    None => {
+                        consumed = buffer.len();
+                        break;
+                    }
+                }
+            }
+            consumed
+        };
+        buffer.drain(..consumed);
+    }
+
+    if !buffer.trim().is_empty() {
+        let res = error_response(None, -32700, "Parse error: unexpected end of input");
+        write_message(&mut writer, &serde_json::to_value(res)?)?;
+    }
+    Ok(())
+}
+
+fn write_message<W: Write>(writer: &mut W, message: &Value) -> Result<()> {
+    writeln!(writer, "{}", serde_json::to_string(message)?)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Sends a server-to-client notification (e.g. `notifications/progress`) while a request runs.
+type Notify<'a> = &'a mut dyn FnMut(Value);
+
+/// Dispatches a single request object or a JSON-RPC batch array and returns the reply, or
+/// `None` when nothing may be sent back because it held only notifications.
+fn dispatch_message(message: Value, ctx: &McpContext, notify: Notify) -> Option<Value> {
+    match message {
+        Value::Array(items) if items.is_empty() => Some(json!(error_response(
+            None,
+            -32600,
+            "Invalid Request: empty batch"
+        ))),
+        Value::Array(items) => {
+            let responses: Vec<JsonRpcResponse> = items
+                .into_iter()
+                .filter_map(|item| dispatch_single(item, ctx, notify))
+                .collect();
+            (!responses.is_empty()).then(|| json!(responses))
+        }
+        other => dispatch_single(other, ctx, notify).map(|res| json!(res)),
+    }
+}
+
+/// Handles one request. A notification (a request without an `id` member) is still
+/// handled for its side effects, but gets no response, not even an error.
+fn dispatch_single(message: Value, ctx: &McpContext, notify: Notify) -> Option<JsonRpcResponse> {
+    let is_notification = message.is_object() && message.get("id").is_none();
+    let id = message.get("id").cloned();
+    let res = match serde_json::from_value::<JsonRpcRequest>(message) {
+        Ok(req) => handle_request_sync(req, ctx, notify),
+        Err(e) => error_response(id, -32600, format!("Invalid Request: {}", e)),
+    };
+    if is_notification {
+        if let Some(error) = &res.error {
+            tracing::debug!("Notification failed (no response is sent): {}", error);
+        }
+        return None;
+    }
+    Some(res)
+}
+
+/// The protocol version to answer `initialize` with: the client's if supported, else the
+/// newest supported one older than it. A client older than every supported version gets
+/// the newest, as the spec asks, and decides itself whether to disconnect. Something that
+/// isn't a `YYYY-MM-DD` version at all is rejected.
+fn negotiate_protocol_version(
+    params: Option<&Value>,
+    pinned: Option<&'static str>,
+) -> Result<&'static str> {
+    let newest = SUPPORTED_PROTOCOL_VERSIONS[0];
+    let requested = match params.and_then(|p| p.get("protocolVersion")) {
+        None => {
+            tracing::warn!("initialize without protocolVersion; assuming {}", newest);
+            return Ok(pinned.unwrap_or(newest));
+        }
+        Some(Value::String(v)) if chrono::NaiveDate::parse_from_str(v, "%Y-%m-%d").is_ok() => {
+            v.as_str()
+        }
+        Some(other) => {
+            return Err(McpError::InvalidParams(format!(
+                "protocolVersion must be a YYYY-MM-DD version, not {}",
+                other
+            ))
+            .into());
+        }
+    };
+    if let Some(pinned) = pinned {
+        if pinned != requested {
+            tracing::warn!(
+                "Client requested MCP protocol version {}; answering with the pinned {}",
+                requested,
+                pinned
+            );
+        }
+        return Ok(pinned);
+    }
+    // Same-format dates compare correctly as strings
+    match SUPPORTED_PROTOCOL_VERSIONS
+        .into_iter()
+        .find(|v| *v <= requested)
+    {
+        Some(version) => {
+            if version != requested {
+                tracing::info!(
+                    "Client requested MCP protocol version {}; negotiated down to {}",
+                    requested,
+                    version
+                );
+            }
+            Ok(version)
+        }
+        None => {
+            tracing::warn!(
+                "Client requested MCP protocol version {}, older than any supported ({}); offering {}",
+                requested,
+                SUPPORTED_PROTOCOL_VERSIONS.join(", "),
+                newest
+            );
+            Ok(newest)
+        }
+    }
+}
+
+/// Result of a `tools/call`: `text` for people, and the same information as `data` so an
+/// agent doesn't have to parse the text.
+fn tool_response(text: impl Into<String>, data: Value) -> Value {
+    json!({ "content": [{ "type": "text", "text": text.into() }], "data": data })
+}
+
+/// [`tool_response`] of a tool that ran but failed, flagged with `isError`.
+fn tool_error(text: impl Into<String>, data: Value) -> Value {
+    let mut response = tool_response(text, data);
+    response["isError"] = json!(true);
+    response
+}
+
+fn error_response(id: Option<Value>, code: i64, message: impl Into<String>) -> JsonRpcResponse {
+    JsonRpcResponse {
+        jsonrpc: "2.0".to_string(),
+        result: None,
+        error: Some(json!({ "code": code, "message": message.into() })),
+        id,
+    }
+}
+
+fn handle_request_sync(req: JsonRpcRequest, ctx: &McpContext, notify: Notify) -> JsonRpcResponse {
+    let id = req.id.clone();
+    let db = &*ctx.db;
+    let _span = tracing::debug_span!("mcp_request", method = %req.method).entered();
+    let result = match req.method.as_str() {
+        "initialize" => {
+            negotiate_protocol_version(req.params.as_ref(), ctx.protocol_version).map(|version| {
+                json!({
+                    "protocolVersion": version,
+                    "capabilities": { "tools": {}, "resources": {} },
+                    "serverInfo": { "name": "mcp-ssh-manager", "version": "0.2.0" }
+                })
+            })
+        }
+        "notifications/initialized" => Ok(Value::Null),
+        // e.g. notifications/cancelled or notifications/roots/list_changed: nothing to do,
+        // and a notification can't be answered with "Method not found"
+        method if method.starts_with("notifications/") => {
+            tracing::debug!("Ignoring notification {}", method);
+            Ok(Value::Null)
+        }
+        "ping" => Ok(json!({})),
+        "tools/list" => Ok(filter_tools(
+            ctx,
+            json!({
+                "tools": [
+                    {
+                        "name": "register_machine",
+                        "description": "Register a new machine",
+                        "inputSchema": { "type": "object", "properties": { "name": { "type": "string" }, "ip_address": { "type": "string" }, "purpose": { "type": "string" }, "ownership": { "type": "string" }, "os_type": { "type": "string", "description": "One of linux, windows, macos, other (case-insensitive; variants such as \"ubuntu\" or \"darwin\" are normalized)" }, "group": { "type": "string", "description": "Optional folder to list the machine under" }, "ssh_options": { "type": "string", "description": "Optional extra ssh arguments, shell-quoted, e.g. \"-J admin@bastion -o IdentitiesOnly=yes\". Allowed: -o, -J, -p, -i, -4, -6, -C; options that run local commands or load libraries (ProxyCommand, LocalCommand, PKCS11Provider, ...) are rejected" }, "jump_host_id": { "type": "integer", "description": "Optional ID of a registered machine to connect through (ssh -J); its first account is used for the hop" }, "requires_vpn": { "type": "boolean", "description": "The machine is only reachable over a VPN; diagnose first checks that it answers and points at the VPN if not" }, "env": { "type": "object", "additionalProperties": { "type": "string" }, "description": "Optional environment variables exported before every run_command, e.g. {\"KUBECONFIG\": \"/etc/kube/config\"}; names must match [A-Za-z_][A-Za-z0-9_]*" }, "username": { "type": "string" }, "auth_type": { "type": "string", "enum": ["password", "key", "agent", "tpm"], "description": "\"agent\" uses ssh-agent and takes an empty credential" }, "credential": { "type": "string" }, "note": { "type": "string", "description": "Optional note about the account, e.g. \"deploy user, don't rotate its key\"; shown unredacted" } }, "required": ["name", "ip_address", "purpose", "ownership", "os_type", "username", "auth_type", "credential"] }
+                    },
+                    {
+                        "name": "register_machines",
+                        "description": "Register several machines in one transaction. Each entry takes the same fields as register_machine; failing entries are reported per index without aborting the rest",
+                        "inputSchema": { "type": "object", "properties": { "machines": { "type": "array", "items": { "type": "object" } } }, "required": ["machines"] }
+                    },
+                    {
+                        "name": "clone_machine",
+                        "description": "Register a copy of a machine under a new name and host: purpose, ownership, OS type, group, SSH options, jump host, VPN flag, environment variables, and constraints are copied; accounts and credentials are not",
+                        "inputSchema": { "type": "object", "properties": { "machine_id": { "type": "integer", "description": "Machine to copy" }, "name": { "type": "string", "description": "Name of the copy; must not be taken" }, "ip_address": { "type": "string", "description": "Host of the copy" } }, "required": ["machine_id", "name", "ip_address"] }
+                    },
+                    {
+                        "name": "list_machines",
+                        "description": "List registered machines, optionally only matching ones and only some fields",
+                        "inputSchema": { "type": "object", "properties": { "fields": { "type": "array", "items": { "type": "string", "enum": MACHINE_FIELDS }, "description": "Only return these fields of each machine (default: all)" }, "filter": { "type": "object", "properties": { "status": { "type": "string" }, "ownership": { "type": "string" }, "group": { "type": "string" } }, "additionalProperties": false, "description": "Only machines whose fields equal all of these values" } } }
+                    },
+                    {
+                        "name": "summary",
+                        "description": "Machine counts by status, total accounts and command logs, and the time of the last backup",
+                        "inputSchema": { "type": "object", "properties": {} }
+                    },
+                    {
+                        "name": "get_machine",
+                        "description": "Get one machine with its accounts (credentials redacted), constraints, connection stats, and its latest diagnose_connection results (newest first)",
+                        "inputSchema": { "type": "object", "properties": { "machine_id": { "type": "integer" } }, "required": ["machine_id"] }
+                    },
+                    {
+                        "name": "find_machines_by_key",
+                        "description": "List the machines and key accounts that use the SSH key with the given fingerprint, e.g. to find hosts still trusting a key being rotated out",
+                        "inputSchema": { "type": "object", "properties": { "fingerprint": { "type": "string", "description": "SHA256 fingerprint as printed by ssh-keygen -l, e.g. \"SHA256:…\"" } }, "required": ["fingerprint"] }
+                    },
+                    {
+                        "name": "export_logs",
+                        "description": "Command logs oldest first, with full stdout/stderr, rendered as JSON or CSV (e.g. for a SIEM); at most 1000 entries, with data.truncated set when there were more",
+                        "inputSchema": { "type": "object", "properties": { "format": { "type": "string", "enum": ["json", "csv"], "description": "Default json" }, "since": { "type": "string", "description": "UTC timestamp \"YYYY-MM-DD HH:MM:SS\" (or a date); only logs at or after it" } } }
+                    },
+                    {
+                        "name": "get_logs",
+                        "description": "Recent command logs, newest first, with stdout/stderr truncated",
+                        "inputSchema": { "type": "object", "properties": { "machine_id": { "type": "integer" }, "limit": { "type": "integer", "description": "Default 50, at most 200" }, "since": { "type": "string", "description": "UTC timestamp \"YYYY-MM-DD HH:MM:SS\" (or a date); only logs at or after it" } } }
+                    },
+                    {
+                        "name": "diagnose_connection",
+                        "description": "Diagnose SSH connection issues and provide agentic hints",
+                        "inputSchema": { "type": "object", "properties": { "machine_id": { "type": "integer" }, "account_id": { "type": "integer", "description": "Account to probe. When omitted and the machine has several accounts, each is tried in turn and the first that connects is reported" }, "format": { "type": "string", "enum": ["text", "markdown"], "description": "\"markdown\" returns a shareable report with machine info, full ssh -v output, and hints" }, "redact": { "type": "boolean", "description": "With format \"markdown\", replace the username, host, and machine name" }, "check_sudo": { "type": "boolean", "description": "After a successful login, run `sudo -n true` and report whether passwordless sudo works, with a hint if not" }, "password_hint": { "type": "boolean", "description": "When a key account is rejected and a password account exists for the same user, add a hint on whether password auth might work. The password is never sent" } }, "required": ["machine_id"] }
+                    },
+                    {
+                        "name": "confirm_host_key",
+                        "description": "Trust a machine's SSH host key after verifying its fingerprint (as reported by diagnose_connection)",
+                        "inputSchema": { "type": "object", "properties": { "machine_id": { "type": "integer" }, "fingerprint": { "type": "string" } }, "required": ["machine_id", "fingerprint"] }
+                    },
+                    {
+                        "name": "sweep_connectivity",
+                        "description": "Probe every machine not under maintenance and mark it active or broken. Machines probed too recently (see the SSH rate limit) are skipped and listed in rate_limited",
+                        "inputSchema": { "type": "object", "properties": { "dry_run": { "type": "boolean", "description": "Only list the machines that would be probed and the longest it could take; nothing is probed or changed" } } }
+                    },
+                    {
+                        "name": "run_command",
+                        "description": "Run a shell command on a registered machine using its stored account. Fails for machines reached through a jump host; of the ssh options only the port is used",
+                        "inputSchema": { "type": "object", "properties": { "machine_id": { "type": "integer" }, "command": { "type": "string" }, "account_id": { "type": "integer", "description": "Required when the machine has more than one account" }, "timeout_secs": { "type": "integer", "description": "Kill the command after this many seconds (default 30, at most 3600); a timeout is reported with timed_out: true" }, "key_passphrase": { "type": "string", "description": "Passphrase of the account's key file when it is protected; used for this call only and never stored" } }, "required": ["machine_id", "command"] }
+                    },
+                    {
+                        "name": "upload_file",
+                        "description": "Copy a local file to a registered machine with scp, using its key or ssh-agent account. Blocked by the machine's \"deny-write PREFIX\" constraints",
+                        "inputSchema": { "type": "object", "properties": { "machine_id": { "type": "integer" }, "local_path": { "type": "string", "description": "Must be inside the mcp.transfer_dir setting when set, and never in the data directory or ~/.ssh" }, "remote_path": { "type": "string" }, "account_id": { "type": "integer", "description": "Required when the machine has more than one account" }, "timeout_secs": { "type": "integer", "description": "Kill the transfer after this many seconds (default 30, at most 3600)" } }, "required": ["machine_id", "local_path", "remote_path"] }
+                    },
+                    {
+                        "name": "download_file",
+                        "description": "Copy a file from a registered machine to a local path with scp, using its key or ssh-agent account. Blocked by the machine's \"deny-read PREFIX\" constraints",
+                        "inputSchema": { "type": "object", "properties": { "machine_id": { "type": "integer" }, "remote_path": { "type": "string" }, "local_path": { "type": "string", "description": "Must be inside the mcp.transfer_dir setting when set, and never in the data directory or ~/.ssh" }, "account_id": { "type": "integer", "description": "Required when the machine has more than one account" }, "timeout_secs": { "type": "integer", "description": "Kill the transfer after this many seconds (default 30, at most 3600)" } }, "required": ["machine_id", "remote_path", "local_path"] }
+                    },
+                    {
+                        "name": "update_credential",
+                        "description": "Replace an account's stored password or key path. The credential is encrypted at rest and never returned",
+                        "inputSchema": { "type": "object", "properties": { "account_id": { "type": "integer" }, "credential": { "type": "string" } }, "required": ["account_id", "credential"] }
+                    },
+                    {
+                        "name": "health_check",
+                        "description": "Check that the server is alive and the database, master key, and knowledge base are ready",
+                        "inputSchema": { "type": "object", "properties": {} }
+                    },
+                    {
+                        "name": "rotate_keys",
+                        "description": "Rotate the master encryption key and re-encrypt the credentials stored under it. Ownership groups with their own key keep it; pass ownership to rotate one of those instead",
+                        "inputSchema": { "type": "object", "properties": { "ownership": { "type": "string", "description": "Rotate only the key of this ownership group (one separated with `owner-key`)" }, "dry_run": { "type": "boolean", "description": "Only decrypt each credential and list the account ids that would be re-encrypted; nothing is changed" } } }
+                    },
+                    {
+                        "name": "generate_identity_key",
+                        "description": "Generate a new device identity keypair (TPM-bound when available, otherwise an ed25519 key sealed in the database and loaded into ssh-agent) and return its public key for authorized_keys. The private key is never returned",
+                        "inputSchema": { "type": "object", "properties": {} }
+                    },
+                    {
+                        "name": "get_public_key",
+                        "description": "Public key of the current device identity key, as generated by generate_identity_key or the onboarding wizard",
+                        "inputSchema": { "type": "object", "properties": {} }
+                    }
+                ]
+            }),
+        )),
+        "resources/list" => handle_list_resources(db),
+        "resources/read" => match req.params.as_ref().and_then(|p| p.get("uri")) {
+            Some(uri) => handle_read_resource(uri, db),
+            None => Err(McpError::InvalidParams("uri is required".into()).into()),
+        },
+        "tools/call" => {
+            if let Some(params) = req.params {
+                let name = params.get("name").and_then(|v| v.as_str());
+
+                let arguments = params.get("arguments");
+                // Without a progressToken the client gets no notifications, only the response
+                let progress_token = params.pointer("/_meta/progressToken").cloned();
+                let mut progress = |done: usize, total: usize| {
+                    if let Some(token) = &progress_token {
+                        notify(json!({
+                            "jsonrpc": "2.0",
+                            "method": "notifications/progress",
+                            "params": { "progressToken": token, "progress": done, "total": total }
+                        }));
+                    }
+                };
+                // Each handler takes its arguments as a typed struct from `mcp_args`
+                let result = match name {
+                    Some(name) if !ctx.allows_tool(name) => {
+                        Err(McpError::PermissionDenied(name.to_string()).into())
+                    }
+                    Some("register_machine") => mcp_args::parse(arguments)
+                        .and_then(|args| handle_register_machine_sync(args, db)),
+                    Some("register_machines") => mcp_args::parse(arguments)
+                        .and_then(|args| handle_register_machines(args, db)),
+                    Some("clone_machine") => {
+                        mcp_args::parse(arguments).and_then(|args| handle_clone_machine(args, db))
+                    }
+                    Some("list_machines") => mcp_args::parse(arguments)
+                        .and_then(|args| handle_list_machines_sync(args, db)),
+                    Some("summary") => handle_summary(db),
+                    Some("get_machine") => {
+                        mcp_args::parse(arguments).and_then(|args| handle_get_machine(args, db))
+                    }
+                    Some("get_logs") => {
+                        mcp_args::parse(arguments).and_then(|args| handle_get_logs(args, db))
+                    }
+                    Some("export_logs") => {
+                        mcp_args::parse(arguments).and_then(|args| handle_export_logs(args, db))
+                    }
+                    Some("find_machines_by_key") => mcp_args::parse(arguments)
+                        .and_then(|args| handle_find_machines_by_key(args, db)),
+                    Some("diagnose_connection") => {
+                        mcp_args::parse(arguments).and_then(|args: mcp_args::DiagnoseArgs| {
+                            ctx.check_rate_limit(args.machine_id)?;
+                            handle_diagnose_connection(args, db)
+                        })
+                    }
+                    Some("confirm_host_key") => mcp_args::parse(arguments)
+                        .and_then(|args| handle_confirm_host_key(args, db)),
+                    Some("sweep_connectivity") => mcp_args::parse(arguments)
+                        .and_then(|args| handle_sweep_connectivity(args, ctx, &mut progress)),
+                    Some("run_command") => {
+                        mcp_args::parse(arguments).and_then(|args: mcp_args::RunCommandArgs| {
+                            ctx.check_rate_limit(args.machine_id)?;
+                            handle_run_command(args, db)
+                        })
+                    }
+                    Some(tool @ ("upload_file" | "download_file")) => mcp_args::parse(arguments)
+                        .and_then(|args: mcp_args::TransferArgs| {
+                            ctx.check_rate_limit(args.machine_id)?;
+                            let direction = if tool == "upload_file" {
+                                transfer::Direction::Upload
+                            } else {
+                                transfer::Direction::Download
+                            };
+                            handle_transfer(args, db, direction)
+                        }),
+                    Some("update_credential") => mcp_args::parse(arguments)
+                        .and_then(|args| handle_update_credential(args, db)),
+                    Some("rotate_keys") => mcp_args::parse(arguments)
+                        .and_then(|args| handle_rotate_keys(args, db, &mut progress)),
+                    Some("generate_identity_key") => handle_generate_identity_key(db),
+                    Some("get_public_key") => handle_get_public_key(db),
+                    Some("health_check") => handle_health_check(db),
+                    Some(other) => Err(McpError::UnknownTool(other.to_string()).into()),
+                    None => Err(McpError::InvalidParams("name is required".into()).into()),
+                };
+                if let (Ok(response), Some(name)) = (&result, name)
+                    && let Some(event) = tool_event(name, response)
+                {
+                    ctx.events.emit(event);
+                }
+                result
+            } else {
+                Err(McpError::InvalidParams("params are required".into()).into())
+            }
+        }
+        other => Err(McpError::MethodNotFound(other.to_string()).into()),
+    };
+
+    match result {
+        Ok(res) => JsonRpcResponse {
+            jsonrpc: "2.0".to_string(),
+            result: Some(res),
+            error: None,
+            id,
+        },
+        Err(e) => {
+            tracing::debug!("Request failed: {}", e);
+            error_response(id, McpError::code_for(&e), e.to_string())
+        }
+    }
+}
+
+/// What a successful call of tool `name` changed, for the GUI; read from the response's
+/// `data`. `None` for tools that only read, for dry runs, and for error results.
+fn tool_event(name: &str, response: &Value) -> Option<events::Event> {
+    if response["isError"].as_bool() == Some(true) || response["data"]["dry_run"] == json!(true) {
+        return None;
+    }
+    let data = &response["data"];
+    let event = match name {
+        "register_machine" | "clone_machine" => events::Event::MachineAdded {
+            name: data["name"].as_str().unwrap_or_default().to_string(),
+        },
+        "register_machines" => events::Event::MachinesAdded {
+            count: data["registered"].as_array().map_or(0, Vec::len),
+        },
+        "update_credential" => events::Event::CredentialUpdated {
+            account_id: data["account_id"].as_i64()?,
+        },
+        "rotate_keys" => events::Event::KeysRotated,
+        "confirm_host_key" => events::Event::HostKeyTrusted {
+            host: data["host"].as_str().unwrap_or_default().to_string(),
+        },
+        "generate_identity_key" => events::Event::IdentityKeyGenerated,
+        // Each of these writes logs, statuses, or diagnose history
+        "diagnose_connection"
+        | "sweep_connectivity"
+        | "run_command"
+        | "upload_file"
+        | "download_file" => events::Event::DataChanged,
+        _ => return None,
+    };
+    Some(event)
+}
+
+/// Drops tools the context doesn't allow from a `tools/list` result.
+fn filter_tools(ctx: &McpContext, mut list: Value) -> Value {
+    if let Some(tools) = list.get_mut("tools").and_then(|t| t.as_array_mut()) {
+        tools.retain(|tool| {
+            tool.get("name")
+                .and_then(|n| n.as_str())
+                .is_some_and(|n| ctx.allows_tool(n))
+        });
+    }
+    list
+}
+
+/// Validates one `register_machine` argument object into the machine and its first account.
+fn parse_registration(args: mcp_args::RegisterMachineArgs) -> Result<(Machine, Account)> {
+    let account = Account {
+        id: None,
+        machine_id: 0,
+        username: args.username,
+        auth_type: args.auth_type,
+        credential: args.credential,
+        key_fingerprint: None,
+        note: db::normalize_note(args.note.as_deref()),
+    };
+    // Validate before inserting the machine so a bad credential doesn't leave an orphan row
+    db::validate_account(&account).map_err(|e| McpError::InvalidParams(e.to_string()))?;
+
+    let machine = Machine {
+        id: None,
+        uuid: None,
+        name: args.name,
+        ip_address: db::normalize_host(&args.ip_address)
+            .map_err(|e| McpError::InvalidParams(e.to_string()))?,
+        purpose: args.purpose,
+        ownership: args.ownership,
+        os_type: args
+            .os_type
+            .parse::<db::OsType>()
+            .map_err(|e| McpError::InvalidParams(e.to_string()))?
+            .to_string(),
+        status: "active".to_string(),
+        group: args.group,
+        ssh_options: db::normalize_ssh_options(args.ssh_options.as_deref())
+            .map_err(|e| McpError::InvalidParams(e.to_string()))?,
+        jump_host_id: args.jump_host_id,
+        requires_vpn: args.requires_vpn,
+        env: parse_env_arg(args.env.as_ref())?,
+    };
+    Ok((machine, account))
+}
+
+/// Turns the typed errors of a failed registration into their [`McpError`]: a taken name,
+/// or a jump host that doesn't exist or would make the chain invalid. Others pass through.
+fn registration_error(e: anyhow::Error) -> anyhow::Error {
+    match e.downcast::<db::DuplicateMachineName>() {
+        Ok(dup) => McpError::DuplicateMachine(dup.0).into(),
+        Err(e) => match e.downcast::<db::InvalidJumpHost>() {
+            Ok(jump) => McpError::InvalidParams(jump.0).into(),
+            Err(e) => e,
+        },
+    }
+}
+
+/// `env` given as a JSON object (or a string holding one), validated with [`db::normalize_env`].
+fn parse_env_arg(env: Option<&Value>) -> Result<Option<String>> {
+    let text = match env {
+        None | Some(Value::Null) => return Ok(None),
+        Some(Value::String(s)) => s.clone(),
+        Some(other) => other.to_string(),
+    };
+    db::normalize_env(Some(&text)).map_err(|e| McpError::InvalidParams(e.to_string()).into())
+}
+
+fn handle_register_machine_sync(
+    args: mcp_args::RegisterMachineArgs,
+    db: &DbHandler,
+) -> Result<Value> {
+    let (machine, account_input) = parse_registration(args)?;
+
+    if db.machine_exists(&machine.name)? {
+        return Err(McpError::DuplicateMachine(machine.name).into());
+    }
+
+    let name = machine.name.clone();
+    let machine_id = db
+        .register_machine_with_account(machine, account_input)
+        .map_err(|e| match registration_error(e) {
+            e if e.is::<McpError>() => e,
+            e => anyhow!("Failed to register machine: {}", e),
+        })?;
+
+    let uuid = db.get_machine(machine_id)?.and_then(|m| m.uuid);
+    Ok(tool_response(
+        format!("Machine registered with ID {}", machine_id),
+        json!({ "machine_id": machine_id, "uuid": uuid, "name": name }),
+    ))
+}
+
+/// Registers every entry of `machines` in one transaction. Entries that fail validation or
+/// insertion are reported per index with their error code; the others are still registered.
+fn handle_register_machines(args: mcp_args::RegisterMachinesArgs, db: &DbHandler) -> Result<Value> {
+    let items = args.machines;
+
+    let mut errors = Vec::new();
+    let mut valid = Vec::new();
+    for (index, item) in items.iter().enumerate() {
+        match mcp_args::parse(Some(item)).and_then(parse_registration) {
+            Ok(entry) => valid.push((index, entry)),
+            Err(e) => errors.push((index, e)),
+        }
+    }
+
+    let (indices, entries): (Vec<usize>, Vec<(Machine, Account)>) = valid.into_iter().unzip();
+    let names: Vec<String> = entries.iter().map(|(m, _)| m.name.clone()).collect();
+    let outcomes = db.add_machines_with_accounts(entries)?;
+
+    let mut registered = Vec::new();
+    for ((index, name), outcome) in indices.into_iter().zip(names).zip(outcomes) {
+        match outcome {
+            Ok(machine_id) => {
+                registered.push(json!({ "index": index, "name": name, "machine_id": machine_id }))
+            }
+            Err(e) => {
+                // Surface the same typed error register_machine would return
+                errors.push((index, registration_error(e)));
+            }
+        }
+    }
+    errors.sort_by_key(|(index, _)| *index);
+    let errors: Vec<Value> = errors
+        .into_iter()
+        .map(|(index, e)| {
+            json!({ "index": index, "code": McpError::code_for(&e), "message": e.to_string() })
+        })
+        .collect();
+
+    let text = format!(
+        "Registered {} of {} machines ({} failed)",
+        registered.len(),
+        items.len(),
+        errors.len()
+    );
+    Ok(tool_response(
+        text,
+        json!({ "registered": registered, "errors": errors }),
+    ))
+}
+
+/// Copies a machine's settings and constraints to a new one; accounts are set up separately.
+fn handle_clone_machine(args: mcp_args::CloneMachineArgs, db: &DbHandler) -> Result<Value> {
+    let source = db
+        .get_machine(args.machine_id)?
+        .ok_or(McpError::MachineNotFound(args.machine_id))?;
+    if args.name.trim().is_empty() {
+        return Err(McpError::InvalidParams("name must not be empty".into()).into());
+    }
+    db::normalize_host(&args.ip_address).map_err(|e| McpError::InvalidParams(e.to_string()))?;
+
+    let machine_id = db
+        .clone_machine(args.machine_id, &args.name, &args.ip_address)
+        .map_err(|e| match e.downcast::<db::DuplicateMachineName>() {
+            Ok(dup) => McpError::DuplicateMachine(dup.0).into(),
+            Err(e) => anyhow!("Failed to clone machine: {}", e),
+        })?;
+
+    let clone = db
+        .get_machine(machine_id)?
+        .ok_or(McpError::MachineNotFound(machine_id))?;
+    Ok(tool_response(
+        format!(
+            "Machine {} ({}) copied to {} with ID {}. It has no accounts yet.",
+            args.machine_id, source.name, clone.name, machine_id
+        ),
+        json!({
+            "machine_id": machine_id,
+            "uuid": clone.uuid,
+            "name": clone.name,
+            "source_machine_id": args.machine_id,
+        }),
+    ))
+}
+
+/// Fields of a machine as serialized, accepted by `list_machines`' `fields`.
+const MACHINE_FIELDS: [&str; 13] = [
+    "id",
+    "uuid",
+    "name",
+    "ip_address",
+    "purpose",
+    "ownership",
+    "os_type",
+    "status",
+    "group",
+    "ssh_options",
+    "jump_host_id",
+    "requires_vpn",
+    "env",
+];
+
+fn handle_list_machines_sync(args: mcp_args::ListMachinesArgs, db: &DbHandler) -> Result<Value> {
+    let fields = args.fields;
+    if let Some(unknown) = fields
+        .iter()
+        .flatten()
+        .find(|f| !MACHINE_FIELDS.contains(&f.as_str()))
+    {
+        return Err(McpError::InvalidParams(format!(
+            "Unknown field \"{}\"; expected one of {}",
+            unknown,
+            MACHINE_FIELDS.join(", ")
+        ))
+        .into());
+    }
+
+    let filter = args.filter.unwrap_or_default();
+    let machines = db.list_machines_filtered(
+        filter.status.as_deref(),
+        filter.ownership.as_deref(),
+        filter.group.as_deref(),
+    )?;
+    let mut listed = serde_json::to_value(&machines)?;
+    if let (Some(fields), Some(items)) = (&fields, listed.as_array_mut()) {
+        for item in items.iter_mut().filter_map(Value::as_object_mut) {
+            item.retain(|key, _| fields.contains(key));
+        }
+    }
+    Ok(tool_response(
+        serde_json::to_string_pretty(&listed)?,
+        json!({ "machines": listed }),
+    ))
+}
+
+fn handle_find_machines_by_key(
+    args: mcp_args::FindMachinesByKeyArgs,
+    db: &DbHandler,
+) -> Result<Value> {
+    let fingerprint = args.fingerprint.as_str();
+
+    let matches: Vec<Value> = db
+        .find_accounts_by_key_fingerprint(fingerprint)?
+        .into_iter()
+        .map(|(machine, account)| json!({ "machine": machine, "account": account }))
+        .collect();
+    let text = format!("{} account(s) use the key {}", matches.len(), fingerprint);
+    Ok(tool_response(text, json!({ "matches": matches })))
+}
+
+/// Most entries `export_logs` returns inline; the CLI's `export-logs` has no limit.
+const EXPORT_LOGS_MAX_ENTRIES: usize = 1000;
+
+fn handle_export_logs(args: mcp_args::ExportLogsArgs, db: &DbHandler) -> Result<Value> {
+    let format = args.format.unwrap_or(ExportFormat::Json);
+    let since = args.since.as_deref();
+
+    // One extra row tells whether the export was cut off
+    let mut logs = db.export_logs(since, Some(EXPORT_LOGS_MAX_ENTRIES + 1))?;
+    let truncated = logs.len() > EXPORT_LOGS_MAX_ENTRIES;
+    logs.truncate(EXPORT_LOGS_MAX_ENTRIES);
+    let exported = render_logs(&logs, format)?;
+    Ok(tool_response(
+        exported,
+        json!({
+            "format": if format == ExportFormat::Csv { "csv" } else { "json" },
+            "count": logs.len(),
+            "truncated": truncated,
+            "logs": logs,
+        }),
+    ))
+}
+
+/// Default and maximum number of entries `get_logs` returns.
+const GET_LOGS_DEFAULT_LIMIT: usize = 50;
+const GET_LOGS_MAX_LIMIT: usize = 200;
+/// stdout/stderr longer than this many characters are cut in `get_logs` results.
+const LOG_OUTPUT_MAX_CHARS: usize = 2000;
+
+/// Cuts `text` to at most `max` characters, noting how much was dropped.
+fn truncate_output(text: &str, max: usize) -> String {
+    match text.char_indices().nth(max) {
+        Some((cut, _)) => format!(
+            "{}\n… ({} more characters)",
+            &text[..cut],
+            text[cut..].chars().count()
+        ),
+        None => text.to_string(),
+    }
+}
+
+fn handle_get_logs(args: mcp_args::GetLogsArgs, db: &DbHandler) -> Result<Value> {
+    let machine_id = args.machine_id;
+    let limit = match args.limit {
+        Some(0) => {
+            return Err(McpError::InvalidParams("limit must be a positive integer".into()).into());
+        }
+        Some(n) => n as usize,
+        None => GET_LOGS_DEFAULT_LIMIT,
+    }
+    .min(GET_LOGS_MAX_LIMIT);
+    let since = args.since.as_deref();
+
+    let logs: Vec<Value> = db
+        .list_logs_paged(machine_id, since, limit, 0)?
+        .into_iter()
+        .map(|log| {
+            json!({
+                "id": log.id,
+                "machine_id": log.machine_id,
+                "machine_name": log.machine_name,
+                "username": log.username,
+                "command": log.command,
+                "exit_code": log.exit_code,
+                "stdout": log.stdout.map(|s| truncate_output(&s, LOG_OUTPUT_MAX_CHARS)),
+                "stderr": log.stderr.map(|s| truncate_output(&s, LOG_OUTPUT_MAX_CHARS)),
+                "timestamp": log.timestamp,
+            })
+        })
+        .collect();
+
+    Ok(tool_response(
+        format!("{} log entries", logs.len()),
+        json!({ "logs": logs }),
+    ))
+}
+
+fn handle_get_machine(args: mcp_args::GetMachineArgs, db: &DbHandler) -> Result<Value> {
+    let detail = machine_detail(db, args.machine_id)?;
+    Ok(tool_response(
+        serde_json::to_string_pretty(&detail)?,
+        detail,
+    ))
+}
+
+/// Machine with its accounts (credentials redacted), constraints, connection stats, and
+/// latest diagnose results, as returned by `get_machine` and machine resources.
+fn machine_detail(db: &DbHandler, machine_id: i64) -> Result<Value> {
+    let machine = db
+        .get_machine(machine_id)?
+        .ok_or(McpError::MachineNotFound(machine_id))?;
+    // Account listings already redact credentials
+    let accounts = db.list_accounts_for_machine(machine_id)?;
+    let constraints = db.get_constraints(machine_id)?;
+    let stats = db.connection_stats(machine_id)?;
+    let mut diagnostics = db.list_diagnostics(machine_id)?;
+    diagnostics.truncate(RECENT_DIAGNOSTICS);
+
+    Ok(json!({
+        "machine": machine,
+        "accounts": accounts,
+        "constraints": constraints,
+        "connection_stats": stats,
+        "recent_diagnostics": diagnostics,
+    }))
+}
+
+/// Every machine as a resource whose contents match `get_machine`.
+fn handle_list_resources(db: &DbHandler) -> Result<Value> {
+    let resources: Vec<Value> = db
+        .list_machines()?
+        .into_iter()
+        .filter_map(|m| {
+            let uuid = m.uuid.as_ref()?;
+            Some(json!({
+                "uri": format!("{}{}", MACHINE_URI_PREFIX, uuid),
+                "name": m.name,
+                "description": format!("{} ({}, {})", m.purpose, m.ip_address, m.status),
+                "mimeType": "application/json",
+            }))
+        })
+        .collect();
+    Ok(json!({ "resources": resources }))
+}
+
+fn handle_read_resource(uri: &Value, db: &DbHandler) -> Result<Value> {
+    let uri = uri
+        .as_str()
+        .ok_or_else(|| McpError::InvalidParams("uri must be a string".into()))?;
+    let key = uri
+        .strip_prefix(MACHINE_URI_PREFIX)
+        .filter(|key| !key.is_empty())
+        .ok_or_else(|| {
+            McpError::InvalidParams(format!(
+                "unknown resource URI \"{}\" (expected {}<uuid>)",
+                uri, MACHINE_URI_PREFIX
+            ))
+        })?;
+    let machine_id = match key.parse::<i64>() {
+        Ok(id) => id,
+        Err(_) => db
+            .get_machine_by_uuid(key)?
+            .and_then(|m| m.id)
+            .ok_or_else(|| McpError::InvalidParams(format!("no machine with uuid \"{}\"", key)))?,
+    };
+    let detail = machine_detail(db, machine_id)?;
+    Ok(json!({
+        "contents": [{
+            "uri": uri,
+            "mimeType": "application/json",
+            "text": serde_json::to_string_pretty(&detail)?,
+        }]
+    }))
+}
+
+/// Picks the account named by `account_id`, or the machine's only account.
+/// With several accounts and no `account_id`, the error lists them so the caller can choose.
+fn select_account(db: &DbHandler, machine_id: i64, account_id: Option<i64>) -> Result<Account> {
+    let accounts = db.list_accounts_for_machine(machine_id)?;
+    if let Some(account_id) = account_id {
+        return accounts
+            .into_iter()
+            .find(|a| a.id == Some(account_id))
+            .ok_or_else(|| {
+                McpError::AccountNotFound(format!(
+                    "Account {} not found on machine {}",
+                    account_id, machine_id
+                ))
+                .into()
+            });
+    }
+    match accounts.len() {
+        0 => Err(
+            McpError::AccountNotFound(format!("No account found for machine {}", machine_id))
+                .into(),
+        ),
+        1 => Ok(accounts.into_iter().next().unwrap()),
+        _ => {
+            let choices: Vec<String> = accounts
+                .iter()
+                .map(|a| format!("{} ({})", a.id.unwrap_or_default(), a.username))
+                .collect();
+            Err(McpError::InvalidParams(format!(
+                "machine {} has multiple accounts: {}. Pass account_id to choose one.",
+                machine_id,
+                choices.join(", ")
+            ))
+            .into())
+        }
+    }
+}
+
+fn handle_diagnose_connection(args: mcp_args::DiagnoseArgs, db: &DbHandler) -> Result<Value> {
+    let mcp_args::DiagnoseArgs {
+        machine_id,
+        account_id,
+        format,
+        redact,
+        check_sudo,
+        password_hint,
+    } = args;
+    let markdown = format == mcp_args::ReportFormat::Markdown;
+
+    // 1. Fetch machine and account info
+    let machine = db
+        .get_machine(machine_id)?
+        .ok_or(McpError::MachineNotFound(machine_id))?;
+
+    let accounts = db.list_accounts_for_machine(machine_id)?;
+    if account_id.is_none() && accounts.len() > 1 {
+        // No account chosen: find out whether any of them can reach the machine
+        let diagnoses = diagnose::run_accounts(db, &machine, &accounts, check_sudo, password_hint)?;
+        if markdown {
+            let reports: Vec<String> = diagnoses
+                .iter()
+                .map(|d| diagnose::render_markdown(d, redact))
+                .collect();
+            // Same data as without `markdown`; only the text differs
+            let mut response = multi_account_response(machine_id, &diagnoses, accounts.len());
+            return Ok(tool_response(
+                reports.join("\n---\n\n"),
+                response["data"].take(),
+            ));
+        }
+        return Ok(multi_account_response(
+            machine_id,
+            &diagnoses,
+            accounts.len(),
+        ));
+    }
+    let account = select_account(db, machine_id, account_id)?;
+
+    // 2. Run SSH command (capturing stderr) and analyze the outcome
+    let diagnosis = diagnose::run(db, machine, &account, check_sudo, password_hint)?;
+
+    let mut response = diagnosis_response(machine_id, &diagnosis);
+    if markdown {
+        return Ok(tool_response(
+            diagnose::render_markdown(&diagnosis, redact),
+            response["data"].take(),
+        ));
+    }
+    Ok(response)
+}
+
+/// Text and structured result for a single-account diagnosis.
+fn diagnosis_response(machine_id: i64, diagnosis: &diagnose::Diagnosis) -> Value {
+    let host = &diagnosis.machine.ip_address;
+    let stderr = &diagnosis.stderr;
+    match &diagnosis.outcome {
+        diagnose::Outcome::Success => match &diagnosis.sudo {
+            Some(sudo) => {
+                let (section, data) = sudo_section(sudo);
+                tool_response(
+                    format!("Connection successful.\n{}", section),
+                    json!({ "connected": true, "sudo": data }),
+                )
+            }
+            None => tool_response(
+                "Connection successful. No issues detected.",
+                json!({ "connected": true, "sudo": null }),
+            ),
+        },
+        // Unknown host key: hand the fingerprint back so the user/agent can confirm it
+        diagnose::Outcome::HostKeyUnknown { fingerprints } => {
+            let text = format!(
+                "Host key for {} is not trusted yet.\nFingerprints:\n{}\n\nAfter verifying the fingerprint with the server owner, call `confirm_host_key` with machine_id {} and the fingerprint, then run diagnose again.",
+                host,
+                fingerprints.join("\n"),
+                machine_id
+            );
+            tool_response(
+                text,
+                json!({
+                    "host_key_unknown": true,
+                    "host": host,
+                    "fingerprints": fingerprints,
+                }),
+            )
+        }
+        diagnose::Outcome::Failed { suggestion } => {
+            let mut response_text = format!("SSH Connection Failed.\n\nSTDERR:\n{}\n\n", stderr);
+            let mut hint_data = serde_json::Map::new();
+            hint_data.insert("connected".to_string(), json!(false));
+            hint_data.insert("stderr".to_string(), json!(stderr));
+
+            if let Some(suggestion) = suggestion {
+                response_text.push_str(&format!("--- AGENT HINT ---\n{}\n", suggestion.message));
+                if let Some(cmd) = &suggestion.command_hint {
+                    response_text.push_str(&format!("Suggested Command: `{}`\n", cmd));
+                }
+                if let Some(script) = &suggestion.script_path {
+                    response_text.push_str(&format!("Suggested Script: `{}`\n", script));
+                }
+
+                // Structure for agent
+                hint_data.insert("agent_hint".to_string(), json!(suggestion));
+            } else {
+                response_text.push_str("No specific troubleshooting hint found.\n");
+            }
+            if let Some(fallback) = &diagnosis.password_fallback {
+                response_text.push_str(&format!(
+                    "--- PASSWORD FALLBACK ---\n{}\n",
+                    fallback.message
+                ));
+                hint_data.insert("password_fallback".to_string(), json!(fallback));
+            }
+
+            tool_response(response_text, Value::Object(hint_data))
+        }
+    }
+}
+
+/// Combined result when every account of a machine was tried: which one connects, or each
+/// account's stderr and hint when none does.
+fn multi_account_response(
+    machine_id: i64,
+    diagnoses: &[diagnose::Diagnosis],
+    total_accounts: usize,
+) -> Value {
+    // Every account would see the same untrusted host key; report it as for one account
+    if let Some(d) = diagnoses
+        .iter()
+        .find(|d| matches!(d.outcome, diagnose::Outcome::HostKeyUnknown { .. }))
+    {
+        return diagnosis_response(machine_id, d);
+    }
+
+    let label = |d: &diagnose::Diagnosis| {
+        format!(
+            "account {} ({})",
+            d.account_id.unwrap_or_default(),
+            d.username
+        )
+    };
+    let working = diagnoses
+        .iter()
+        .find(|d| matches!(d.outcome, diagnose::Outcome::Success));
+    let results: Vec<Value> = diagnoses
+        .iter()
+        .map(|d| {
+            let suggestion = match &d.outcome {
+                diagnose::Outcome::Failed { suggestion } => suggestion.as_ref(),
+                _ => None,
+            };
+            json!({
+                "account_id": d.account_id,
+                "username": d.username,
+                "connected": matches!(d.outcome, diagnose::Outcome::Success),
+                "agent_hint": suggestion,
+                "password_fallback": d.password_fallback,
+            })
+        })
+        .collect();
+
+    let mut text = match working {
+        Some(d) => format!("Connection successful with {}.\n", label(d)),
+        None => format!(
+            "SSH connection failed for all {} probed account(s).\n",
+            diagnoses.len()
+        ),
+    };
+    for d in diagnoses {
+        let diagnose::Outcome::Failed { suggestion } = &d.outcome else {
+            continue;
+        };
+        text.push_str(&format!("\n--- {} failed ---\n", label(d)));
+        if working.is_none() {
+            text.push_str(&format!("STDERR:\n{}\n", d.stderr));
+        }
+        match suggestion {
+            Some(s) => {
+                text.push_str(&format!("Hint: {}\n", s.message));
+                if let Some(cmd) = &s.command_hint {
+                    text.push_str(&format!("Suggested Command: `{}`\n", cmd));
+                }
+            }
+            None => text.push_str("No specific troubleshooting hint found.\n"),
+        }
+        if let Some(fallback) = &d.password_fallback {
+            text.push_str(&format!("Password fallback: {}\n", fallback.message));
+        }
+    }
+    if let Some(sudo) = working.and_then(|d| d.sudo.as_ref()) {
+        text.push_str(&sudo_section(sudo).0);
+    }
+    if diagnoses.len() < total_accounts && working.is_none() {
+        text.push_str(&format!(
+            "\nOnly the first {} of {} accounts were probed.\n",
+            diagnoses.len(),
+            total_accounts
+        ));
+    }
+
+    tool_response(
+        text,
+        json!({
+            "working_account_id": working.and_then(|d| d.account_id),
+            "accounts": results,
+            "sudo": working.and_then(|d| d.sudo.as_ref()).map(|s| sudo_section(s).1),
+        }),
+    )
+}
+
+/// Text section and structured `sudo` result of a diagnose sudo check.
+fn sudo_section(sudo: &diagnose::SudoCheck) -> (String, Value) {
+    let mut text = String::from("\n--- Sudo check (sudo -n true) ---\n");
+    if sudo.passwordless {
+        text.push_str("Passwordless sudo works.\n");
+    } else {
+        text.push_str(&format!(
+            "Passwordless sudo failed.\nOUTPUT:\n{}\n",
+            sudo.output.trim_end()
+        ));
+        match &sudo.suggestion {
+            Some(s) => {
+                text.push_str(&format!("Hint: {}\n", s.message));
+                if let Some(cmd) = &s.command_hint {
+                    text.push_str(&format!("Suggested Command: `{}`\n", cmd));
+                }
+            }
+            None => text.push_str("No specific sudo hint found.\n"),
+        }
+    }
+    let data = json!({
+        "passwordless": sudo.passwordless,
+        "agent_hint": sudo.suggestion,
+    });
+    (text, data)
+}
+
+fn handle_confirm_host_key(args: mcp_args::ConfirmHostKeyArgs, db: &DbHandler) -> Result<Value> {
+    let machine_id = args.machine_id;
+    let fingerprint = args.fingerprint.as_str();
+
+    let machine = db
+        .get_machine(machine_id)?
+        .ok_or(McpError::MachineNotFound(machine_id))?;
+
+    // Re-scan and only trust the key if it still matches what the caller verified
+    let port = health::ssh_port(&machine).map_err(|e| McpError::InvalidParams(e.to_string()))?;
+    let scanned = known_hosts::scan_host_key(&machine.ip_address, port)?;
+    let confirmed = scanned.only(fingerprint).ok_or_else(|| {
+        McpError::HostKeyMismatch(format!(
+            "Fingerprint does not match the key currently presented by {}",
+            machine.ip_address
+        ))
+    })?;
+    let matched = &confirmed.fingerprints[0];
+
+    // Only the confirmed key; the host's other key types stay untrusted
+    known_hosts::add_to_known_hosts(&confirmed, known_hosts::active_file(db).as_deref())?;
+    db.confirm_host_key(&machine.ip_address, matched)?;
+
+    Ok(tool_response(
+        format!(
+            "Host key for {} trusted. Run diagnose_connection again to retry.",
+            machine.ip_address
+        ),
+        json!({ "host": machine.ip_address, "fingerprint": matched }),
+    ))
+}
+
+/// Machines still inside their rate-limit interval are skipped and listed as `rate_limited`.
+fn handle_sweep_connectivity(
+    args: mcp_args::SweepConnectivityArgs,
+    ctx: &McpContext,
+    progress: &mut dyn FnMut(usize, usize),
+) -> Result<Value> {
+    let db = ctx.db.as_ref();
+    if args.dry_run {
+        let (ids, max_duration) = health::plan_sweep(db)?;
+        let names: HashMap<i64, String> = db
+            .list_machines()?
+            .into_iter()
+            .filter_map(|m| Some((m.id?, m.name)))
+            .collect();
+        let targets: Vec<Value> = ids
+            .iter()
+            .map(|id| json!({ "machine_id": id, "name": names.get(id) }))
+            .collect();
+        return Ok(tool_response(
+            format!(
+                "Dry run: would probe {} machines, taking at most {} seconds. Nothing was changed.",
+                targets.len(),
+                max_duration.as_secs()
+            ),
+            json!({
+                "dry_run": true,
+                "targets": targets,
+                "max_duration_secs": max_duration.as_secs(),
+            }),
+        ));
+    }
+    let mut rate_limited = Vec::new();
+    let mut skip = |machine_id| {
+        let limited = ctx.check_rate_limit(machine_id).is_err();
+        if limited {
+            rate_limited.push(machine_id);
+        }
+        limited
+    };
+    let results: Vec<Value> = health::sweep_connectivity(db, &mut skip, progress)
+        .into_iter()
+        .map(|(machine_id, reachable)| json!({ "machine_id": machine_id, "reachable": reachable }))
+        .collect();
+    let broken = results
+        .iter()
+        .filter(|r| r["reachable"] == json!(false))
+        .count();
+
+    Ok(tool_response(
+        format!(
+            "Probed {} machines: {} reachable, {} marked broken, {} skipped by the rate limit.",
+            results.len(),
+            results.len() - broken,
+            broken,
+            rate_limited.len()
+        ),
+        json!({ "results": results, "rate_limited": rate_limited }),
+    ))
+}
+
+/// Default and maximum `timeout_secs` for `run_command`.
+const RUN_COMMAND_DEFAULT_TIMEOUT_SECS: u64 = 30;
+const RUN_COMMAND_MAX_TIMEOUT_SECS: u64 = 3600;
+
+/// Optional `timeout_secs` argument shared by `run_command` and the file transfer tools.
+fn run_command_timeout(timeout_secs: Option<u64>) -> Result<u64> {
+    match timeout_secs {
+        Some(n) if (1..=RUN_COMMAND_MAX_TIMEOUT_SECS).contains(&n) => Ok(n),
+        Some(_) => Err(McpError::InvalidParams(format!(
+            "timeout_secs must be between 1 and {}",
+            RUN_COMMAND_MAX_TIMEOUT_SECS
+        ))
+        .into()),
+        None => Ok(RUN_COMMAND_DEFAULT_TIMEOUT_SECS),
+    }
+}
+
+/// Port `run_command`'s engine connects to. The engine speaks SSH itself: it honors a `-p`
+/// (or `-o Port=`) in the machine's ssh options, refuses a machine it could only reach
+/// through a jump host rather than connect straight to it, and ignores the other options.
+fn engine_port(machine: &Machine) -> Result<u16> {
+    let jump_error = || {
+        anyhow!(
+            "Machine {} is reached through a jump host, which run_command can't use; connect from a terminal instead",
+            machine.name
+        )
+    };
+    if machine.jump_host_id.is_some() {
+        return Err(jump_error());
+    }
+    let options = health::machine_ssh_options(machine);
+    let mut args = options.iter().map(String::as_str);
+    while let Some(arg) = args.next() {
+        match (arg, args.clone().next()) {
+            ("-J", _) => return Err(jump_error()),
+            ("-o", Some(option)) => {
+                let (keyword, _) = health::split_ssh_option(option);
+                match keyword.to_ascii_lowercase().as_str() {
+                    "proxyjump" => return Err(jump_error()),
+                    "port" => {}
+                    _ => tracing::warn!(
+                        "run_command on machine {} ignores the ssh option -o {}",
+                        machine.name,
+                        option
+                    ),
+                }
+            }
+            ("-i", _) => tracing::warn!(
+                "run_command on machine {} ignores -i; the account's credential is used",
+                machine.name
+            ),
+            _ => continue,
+        }
+        args.next();
+    }
+    health::ssh_port(machine)
+}
+
+fn handle_run_command(args: mcp_args::RunCommandArgs, db: &DbHandler) -> Result<Value> {
+    let machine_id = args.machine_id;
+    let command = args.command.as_str();
+
+    let machine = db
+        .get_machine(machine_id)?
+        .ok_or(McpError::MachineNotFound(machine_id))?;
+    let account = select_account(db, machine_id, args.account_id)?;
+    let account_id = account.id.ok_or_else(|| anyhow!("Account has no id"))?;
+
+    let timeout_secs = run_command_timeout(args.timeout_secs)?;
+
+    // Used for this call only: never stored, logged, or echoed back
+    let key_passphrase = args.key_passphrase;
+
+    let credential = db.reveal_credential(account_id)?;
+    let (key_path, password) = match account.auth_type.parse::<AuthType>()? {
+        AuthType::Password => (None, Some(credential)),
+        // The engine takes a key's passphrase in place of the password
+        AuthType::Key => {
+            let path = db::expand_home(&credential);
+            if key_passphrase.is_none() && known_hosts::key_needs_passphrase(&path) {
+                return Err(McpError::InvalidParams(format!(
+                    "Key file {} is passphrase-protected; pass key_passphrase",
+                    path.display()
+                ))
+                .into());
+            }
+            (Some(path.to_string_lossy().to_string()), key_passphrase)
+        }
+        // Neither key nor password: the engine falls back to ssh-agent
+        // (TPM-backed keys are also exposed through the agent)
+        AuthType::Agent | AuthType::Tpm => (None, None),
+    };
+
+    let port = engine_port(&machine).map_err(|e| McpError::InvalidParams(e.to_string()))?;
+    // `export` needs a POSIX shell, which Windows' OpenSSH doesn't start by default
+    let command =
+        if machine.env.is_some() && matches!(machine.os_type.parse(), Ok(db::OsType::Windows)) {
+            tracing::warn!(
+                "run_command on Windows machine {} ignores its environment variables",
+                machine_id
+            );
+            command.to_string()
+        } else {
+            format!("{}{}", db::env_exports(machine.env.as_deref())?, command)
+        };
+    let command = command.as_str();
+    let mut job = ssh_worker::Job {
+        host: machine.ip_address.clone(),
+        port,
+        username: account.username.clone(),
+        key_path,
+        password,
+        command: command.to_string(),
+    };
+    let result = ssh_worker::run(&job, Duration::from_secs(timeout_secs));
+    if let Some(password) = job.password.as_mut() {
+        password.clear();
+    }
+    let Some(ssh_worker::JobResult {
+        stdout,
+        stderr,
+        exit_code,
+    }) = result?
+    else {
+        // The worker has been killed and reaped; record the attempt without an exit code
+        let message = format!("Command timed out after {}s and was killed", timeout_secs);
+        db.add_log(
+            machine_id,
+            &account.username,
+            command,
+            None,
+            Some(message.as_bytes()),
+            None,
+        )?;
+        return Ok(tool_error(
+            message,
+            json!({ "timed_out": true, "timeout_secs": timeout_secs, "exit_code": null }),
+        ));
+    };
+    if let Err(e) = db.record_connection(machine_id) {
+        tracing::warn!(
+            "Failed to record connection for machine {}: {}",
+            machine_id,
+            e
+        );
+    }
+
+    db.add_log(
+        machine_id,
+        &account.username,
+        command,
+        Some(&stdout),
+        Some(stderr.as_bytes()),
+        Some(exit_code),
+    )?;
+
+    Ok(tool_response(
+        format!(
+            "Exit code: {}\n\nSTDOUT:\n{}\n\nSTDERR:\n{}",
+            exit_code, stdout, stderr
+        ),
+        json!({ "exit_code": exit_code, "stdout": stdout, "stderr": stderr, "timed_out": false }),
+    ))
+}
+
+/// The configured `mcp.transfer_dir`, if any.
+fn transfer_dir(db: &DbHandler) -> Result<Option<PathBuf>> {
+    Ok(db
+        .get_setting(db::SETTING_TRANSFER_DIR)?
+        .filter(|dir| !dir.trim().is_empty())
+        .map(|dir| db::expand_home(dir.trim())))
+}
+
+/// Local paths file transfers never read or write: the data directory, the database and
+/// its backups, and `~/.ssh`.
+fn protected_local_paths(db: &DbHandler) -> Result<Vec<PathBuf>> {
+    let mut paths = vec![db::data_dir()?, db::backup_dir()?];
+    for suffix in ["", "-wal", "-shm"] {
+        let mut path = db.db_path().as_os_str().to_owned();
+        path.push(suffix);
+        paths.push(PathBuf::from(path));
+    }
+    if let Some(dirs) = directories::UserDirs::new() {
+        paths.push(dirs.home_dir().join(".ssh"));
+    }
+    Ok(paths)
+}
+
+/// `upload_file` / `download_file`: one scp copy, logged like a command with the byte count.
+fn handle_transfer(
+    args: mcp_args::TransferArgs,
+    db: &DbHandler,
+    direction: transfer::Direction,
+) -> Result<Value> {
+    let non_empty = |key: &str, value: &str| {
+        if value.trim().is_empty() {
+            return Err(McpError::InvalidParams(format!(
+                "{} must not be empty",
+                key
+            )));
+        }
+        Ok(())
+    };
+    non_empty("local_path", &args.local_path)?;
+    non_empty("remote_path", &args.remote_path)?;
+    let machine_id = args.machine_id;
+    let local = transfer::check_local_path(
+        &db::expand_home(&args.local_path),
+        transfer_dir(db)?.as_deref(),
+        &protected_local_paths(db)?,
+    )
+    .map_err(|e| McpError::PathNotAllowed(e.to_string()))?;
+    let remote_path = args.remote_path.as_str();
+    let timeout_secs = run_command_timeout(args.timeout_secs)?;
+
+    let machine = db
+        .get_machine(machine_id)?
+        .ok_or(McpError::MachineNotFound(machine_id))?;
+    transfer::check_constraints(&db.get_constraints(machine_id)?, direction, remote_path)
+        .map_err(|e| McpError::PathNotAllowed(e.to_string()))?;
+    if direction == transfer::Direction::Upload && !local.is_file() {
+        return Err(McpError::InvalidParams(format!("{} is not a file", local.display())).into());
+    }
+    let account = select_account(db, machine_id, args.account_id)?;
+    let account_id = account.id.ok_or_else(|| anyhow!("Account has no id"))?;
+
+    // scp runs in batch mode: a key without a passphrase, or whatever ssh-agent holds
+    let identity = match account.auth_type.parse::<AuthType>()? {
+        AuthType::Key => {
+            let path = db::expand_home(&db.reveal_credential(account_id)?);
+            if known_hosts::key_needs_passphrase(&path) {
+                return Err(McpError::InvalidParams(format!(
+                    "Key file {} is passphrase-protected; load it into ssh-agent to transfer files",
+                    path.display()
+                ))
+                .into());
+            }
+            Some(path)
+        }
+        AuthType::Agent | AuthType::Tpm => None,
+        AuthType::Password => {
+            return Err(McpError::InvalidParams(
+                "File transfer needs a key or ssh-agent account, not a password account".into(),
+            )
+            .into());
+        }
+    };
+
+    let remote = transfer::scp_target(&account.username, &machine.ip_address, remote_path);
+    let command = transfer::log_command(direction, &local, &remote);
+    let output = transfer::scp(
+        direction,
+        &local,
+        &remote,
+        identity.as_deref(),
+        known_hosts::active_file(db).as_deref(),
+        &health::machine_ssh_args(db, &machine)?,
+        Duration::from_secs(timeout_secs),
+    )?;
+    let Some(output) = output else {
+        let message = format!("Transfer timed out after {}s and was killed", timeout_secs);
+        db.add_log(
+            machine_id,
+            &account.username,
+            &command,
+            None,
+            Some(message.as_bytes()),
+            None,
+        )?;
+        return Ok(tool_error(
+            message,
+            json!({ "timed_out": true, "timeout_secs": timeout_secs }),
+        ));
+    };
+
+    let exit_code = output.status.code();
+    let bytes = if output.status.success() {
+        std::fs::metadata(&local).map(|m| m.len()).ok()
+    } else {
+        None
+    };
+    db.add_log(
+        machine_id,
+        &account.username,
+        &command,
+        bytes.map(|n| format!("{} bytes", n)).as_deref(),
+        Some(&output.stderr),
+        exit_code,
+    )?;
+    if !output.status.success() {
+        let stderr = terminal_text::decode(&output.stderr);
+        return Ok(tool_error(
+            format!(
+                "{} failed (exit code {:?}):\n{}",
+                command,
+                exit_code,
+                stderr.trim()
+            ),
+            json!({ "exit_code": exit_code, "stderr": stderr, "timed_out": false }),
+        ));
+    }
+    if let Err(e) = db.record_connection(machine_id) {
+        tracing::warn!(
+            "Failed to record connection for machine {}: {}",
+            machine_id,
+            e
+        );
+    }
+    Ok(tool_response(
+        format!("{} ({} bytes)", command, bytes.unwrap_or(0)),
+        json!({ "exit_code": exit_code, "bytes": bytes, "local_path": local, "remote": remote, "timed_out": false }),
+    ))
+}
+
+fn handle_summary(db: &DbHandler) -> Result<Value> {
+    let summary = db.summary()?;
+    let statuses: Vec<String> = summary
+        .machines_by_status
+        .iter()
+        .map(|(status, count)| format!("{} {}", count, status))
+        .collect();
+    let text = format!(
+        "{} machines ({}), {} accounts, {} command logs. Last backup: {}. Last key rotation: {}{}",
+        summary.machines,
+        if statuses.is_empty() {
+            "none".to_string()
+        } else {
+            statuses.join(", ")
+        },
+        summary.accounts,
+        summary.logs,
+        summary.last_backup.as_deref().unwrap_or("never"),
+        summary.last_key_rotation.as_deref().unwrap_or("never"),
+        if summary.key_rotation_due {
+            " (rotation due)"
+        } else {
+            ""
+        }
+    );
+    Ok(tool_response(text, serde_json::to_value(&summary)?))
+}
+
+fn handle_health_check(db: &DbHandler) -> Result<Value> {
+    let db_ok = db.ping().is_ok();
+    let (machines, accounts) = db.record_counts().unwrap_or((0, 0));
+    let knowledge_file = knowledge::locate_knowledge_file();
+    let (last_key_rotation, key_rotation_due) = db.key_rotation_status().unwrap_or((None, false));
+
+    let health = json!({
+        "status": if db_ok && db.master_key_loaded() { "ok" } else { "degraded" },
+        "database_reachable": db_ok,
+        "machines": machines,
+        "accounts": accounts,
+        "master_key_loaded": db.master_key_loaded(),
+        "master_key_source": match db.key_source() {
+            db::KeySource::Keyring => "keyring",
+            db::KeySource::Provided => "provided",
+            db::KeySource::Unavailable(_) => "unavailable",
+        },
+        "last_key_rotation": last_key_rotation,
+        "key_rotation_due": key_rotation_due,
+        "knowledge_file_found": knowledge_file.is_some(),
+        "knowledge_file": knowledge_file,
+    });
+
+    Ok(tool_response(
+        serde_json::to_string_pretty(&health)?,
+        health,
+    ))
+}
+
+fn handle_update_credential(args: mcp_args::UpdateCredentialArgs, db: &DbHandler) -> Result<Value> {
+    let account_id = args.account_id;
+    let credential = args.credential.as_str();
+
+    let account = db
+        .get_account(account_id)?
+        .ok_or_else(|| McpError::AccountNotFound(format!("Account {} not found", account_id)))?;
+    let candidate = Account {
+        credential: credential.to_string(),
+        ..account.clone()
+    };
+    db::validate_account(&candidate).map_err(|e| McpError::InvalidParams(e.to_string()))?;
+
+    // Encrypted inside update_account_credential; never echo the value back
+    db.update_account_credential(account_id, credential)?;
+    let key_fingerprint = db.get_account(account_id)?.and_then(|a| a.key_fingerprint);
+
+    Ok(tool_response(
+        format!(
+            "Credential for account {} ({}) updated.",
+            account_id, account.username
+        ),
+        json!({ "account_id": account_id, "updated": true, "key_fingerprint": key_fingerprint }),
+    ))
+}
+
+fn handle_generate_identity_key(db: &DbHandler) -> Result<Value> {
+    let hardware = rust_ssh::security::tpm::is_tpm_available();
+    let key = security::generate_identity_key(db, hardware)?;
+    Ok(tool_response(
+        &key.public_key,
+        json!({
+            "public_key": key.public_key,
+            "kind": if hardware { "tpm" } else { "software" }
+        }),
+    ))
+}
+
+fn handle_get_public_key(db: &DbHandler) -> Result<Value> {
+    let public_key = security::identity_public_key(db)?;
+    let text = public_key
+        .clone()
+        .unwrap_or_else(|| "No identity key has been generated yet".to_string());
+    Ok(tool_response(text, json!({ "public_key": public_key })))
+}
+
+fn handle_rotate_keys(
+    args: mcp_args::RotateKeysArgs,
+    db: &DbHandler,
+    progress: &mut dyn FnMut(usize, usize),
+) -> Result<Value> {
+    let ownership = args.ownership.as_deref();
+    if args.dry_run {
+        let plan = db.plan_key_rotation(ownership)?;
+        let scope = match ownership {
+            Some(ownership) => format!("the key of ownership group \"{}\"", ownership),
+            None => "the master key".to_string(),
+        };
+        let mut text = format!(
+            "Dry run: rotating {} would re-encrypt {} accounts. Nothing was changed.",
+            scope,
+            plan.accounts.len()
+        );
+        if !plan.undecryptable.is_empty() {
+            text.push_str(&format!(
+                " {} accounts don't decrypt with the current key, so the rotation would fail: {:?}",
+                plan.undecryptable.len(),
+                plan.undecryptable
+            ));
+        }
+        return Ok(tool_response(
+            text,
+            json!({
+                "dry_run": true,
+                "ownership": ownership,
+                "account_ids": plan.accounts,
+                "undecryptable_account_ids": plan.undecryptable,
+                "would_succeed": plan.undecryptable.is_empty(),
+            }),
+        ));
+    }
+    if let Some(ownership) = ownership {
+        db.rotate_ownership_key(ownership, progress)?;
+        return Ok(tool_response(
+            format!(
+                "Key of ownership group \"{}\" rotated and its credentials re-encrypted successfully.",
+                ownership
+            ),
+            json!({ "rotated": true, "ownership": ownership }),
+        ));
+    }
+    db.rotate_keys(progress)?;
+    Ok(tool_response(
+        "Master key rotated and all credentials re-encrypted successfully.",
+        json!({ "rotated": true, "ownership": null }),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn machine_with(ssh_options: Option<&str>, jump_host_id: Option<i64>) -> Machine {
+        Machine {
+            id: Some(1),
+            uuid: None,
+            name: "web-01".to_string(),
+            ip_address: "10.0.0.1".to_string(),
+            purpose: "test".to_string(),
+            ownership: "personal".to_string(),
+            os_type: "linux".to_string(),
+            status: "active".to_string(),
+            group: None,
+            ssh_options: ssh_options.map(str::to_string),
+            jump_host_id,
+            requires_vpn: false,
+            env: None,
+        }
+    }
+
+    fn test_context() -> McpContext {
+        let db = DbHandler::new_in_memory_with_key([7u8; 32]).unwrap();
+        McpContext::new(Arc::new(db), false)
+    }
+
+    /// Runs a session over `input` and returns each message written back.
+    fn session(ctx: &McpContext, input: &[u8]) -> Vec<Value> {
+        let mut output = Vec::new();
+        run_mcp_session(input, &mut output, ctx).unwrap();
+        String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    const PING: &str = r#"{"jsonrpc":"2.0","id":1,"method":"ping"}"#;
+
+    #[test]
+    fn test_session_closes_on_oversize_message() {
+        let mut ctx = test_context();
+        ctx.max_message_bytes = 64;
+        let input = format!(
+            "{}\n{{\"padding\":\"{}\"}}\n{}\n",
+            PING,
+            "x".repeat(100),
+            PING
+        );
+        let replies = session(&ctx, input.as_bytes());
+        assert_eq!(replies.len(), 2);
+        assert_eq!(replies[0]["result"], json!({}));
+        assert_eq!(replies[1]["error"]["code"], -32600);
+    }
+
+    #[test]
+    fn test_session_answers_invalid_utf8_and_keeps_going() {
+        let mut input = b"{\"jsonrpc\":\"2.0\",\"id\":0,\"method\":\"\xff\xfe\"}\n".to_vec();
+        input.extend_from_slice(format!("{}\n", PING).as_bytes());
+        let replies = session(&test_context(), &input);
+        assert_eq!(replies.len(), 2);
+        assert_eq!(replies[0]["error"]["code"], -32700);
+        assert_eq!(replies[1]["id"], 1);
+    }
+
+    #[test]
+    fn test_session_reads_multi_line_values_and_reports_a_cut_one() {
+        // A client dropping mid-request leaves a partial value behind
+        let input =
+            "{\n  \"jsonrpc\": \"2.0\",\n  \"id\": 1,\n  \"method\": \"ping\"\n}\n{\"jsonrpc\":";
+        let replies = session(&test_context(), input.as_bytes());
+        assert_eq!(replies.len(), 2);
+        assert_eq!(replies[0]["result"], json!({}));
+        assert_eq!(replies[1]["error"]["code"], -32700);
+    }
+
+    #[test]
+    fn test_dispatch_never_answers_notifications() {
+        let ctx = test_context();
+        let dispatch = |message| dispatch_message(message, &ctx, &mut |_| {});
+        let batch = json!([
+            { "jsonrpc": "2.0", "method": "notifications/initialized" },
+            { "jsonrpc": "2.0", "method": "notifications/cancelled", "params": { "requestId": 3 } },
+        ]);
+        assert_eq!(dispatch(batch), None);
+        assert_eq!(
+            dispatch(json!({ "jsonrpc": "2.0", "method": "notifications/unknown/thing" })),
+            None
+        );
+        // Unknown notification methods are accepted, even when sent with an id
+        let reply = dispatch(json!({ "jsonrpc": "2.0", "id": 5, "method": "notifications/x" }));
+        assert_eq!(reply.unwrap()["error"], Value::Null);
+
+        let mixed = json!([
+            { "jsonrpc": "2.0", "method": "notifications/initialized" },
+            { "jsonrpc": "2.0", "id": 2, "method": "ping" },
+        ]);
+        let reply = dispatch(mixed).unwrap();
+        assert_eq!(reply.as_array().map(Vec::len), Some(1));
+        assert_eq!(reply[0]["id"], 2);
+        assert_eq!(dispatch(json!([])).unwrap()["error"]["code"], -32600);
+    }
+
+    #[test]
+    fn test_initialize_negotiates_protocol_version() {
+        let ctx = test_context();
+        let initialize = |version: Value| {
+            let request = json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": "initialize",
+                "params": { "protocolVersion": version },
+            });
+            dispatch_message(request, &ctx, &mut |_| {}).unwrap()
+        };
+        assert_eq!(
+            initialize(json!("2025-03-26"))["result"]["protocolVersion"],
+            "2025-03-26"
+        );
+        assert_eq!(
+            initialize(json!("2025-04-01"))["result"]["protocolVersion"],
+            "2025-03-26"
+        );
+        assert_eq!(
+            initialize(json!("2099-01-01"))["result"]["protocolVersion"],
+            SUPPORTED_PROTOCOL_VERSIONS[0]
+        );
+        assert_eq!(initialize(json!(2024))["error"]["code"], -32602);
+    }
+
+    #[test]
+    fn test_engine_port_honors_port_and_refuses_jumps() {
+        assert_eq!(engine_port(&machine_with(None, None)).unwrap(), 22);
+        assert_eq!(
+            engine_port(&machine_with(
+                Some("-C -p 2222 -o ServerAliveInterval=30"),
+                None
+            ))
+            .unwrap(),
+            2222
+        );
+        assert_eq!(
+            engine_port(&machine_with(Some("-o Port=2200"), None)).unwrap(),
+            2200
+        );
+        assert!(engine_port(&machine_with(None, Some(2))).is_err());
+        assert!(engine_port(&machine_with(Some("-J admin@bastion"), None)).is_err());
+        assert!(engine_port(&machine_with(Some("-oProxyJump=bastion"), None)).is_err());
+    }
+
+    #[test]
+    fn test_check_listen_addr_refuses_remote_without_flag() {
+        assert!(check_listen_addr("127.0.0.1:8765", false).is_ok());
+        assert!(check_listen_addr("[::1]:8765", false).is_ok());
+        assert!(check_listen_addr("0.0.0.0:8765", false).is_err());
+        assert!(check_listen_addr("192.0.2.1:8765", false).is_err());
+        assert!(check_listen_addr("0.0.0.0:8765", true).is_ok());
+        assert!(check_listen_addr("no-port", true).is_err());
+    }
 }
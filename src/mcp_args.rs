@@ -0,0 +1,214 @@
+use crate::mcp_error::McpError;
+use anyhow::Result;
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+/// Deserializes a tool's `arguments` into its argument struct; absent or null arguments count
+/// as `{}`. Failures are invalid params (-32602) naming the offending field, e.g.
+/// `filter.status: invalid type: integer `1`, expected a string`.
+pub fn parse<T: DeserializeOwned>(arguments: Option<&Value>) -> Result<T> {
+    let empty = Value::Object(serde_json::Map::new());
+    let arguments = arguments.filter(|v| !v.is_null()).unwrap_or(&empty);
+    serde_path_to_error::deserialize(arguments).map_err(|e| {
+        let path = e.path().to_string();
+        let message = match path.as_str() {
+            // Missing fields are reported on the enclosing object and name themselves
+            "." => e.into_inner().to_string(),
+            _ => format!("{}: {}", path, e.into_inner()),
+        };
+        McpError::InvalidParams(message).into()
+    })
+}
+
+/// `register_machine`, and each entry of `register_machines`.
+#[derive(Debug, Deserialize)]
+pub struct RegisterMachineArgs {
+    pub name: String,
+    pub ip_address: String,
+    pub purpose: String,
+    pub ownership: String,
+    pub os_type: String,
+    pub group: Option<String>,
+    pub ssh_options: Option<String>,
+    pub jump_host_id: Option<i64>,
+    #[serde(default)]
+    pub requires_vpn: bool,
+    /// An object of names to values, or a string holding one
+    pub env: Option<Value>,
+    pub username: String,
+    pub auth_type: String,
+    pub credential: String,
+    pub note: Option<String>,
+}
+
+/// `register_machines`. Entries are parsed one by one so a bad one fails only its index.
+#[derive(Debug, Deserialize)]
+pub struct RegisterMachinesArgs {
+    pub machines: Vec<Value>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ListMachinesArgs {
+    pub fields: Option<Vec<String>>,
+    pub filter: Option<MachineFilter>,
+}
+
+/// `list_machines`' `filter`; unlike the arguments themselves, unknown keys are rejected.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MachineFilter {
+    pub status: Option<String>,
+    pub ownership: Option<String>,
+    pub group: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CloneMachineArgs {
+    pub machine_id: i64,
+    pub name: String,
+    pub ip_address: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GetMachineArgs {
+    pub machine_id: i64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FindMachinesByKeyArgs {
+    pub fingerprint: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ExportLogsArgs {
+    pub format: Option<crate::ExportFormat>,
+    pub since: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GetLogsArgs {
+    pub machine_id: Option<i64>,
+    pub limit: Option<u64>,
+    pub since: Option<String>,
+}
+
+/// Text of a `diagnose_connection` result.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReportFormat {
+    #[default]
+    Text,
+    Markdown,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DiagnoseArgs {
+    pub machine_id: i64,
+    pub account_id: Option<i64>,
+    #[serde(default)]
+    pub format: ReportFormat,
+    #[serde(default)]
+    pub redact: bool,
+    #[serde(default)]
+    pub check_sudo: bool,
+    #[serde(default)]
+    pub password_hint: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ConfirmHostKeyArgs {
+    pub machine_id: i64,
+    pub fingerprint: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SweepConnectivityArgs {
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RunCommandArgs {
+    pub machine_id: i64,
+    pub command: String,
+    pub account_id: Option<i64>,
+    pub timeout_secs: Option<u64>,
+    /// Used for this call only: never stored, logged, or echoed back
+    pub key_passphrase: Option<String>,
+}
+
+/// `upload_file` and `download_file`.
+#[derive(Debug, Deserialize)]
+pub struct TransferArgs {
+    pub machine_id: i64,
+    pub local_path: String,
+    pub remote_path: String,
+    pub account_id: Option<i64>,
+    pub timeout_secs: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateCredentialArgs {
+    pub account_id: i64,
+    pub credential: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RotateKeysArgs {
+    pub ownership: Option<String>,
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn invalid_params<T: DeserializeOwned + std::fmt::Debug>(arguments: Value) -> String {
+        let error = parse::<T>(Some(&arguments)).unwrap_err();
+        assert_eq!(McpError::code_for(&error), -32602);
+        error.to_string()
+    }
+
+    #[test]
+    fn test_parse_names_the_offending_field() {
+        let args: DiagnoseArgs = parse(Some(&json!({ "machine_id": 3 }))).unwrap();
+        assert_eq!(args.machine_id, 3);
+        assert_eq!(args.format, ReportFormat::Text);
+        assert!(!args.check_sudo);
+
+        assert_eq!(
+            invalid_params::<DiagnoseArgs>(json!({})),
+            "Invalid params: missing field `machine_id`"
+        );
+        assert!(
+            invalid_params::<DiagnoseArgs>(json!({ "machine_id": "3" }))
+                .starts_with("Invalid params: machine_id: invalid type: string")
+        );
+        assert!(
+            invalid_params::<DiagnoseArgs>(json!({ "machine_id": 3, "format": "html" }))
+                .starts_with("Invalid params: format: unknown variant `html`")
+        );
+        assert!(
+            invalid_params::<ListMachinesArgs>(json!({ "filter": { "status": 1 } }))
+                .starts_with("Invalid params: filter.status: invalid type: integer")
+        );
+        let unknown = invalid_params::<ListMachinesArgs>(json!({ "filter": { "name": "web" } }));
+        assert!(unknown.starts_with("Invalid params: filter"));
+        assert!(unknown.contains("unknown field `name`"));
+    }
+
+    #[test]
+    fn test_parse_treats_missing_arguments_as_empty() {
+        let args: RotateKeysArgs = parse(None).unwrap();
+        assert!(args.ownership.is_none() && !args.dry_run);
+        let args: GetLogsArgs = parse(Some(&Value::Null)).unwrap();
+        assert!(args.limit.is_none());
+        assert_eq!(
+            invalid_params::<UpdateCredentialArgs>(json!({ "account_id": 1 })),
+            "Invalid params: missing field `credential`"
+        );
+    }
+}
@@ -0,0 +1,120 @@
+use crate::security::KeyringUnavailable;
+use std::fmt;
+
+/// Errors that map to a specific JSON-RPC error code. Handlers return them inside
+/// `anyhow::Error`; anything else is reported as an internal error (-32603).
+#[derive(Debug)]
+pub enum McpError {
+    /// Unknown JSON-RPC method (-32601).
+    MethodNotFound(String),
+    /// Unknown tool in `tools/call` (-32601).
+    UnknownTool(String),
+    /// Missing or invalid params/arguments (-32602).
+    InvalidParams(String),
+    /// No machine with the given id (-32001).
+    MachineNotFound(i64),
+    /// The requested account doesn't exist or the machine has none (-32002).
+    AccountNotFound(String),
+    /// The presented host key doesn't match the fingerprint the caller confirmed (-32003).
+    HostKeyMismatch(String),
+    /// A machine with this name is already registered (-32004).
+    DuplicateMachine(String),
+    /// The tool isn't available on this server, e.g. a mutating tool in read-only mode (-32005).
+    PermissionDenied(String),
+    /// The path is outside what the machine's constraints or the transfer settings allow (-32005).
+    PathNotAllowed(String),
+    /// SSH spawns against this machine are arriving faster than the configured interval (-32006).
+    RateLimited {
+        machine_id: i64,
+        retry_after_secs: u64,
+    },
+}
+
+impl McpError {
+    pub const INTERNAL_ERROR: i64 = -32603;
+    /// A credential was needed but the master key is unavailable ([`KeyringUnavailable`]).
+    pub const KEYRING_UNAVAILABLE: i64 = -32007;
+
+    pub fn code(&self) -> i64 {
+        match self {
+            McpError::MethodNotFound(_) | McpError::UnknownTool(_) => -32601,
+            McpError::InvalidParams(_) => -32602,
+            McpError::MachineNotFound(_) => -32001,
+            McpError::AccountNotFound(_) => -32002,
+            McpError::HostKeyMismatch(_) => -32003,
+            McpError::DuplicateMachine(_) => -32004,
+            McpError::PermissionDenied(_) | McpError::PathNotAllowed(_) => -32005,
+            McpError::RateLimited { .. } => -32006,
+        }
+    }
+
+    /// JSON-RPC code for any handler error, falling back to internal error.
+    pub fn code_for(error: &anyhow::Error) -> i64 {
+        if error.downcast_ref::<KeyringUnavailable>().is_some() {
+            return Self::KEYRING_UNAVAILABLE;
+        }
+        error
+            .downcast_ref::<McpError>()
+            .map(McpError::code)
+            .unwrap_or(Self::INTERNAL_ERROR)
+    }
+}
+
+impl fmt::Display for McpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            McpError::MethodNotFound(method) => write!(f, "Method not found: {}", method),
+            McpError::UnknownTool(tool) => write!(f, "Unknown tool: {}", tool),
+            McpError::InvalidParams(msg) => write!(f, "Invalid params: {}", msg),
+            McpError::MachineNotFound(id) => write!(f, "Machine {} not found", id),
+            McpError::AccountNotFound(msg) => write!(f, "{}", msg),
+            McpError::HostKeyMismatch(msg) => write!(f, "{}", msg),
+            McpError::PermissionDenied(tool) => {
+                write!(
+                    f,
+                    "Permission denied: {} is disabled in read-only mode",
+                    tool
+                )
+            }
+            McpError::PathNotAllowed(msg) => write!(f, "Permission denied: {}", msg),
+            McpError::RateLimited {
+                machine_id,
+                retry_after_secs,
+            } => write!(
+                f,
+                "Rate limited: machine {} was probed too recently, retry in {} seconds",
+                machine_id, retry_after_secs
+            ),
+            McpError::DuplicateMachine(name) => {
+                write!(f, "a machine named \"{}\" already exists", name)
+            }
+        }
+    }
+}
+
+impl std::error::Error for McpError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_code_for_downcasts_typed_errors() {
+        let not_found: anyhow::Error = McpError::MachineNotFound(7).into();
+        assert_eq!(McpError::code_for(&not_found), -32001);
+        assert_eq!(not_found.to_string(), "Machine 7 not found");
+
+        let unknown: anyhow::Error = McpError::UnknownTool("nope".into()).into();
+        assert_eq!(McpError::code_for(&unknown), -32601);
+
+        let keyring: anyhow::Error = KeyringUnavailable {
+            service: "mcp-ssh-manager".into(),
+            reason: "no Secret Service".into(),
+        }
+        .into();
+        assert_eq!(McpError::code_for(&keyring), McpError::KEYRING_UNAVAILABLE);
+
+        let other = anyhow::anyhow!("disk on fire");
+        assert_eq!(McpError::code_for(&other), McpError::INTERNAL_ERROR);
+    }
+}
@@ -0,0 +1,74 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::time::Duration;
+
+/// Lets the GUI stop the background MCP server on exit without cutting a request
+/// (and its DB transaction) off half-way: once a stop is requested no new request
+/// starts, and the GUI waits for the running one to finish.
+#[derive(Default)]
+pub struct Shutdown {
+    requested: AtomicBool,
+    in_flight: Mutex<usize>,
+    idle: Condvar,
+}
+
+/// Marks a request as running until dropped.
+pub struct InFlight<'a>(&'a Shutdown);
+
+impl Shutdown {
+    fn count(&self) -> MutexGuard<'_, usize> {
+        self.in_flight.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Registers a request about to run, or `None` once a stop has been requested.
+    pub fn begin(&self) -> Option<InFlight<'_>> {
+        // Checked under the lock so a request can't slip in after the waiter saw zero
+        let mut count = self.count();
+        if self.requested.load(Ordering::SeqCst) {
+            return None;
+        }
+        *count += 1;
+        Some(InFlight(self))
+    }
+
+    /// Stops new requests and waits up to `timeout` for running ones to finish.
+    /// Returns false if some were still running when the wait gave up.
+    pub fn request_and_wait(&self, timeout: Duration) -> bool {
+        let count = self.count();
+        self.requested.store(true, Ordering::SeqCst);
+        let (count, _) = self
+            .idle
+            .wait_timeout_while(count, timeout, |n| *n > 0)
+            .unwrap_or_else(|e| e.into_inner());
+        *count == 0
+    }
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        let mut count = self.0.count();
+        *count -= 1;
+        if *count == 0 {
+            self.0.idle.notify_all();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_waits_for_in_flight_request() {
+        let shutdown = Shutdown::default();
+        let request = shutdown.begin().expect("not stopped yet");
+        std::thread::scope(|s| {
+            let waiter = s.spawn(|| shutdown.request_and_wait(Duration::from_secs(5)));
+            std::thread::sleep(Duration::from_millis(50));
+            drop(request);
+            assert!(waiter.join().unwrap());
+        });
+        assert!(shutdown.begin().is_none());
+        assert!(shutdown.request_and_wait(Duration::from_millis(10)));
+    }
+}
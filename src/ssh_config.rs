@@ -0,0 +1,258 @@
+use crate::db::{self, Account, AuthType, DbHandler, Machine};
+use crate::health::{self, DEFAULT_SSH_PORT};
+use anyhow::{Result, anyhow};
+
+/// One concrete `Host` alias from an OpenSSH client config.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SshHostEntry {
+    pub alias: String,
+    pub host_name: Option<String>,
+    pub user: Option<String>,
+    pub port: Option<u16>,
+    pub identity_file: Option<String>,
+}
+
+/// Parses the `Host` blocks of an ssh config. Patterns containing `*`, `?`, or `!`
+/// are skipped, as are `Match` blocks. Like ssh, the first value seen for a keyword wins.
+pub fn parse(content: &str) -> Vec<SshHostEntry> {
+    let mut entries: Vec<SshHostEntry> = Vec::new();
+    // Indices into `entries` that the current block's options apply to
+    let mut current: Vec<usize> = Vec::new();
+
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (keyword, value) = match line.split_once(|c: char| c.is_whitespace() || c == '=') {
+            Some((k, v)) => (
+                k,
+                v.trim_start_matches(|c: char| c.is_whitespace() || c == '='),
+            ),
+            None => (line, ""),
+        };
+        let value = value.trim().trim_matches('"');
+
+        match keyword.to_ascii_lowercase().as_str() {
+            "host" => {
+                current.clear();
+                for alias in value.split_whitespace() {
+                    if alias.contains(['*', '?', '!']) {
+                        continue;
+                    }
+                    current.push(entries.len());
+                    entries.push(SshHostEntry {
+                        alias: alias.to_string(),
+                        ..Default::default()
+                    });
+                }
+            }
+            "match" => current.clear(),
+            "hostname" => set_first(&mut entries, &current, |e| &mut e.host_name, value),
+            "user" => set_first(&mut entries, &current, |e| &mut e.user, value),
+            "identityfile" => set_first(&mut entries, &current, |e| &mut e.identity_file, value),
+            "port" => {
+                if let Ok(port) = value.parse::<u16>() {
+                    for &i in &current {
+                        entries[i].port.get_or_insert(port);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    entries
+}
+
+fn set_first(
+    entries: &mut [SshHostEntry],
+    current: &[usize],
+    field: impl Fn(&mut SshHostEntry) -> &mut Option<String>,
+    value: &str,
+) {
+    for &i in current {
+        field(&mut entries[i]).get_or_insert_with(|| value.to_string());
+    }
+}
+
+/// Upserts each entry as a machine (named after its alias) with one account: key auth when
+/// an `IdentityFile` is set, ssh-agent otherwise. Returns `(alias, outcome)` per entry.
+/// With `dry_run`, nothing is written and the outcome describes what would happen.
+pub fn import(db: &DbHandler, entries: &[SshHostEntry], dry_run: bool) -> Vec<(String, String)> {
+    entries
+        .iter()
+        .map(|entry| {
+            let outcome =
+                import_entry(db, entry, dry_run).unwrap_or_else(|e| format!("skipped: {}", e));
+            (entry.alias.clone(), outcome)
+        })
+        .collect()
+}
+
+/// `options` with any `-p` replaced by `port` (dropped for the default port). Without a
+/// `Port` in the config, `options` is kept as is.
+fn with_port(options: Option<&str>, port: Option<u16>) -> Result<Option<String>> {
+    let Some(port) = port else {
+        return Ok(options.map(str::to_string));
+    };
+    let args = health::parse_ssh_options(options.unwrap_or_default())?;
+    let mut kept: Vec<String> = Vec::with_capacity(args.len() + 2);
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == "-p" {
+            args.next();
+        } else {
+            kept.push(arg);
+        }
+    }
+    if port != DEFAULT_SSH_PORT {
+        kept.extend(["-p".to_string(), port.to_string()]);
+    }
+    Ok((!kept.is_empty()).then(|| shell_words::join(&kept)))
+}
+
+fn import_entry(db: &DbHandler, entry: &SshHostEntry, dry_run: bool) -> Result<String> {
+    let host = db::normalize_host(entry.host_name.as_deref().unwrap_or(&entry.alias))?;
+    let username = match &entry.user {
+        Some(user) => user.clone(),
+        None => std::env::var("USER")
+            .or_else(|_| std::env::var("USERNAME"))
+            .map_err(|_| anyhow!("no User and the current user is unknown"))?,
+    };
+    let (auth_type, credential) = match &entry.identity_file {
+        Some(path) => (AuthType::Key, path.clone()),
+        None => (AuthType::Agent, String::new()),
+    };
+
+    let existing = db
+        .list_machines()?
+        .into_iter()
+        .find(|m| m.name == entry.alias);
+    let mut account = Account {
+        id: None,
+        machine_id: existing.as_ref().and_then(|m| m.id).unwrap_or(0),
+        username: username.clone(),
+        auth_type: auth_type.to_string(),
+        credential,
+        key_fingerprint: None,
+        note: None,
+    };
+    db::validate_account(&account)?;
+
+    let summary = format!("{}@{} ({})", username, host, auth_type);
+    match existing {
+        Some(mut machine) => {
+            let machine_id = machine.id.unwrap_or_default();
+            let existing_account = db
+                .list_accounts_for_machine(machine_id)?
+                .into_iter()
+                .find(|a| a.username == username);
+            if dry_run {
+                return Ok(format!("would update {}", summary));
+            }
+            machine.ip_address = host;
+            machine.ssh_options = with_port(machine.ssh_options.as_deref(), entry.port)?;
+            db.update_machine(&machine)?;
+            match existing_account {
+                Some(a) if a.auth_type.parse::<AuthType>().ok() == Some(auth_type) => {
+                    db.update_account_credential(a.id.unwrap_or_default(), &account.credential)?;
+                }
+                _ => {
+                    account.machine_id = machine_id;
+                    db.add_account(account)?;
+                }
+            }
+            Ok(format!("updated {}", summary))
+        }
+        None => {
+            if dry_run {
+                return Ok(format!("would add {}", summary));
+            }
+            let machine_id = db.add_machine(Machine {
+                id: None,
+                uuid: None,
+                name: entry.alias.clone(),
+                ip_address: host,
+                purpose: "Imported from ssh config".to_string(),
+                ownership: "personal".to_string(),
+                os_type: "other".to_string(),
+                status: "active".to_string(),
+                group: None,
+                ssh_options: with_port(None, entry.port)?,
+                jump_host_id: None,
+                requires_vpn: false,
+                env: None,
+            })?;
+            account.machine_id = machine_id;
+            db.add_account(account)?;
+            Ok(format!("added {}", summary))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_host_blocks() {
+        let config = r#"
+# personal boxes
+Host web db
+    HostName 10.0.0.5
+    User deploy
+    IdentityFile ~/.ssh/id_ed25519
+
+Host *.internal !bastion
+    User ignored
+
+Host nas
+    HostName=nas.local
+    Port 2222
+    User admin
+    User second
+
+Host *
+    ServerAliveInterval 30
+"#;
+        let entries = parse(config);
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].alias, "web");
+        assert_eq!(entries[1].alias, "db");
+        assert_eq!(entries[1].host_name.as_deref(), Some("10.0.0.5"));
+        assert_eq!(
+            entries[1].identity_file.as_deref(),
+            Some("~/.ssh/id_ed25519")
+        );
+        assert_eq!(
+            entries[2],
+            SshHostEntry {
+                alias: "nas".to_string(),
+                host_name: Some("nas.local".to_string()),
+                user: Some("admin".to_string()),
+                port: Some(2222),
+                identity_file: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_with_port_replaces_only_the_port() {
+        assert_eq!(
+            with_port(None, Some(2222)).unwrap().as_deref(),
+            Some("-p 2222")
+        );
+        assert_eq!(with_port(None, Some(22)).unwrap(), None);
+        assert_eq!(
+            with_port(Some("-C -p 2200 -o ServerAliveInterval=30"), Some(2222))
+                .unwrap()
+                .as_deref(),
+            Some("-C -o 'ServerAliveInterval=30' -p 2222")
+        );
+        assert_eq!(with_port(Some("-p2200"), Some(22)).unwrap(), None);
+        assert_eq!(
+            with_port(Some("-p 2200"), None).unwrap().as_deref(),
+            Some("-p 2200")
+        );
+    }
+}
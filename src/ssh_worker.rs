@@ -0,0 +1,68 @@
+use crate::health;
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::io::Read;
+use std::process::Command;
+use std::time::Duration;
+
+/// Hidden subcommand the worker process is started with.
+pub const WORKER_SUBCOMMAND: &str = "ssh-worker";
+
+/// One `rust_ssh::run_command` call, sent to the worker on stdin so the password
+/// never appears in its command line.
+#[derive(Serialize, Deserialize)]
+pub struct Job {
+    pub host: String,
+    pub port: u16,
+    pub username: String,
+    pub key_path: Option<String>,
+    pub password: Option<String>,
+    pub command: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct JobResult {
+    pub stdout: String,
+    pub stderr: String,
+    pub exit_code: i32,
+}
+
+/// Runs `job` in a child copy of this executable so a hung command can be killed.
+/// `Ok(None)` means it was still running after `timeout` and has been killed and reaped.
+pub fn run(job: &Job, timeout: Duration) -> Result<Option<JobResult>> {
+    let mut cmd = Command::new(std::env::current_exe()?);
+    cmd.arg(WORKER_SUBCOMMAND);
+    let input = serde_json::to_vec(job)?;
+    let Some(output) = health::run_with_timeout(cmd, Some(&input), timeout)? else {
+        return Ok(None);
+    };
+    if !output.status.success() {
+        return Err(anyhow!(
+            "{}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(Some(serde_json::from_slice(&output.stdout)?))
+}
+
+/// Worker side: reads a [`Job`] from stdin, runs it, and prints the [`JobResult`] as JSON.
+pub fn serve() -> Result<()> {
+    let mut input = Vec::new();
+    std::io::stdin().read_to_end(&mut input)?;
+    let job: Job = serde_json::from_slice(&input)?;
+    let (stdout, stderr, exit_code) = rust_ssh::run_command(
+        &job.host,
+        job.port,
+        &job.username,
+        job.key_path.as_deref(),
+        job.password.as_deref(),
+        &job.command,
+    )?;
+    let result = JobResult {
+        stdout,
+        stderr,
+        exit_code,
+    };
+    println!("{}", serde_json::to_string(&result)?);
+    Ok(())
+}
@@ -0,0 +1,66 @@
+/// Turns raw ssh output into plain text: invalid UTF-8 becomes U+FFFD and the result
+/// goes through [`clean`].
+pub fn decode(raw: &[u8]) -> String {
+    clean(&String::from_utf8_lossy(raw))
+}
+
+/// Strips ANSI escape sequences (CSI such as colors, OSC such as window titles, and
+/// two-byte escapes) and other control characters except tab, and normalizes CRLF and
+/// bare CR to LF.
+pub fn clean(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\x1b' => match chars.next() {
+                // CSI: parameters and intermediates up to a final byte in @..~
+                Some('[') => {
+                    for c in chars.by_ref() {
+                        if ('@'..='~').contains(&c) {
+                            break;
+                        }
+                    }
+                }
+                // OSC: terminated by BEL or ST (ESC \)
+                Some(']') => {
+                    while let Some(c) = chars.next() {
+                        if c == '\x07' {
+                            break;
+                        }
+                        if c == '\x1b' && chars.peek() == Some(&'\\') {
+                            chars.next();
+                            break;
+                        }
+                    }
+                }
+                _ => {}
+            },
+            '\r' => {
+                if chars.peek() == Some(&'\n') {
+                    chars.next();
+                }
+                out.push('\n');
+            }
+            '\n' | '\t' => out.push(c),
+            c if c.is_control() => {}
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clean_strips_escapes_and_normalizes_newlines() {
+        let raw = "\x1b[1;31mPermission denied\x1b[0m\r\n\x1b]0;title\x07debug1: ok\rnext\x08";
+        assert_eq!(clean(raw), "Permission denied\ndebug1: ok\nnext");
+    }
+
+    #[test]
+    fn test_decode_replaces_invalid_utf8() {
+        assert_eq!(decode(b"bad \xff byte\x1b[0m"), "bad \u{fffd} byte");
+    }
+}
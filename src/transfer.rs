@@ -0,0 +1,300 @@
+use crate::db::Constraint;
+use crate::health;
+use anyhow::{Result, anyhow};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use std::time::Duration;
+
+/// ssh connect timeout; the transfer as a whole is bounded by the caller's timeout.
+const CONNECT_TIMEOUT_SECS: u64 = 10;
+/// Constraint blocking uploads to a remote path prefix, e.g. `deny-write /etc`.
+const DENY_WRITE_RULE: &str = "deny-write";
+/// Constraint blocking downloads from a remote path prefix, e.g. `deny-read /root`.
+const DENY_READ_RULE: &str = "deny-read";
+/// Characters the remote shell or scp's glob expansion would interpret, refused in a
+/// remote path while a deny rule applies; whitespace is refused as well. Quotes are among
+/// them because legacy scp hands the path to the remote shell, which strips them.
+const REMOTE_PATH_METACHARACTERS: &[char] = &[
+    '*', '?', '[', ']', '{', '}', '$', '`', '\\', ';', '|', '&', '<', '>', '\'', '"', '~', '(',
+    ')', '!', '#',
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Direction {
+    Upload,
+    Download,
+}
+
+/// `user@host:path` for scp, bracketing IPv6 literals.
+pub fn scp_target(user: &str, host: &str, remote_path: &str) -> String {
+    let host = host
+        .strip_prefix('[')
+        .and_then(|h| h.strip_suffix(']'))
+        .unwrap_or(host);
+    if host.contains(':') {
+        format!("{}@[{}]:{}", user, host, remote_path)
+    } else {
+        format!("{}@{}:{}", user, host, remote_path)
+    }
+}
+
+/// Synthetic command recorded in `command_logs`: "scp SOURCE -> DESTINATION".
+pub fn log_command(direction: Direction, local: &Path, remote: &str) -> String {
+    match direction {
+        Direction::Upload => format!("scp {} -> {}", local.display(), remote),
+        Direction::Download => format!("scp {} -> {}", remote, local.display()),
+    }
+}
+
+/// Checks `remote_path` against the machine's `deny-write PREFIX` (uploads) and
+/// `deny-read PREFIX` (downloads) constraints; other constraints are free-form notes and
+/// ignored. While such a rule exists the path must be absolute and free of `..`, so it
+/// can't be steered around the prefix, and free of glob and shell metacharacters, which the
+/// remote side would expand (`/e?c/shadow`). Paths are compared component by component
+/// after dropping empty (`//`) and `.` components, so `/etc//./passwd` is caught by `/etc`.
+pub fn check_constraints(
+    constraints: &[Constraint],
+    direction: Direction,
+    remote_path: &str,
+) -> Result<()> {
+    let rule = match direction {
+        Direction::Upload => DENY_WRITE_RULE,
+        Direction::Download => DENY_READ_RULE,
+    };
+    let prefixes: Vec<&str> = constraints
+        .iter()
+        .filter_map(|c| {
+            let (name, prefix) = c.rule_text.trim().split_once(char::is_whitespace)?;
+            (name == rule).then(|| prefix.trim())
+        })
+        .collect();
+    if prefixes.is_empty() {
+        return Ok(());
+    }
+    let components = path_components(remote_path);
+    if !remote_path.starts_with('/') || components.contains(&"..") {
+        return Err(anyhow!(
+            "remote_path must be absolute and must not contain '..' on a machine with {} constraints",
+            rule
+        ));
+    }
+    if remote_path.contains(REMOTE_PATH_METACHARACTERS) || remote_path.contains(char::is_whitespace)
+    {
+        return Err(anyhow!(
+            "remote_path must not contain glob or shell metacharacters or whitespace on a machine with {} constraints",
+            rule
+        ));
+    }
+    for prefix in prefixes {
+        if components.starts_with(&path_components(prefix)) {
+            return Err(anyhow!(
+                "{} is blocked by the constraint \"{} {}\"",
+                remote_path,
+                rule,
+                prefix
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Resolves `local` (symlinks and `..`; a file that doesn't exist yet through its
+/// directory) and checks that it lies inside `transfer_dir` when one is configured and
+/// outside every `protected` directory, such as the data directory and `~/.ssh`. Returns
+/// the resolved path to transfer to or from.
+pub fn check_local_path(
+    local: &Path,
+    transfer_dir: Option<&Path>,
+    protected: &[PathBuf],
+) -> Result<PathBuf> {
+    let resolved = match local.canonicalize() {
+        Ok(path) => path,
+        Err(_) => {
+            let name = local
+                .file_name()
+                .ok_or_else(|| anyhow!("{} is not a file path", local.display()))?;
+            let parent = match local.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => parent,
+                _ => Path::new("."),
+            };
+            parent
+                .canonicalize()
+                .map_err(|e| anyhow!("{}: {}", parent.display(), e))?
+                .join(name)
+        }
+    };
+    // A directory that doesn't exist can't contain anything
+    let canonical = |dir: &Path| dir.canonicalize().unwrap_or_else(|_| dir.to_path_buf());
+    if let Some(dir) = transfer_dir.filter(|dir| !resolved.starts_with(canonical(dir))) {
+        return Err(anyhow!(
+            "{} is outside the transfer directory {}",
+            local.display(),
+            dir.display()
+        ));
+    }
+    if let Some(dir) = protected
+        .iter()
+        .find(|dir| resolved.starts_with(canonical(dir)))
+    {
+        return Err(anyhow!(
+            "{} is inside {}, which file transfers may not touch",
+            local.display(),
+            dir.display()
+        ));
+    }
+    Ok(resolved)
+}
+
+/// `path` split at `/`, without the empty and `.` components that don't change where it
+/// points.
+fn path_components(path: &str) -> Vec<&str> {
+    path.split('/')
+        .filter(|part| !part.is_empty() && *part != ".")
+        .collect()
+}
+
+/// Copies one file with `scp` in batch mode, so only key and agent authentication work.
+/// `options` are the machine's ssh options. `Ok(None)` means it was still running after
+/// `timeout` and has been killed.
+pub fn scp(
+    direction: Direction,
+    local: &Path,
+    remote: &str,
+    identity: Option<&Path>,
+    known_hosts: Option<&Path>,
+    options: &[String],
+    timeout: Duration,
+) -> Result<Option<Output>> {
+    let mut cmd = Command::new("scp");
+    cmd.args([
+        "-o",
+        "BatchMode=yes",
+        "-o",
+        &format!("ConnectTimeout={}", CONNECT_TIMEOUT_SECS),
+    ]);
+    cmd.args(crate::known_hosts::ssh_options(known_hosts));
+    // scp spells ssh's -p (port) as -P; option values never start with '-'
+    cmd.args(
+        options
+            .iter()
+            .map(|arg| if arg == "-p" { "-P" } else { arg.as_str() }),
+    );
+    if let Some(key) = identity {
+        cmd.args(["-o", "IdentitiesOnly=yes", "-i"]).arg(key);
+    }
+    match direction {
+        Direction::Upload => cmd.arg(local).arg(remote),
+        Direction::Download => cmd.arg(remote).arg(local),
+    };
+    health::run_with_timeout(cmd, None, timeout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(text: &str) -> Constraint {
+        Constraint {
+            id: None,
+            machine_id: 1,
+            rule_text: text.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_scp_target_brackets_ipv6() {
+        assert_eq!(
+            scp_target("alice", "10.0.0.1", "/tmp/a"),
+            "alice@10.0.0.1:/tmp/a"
+        );
+        assert_eq!(
+            scp_target("alice", "fe80::1", "/tmp/a"),
+            "alice@[fe80::1]:/tmp/a"
+        );
+        assert_eq!(scp_target("alice", "[::1]", "a"), "alice@[::1]:a");
+    }
+
+    #[test]
+    fn test_check_local_path() {
+        let root = std::env::temp_dir().join(format!(
+            "mcp-ssh-manager-test-transfer-{}-{:016x}",
+            std::process::id(),
+            rand::random::<u64>()
+        ));
+        let (allowed, secret) = (root.join("transfers"), root.join("secret"));
+        std::fs::create_dir_all(&allowed).unwrap();
+        std::fs::create_dir_all(&secret).unwrap();
+        std::fs::write(secret.join("key"), "").unwrap();
+        let protected = [secret.clone()];
+
+        // A download target that doesn't exist yet resolves through its directory
+        let target = check_local_path(&allowed.join("new.txt"), Some(&allowed), &protected);
+        assert!(target.unwrap().ends_with("transfers/new.txt"));
+        assert!(check_local_path(&root.join("other.txt"), Some(&allowed), &protected).is_err());
+        assert!(
+            check_local_path(&allowed.join("../secret/key"), Some(&allowed), &protected).is_err()
+        );
+        assert!(check_local_path(&allowed.join("../secret/key"), None, &protected).is_err());
+        assert!(check_local_path(&secret.join("new"), None, &protected).is_err());
+        assert!(check_local_path(&root.join("other.txt"), None, &protected).is_ok());
+        assert!(check_local_path(&root.join("missing/file"), None, &protected).is_err());
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_check_constraints() {
+        let rules = [
+            rule("deny-write /etc/"),
+            rule("no reboots during office hours"),
+        ];
+        assert!(check_constraints(&rules, Direction::Upload, "/etc/nginx/nginx.conf").is_err());
+        assert!(check_constraints(&rules, Direction::Upload, "/etc").is_err());
+        assert!(check_constraints(&rules, Direction::Upload, "/etcetera/file").is_ok());
+        assert!(check_constraints(&rules, Direction::Upload, "//etc/passwd").is_err());
+        assert!(check_constraints(&rules, Direction::Upload, "/./etc/passwd").is_err());
+        assert!(check_constraints(&rules, Direction::Upload, "/etc//./").is_err());
+        assert!(
+            check_constraints(
+                &[rule("deny-read /var//./log")],
+                Direction::Download,
+                "/var/log/syslog"
+            )
+            .is_err()
+        );
+        assert!(check_constraints(&rules, Direction::Upload, "/tmp/../etc/passwd").is_err());
+        assert!(check_constraints(&rules, Direction::Upload, "nginx.conf").is_err());
+        // Expanded by the remote side into a blocked path
+        let read = [rule("deny-read /etc")];
+        for path in [
+            "/e?c/shadow",
+            "/e*/shadow",
+            "/[e]tc/shadow",
+            "/{etc,tmp}/shadow",
+            "/$HOME/.ssh/id_rsa",
+            "/tmp/`cat x`",
+            "/tmp/a\\b",
+            "/tmp/x;cat /etc/shadow",
+            "/tmp/x|y",
+            "/tmp/x&y",
+            "/tmp/<x>",
+            "/tmp/a b",
+            "/tmp/a\tb",
+            "/e't'c/shadow",
+            "/e\"tc\"/shadow",
+            "/tmp/~root",
+            "/tmp/$(id)",
+            "/tmp/!x",
+            "/tmp/x#y",
+        ] {
+            assert!(
+                check_constraints(&read, Direction::Download, path).is_err(),
+                "{}",
+                path
+            );
+        }
+        assert!(check_constraints(&read, Direction::Download, "/tmp/report-1.txt").is_ok());
+        assert!(check_constraints(&[], Direction::Download, "/e?c/*").is_ok());
+        assert!(check_constraints(&rules, Direction::Download, "/etc/hosts").is_ok());
+        assert!(check_constraints(&[], Direction::Upload, "relative/ok").is_ok());
+    }
+}